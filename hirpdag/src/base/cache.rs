@@ -0,0 +1,289 @@
+// ==== Rewrite Cache Base
+//
+// Persistent rewrite caches shared by all hirpdag modules.
+//
+// Unlike `HirpdagRewriteMemoized`, which lives and dies with one rewriter,
+// a `HirpdagRewriteCacheTable` can outlive rewrite calls and passes. Entries
+// are keyed by the input node's creation id, which is never reused, and hold
+// only a weak handle to the input, so a cache does not keep its inputs alive
+// and entries for dead inputs are dropped.
+
+/// A node which can key a [`HirpdagRewriteCacheTable`].
+///
+/// Implemented for every generated hirpdag struct type.
+pub trait HirpdagWeakKey: Clone + Eq {
+    /// A handle which does not keep the node alive.
+    type Weak;
+
+    /// The creation id of the node. Ids are never reused, even after the
+    /// node dies.
+    fn hirpdag_key_id(&self) -> u64;
+    fn hirpdag_key_downgrade(&self) -> Self::Weak;
+    /// Returns the node if it is still alive.
+    fn hirpdag_key_upgrade(weak: &Self::Weak) -> Option<Self>;
+}
+
+/// Counters of a rewrite cache, for tuning its capacity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HirpdagCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries removed to stay within the capacity.
+    pub evictions: u64,
+    /// Entries removed because their input node died.
+    pub purged: u64,
+}
+
+impl std::ops::Add for HirpdagCacheStats {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            evictions: self.evictions + other.evictions,
+            purged: self.purged + other.purged,
+        }
+    }
+}
+
+struct HirpdagCacheEntry<K: HirpdagWeakKey> {
+    key: K::Weak,
+    // None when the rewrite returned its input, so the entry does not hold a
+    // strong reference to its own key.
+    value: Option<K>,
+    stamp: u64,
+}
+
+struct HirpdagCacheInner<K: HirpdagWeakKey> {
+    entries: std::collections::HashMap<u64, HirpdagCacheEntry<K>>,
+    // Least recently used first: stamp -> key id.
+    lru: std::collections::BTreeMap<u64, u64>,
+    clock: u64,
+    capacity: Option<usize>,
+    // Number of entries at which dead inputs are next purged.
+    next_purge: usize,
+    stats: HirpdagCacheStats,
+}
+
+const HIRPDAG_CACHE_MIN_PURGE: usize = 64;
+
+impl<K: HirpdagWeakKey> HirpdagCacheInner<K> {
+    fn touch(&mut self, id: u64) {
+        self.clock += 1;
+        let stamp = self.clock;
+        if let Some(entry) = self.entries.get_mut(&id) {
+            self.lru.remove(&entry.stamp);
+            entry.stamp = stamp;
+            self.lru.insert(stamp, id);
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> bool {
+        match self.entries.remove(&id) {
+            Some(entry) => {
+                self.lru.remove(&entry.stamp);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn purge(&mut self) -> usize {
+        let dead: Vec<u64> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| K::hirpdag_key_upgrade(&entry.key).is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &dead {
+            self.remove(*id);
+        }
+        self.stats.purged += dead.len() as u64;
+        dead.len()
+    }
+}
+
+/// A rewrite cache for one hirpdag type: maps input nodes to the result of
+/// rewriting them.
+///
+/// - Entries are keyed weakly on the input node. Entries whose input died
+///   are purged as the cache grows, or explicitly with
+///   [`purge`](Self::purge).
+/// - With a capacity, the least recently used entries are evicted.
+/// - Entries can be invalidated individually or cleared.
+/// - The table is internally synchronized, so it can be shared between
+///   threads when the node type can.
+///
+/// Results are held strongly. A result which contains its own input (e.g.
+/// wraps it) keeps the input alive until the entry is evicted or
+/// invalidated.
+pub struct HirpdagRewriteCacheTable<K: HirpdagWeakKey> {
+    inner: std::sync::Mutex<HirpdagCacheInner<K>>,
+}
+
+impl<K: HirpdagWeakKey> Default for HirpdagRewriteCacheTable<K> {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl<K: HirpdagWeakKey> HirpdagRewriteCacheTable<K> {
+    /// A cache holding at most `capacity` entries, or unbounded.
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            inner: std::sync::Mutex::new(HirpdagCacheInner {
+                entries: std::collections::HashMap::new(),
+                lru: std::collections::BTreeMap::new(),
+                clock: 0,
+                capacity,
+                next_purge: HIRPDAG_CACHE_MIN_PURGE,
+                stats: HirpdagCacheStats::default(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HirpdagCacheInner<K>> {
+        // Rewrites run outside the lock, so a poisoned lock can only come
+        // from a panic between consistent states.
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// The cached rewrite of `x`, if any.
+    pub fn get(&self, x: &K) -> Option<K> {
+        let mut inner = self.lock();
+        let id = x.hirpdag_key_id();
+        let value = match inner.entries.get(&id) {
+            Some(entry) => entry.value.clone().unwrap_or_else(|| x.clone()),
+            None => {
+                inner.stats.misses += 1;
+                return None;
+            }
+        };
+        inner.stats.hits += 1;
+        inner.touch(id);
+        Some(value)
+    }
+
+    /// Caches `y` as the rewrite of `x`.
+    pub fn insert(&self, x: &K, y: K) {
+        let mut inner = self.lock();
+        let id = x.hirpdag_key_id();
+        let value = if y == *x { None } else { Some(y) };
+        inner.remove(id);
+        inner.clock += 1;
+        let stamp = inner.clock;
+        inner.entries.insert(
+            id,
+            HirpdagCacheEntry {
+                key: x.hirpdag_key_downgrade(),
+                value,
+                stamp,
+            },
+        );
+        inner.lru.insert(stamp, id);
+
+        if inner.entries.len() >= inner.next_purge {
+            inner.purge();
+            inner.next_purge = std::cmp::max(2 * inner.entries.len(), HIRPDAG_CACHE_MIN_PURGE);
+        }
+        if let Some(capacity) = inner.capacity {
+            while inner.entries.len() > capacity {
+                let (_, oldest) = inner.lru.pop_first().expect("lru tracks every entry");
+                inner.entries.remove(&oldest);
+                inner.stats.evictions += 1;
+            }
+        }
+    }
+
+    /// The cached rewrite of `x`, or else `rewrite(x)`, which is cached.
+    ///
+    /// `rewrite` runs without holding the cache's lock, so it may use the
+    /// cache for children of `x`.
+    pub fn get_or_insert_with<F: FnOnce(&K) -> K>(&self, x: &K, rewrite: F) -> K {
+        if let Some(y) = self.get(x) {
+            return y;
+        }
+        let y = rewrite(x);
+        self.insert(x, y.clone());
+        y
+    }
+
+    /// Removes the entry for `x`. Returns whether there was one.
+    pub fn invalidate(&self, x: &K) -> bool {
+        self.lock().remove(x.hirpdag_key_id())
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.entries.clear();
+        inner.lru.clear();
+        inner.next_purge = HIRPDAG_CACHE_MIN_PURGE;
+    }
+
+    /// Removes the entries whose input node died. Returns how many.
+    pub fn purge(&self) -> usize {
+        self.lock().purge()
+    }
+
+    /// Number of entries, including entries for dead inputs not yet purged.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> HirpdagCacheStats {
+        self.lock().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A key which is alive while `alive` holds its id.
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Key(u64, std::rc::Rc<std::cell::RefCell<Vec<u64>>>);
+
+    impl HirpdagWeakKey for Key {
+        type Weak = Key;
+        fn hirpdag_key_id(&self) -> u64 {
+            self.0
+        }
+        fn hirpdag_key_downgrade(&self) -> Self::Weak {
+            self.clone()
+        }
+        fn hirpdag_key_upgrade(weak: &Self::Weak) -> Option<Self> {
+            weak.1.borrow().contains(&weak.0).then(|| weak.clone())
+        }
+    }
+
+    #[test]
+    fn test_cache_lru_and_purge() {
+        let alive = std::rc::Rc::new(std::cell::RefCell::new(vec![1, 2, 3]));
+        let key = |n| Key(n, alive.clone());
+        let cache = HirpdagRewriteCacheTable::new(Some(2));
+        cache.insert(&key(1), key(10));
+        cache.insert(&key(2), key(2));
+        assert_eq!(cache.get(&key(1)), Some(key(10)));
+        // 2 is the least recently used.
+        cache.insert(&key(3), key(30));
+        assert_eq!(cache.get(&key(2)), None);
+        assert_eq!(cache.len(), 2);
+
+        alive.borrow_mut().retain(|&n| n != 3);
+        assert_eq!(cache.purge(), 1);
+        assert_eq!(cache.get(&key(1)), Some(key(10)));
+        assert!(cache.invalidate(&key(1)));
+        assert!(cache.is_empty());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!((stats.evictions, stats.purged), (1, 1));
+    }
+}
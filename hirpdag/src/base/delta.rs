@@ -0,0 +1,89 @@
+// ==== Delta Archives
+//
+// A delta archive holds only the nodes its base archive does not. Its node
+// table continues the base's: indices below the base's node count refer to
+// base nodes, and the delta's own nodes follow in post-order DFS order, so
+// children still precede parents across both. The binary header names the
+// base by its `HirpdagArchiveBaseId`, and loading seeds the deserialization
+// session with the base's reconstructed nodes before decoding the delta.
+//
+// A base is identified by a hash of its whole binary archive, header
+// included. A delta's header names its own base, so the id of a delta used
+// as the base of another pins the whole chain.
+
+use crate::base::serialize::HirpdagDeserializeError;
+use crate::base::stable_hash::HirpdagStableHasher;
+
+/// Identifies the base archive of a delta archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagArchiveBaseId {
+    /// FNV-1a hash of the base's binary archive bytes.
+    pub hash: u64,
+    /// The number of nodes the base defines, including those of its own
+    /// base: the first node index of a delta against it.
+    pub nodes: u64,
+}
+
+impl HirpdagArchiveBaseId {
+    /// The id of the binary archive `bytes`, defining `nodes` nodes.
+    pub fn of_archive(bytes: &[u8], nodes: u64) -> Self {
+        Self {
+            hash: hirpdag_fnv1a_64(bytes),
+            nodes,
+        }
+    }
+}
+
+impl std::fmt::Display for HirpdagArchiveBaseId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#018x} ({} nodes)", self.hash, self.nodes)
+    }
+}
+
+/// FNV-1a 64-bit hash. Base ids are stored in archives, so this must be
+/// stable across Rust releases and platforms (unlike std's DefaultHasher).
+fn hirpdag_fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hasher = HirpdagStableHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Fails with `BaseMismatch` unless an archive written against the `found`
+/// base (`None` for a full archive) is being read against the `expected`
+/// one.
+pub fn hirpdag_check_base(
+    found: Option<&HirpdagArchiveBaseId>,
+    expected: Option<&HirpdagArchiveBaseId>,
+) -> Result<(), HirpdagDeserializeError> {
+    if found != expected {
+        return Err(HirpdagDeserializeError::BaseMismatch {
+            expected: expected.copied(),
+            found: found.copied(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_base() {
+        let a = HirpdagArchiveBaseId::of_archive(b"a", 1);
+        let b = HirpdagArchiveBaseId::of_archive(b"b", 1);
+        assert_eq!(a.hash, 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(a, b);
+        assert!(hirpdag_check_base(None, None).is_ok());
+        assert!(hirpdag_check_base(Some(&a), Some(&a)).is_ok());
+        assert_eq!(
+            hirpdag_check_base(Some(&a), Some(&b)),
+            Err(HirpdagDeserializeError::BaseMismatch {
+                expected: Some(b),
+                found: Some(a),
+            })
+        );
+        assert!(hirpdag_check_base(Some(&a), None).is_err());
+        assert!(hirpdag_check_base(None, Some(&a)).is_err());
+    }
+}
@@ -0,0 +1,472 @@
+// ==== Structural Diff
+//
+// Diffs two versions of a value by walking them in parallel. Hash-consing
+// makes equal subgraphs pointer-equal, so unchanged subgraphs are skipped
+// in O(1), and the walk only visits the paths that changed.
+//
+// A change is reported at the deepest node where the versions differ in
+// more than their children: two nodes whose fields differ only in child
+// nodes are descended into; otherwise the node is replaced as a whole.
+// Vectors of nodes keep their common prefix and suffix, pair up the rest
+// by position, and add or remove the excess.
+//
+// A patch re-applied to the old value rebuilds the nodes along the changed
+// paths, and produces exactly the new value. It checks the nodes it
+// replaces or removes, so it fails on a value it was not computed from.
+
+use crate::base::basic_traits::IsNumber;
+
+/// A step on the path from a value to a part of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HirpdagPathSegment {
+    /// A field of a struct node.
+    Field(&'static str),
+    /// The active variant of an enum value.
+    Variant(&'static str),
+    /// An item of a vector.
+    Index(usize),
+}
+
+/// The path from a value to a part of it, shown as `field.Variant[0]`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct HirpdagPath(pub Vec<HirpdagPathSegment>);
+
+impl std::fmt::Display for HirpdagPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("(root)");
+        }
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                HirpdagPathSegment::Field(name) | HirpdagPathSegment::Variant(name) => {
+                    if i != 0 {
+                        f.write_str(".")?;
+                    }
+                    f.write_str(name)?;
+                }
+                HirpdagPathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What changed at a path. `N` is the module's `HirpdagNodeRef`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HirpdagChangeKind<N> {
+    /// A node was inserted into a vector, at this index of the new vector.
+    Added(N),
+    /// A node was removed from a vector, at this index of the old vector.
+    Removed(N),
+    /// A node was replaced as a whole.
+    Replaced { old: N, new: N },
+}
+
+/// A change at a path.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HirpdagChange<N> {
+    pub path: HirpdagPath,
+    pub kind: HirpdagChangeKind<N>,
+}
+
+/// The changes from one version of a value to another, in the order the
+/// paths were walked.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HirpdagPatch<N> {
+    pub changes: Vec<HirpdagChange<N>>,
+}
+
+impl<N: Clone + PartialEq> HirpdagPatch<N> {
+    /// The changes from `old` to `new`, or `None` if they differ but no
+    /// node holds the difference, as for two different strings.
+    pub fn between<T: HirpdagDiff<N>>(old: &T, new: &T) -> Option<Self> {
+        let mut d = HirpdagDiffer {
+            path: Vec::new(),
+            changes: Vec::new(),
+        };
+        if !old.hirpdag_diff(new, &mut d) {
+            return None;
+        }
+        Some(Self { changes: d.changes })
+    }
+
+    /// Whether the two versions were the same.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Applies the patch to `old`, the value it was computed from,
+    /// producing the new value.
+    pub fn apply<T: HirpdagDiff<N>>(&self, old: &T) -> Result<T, HirpdagPatchError> {
+        let steps: Vec<HirpdagPatchStep<'_, N>> = self
+            .changes
+            .iter()
+            .map(|change| HirpdagPatchStep {
+                rest: &change.path.0,
+                change,
+            })
+            .collect();
+        old.hirpdag_patch(&steps)
+    }
+}
+
+/// A patch which does not apply to the value it is applied to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HirpdagPatchError {
+    pub path: HirpdagPath,
+    pub message: String,
+}
+
+impl std::fmt::Display for HirpdagPatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hirpdag: patch does not apply at {}: {}",
+            self.path, self.message
+        )
+    }
+}
+
+impl std::error::Error for HirpdagPatchError {}
+
+/// Implemented by every field type, and the generated types, to be diffed
+/// and patched.
+pub trait HirpdagDiff<N>: Clone + PartialEq {
+    /// Adds the changes from `self` to `new` to `d`, or returns false if
+    /// they are not expressible below this value, so whatever node holds
+    /// it must be replaced.
+    fn hirpdag_diff(&self, new: &Self, d: &mut HirpdagDiffer<N>) -> bool;
+
+    /// Applies the changes at paths below this value.
+    fn hirpdag_patch(&self, steps: &[HirpdagPatchStep<'_, N>]) -> Result<Self, HirpdagPatchError>;
+
+    /// This value as a node, if it is one.
+    fn hirpdag_node(&self) -> Option<N> {
+        None
+    }
+
+    /// The value that is the node `node`, if this type's values are nodes.
+    fn hirpdag_from_node(_node: &N) -> Option<Self> {
+        None
+    }
+}
+
+/// Collects changes while diffing.
+pub struct HirpdagDiffer<N> {
+    path: Vec<HirpdagPathSegment>,
+    changes: Vec<HirpdagChange<N>>,
+}
+
+impl<N> HirpdagDiffer<N> {
+    /// Diffs `old` against `new` below `segment`.
+    pub fn nested<T: HirpdagDiff<N>>(
+        &mut self,
+        segment: HirpdagPathSegment,
+        old: &T,
+        new: &T,
+    ) -> bool {
+        self.path.push(segment);
+        let diffable = old.hirpdag_diff(new, self);
+        self.path.pop();
+        diffable
+    }
+
+    /// Adds a change at the current path.
+    pub fn change(&mut self, kind: HirpdagChangeKind<N>) {
+        self.changes.push(HirpdagChange {
+            path: HirpdagPath(self.path.clone()),
+            kind,
+        });
+    }
+
+    /// Replaces the changes added since `diff` was called with `kind`, if
+    /// `diff` returns false.
+    pub fn or_replace(
+        &mut self,
+        diff: impl FnOnce(&mut Self) -> bool,
+        kind: impl FnOnce() -> HirpdagChangeKind<N>,
+    ) {
+        let mark = self.changes.len();
+        if !diff(self) {
+            self.changes.truncate(mark);
+            self.change(kind());
+        }
+    }
+
+    fn with_segment(&mut self, segment: HirpdagPathSegment, kind: HirpdagChangeKind<N>) {
+        self.path.push(segment);
+        self.change(kind);
+        self.path.pop();
+    }
+}
+
+/// A change being applied, with the part of its path below the value it is
+/// being applied to.
+pub struct HirpdagPatchStep<'a, N> {
+    pub rest: &'a [HirpdagPathSegment],
+    pub change: &'a HirpdagChange<N>,
+}
+
+impl<N: Clone + PartialEq> HirpdagPatchStep<'_, N> {
+    /// An error for this change.
+    pub fn error(&self, message: impl Into<String>) -> HirpdagPatchError {
+        HirpdagPatchError {
+            path: self.change.path.clone(),
+            message: message.into(),
+        }
+    }
+
+    /// The node replacing `current`, if a step replaces the node itself.
+    pub fn replacement(steps: &[Self], current: &N) -> Result<Option<N>, HirpdagPatchError> {
+        let step = match steps.iter().find(|step| step.rest.is_empty()) {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        if steps.len() != 1 {
+            return Err(step.error("the node is replaced and changed below"));
+        }
+        match &step.change.kind {
+            HirpdagChangeKind::Replaced { old, new } if old == current => Ok(Some(new.clone())),
+            HirpdagChangeKind::Replaced { .. } => Err(step.error("the replaced node differs")),
+            _ => Err(step.error("expected a vector item")),
+        }
+    }
+
+    /// Fails on any step whose path does not start with one of `segments`.
+    pub fn check(steps: &[Self], segments: &[HirpdagPathSegment]) -> Result<(), HirpdagPatchError> {
+        match steps
+            .iter()
+            .find(|step| !step.rest.first().is_some_and(|s| segments.contains(s)))
+        {
+            Some(step) => Err(step.error("no such path")),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the steps below `segment` to `value`, the part of the value
+    /// at `segment`.
+    pub fn nested<T: HirpdagDiff<N>>(
+        steps: &[Self],
+        segment: HirpdagPathSegment,
+        value: &T,
+    ) -> Result<T, HirpdagPatchError> {
+        let nested: Vec<HirpdagPatchStep<'_, N>> = steps
+            .iter()
+            .filter(|step| step.rest.first() == Some(&segment))
+            .map(|step| HirpdagPatchStep {
+                rest: &step.rest[1..],
+                change: step.change,
+            })
+            .collect();
+        if nested.is_empty() {
+            return Ok(value.clone());
+        }
+        value.hirpdag_patch(&nested)
+    }
+}
+
+/// Leaf values: a difference must replace the node holding them.
+fn hirpdag_patch_leaf<N: Clone + PartialEq, T: Clone>(
+    value: &T,
+    steps: &[HirpdagPatchStep<'_, N>],
+) -> Result<T, HirpdagPatchError> {
+    HirpdagPatchStep::check(steps, &[])?;
+    Ok(value.clone())
+}
+
+impl<N: Clone + PartialEq, P: IsNumber + Clone + PartialEq> HirpdagDiff<N> for P {
+    fn hirpdag_diff(&self, new: &Self, _d: &mut HirpdagDiffer<N>) -> bool {
+        self == new
+    }
+
+    fn hirpdag_patch(&self, steps: &[HirpdagPatchStep<'_, N>]) -> Result<Self, HirpdagPatchError> {
+        hirpdag_patch_leaf(self, steps)
+    }
+}
+
+impl<N: Clone + PartialEq> HirpdagDiff<N> for String {
+    fn hirpdag_diff(&self, new: &Self, _d: &mut HirpdagDiffer<N>) -> bool {
+        self == new
+    }
+
+    fn hirpdag_patch(&self, steps: &[HirpdagPatchStep<'_, N>]) -> Result<Self, HirpdagPatchError> {
+        hirpdag_patch_leaf(self, steps)
+    }
+}
+
+impl<N: Clone + PartialEq> HirpdagDiff<N> for bool {
+    fn hirpdag_diff(&self, new: &Self, _d: &mut HirpdagDiffer<N>) -> bool {
+        self == new
+    }
+
+    fn hirpdag_patch(&self, steps: &[HirpdagPatchStep<'_, N>]) -> Result<Self, HirpdagPatchError> {
+        hirpdag_patch_leaf(self, steps)
+    }
+}
+
+/// An option adds no path segment: `Some` is diffed as its value.
+impl<N: Clone + PartialEq, T: HirpdagDiff<N>> HirpdagDiff<N> for Option<T> {
+    fn hirpdag_diff(&self, new: &Self, d: &mut HirpdagDiffer<N>) -> bool {
+        match (self, new) {
+            (None, None) => true,
+            (Some(old), Some(new)) => old.hirpdag_diff(new, d),
+            _ => false,
+        }
+    }
+
+    fn hirpdag_patch(&self, steps: &[HirpdagPatchStep<'_, N>]) -> Result<Self, HirpdagPatchError> {
+        match self {
+            None => hirpdag_patch_leaf(self, steps),
+            Some(value) => Ok(Some(value.hirpdag_patch(steps)?)),
+        }
+    }
+}
+
+impl<N: Clone + PartialEq, T: HirpdagDiff<N>> HirpdagDiff<N> for Vec<T> {
+    fn hirpdag_diff(&self, new: &Self, d: &mut HirpdagDiffer<N>) -> bool {
+        if self == new {
+            return true;
+        }
+        let prefix = self.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = self[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_end = self.len() - suffix;
+        let new_end = new.len() - suffix;
+        let paired = (old_end - prefix).min(new_end - prefix);
+        for i in prefix..prefix + paired {
+            if !d.nested(HirpdagPathSegment::Index(i), &self[i], &new[i]) {
+                return false;
+            }
+        }
+        for (i, item) in self.iter().enumerate().take(old_end).skip(prefix + paired) {
+            match item.hirpdag_node() {
+                Some(node) => d.with_segment(
+                    HirpdagPathSegment::Index(i),
+                    HirpdagChangeKind::Removed(node),
+                ),
+                None => return false,
+            }
+        }
+        for (i, item) in new.iter().enumerate().take(new_end).skip(prefix + paired) {
+            match item.hirpdag_node() {
+                Some(node) => {
+                    d.with_segment(HirpdagPathSegment::Index(i), HirpdagChangeKind::Added(node))
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Items are patched at their old indices, then removed by their old
+    /// indices, then added at their new indices.
+    fn hirpdag_patch(&self, steps: &[HirpdagPatchStep<'_, N>]) -> Result<Self, HirpdagPatchError> {
+        let mut items = self.clone();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut nested = Vec::new();
+        for step in steps {
+            let index = match step.rest.first() {
+                Some(HirpdagPathSegment::Index(index)) => *index,
+                _ => return Err(step.error("expected a vector index")),
+            };
+            match &step.change.kind {
+                HirpdagChangeKind::Removed(node) if step.rest.len() == 1 => {
+                    removed.push((index, node, step))
+                }
+                HirpdagChangeKind::Added(node) if step.rest.len() == 1 => {
+                    added.push((index, node, step))
+                }
+                _ if index < items.len() => nested.push(index),
+                _ => return Err(step.error("index out of range")),
+            }
+        }
+        nested.sort_unstable();
+        nested.dedup();
+        for index in nested {
+            let item_steps: Vec<HirpdagPatchStep<'_, N>> = steps
+                .iter()
+                .filter(|step| step.rest.first() == Some(&HirpdagPathSegment::Index(index)))
+                .filter(|step| {
+                    step.rest.len() > 1
+                        || matches!(step.change.kind, HirpdagChangeKind::Replaced { .. })
+                })
+                .map(|step| HirpdagPatchStep {
+                    rest: &step.rest[1..],
+                    change: step.change,
+                })
+                .collect();
+            items[index] = self[index].hirpdag_patch(&item_steps)?;
+        }
+        removed.sort_unstable_by_key(|(index, _, _)| std::cmp::Reverse(*index));
+        for (index, node, step) in removed {
+            if items
+                .get(index)
+                .and_then(|item| item.hirpdag_node())
+                .as_ref()
+                != Some(node)
+            {
+                return Err(step.error("the removed node differs"));
+            }
+            items.remove(index);
+        }
+        added.sort_unstable_by_key(|(index, _, _)| *index);
+        for (index, node, step) in added {
+            let item = T::hirpdag_from_node(node)
+                .ok_or_else(|| step.error("the added node has the wrong type"))?;
+            if index > items.len() {
+                return Err(step.error("index out of range"));
+            }
+            items.insert(index, item);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_display() {
+        assert_eq!(HirpdagPath::default().to_string(), "(root)");
+        let path = HirpdagPath(vec![
+            HirpdagPathSegment::Field("op"),
+            HirpdagPathSegment::Variant("Add"),
+            HirpdagPathSegment::Index(1),
+            HirpdagPathSegment::Field("name"),
+        ]);
+        assert_eq!(path.to_string(), "op.Add[1].name");
+        assert_eq!(
+            HirpdagPath(vec![HirpdagPathSegment::Index(0)]).to_string(),
+            "[0]"
+        );
+    }
+
+    #[test]
+    fn test_leaf_vectors() {
+        // Vectors of leaves cannot express a change below themselves.
+        let patch = HirpdagPatch::<u32>::between(&vec![1u8, 2], &vec![1u8, 2]);
+        assert!(patch.unwrap().is_empty());
+        // Outside any node, a difference has no patch.
+        assert_eq!(
+            HirpdagPatch::<u32>::between(&vec![1u8, 2], &vec![1u8, 3]),
+            None
+        );
+        assert_eq!(
+            HirpdagPatch::<u32>::between(&vec![1u8, 2], &vec![1u8]),
+            None
+        );
+        let mut d = HirpdagDiffer::<u32> {
+            path: Vec::new(),
+            changes: Vec::new(),
+        };
+        assert!(!vec![1u8, 2].hirpdag_diff(&vec![1u8, 3], &mut d));
+        assert!(!vec![1u8, 2].hirpdag_diff(&vec![1u8], &mut d));
+        assert!(d.changes.is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+// ==== Graphviz Export
+//
+// `Debug` on a node recurses into its children, so a shared subgraph prints
+// once per path to it. The generated `hirpdag_to_dot` draws a DAG as a
+// Graphviz DOT digraph instead, each unique node once: labelled with its
+// type, its leaf fields and optionally its metadata, with an edge per child
+// reference labelled by the field (and vector index) holding it.
+//
+// Nodes are drawn in breadth-first order from the roots, so the output only
+// depends on the roots and the options.
+
+use crate::base::basic_traits::IsNumber;
+use crate::base::meta::HirpdagMeta;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+/// A node which can be drawn by [`hirpdag_dot`]. Implemented by the
+/// generated `HirpdagNodeRef`.
+pub trait HirpdagDotNode: Clone + Eq + std::hash::Hash {
+    /// The name of the node's hirpdag type.
+    fn hirpdag_dot_type_name(&self) -> &'static str;
+    fn hirpdag_dot_meta(&self) -> HirpdagMeta;
+    /// Describes the node's fields to `fields`.
+    fn hirpdag_dot_fields(&self, fields: &mut HirpdagDotFields<Self>);
+}
+
+/// Implemented by every field type to describe its value to a node's
+/// [`HirpdagDotFields`]: leaf values as text, child references as edges.
+pub trait HirpdagDotField<N> {
+    fn hirpdag_dot_field(&self, fields: &mut HirpdagDotFields<N>);
+}
+
+/// The fields of a node being drawn: its leaf values and the nodes it
+/// references, each under the path of the field holding it, e.g. `args[1]`.
+pub struct HirpdagDotFields<N> {
+    path: String,
+    leaves: Vec<(String, String)>,
+    edges: Vec<(String, N)>,
+}
+
+impl<N> HirpdagDotFields<N> {
+    fn new() -> Self {
+        Self {
+            path: String::new(),
+            leaves: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    /// Describes `value` under `segment`: a field or enum variant name, or
+    /// an `[index]`.
+    pub fn nested<T: HirpdagDotField<N> + ?Sized>(&mut self, segment: &str, value: &T) {
+        let len = self.path.len();
+        if len > 0 && !segment.starts_with('[') {
+            self.path.push('.');
+        }
+        self.path.push_str(segment);
+        value.hirpdag_dot_field(self);
+        self.path.truncate(len);
+    }
+
+    /// Records a leaf value at the current path.
+    pub fn leaf(&mut self, value: impl std::fmt::Debug) {
+        self.leaves
+            .push((self.path.clone(), format!("{:?}", value)));
+    }
+
+    /// Records a reference to `node` at the current path.
+    pub fn edge(&mut self, node: N) {
+        self.edges.push((self.path.clone(), node));
+    }
+}
+
+impl<N, P: IsNumber + std::fmt::Debug> HirpdagDotField<N> for P {
+    fn hirpdag_dot_field(&self, fields: &mut HirpdagDotFields<N>) {
+        fields.leaf(self);
+    }
+}
+
+impl<N> HirpdagDotField<N> for String {
+    fn hirpdag_dot_field(&self, fields: &mut HirpdagDotFields<N>) {
+        fields.leaf(self);
+    }
+}
+
+impl<N> HirpdagDotField<N> for bool {
+    fn hirpdag_dot_field(&self, fields: &mut HirpdagDotFields<N>) {
+        fields.leaf(self);
+    }
+}
+
+impl<N, T: HirpdagDotField<N>> HirpdagDotField<N> for Option<T> {
+    fn hirpdag_dot_field(&self, fields: &mut HirpdagDotFields<N>) {
+        match self {
+            None => fields.leaf(None::<()>),
+            Some(value) => value.hirpdag_dot_field(fields),
+        }
+    }
+}
+
+impl<N, T: HirpdagDotField<N>> HirpdagDotField<N> for Vec<T> {
+    fn hirpdag_dot_field(&self, fields: &mut HirpdagDotFields<N>) {
+        if self.is_empty() {
+            fields.leaf(Vec::<()>::new());
+        }
+        for (index, value) in self.iter().enumerate() {
+            fields.nested(&format!("[{}]", index), value);
+        }
+    }
+}
+
+/// What [`hirpdag_dot`] draws.
+#[derive(Clone, Debug)]
+pub struct HirpdagDotOptions<N> {
+    /// Draw only nodes at most this many drawn nodes below a root (roots
+    /// are at depth 0). Nodes whose children are cut off are dashed.
+    pub max_depth: Option<usize>,
+    /// Draw only nodes of these types. A reference to a node of another
+    /// type is followed through it to the drawn nodes it reaches, and the
+    /// edge labelled with the whole path, e.g. `body[0].value`. A drawn
+    /// node reached by several paths gets one edge, labelled with the first
+    /// path and the number of paths, e.g. `body[0].value (4 paths)`.
+    pub types: Option<Vec<&'static str>>,
+    /// Nodes to fill in.
+    pub highlight: HashSet<N>,
+    /// Add each node's count, height and flags to its label.
+    pub meta: bool,
+}
+
+impl<N> Default for HirpdagDotOptions<N> {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            types: None,
+            highlight: HashSet::new(),
+            meta: false,
+        }
+    }
+}
+
+/// Escapes `text` for a double-quoted DOT string.
+fn hirpdag_dot_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Draws the nodes reachable from `roots` as a DOT digraph. Each root is a
+/// plain-text node with the given label, pointing at its node.
+pub fn hirpdag_dot<N: HirpdagDotNode>(
+    roots: &[(String, N)],
+    options: &HirpdagDotOptions<N>,
+) -> String {
+    let drawn = |node: &N| {
+        options
+            .types
+            .as_ref()
+            .is_none_or(|types| types.contains(&node.hirpdag_dot_type_name()))
+    };
+    // The drawn nodes referenced by a node's fields, passing through the
+    // nodes which are not drawn, with their edge labels.
+    let mut hidden_targets: HashMap<N, Vec<HirpdagDotTarget<N>>> = HashMap::new();
+    let mut targets = |edges: Vec<(String, N)>| {
+        let mut out = Vec::new();
+        for (path, node) in edges {
+            if drawn(&node) {
+                out.push((path, node));
+                continue;
+            }
+            for target in hirpdag_dot_hidden(&node, &drawn, &mut hidden_targets) {
+                out.push((format!("{}.{}", path, target.label()), target.node.clone()));
+            }
+        }
+        out
+    };
+
+    let mut out = String::from("digraph hirpdag {\n    node [shape=box];\n");
+    let mut ids: HashMap<N, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    for (position, (label, root)) in roots.iter().enumerate() {
+        writeln!(
+            out,
+            "    r{} [shape=plaintext, label=\"{}\"];",
+            position,
+            hirpdag_dot_escape(label)
+        )
+        .unwrap();
+        for (path, target) in targets(vec![(String::new(), root.clone())]) {
+            let id = hirpdag_dot_visit(&mut ids, &mut queue, target, 0);
+            let path = path.trim_start_matches('.');
+            if path.is_empty() {
+                writeln!(out, "    r{} -> n{};", position, id).unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "    r{} -> n{} [label=\"{}\"];",
+                    position,
+                    id,
+                    hirpdag_dot_escape(path)
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    while let Some((node, depth)) = queue.pop_front() {
+        let id = ids[&node];
+        let mut fields = HirpdagDotFields::new();
+        node.hirpdag_dot_fields(&mut fields);
+        let mut label = format!("{}\\l", node.hirpdag_dot_type_name());
+        for (path, value) in &fields.leaves {
+            label.push_str(&hirpdag_dot_escape(&format!("{} = {}", path, value)));
+            label.push_str("\\l");
+        }
+        if options.meta {
+            let meta = node.hirpdag_dot_meta();
+            write!(
+                label,
+                "count={} height={} flags={:#06x}\\l",
+                meta.get_count(),
+                meta.get_height(),
+                meta.get_flags()
+            )
+            .unwrap();
+        }
+        let edges = targets(fields.edges);
+        let truncated = !edges.is_empty() && options.max_depth.is_some_and(|max| depth >= max);
+        let style = match (options.highlight.contains(&node), truncated) {
+            (false, false) => "",
+            (true, false) => ", style=filled, fillcolor=yellow",
+            (false, true) => ", style=dashed",
+            (true, true) => ", style=\"filled,dashed\", fillcolor=yellow",
+        };
+        writeln!(out, "    n{} [label=\"{}\"{}];", id, label, style).unwrap();
+        if truncated {
+            continue;
+        }
+        for (path, target) in edges {
+            let target = hirpdag_dot_visit(&mut ids, &mut queue, target, depth + 1);
+            writeln!(
+                out,
+                "    n{} -> n{} [label=\"{}\"];",
+                id,
+                target,
+                hirpdag_dot_escape(&path)
+            )
+            .unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// The id of `node`, numbering and queueing it at `depth` if it is new.
+fn hirpdag_dot_visit<N: HirpdagDotNode>(
+    ids: &mut HashMap<N, usize>,
+    queue: &mut VecDeque<(N, usize)>,
+    node: N,
+    depth: usize,
+) -> usize {
+    let next = ids.len();
+    *ids.entry(node.clone()).or_insert_with(|| {
+        queue.push_back((node, depth));
+        next
+    })
+}
+
+/// A drawn node reached from a hidden node, by `paths` paths, the first of
+/// them `path`.
+struct HirpdagDotTarget<N> {
+    path: String,
+    node: N,
+    paths: u64,
+}
+
+impl<N> HirpdagDotTarget<N> {
+    fn label(&self) -> String {
+        if self.paths == 1 {
+            self.path.clone()
+        } else {
+            format!("{} ({} paths)", self.path, self.paths)
+        }
+    }
+}
+
+/// The drawn nodes reached from the hidden `node`, each once. Memoized, as
+/// hidden nodes may be shared; a chain of shared hidden nodes has
+/// exponentially many paths, but as many targets as the drawn nodes below
+/// it at most.
+fn hirpdag_dot_hidden<'m, N: HirpdagDotNode>(
+    node: &N,
+    drawn: &impl Fn(&N) -> bool,
+    memo: &'m mut HashMap<N, Vec<HirpdagDotTarget<N>>>,
+) -> &'m [HirpdagDotTarget<N>] {
+    if !memo.contains_key(node) {
+        let mut fields = HirpdagDotFields::new();
+        node.hirpdag_dot_fields(&mut fields);
+        let mut targets: Vec<HirpdagDotTarget<N>> = Vec::new();
+        let mut positions: HashMap<N, usize> = HashMap::new();
+        let mut add = |path: String, node: &N, paths: u64| match positions.get(node) {
+            Some(&position) => {
+                let target = &mut targets[position];
+                target.paths = target.paths.saturating_add(paths);
+            }
+            None => {
+                positions.insert(node.clone(), targets.len());
+                targets.push(HirpdagDotTarget {
+                    path,
+                    node: node.clone(),
+                    paths,
+                });
+            }
+        };
+        for (path, child) in fields.edges {
+            if drawn(&child) {
+                add(path, &child, 1);
+                continue;
+            }
+            for target in hirpdag_dot_hidden(&child, drawn, memo) {
+                add(
+                    format!("{}.{}", path, target.path),
+                    &target.node,
+                    target.paths,
+                );
+            }
+        }
+        memo.insert(node.clone(), targets);
+    }
+    &memo[node]
+}
@@ -0,0 +1,229 @@
+// ==== E-graph Base
+//
+// Equality saturation support shared by all hirpdag modules.
+//
+// Hash-consing already provides the "hashcons" half of an e-graph: an e-node
+// whose children are class representatives is just an interned hirpdag node,
+// so congruent e-nodes are pointer-equal. This module holds the other half,
+// the union-find over equivalence classes, plus the per-type class storage
+// used by the `HirpdagEGraph` generated per `#[hirpdag_module]` module.
+
+/// Identifies an equivalence class (e-class) of a generated `HirpdagEGraph`.
+///
+/// Ids are only meaningful for the e-graph that issued them. After a union an
+/// id may no longer be canonical; `HirpdagEGraph::find` returns the canonical
+/// id of its class.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HirpdagEClassId(u32);
+
+impl HirpdagEClassId {
+    /// Position of this id in the union-find.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Union-find over e-class ids.
+///
+/// `union` keeps the root of its first argument, so the representative of a
+/// class only changes when the class is merged into another one.
+#[derive(Clone, Debug, Default)]
+pub struct HirpdagUnionFind {
+    parents: Vec<HirpdagEClassId>,
+}
+
+impl HirpdagUnionFind {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new singleton class.
+    pub fn make_set(&mut self) -> HirpdagEClassId {
+        let id = HirpdagEClassId(
+            u32::try_from(self.parents.len()).expect("hirpdag e-graph has too many e-classes"),
+        );
+        self.parents.push(id);
+        id
+    }
+
+    /// Returns the canonical id of the class containing `id`.
+    pub fn find(&self, mut id: HirpdagEClassId) -> HirpdagEClassId {
+        while self.parents[id.index()] != id {
+            id = self.parents[id.index()];
+        }
+        id
+    }
+
+    /// Like [`find`](Self::find), additionally halving the path to the root
+    /// so later lookups are faster.
+    pub fn find_mut(&mut self, mut id: HirpdagEClassId) -> HirpdagEClassId {
+        while self.parents[id.index()] != id {
+            let grandparent = self.parents[self.parents[id.index()].index()];
+            self.parents[id.index()] = grandparent;
+            id = grandparent;
+        }
+        id
+    }
+
+    /// Merges the classes of `a` and `b`.
+    ///
+    /// Returns `(root, merged)` — the surviving root and the root that was
+    /// merged into it — or `None` if they were already the same class.
+    pub fn union(
+        &mut self,
+        a: HirpdagEClassId,
+        b: HirpdagEClassId,
+    ) -> Option<(HirpdagEClassId, HirpdagEClassId)> {
+        let a = self.find_mut(a);
+        let b = self.find_mut(b);
+        if a == b {
+            return None;
+        }
+        self.parents[b.index()] = a;
+        Some((a, b))
+    }
+
+    /// Number of ids issued (including ids that are no longer canonical).
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+}
+
+/// The e-classes of one hirpdag type.
+///
+/// `known` maps every node the e-graph has seen (canonical e-nodes and the
+/// nodes they were added as) to its class, possibly by a stale id. `members`
+/// holds the e-nodes of each class keyed by canonical id; the first member is
+/// the class representative that canonical children refer to.
+#[derive(Clone, Debug)]
+pub struct HirpdagEClasses<N> {
+    known: std::collections::HashMap<N, HirpdagEClassId>,
+    members: std::collections::BTreeMap<HirpdagEClassId, Vec<N>>,
+}
+
+impl<N> Default for HirpdagEClasses<N> {
+    fn default() -> Self {
+        Self {
+            known: std::collections::HashMap::new(),
+            members: std::collections::BTreeMap::new(),
+        }
+    }
+}
+
+impl<N> HirpdagEClasses<N>
+where
+    N: Clone + Eq + std::hash::Hash,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The class `node` was recorded in, if it is known. The id may be stale.
+    pub fn get(&self, node: &N) -> Option<HirpdagEClassId> {
+        self.known.get(node).copied()
+    }
+
+    /// Starts the new class `id` with `node` as its representative.
+    pub fn insert_class(&mut self, id: HirpdagEClassId, node: N) {
+        self.known.insert(node.clone(), id);
+        self.members.insert(id, vec![node]);
+    }
+
+    /// Records that `node` belongs to class `id` without making it an e-node.
+    pub fn insert_known(&mut self, node: N, id: HirpdagEClassId) {
+        self.known.entry(node).or_insert(id);
+    }
+
+    /// Whether `root` is the canonical id of one of this type's classes.
+    pub fn contains_class(&self, root: HirpdagEClassId) -> bool {
+        self.members.contains_key(&root)
+    }
+
+    /// The representative of the class `root`.
+    pub fn leader(&self, root: HirpdagEClassId) -> Option<&N> {
+        self.members.get(&root).and_then(|members| members.first())
+    }
+
+    /// The e-nodes of the class `root`.
+    pub fn members(&self, root: HirpdagEClassId) -> &[N] {
+        self.members
+            .get(&root)
+            .map_or(&[], |members| members.as_slice())
+    }
+
+    /// Moves the e-nodes of class `merged` into class `root`, keeping the
+    /// representative of `root`.
+    pub fn merge(&mut self, root: HirpdagEClassId, merged: HirpdagEClassId) {
+        if let Some(nodes) = self.members.remove(&merged) {
+            self.members.entry(root).or_default().extend(nodes);
+        }
+    }
+
+    /// Every (canonical class id, e-node) pair, in class id order.
+    pub fn iter(&self) -> impl Iterator<Item = (HirpdagEClassId, &N)> {
+        self.members
+            .iter()
+            .flat_map(|(id, nodes)| nodes.iter().map(move |node| (*id, node)))
+    }
+
+    /// Number of classes of this type.
+    pub fn number_of_classes(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Number of e-nodes of this type.
+    pub fn number_of_nodes(&self) -> usize {
+        self.members.values().map(Vec::len).sum()
+    }
+}
+
+/// Why `HirpdagEGraph::run` stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HirpdagEGraphStop {
+    /// No rule added a node or merged two classes: the e-graph is saturated.
+    Saturated,
+    /// The iteration limit was reached before saturation.
+    IterationLimit,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_union_find() {
+        let mut uf = HirpdagUnionFind::new();
+        let a = uf.make_set();
+        let b = uf.make_set();
+        let c = uf.make_set();
+        assert_eq!(uf.union(a, b), Some((a, b)));
+        assert_eq!(uf.union(b, a), None);
+        assert_eq!(uf.find(b), a);
+        assert_eq!(uf.union(c, b), Some((c, a)));
+        assert_eq!(uf.find(a), c);
+        assert_eq!(uf.find_mut(b), c);
+        assert_eq!(uf.len(), 3);
+    }
+
+    #[test]
+    fn test_eclasses_merge_keeps_leader() {
+        let mut uf = HirpdagUnionFind::new();
+        let mut classes = HirpdagEClasses::<&str>::new();
+        let a = uf.make_set();
+        let b = uf.make_set();
+        classes.insert_class(a, "a");
+        classes.insert_class(b, "b");
+        let (root, merged) = uf.union(a, b).unwrap();
+        classes.merge(root, merged);
+        assert_eq!(classes.leader(root), Some(&"a"));
+        assert_eq!(classes.members(root), &["a", "b"]);
+        assert!(!classes.contains_class(b));
+        assert_eq!(classes.get(&"b"), Some(b));
+        assert_eq!(classes.number_of_classes(), 1);
+        assert_eq!(classes.number_of_nodes(), 2);
+    }
+}
@@ -0,0 +1,533 @@
+// ==== Payload Frames
+//
+// The binary header's `flags` select how the archive payload is stored.
+// Without flags the postcard payload follows the header directly, as it
+// always has. With any flag set, the payload is framed:
+//
+//   stored length (postcard varint), stored bytes, [CRC32C trailer]
+//
+// where the stored bytes are the payload, or with `HIRPDAG_FLAG_LZ4` the
+// payload as one LZ4 block prefixed by its uncompressed length (u32 LE), and
+// the trailer (`HIRPDAG_FLAG_CRC32C`) is the CRC32C of the stored bytes, u32
+// LE. The length prefix lets a reader take the whole frame and verify the
+// checksum before decompressing or decoding anything; a framed payload is
+// therefore held in memory as a whole, even by the streaming entry points.
+//
+// With `HIRPDAG_FLAG_INDEX`, the (decompressed) payload starts with the
+// archive's `HirpdagArchiveIndex`, after its encoded length, so that readers
+// not after the index skip it without decoding it. With
+// `HIRPDAG_FLAG_SIDE_TABLES`, the user side-tables follow (after the index,
+// if any), also length-prefixed: they are decoded after the node table,
+// which they reference, and skipped by readers not after them.
+// `HIRPDAG_FLAG_ROOT_LIST` only changes how the archive holds its roots, but
+// frames the payload like any other flag.
+
+#[cfg(feature = "postcard")]
+use crate::base::index::HirpdagArchiveIndex;
+#[cfg(feature = "postcard")]
+use crate::base::serialize::{HirpdagBinaryHeader, HirpdagDeserializeError, HirpdagSerializeError};
+
+/// Header flag: the framed payload ends with a CRC32C checksum.
+pub const HIRPDAG_FLAG_CRC32C: u32 = 1 << 0;
+
+/// Header flag: the framed payload is LZ4 block compressed.
+pub const HIRPDAG_FLAG_LZ4: u32 = 1 << 1;
+
+/// Header flag: the payload starts with an index of the node table.
+pub const HIRPDAG_FLAG_INDEX: u32 = 1 << 2;
+
+/// Header flag: the archive holds a root list instead of typed roots (see
+/// `HirpdagRootLayout`).
+pub const HIRPDAG_FLAG_ROOT_LIST: u32 = 1 << 3;
+
+/// Header flag: the payload carries user side-tables (see
+/// `HirpdagSideTable`).
+pub const HIRPDAG_FLAG_SIDE_TABLES: u32 = 1 << 4;
+
+/// Every header flag this library reads.
+pub const HIRPDAG_KNOWN_FLAGS: u32 = HIRPDAG_FLAG_CRC32C
+    | HIRPDAG_FLAG_LZ4
+    | HIRPDAG_FLAG_INDEX
+    | HIRPDAG_FLAG_ROOT_LIST
+    | HIRPDAG_FLAG_SIDE_TABLES;
+
+const HIRPDAG_CRC32C_TABLE: [u32; 256] = hirpdag_crc32c_table();
+
+// CRC-32C (Castagnoli), reflected polynomial.
+const fn hirpdag_crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32C (Castagnoli) checksum of `bytes`, as used by the payload trailer.
+pub fn hirpdag_crc32c(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = HIRPDAG_CRC32C_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(feature = "postcard")]
+fn hirpdag_check_flags(flags: u32) -> Result<(), String> {
+    if flags & !HIRPDAG_KNOWN_FLAGS != 0 {
+        return Err(format!(
+            "unsupported hirpdag archive flags {:#x}",
+            flags & !HIRPDAG_KNOWN_FLAGS
+        ));
+    }
+    Ok(())
+}
+
+/// Frames a postcard archive `payload` as `flags` select, giving the bytes
+/// to write after the header. Without flags, the payload itself. `index`
+/// is the payload's index, given exactly if `flags` has
+/// `HIRPDAG_FLAG_INDEX`, and `side_tables` the encoded side-tables, given
+/// exactly if `flags` has `HIRPDAG_FLAG_SIDE_TABLES`.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_frame_payload(
+    flags: u32,
+    index: Option<&HirpdagArchiveIndex>,
+    side_tables: Option<&[u8]>,
+    payload: Vec<u8>,
+) -> Result<Vec<u8>, HirpdagSerializeError> {
+    if flags == 0 && index.is_none() && side_tables.is_none() {
+        return Ok(payload);
+    }
+    hirpdag_check_flags(flags).map_err(HirpdagSerializeError::Format)?;
+    if (flags & HIRPDAG_FLAG_INDEX != 0) != index.is_some() {
+        return Err(HirpdagSerializeError::Format(
+            "an archive index is written exactly with HIRPDAG_FLAG_INDEX".to_string(),
+        ));
+    }
+    if (flags & HIRPDAG_FLAG_SIDE_TABLES != 0) != side_tables.is_some() {
+        return Err(HirpdagSerializeError::Format(
+            "side-tables are written exactly with HIRPDAG_FLAG_SIDE_TABLES".to_string(),
+        ));
+    }
+    let mut sections = Vec::new();
+    if let Some(index) = index {
+        let index =
+            postcard::to_stdvec(index).map_err(|e| HirpdagSerializeError::Format(e.to_string()))?;
+        hirpdag_push_section(&mut sections, &index)?;
+    }
+    if let Some(side_tables) = side_tables {
+        hirpdag_push_section(&mut sections, side_tables)?;
+    }
+    let payload = if sections.is_empty() {
+        payload
+    } else {
+        sections.extend(payload);
+        sections
+    };
+    let stored = if flags & HIRPDAG_FLAG_LZ4 != 0 {
+        hirpdag_lz4_compress(&payload).map_err(HirpdagSerializeError::Format)?
+    } else {
+        payload
+    };
+    let mut bytes = postcard::to_stdvec(&(stored.len() as u64))
+        .map_err(|e| HirpdagSerializeError::Format(e.to_string()))?;
+    bytes.extend_from_slice(&stored);
+    if flags & HIRPDAG_FLAG_CRC32C != 0 {
+        bytes.extend_from_slice(&hirpdag_crc32c(&stored).to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+// Appends `section` to `bytes`, after its length.
+#[cfg(feature = "postcard")]
+fn hirpdag_push_section(bytes: &mut Vec<u8>, section: &[u8]) -> Result<(), HirpdagSerializeError> {
+    bytes.extend(
+        postcard::to_stdvec(&(section.len() as u64))
+            .map_err(|e| HirpdagSerializeError::Format(e.to_string()))?,
+    );
+    bytes.extend_from_slice(section);
+    Ok(())
+}
+
+/// Takes the framed payload following a header with `header.flags` from
+/// `rest`, verifies its checksum and decompresses it, skipping any index and
+/// side-tables. Without flags, `rest` is the payload itself.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_unframe_payload<'a>(
+    header: &HirpdagBinaryHeader,
+    rest: &'a [u8],
+) -> Result<std::borrow::Cow<'a, [u8]>, HirpdagDeserializeError> {
+    let payload = hirpdag_unframe_whole(header, rest)?;
+    Ok(hirpdag_split_sections(header.flags, payload, false)?.archive)
+}
+
+/// [`hirpdag_unframe_payload`], also decoding the payload's index, if it
+/// has one.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_unframe_indexed<'a>(
+    header: &HirpdagBinaryHeader,
+    rest: &'a [u8],
+) -> Result<(Option<HirpdagArchiveIndex>, std::borrow::Cow<'a, [u8]>), HirpdagDeserializeError> {
+    let payload = hirpdag_unframe_whole(header, rest)?;
+    let sections = hirpdag_split_sections(header.flags, payload, true)?;
+    Ok((sections.index, sections.archive))
+}
+
+// The encoded side-tables and the archive payload.
+#[cfg(feature = "postcard")]
+type HirpdagSideTablesPayload = (Vec<u8>, Vec<u8>);
+
+/// [`hirpdag_unframe_payload`] for an archive with side-tables: the encoded
+/// side-tables and the payload, or `None` if the archive has none.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_unframe_side_tables(
+    header: &HirpdagBinaryHeader,
+    rest: &[u8],
+) -> Result<Option<HirpdagSideTablesPayload>, HirpdagDeserializeError> {
+    if header.flags & HIRPDAG_FLAG_SIDE_TABLES == 0 {
+        return Ok(None);
+    }
+    let payload = hirpdag_unframe_whole(header, rest)?;
+    let sections = hirpdag_split_sections(header.flags, payload, false)?;
+    Ok(sections
+        .side_tables
+        .map(|side_tables| (side_tables, sections.archive.into_owned())))
+}
+
+// An unframed payload, split into its sections.
+#[cfg(feature = "postcard")]
+struct HirpdagPayloadSections<'a> {
+    index: Option<HirpdagArchiveIndex>,
+    side_tables: Option<Vec<u8>>,
+    archive: std::borrow::Cow<'a, [u8]>,
+}
+
+// Splits the index (decoded if `decode_index`) and the side-tables off an
+// unframed payload.
+#[cfg(feature = "postcard")]
+fn hirpdag_split_sections(
+    flags: u32,
+    payload: std::borrow::Cow<'_, [u8]>,
+    decode_index: bool,
+) -> Result<HirpdagPayloadSections<'_>, HirpdagDeserializeError> {
+    let mut rest: &[u8] = &payload;
+    let mut index = None;
+    if flags & HIRPDAG_FLAG_INDEX != 0 {
+        let (index_bytes, after) = hirpdag_take_section(rest)?;
+        if decode_index {
+            let (decoded, unused): (HirpdagArchiveIndex, &[u8]) =
+                postcard::take_from_bytes(index_bytes)
+                    .map_err(crate::base::hirpdag_postcard_de_error)?;
+            if !unused.is_empty() {
+                return Err(HirpdagDeserializeError::Format(
+                    "the archive index is inconsistent".to_string(),
+                ));
+            }
+            index = Some(decoded);
+        }
+        rest = after;
+    }
+    let mut side_tables = None;
+    if flags & HIRPDAG_FLAG_SIDE_TABLES != 0 {
+        let (side_tables_bytes, after) = hirpdag_take_section(rest)?;
+        side_tables = Some(side_tables_bytes.to_vec());
+        rest = after;
+    }
+    let start = payload.len() - rest.len();
+    let archive = match payload {
+        std::borrow::Cow::Borrowed(bytes) => std::borrow::Cow::Borrowed(&bytes[start..]),
+        std::borrow::Cow::Owned(mut bytes) => {
+            bytes.drain(..start);
+            std::borrow::Cow::Owned(bytes)
+        }
+    };
+    Ok(HirpdagPayloadSections {
+        index,
+        side_tables,
+        archive,
+    })
+}
+
+// Takes a length-prefixed section off the start of `bytes`, giving the
+// section and the bytes after it.
+#[cfg(feature = "postcard")]
+fn hirpdag_take_section(bytes: &[u8]) -> Result<(&[u8], &[u8]), HirpdagDeserializeError> {
+    let (len, rest): (u64, &[u8]) =
+        postcard::take_from_bytes(bytes).map_err(crate::base::hirpdag_postcard_de_error)?;
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= rest.len())
+        .ok_or(HirpdagDeserializeError::Truncated { at_node: None })?;
+    Ok(rest.split_at(len))
+}
+
+// The whole unframed payload, index included.
+#[cfg(feature = "postcard")]
+fn hirpdag_unframe_whole<'a>(
+    header: &HirpdagBinaryHeader,
+    rest: &'a [u8],
+) -> Result<std::borrow::Cow<'a, [u8]>, HirpdagDeserializeError> {
+    if header.flags == 0 {
+        return Ok(std::borrow::Cow::Borrowed(rest));
+    }
+    hirpdag_check_flags(header.flags).map_err(HirpdagDeserializeError::Format)?;
+    let (len, rest): (u64, &[u8]) =
+        postcard::take_from_bytes(rest).map_err(crate::base::hirpdag_postcard_de_error)?;
+    let trailer = if header.flags & HIRPDAG_FLAG_CRC32C != 0 {
+        4
+    } else {
+        0
+    };
+    let len = usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_add(trailer))
+        .filter(|len| *len <= rest.len())
+        .ok_or(HirpdagDeserializeError::Truncated { at_node: None })?;
+    if len < rest.len() {
+        return Err(HirpdagDeserializeError::TrailingBytes {
+            at_node: crate::base::hirpdag_de_node(),
+        });
+    }
+    hirpdag_unframe(header.flags, rest)
+}
+
+/// Streaming variant of [`hirpdag_unframe_payload`]: reads exactly the
+/// framed payload from `reader`, skipping any index and side-tables. `None` without flags,
+/// where the payload is decoded straight from `reader`.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_unframe_payload_from<R: std::io::Read>(
+    header: &HirpdagBinaryHeader,
+    mut reader: R,
+) -> Result<Option<Vec<u8>>, HirpdagDeserializeError> {
+    use std::io::Read;
+    if header.flags == 0 {
+        return Ok(None);
+    }
+    hirpdag_check_flags(header.flags).map_err(HirpdagDeserializeError::Format)?;
+    let len: u64 = crate::base::serialize::hirpdag_postcard_from_reader(&mut reader)?;
+    let trailer = if header.flags & HIRPDAG_FLAG_CRC32C != 0 {
+        4
+    } else {
+        0
+    };
+    let len = len.saturating_add(trailer);
+    // Grown as data arrives, so a corrupt length cannot allocate up front.
+    let mut frame = Vec::new();
+    reader
+        .take(len)
+        .read_to_end(&mut frame)
+        .map_err(|e| HirpdagDeserializeError::Io(e.to_string()))?;
+    if frame.len() as u64 != len {
+        return Err(HirpdagDeserializeError::Truncated { at_node: None });
+    }
+    let payload = hirpdag_unframe(header.flags, &frame)?;
+    let sections = hirpdag_split_sections(header.flags, payload, false)?;
+    Ok(Some(sections.archive.into_owned()))
+}
+
+// Verifies and decompresses a whole frame after its length prefix. An
+// uncompressed payload is borrowed from the frame.
+#[cfg(feature = "postcard")]
+fn hirpdag_unframe(
+    flags: u32,
+    frame: &[u8],
+) -> Result<std::borrow::Cow<'_, [u8]>, HirpdagDeserializeError> {
+    let stored = if flags & HIRPDAG_FLAG_CRC32C != 0 {
+        let (stored, trailer) = frame.split_at(frame.len() - 4);
+        let expected = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let found = hirpdag_crc32c(stored);
+        if found != expected {
+            return Err(HirpdagDeserializeError::ChecksumMismatch { expected, found });
+        }
+        stored
+    } else {
+        frame
+    };
+    if flags & HIRPDAG_FLAG_LZ4 != 0 {
+        return hirpdag_lz4_decompress(stored)
+            .map(std::borrow::Cow::Owned)
+            .map_err(HirpdagDeserializeError::Format);
+    }
+    Ok(std::borrow::Cow::Borrowed(stored))
+}
+
+#[cfg(feature = "lz4")]
+fn hirpdag_lz4_compress(payload: &[u8]) -> Result<Vec<u8>, String> {
+    Ok(lz4_flex::compress_prepend_size(payload))
+}
+
+/// The most an LZ4 block expands: a byte of it extends a match by 255.
+#[cfg(feature = "lz4")]
+const HIRPDAG_LZ4_MAX_RATIO: usize = 255;
+
+// The size prefix is checked against what the block can expand to before
+// anything is allocated for it.
+#[cfg(feature = "lz4")]
+fn hirpdag_lz4_decompress(stored: &[u8]) -> Result<Vec<u8>, String> {
+    if stored.len() < 4 {
+        return Err("LZ4: missing the uncompressed size".to_string());
+    }
+    let (size, block) = stored.split_at(4);
+    let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+    if size > block.len().saturating_mul(HIRPDAG_LZ4_MAX_RATIO) {
+        return Err(format!(
+            "LZ4: uncompressed size {} is impossible for a {} byte block",
+            size,
+            block.len()
+        ));
+    }
+    let mut payload = vec![0; size];
+    let written =
+        lz4_flex::block::decompress_into(block, &mut payload).map_err(|e| format!("LZ4: {}", e))?;
+    if written != size {
+        return Err(format!(
+            "LZ4: the block decompresses to {} bytes, not {}",
+            written, size
+        ));
+    }
+    Ok(payload)
+}
+
+#[cfg(all(feature = "postcard", not(feature = "lz4")))]
+const HIRPDAG_NO_LZ4: &str = "LZ4 compression needs hirpdag's \"lz4\" feature";
+
+#[cfg(all(feature = "postcard", not(feature = "lz4")))]
+fn hirpdag_lz4_compress(_payload: &[u8]) -> Result<Vec<u8>, String> {
+    Err(HIRPDAG_NO_LZ4.to_string())
+}
+
+#[cfg(all(feature = "postcard", not(feature = "lz4")))]
+fn hirpdag_lz4_decompress(_stored: &[u8]) -> Result<Vec<u8>, String> {
+    Err(HIRPDAG_NO_LZ4.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c() {
+        // The CRC-32C check value.
+        assert_eq!(hirpdag_crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(hirpdag_crc32c(b""), 0);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_frame() {
+        use crate::base::serialize::HirpdagSchemaFingerprint;
+        let payload: Vec<u8> = b"hirpdag ".repeat(64);
+        let mut header = HirpdagBinaryHeader::new(HirpdagSchemaFingerprint {
+            hash: 1,
+            name: "test".to_string(),
+        });
+        let mut flag_sets = vec![0, HIRPDAG_FLAG_CRC32C];
+        if cfg!(feature = "lz4") {
+            flag_sets.extend([HIRPDAG_FLAG_LZ4, HIRPDAG_FLAG_LZ4 | HIRPDAG_FLAG_CRC32C]);
+        }
+        for flags in flag_sets {
+            header.flags = flags;
+            let framed = hirpdag_frame_payload(flags, None, None, payload.clone()).unwrap();
+            assert_eq!(
+                hirpdag_unframe_payload(&header, &framed).unwrap(),
+                &payload[..]
+            );
+            let streamed = hirpdag_unframe_payload_from(&header, &framed[..]).unwrap();
+            assert_eq!(streamed.as_deref().unwrap_or(&framed), &payload[..]);
+            if flags & HIRPDAG_FLAG_LZ4 != 0 {
+                assert!(framed.len() < payload.len() / 4);
+            }
+        }
+
+        header.flags = HIRPDAG_FLAG_CRC32C;
+        let mut framed = hirpdag_frame_payload(header.flags, None, None, payload.clone()).unwrap();
+        framed[10] ^= 1;
+        assert!(matches!(
+            hirpdag_unframe_payload(&header, &framed),
+            Err(HirpdagDeserializeError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(
+            hirpdag_unframe_payload(&header, &framed[..framed.len() - 1]),
+            Err(HirpdagDeserializeError::Truncated { at_node: None })
+        );
+        framed.push(0);
+        assert_eq!(
+            hirpdag_unframe_payload(&header, &framed),
+            Err(HirpdagDeserializeError::TrailingBytes { at_node: None })
+        );
+        header.flags = 1 << 7;
+        assert!(hirpdag_unframe_payload(&header, &framed).is_err());
+
+        if cfg!(feature = "lz4") {
+            // A forged uncompressed size, after the 1 byte frame length.
+            header.flags = HIRPDAG_FLAG_LZ4;
+            let mut framed =
+                hirpdag_frame_payload(header.flags, None, None, payload.clone()).unwrap();
+            for size in [u32::MAX, payload.len() as u32 + 1, payload.len() as u32 - 1] {
+                framed[1..5].copy_from_slice(&size.to_le_bytes());
+                let error = hirpdag_unframe_payload(&header, &framed).unwrap_err();
+                assert!(
+                    matches!(&error, HirpdagDeserializeError::Format(m) if m.starts_with("LZ4: ")),
+                    "{:?}",
+                    error
+                );
+                assert!(hirpdag_unframe_payload_from(&header, &framed[..]).is_err());
+            }
+        }
+
+        let index = HirpdagArchiveIndex {
+            offsets: vec![1, 2],
+            children: vec![vec![], vec![0]],
+            roots: vec![vec![1]],
+        };
+        for flags in [HIRPDAG_FLAG_INDEX, HIRPDAG_FLAG_INDEX | HIRPDAG_FLAG_CRC32C] {
+            header.flags = flags;
+            let framed = hirpdag_frame_payload(flags, Some(&index), None, payload.clone()).unwrap();
+            assert_eq!(
+                hirpdag_unframe_payload(&header, &framed).unwrap(),
+                &payload[..]
+            );
+            let (read, unframed) = hirpdag_unframe_indexed(&header, &framed).unwrap();
+            assert_eq!(read.as_ref(), Some(&index));
+            assert_eq!(unframed, &payload[..]);
+            let streamed = hirpdag_unframe_payload_from(&header, &framed[..]).unwrap();
+            assert_eq!(streamed.as_deref(), Some(&payload[..]));
+        }
+        assert!(hirpdag_frame_payload(HIRPDAG_FLAG_INDEX, None, None, payload.clone()).is_err());
+        assert!(hirpdag_frame_payload(0, Some(&index), None, payload.clone()).is_err());
+
+        let side_tables = b"side-tables".to_vec();
+        for flags in [
+            HIRPDAG_FLAG_SIDE_TABLES,
+            HIRPDAG_FLAG_SIDE_TABLES | HIRPDAG_FLAG_INDEX | HIRPDAG_FLAG_CRC32C,
+        ] {
+            header.flags = flags;
+            let index = (flags & HIRPDAG_FLAG_INDEX != 0).then_some(&index);
+            let framed =
+                hirpdag_frame_payload(flags, index, Some(&side_tables), payload.clone()).unwrap();
+            let (read, unframed) = hirpdag_unframe_side_tables(&header, &framed)
+                .unwrap()
+                .unwrap();
+            assert_eq!((read, unframed), (side_tables.clone(), payload.clone()));
+            let (read, unframed) = hirpdag_unframe_indexed(&header, &framed).unwrap();
+            assert_eq!(read.as_ref(), index);
+            assert_eq!(unframed, &payload[..]);
+            let streamed = hirpdag_unframe_payload_from(&header, &framed[..]).unwrap();
+            assert_eq!(streamed.as_deref(), Some(&payload[..]));
+        }
+        header.flags = 0;
+        assert_eq!(hirpdag_unframe_side_tables(&header, &payload), Ok(None));
+        assert!(hirpdag_frame_payload(HIRPDAG_FLAG_SIDE_TABLES, None, None, payload).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+// ==== Archive Indices
+//
+// An indexed binary archive (`HIRPDAG_FLAG_INDEX`) starts its payload with a
+// `HirpdagArchiveIndex`: where each node of the node table starts, which
+// nodes each node references, and which nodes the roots are. A partial read
+// finds the nodes reachable from the selected roots from the index alone,
+// then decodes just those, seeking to each by its offset. The other nodes
+// are neither decoded nor interned.
+
+use crate::base::serialize::HirpdagDeserializeError;
+
+/// The index of a binary archive's node table, for partial reads.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagArchiveIndex {
+    /// The byte offset of each node's encoding, from the start of the
+    /// archive (after the index).
+    pub offsets: Vec<u64>,
+    /// The node indices each node references, ascending and without
+    /// duplicates.
+    pub children: Vec<Vec<u64>>,
+    /// The node index of each root, per root type in the order of the
+    /// `HirpdagArchiveRoots` fields.
+    pub roots: Vec<Vec<u64>>,
+}
+
+impl HirpdagArchiveIndex {
+    /// The node index of the root at `position` among the roots of the
+    /// `field`-th root type, named `name`.
+    pub fn root(
+        &self,
+        field: usize,
+        name: &'static str,
+        position: usize,
+    ) -> Result<u64, HirpdagDeserializeError> {
+        self.roots
+            .get(field)
+            .and_then(|roots| roots.get(position))
+            .copied()
+            .ok_or(HirpdagDeserializeError::MissingRoot {
+                root: name,
+                position: position as u64,
+            })
+    }
+
+    /// Marks the nodes reachable from the nodes `from`, by node index.
+    pub fn reachable(&self, from: &[u64]) -> Result<Vec<bool>, HirpdagDeserializeError> {
+        if self.children.len() != self.offsets.len() {
+            return Err(HirpdagDeserializeError::Format(
+                "the archive index is inconsistent".to_string(),
+            ));
+        }
+        let mut reached = vec![false; self.offsets.len()];
+        for &node in from {
+            *reached
+                .get_mut(node as usize)
+                .ok_or(HirpdagDeserializeError::InvalidNodeIndex {
+                    index: node,
+                    at_node: None,
+                })? = true;
+        }
+        // Children precede their parents, so one backward pass suffices.
+        for node in (0..reached.len()).rev() {
+            if !reached[node] {
+                continue;
+            }
+            for &child in &self.children[node] {
+                if child >= node as u64 {
+                    return Err(HirpdagDeserializeError::InvalidNodeIndex {
+                        index: child,
+                        at_node: Some(node as u64),
+                    });
+                }
+                reached[child as usize] = true;
+            }
+        }
+        Ok(reached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reachable() {
+        // Node 3 references nodes 1 and 2; nodes 1 and 4 reference node 0.
+        let index = HirpdagArchiveIndex {
+            offsets: vec![0, 4, 8, 12, 16],
+            children: vec![vec![], vec![0], vec![], vec![1, 2], vec![0]],
+            roots: vec![vec![3, 4]],
+        };
+        assert_eq!(
+            index.reachable(&[4]).unwrap(),
+            vec![true, false, false, false, true]
+        );
+        assert_eq!(
+            index.reachable(&[3]).unwrap(),
+            vec![true, true, true, true, false]
+        );
+        assert_eq!(index.root(0, "node", 1), Ok(4));
+        assert_eq!(
+            index.root(0, "node", 2),
+            Err(HirpdagDeserializeError::MissingRoot {
+                root: "node",
+                position: 2
+            })
+        );
+        assert_eq!(
+            index.reachable(&[5]),
+            Err(HirpdagDeserializeError::InvalidNodeIndex {
+                index: 5,
+                at_node: None
+            })
+        );
+
+        let forward = HirpdagArchiveIndex {
+            children: vec![vec![1], vec![]],
+            offsets: vec![0, 4],
+            roots: vec![],
+        };
+        assert_eq!(
+            forward.reachable(&[0]),
+            Err(HirpdagDeserializeError::InvalidNodeIndex {
+                index: 1,
+                at_node: Some(0)
+            })
+        );
+    }
+}
@@ -0,0 +1,478 @@
+// ==== Archive Inspection
+//
+// Decodes any hirpdag archive without the generated Rust types, for
+// debugging, diffing and tooling. Binary archives which embed their schema
+// (`HirpdagSerializeOptions::embed_schema`) are self-describing; others, and
+// JSON archives, are inspected with a schema supplied by the caller (e.g. the
+// writing module's `hirpdag_schema()`).
+//
+// `HirpdagDynArchive::validate` checks the invariants the generated
+// deserializers rely on, and reports every violation instead of stopping at
+// the first one.
+
+#[cfg(feature = "postcard")]
+use crate::base::frame::{hirpdag_unframe_payload, hirpdag_unframe_payload_from};
+use crate::base::schema::{
+    HirpdagDynArchive, HirpdagDynNode, HirpdagDynValue, HirpdagSchema, HirpdagSchemaKind,
+    HirpdagSchemaTy,
+};
+use crate::base::serialize::HirpdagBinaryHeader;
+#[cfg(any(feature = "postcard", feature = "json"))]
+use crate::base::serialize::HirpdagDeserializeError;
+#[cfg(feature = "postcard")]
+use crate::base::serialize::{hirpdag_parse_versioned_header, hirpdag_parse_versioned_header_from};
+
+/// A binary archive decoded without its Rust types.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HirpdagInspection {
+    /// The binary header layout version (see `HIRPDAG_HEADER_VERSION`).
+    pub header_version: u32,
+    pub header: HirpdagBinaryHeader,
+    /// The schema the archive was decoded with.
+    pub schema: HirpdagSchema,
+    pub archive: HirpdagDynArchive,
+}
+
+/// Decodes a binary archive which embeds its schema.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_inspect(bytes: &[u8]) -> Result<HirpdagInspection, HirpdagDeserializeError> {
+    hirpdag_inspect_impl(bytes, None)
+}
+
+/// Decodes a binary archive with `schema`, unless it embeds its own.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_inspect_with_schema(
+    bytes: &[u8],
+    schema: &HirpdagSchema,
+) -> Result<HirpdagInspection, HirpdagDeserializeError> {
+    hirpdag_inspect_impl(bytes, Some(schema))
+}
+
+/// Streaming variant of [`hirpdag_inspect_with_schema`]; `schema` may be
+/// `None` for archives which embed theirs.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_inspect_from<R: std::io::Read>(
+    mut reader: R,
+    schema: Option<&HirpdagSchema>,
+) -> Result<HirpdagInspection, HirpdagDeserializeError> {
+    let (header_version, header) = hirpdag_parse_versioned_header_from(&mut reader)?;
+    let schema = hirpdag_inspection_schema(&header, schema)?;
+    let archive = match hirpdag_unframe_payload_from(&header, &mut reader)? {
+        Some(payload) => HirpdagDynArchive::from_postcard(&schema, header.root_layout(), &payload)?,
+        None => HirpdagDynArchive::from_postcard_reader(&schema, header.root_layout(), reader)?,
+    };
+    Ok(HirpdagInspection {
+        header_version,
+        header,
+        schema,
+        archive,
+    })
+}
+
+/// Decodes a JSON archive. JSON archives carry no header, so the schema
+/// must be supplied.
+#[cfg(feature = "json")]
+pub fn hirpdag_inspect_json(
+    text: &str,
+    schema: &HirpdagSchema,
+) -> Result<HirpdagDynArchive, HirpdagDeserializeError> {
+    HirpdagDynArchive::from_json(schema, text)
+}
+
+#[cfg(feature = "postcard")]
+fn hirpdag_inspect_impl(
+    bytes: &[u8],
+    schema: Option<&HirpdagSchema>,
+) -> Result<HirpdagInspection, HirpdagDeserializeError> {
+    let (header_version, header, rest) = hirpdag_parse_versioned_header(bytes)?;
+    let schema = hirpdag_inspection_schema(&header, schema)?;
+    let payload = hirpdag_unframe_payload(&header, rest)?;
+    let archive = HirpdagDynArchive::from_postcard(&schema, header.root_layout(), &payload)?;
+    Ok(HirpdagInspection {
+        header_version,
+        header,
+        schema,
+        archive,
+    })
+}
+
+// The embedded schema wins: it is the one the archive was written with.
+#[cfg(feature = "postcard")]
+fn hirpdag_inspection_schema(
+    header: &HirpdagBinaryHeader,
+    schema: Option<&HirpdagSchema>,
+) -> Result<HirpdagSchema, HirpdagDeserializeError> {
+    header.schema.as_ref().or(schema).cloned().ok_or_else(|| {
+        HirpdagDeserializeError::Format(format!(
+            "the archive written by \"{}\" (hash {:#018x}) does not embed its schema",
+            header.fingerprint.name, header.fingerprint.hash
+        ))
+    })
+}
+
+/// A violated archive invariant, found by [`HirpdagDynArchive::validate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HirpdagArchiveProblem {
+    /// A node's type is not a struct type of the schema.
+    UnknownType { node: u64, type_name: String },
+    /// A node refers to itself, a later node, or past the end of the table.
+    /// Children must precede their parents.
+    ForwardReference { node: u64, child: u64 },
+    /// A node refers to a node of another type than its field declares.
+    RefTypeMismatch {
+        node: u64,
+        child: u64,
+        expected: String,
+        found: String,
+    },
+    /// A root index is past the end of the node table.
+    RootOutOfRange { root_type: String, index: u64 },
+    /// The node index of the root list entry at `position` is past the end
+    /// of the node table.
+    ListRootOutOfRange { position: u64, index: u64 },
+    /// A root index refers to a node of another type.
+    RootTypeMismatch {
+        root_type: String,
+        index: u64,
+        found: String,
+    },
+    /// A node is equal to an earlier one. Writers emit each hashconsed node
+    /// once, so duplicates mean lost sharing.
+    DuplicateNode { node: u64, first: u64 },
+    /// No root reaches the node. Writers only emit reachable nodes.
+    Unreachable { node: u64 },
+}
+
+impl std::fmt::Display for HirpdagArchiveProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownType { node, type_name } => {
+                write!(f, "node {} has unknown type {}", node, type_name)
+            }
+            Self::ForwardReference { node, child } => {
+                write!(f, "node {} refers forward to node {}", node, child)
+            }
+            Self::RefTypeMismatch {
+                node,
+                child,
+                expected,
+                found,
+            } => write!(
+                f,
+                "node {} refers to node {} as {}, but it is a {}",
+                node, child, expected, found
+            ),
+            Self::RootOutOfRange { root_type, index } => {
+                write!(f, "{} root {} is out of range", root_type, index)
+            }
+            Self::RootTypeMismatch {
+                root_type,
+                index,
+                found,
+            } => write!(f, "{} root {} is a {}", root_type, index, found),
+            Self::ListRootOutOfRange { position, index } => write!(
+                f,
+                "root list entry {} (node {}) is out of range",
+                position, index
+            ),
+            Self::DuplicateNode { node, first } => {
+                write!(f, "node {} duplicates node {}", node, first)
+            }
+            Self::Unreachable { node } => write!(f, "node {} is unreachable from the roots", node),
+        }
+    }
+}
+
+impl HirpdagDynArchive {
+    /// Checks the archive against `schema`: every reference points to an
+    /// earlier node of the declared type, roots are in range and of their
+    /// type (root list entries of any type), and every node is distinct and
+    /// reachable. Returns every problem
+    /// found, or nothing for a well-formed archive.
+    pub fn validate(&self, schema: &HirpdagSchema) -> Vec<HirpdagArchiveProblem> {
+        let mut problems = Vec::new();
+        let type_of = |index: u64| {
+            self.nodes
+                .get(index as usize)
+                .map(|node| node.type_name.as_str())
+        };
+
+        let mut first_seen: std::collections::HashMap<String, u64> =
+            std::collections::HashMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let index = index as u64;
+            let fields = match schema.get(&node.type_name).map(|t| &t.kind) {
+                Some(HirpdagSchemaKind::Struct(fields)) => fields,
+                _ => {
+                    problems.push(HirpdagArchiveProblem::UnknownType {
+                        node: index,
+                        type_name: node.type_name.clone(),
+                    });
+                    continue;
+                }
+            };
+            let mut refs = Vec::new();
+            for field in fields {
+                if let Some(value) = node.field(&field.name) {
+                    hirpdag_typed_refs(schema, &field.ty, value, &mut refs);
+                }
+            }
+            for (child, expected) in refs {
+                if child >= index {
+                    problems.push(HirpdagArchiveProblem::ForwardReference { node: index, child });
+                } else if let Some(found) = type_of(child).filter(|found| *found != expected) {
+                    problems.push(HirpdagArchiveProblem::RefTypeMismatch {
+                        node: index,
+                        child,
+                        expected: expected.to_string(),
+                        found: found.to_string(),
+                    });
+                }
+            }
+            // f64 fields rule out Hash; the Debug form identifies the node.
+            let key = format!("{:?}", node);
+            match first_seen.get(&key) {
+                Some(&first) => {
+                    problems.push(HirpdagArchiveProblem::DuplicateNode { node: index, first })
+                }
+                None => {
+                    first_seen.insert(key, index);
+                }
+            }
+        }
+
+        for (root_type, indices) in &self.roots {
+            for &index in indices {
+                match type_of(index) {
+                    None => problems.push(HirpdagArchiveProblem::RootOutOfRange {
+                        root_type: root_type.clone(),
+                        index,
+                    }),
+                    Some(found) if found != root_type => {
+                        problems.push(HirpdagArchiveProblem::RootTypeMismatch {
+                            root_type: root_type.clone(),
+                            index,
+                            found: found.to_string(),
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        for (position, index) in self.root_list.iter().flat_map(|l| l.nodes()).enumerate() {
+            if type_of(*index).is_none() {
+                problems.push(HirpdagArchiveProblem::ListRootOutOfRange {
+                    position: position as u64,
+                    index: *index,
+                });
+            }
+        }
+
+        let reachable = self.reachable();
+        for (index, reached) in reachable.iter().enumerate() {
+            if !reached {
+                problems.push(HirpdagArchiveProblem::Unreachable { node: index as u64 });
+            }
+        }
+        problems
+    }
+
+    /// Whether each node is reachable from the roots. References out of
+    /// range are ignored.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reached = vec![false; self.nodes.len()];
+        let mut stack: Vec<u64> = self.root_nodes().collect();
+        while let Some(index) = stack.pop() {
+            match reached.get_mut(index as usize) {
+                Some(seen) if !*seen => {
+                    *seen = true;
+                    stack.extend(self.nodes[index as usize].children());
+                }
+                _ => {}
+            }
+        }
+        reached
+    }
+
+    /// The number of references to each node, from other nodes and roots.
+    /// A node referenced more than once is shared.
+    pub fn reference_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.nodes.len()];
+        let children = self.nodes.iter().flat_map(HirpdagDynNode::children);
+        for index in children.chain(self.root_nodes()) {
+            if let Some(count) = counts.get_mut(index as usize) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// Collects the node references in `value` with the struct type each is
+/// declared to refer to.
+fn hirpdag_typed_refs<'s>(
+    schema: &'s HirpdagSchema,
+    ty: &'s HirpdagSchemaTy,
+    value: &HirpdagDynValue,
+    out: &mut Vec<(u64, &'s str)>,
+) {
+    match (ty, value) {
+        (HirpdagSchemaTy::Ref(name), HirpdagDynValue::Ref(index)) => out.push((*index, name)),
+        (HirpdagSchemaTy::Option(inner), HirpdagDynValue::Option(Some(value))) => {
+            hirpdag_typed_refs(schema, inner, value, out)
+        }
+        (HirpdagSchemaTy::Vec(inner), HirpdagDynValue::Seq(items)) => {
+            for item in items {
+                hirpdag_typed_refs(schema, inner, item, out);
+            }
+        }
+        (HirpdagSchemaTy::Enum(enum_name), HirpdagDynValue::Variant { variant, value, .. }) => {
+            if let Some(HirpdagSchemaKind::Enum(variants)) = schema.get(enum_name).map(|t| &t.kind)
+            {
+                if let Some(v) = variants.iter().find(|v| &v.name == variant) {
+                    hirpdag_typed_refs(schema, &v.ty, value, out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+impl std::fmt::Display for HirpdagDynValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bool(v) => write!(f, "{}", v),
+            Self::Unsigned(v) => write!(f, "{}", v),
+            Self::Signed(v) => write!(f, "{}", v),
+            Self::Float(v) => write!(f, "{:?}", v),
+            Self::Char(v) => write!(f, "{:?}", v),
+            Self::String(v) => write!(f, "{:?}", v),
+            Self::Option(None) => write!(f, "None"),
+            Self::Option(Some(v)) => write!(f, "Some({})", v),
+            Self::Seq(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Self::Ref(index) => write!(f, "#{}", index),
+            Self::Variant {
+                enum_name,
+                variant,
+                value,
+            } => write!(f, "{}::{}({})", enum_name, variant, value),
+        }
+    }
+}
+
+/// Prints the node as `Type { field: value, .. }`, with references as
+/// `#index`.
+impl std::fmt::Display for HirpdagDynNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {{", self.type_name)?;
+        for (i, (name, value)) in self.fields.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}: {}", sep, name, value)?;
+        }
+        write!(f, " }}")
+    }
+}
+
+// The tests build archives from JSON.
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::base::schema::{HirpdagSchemaField, HirpdagSchemaType};
+
+    fn schema() -> HirpdagSchema {
+        let field = |name: &str, ty| HirpdagSchemaField {
+            name: name.to_string(),
+            ty,
+        };
+        let ty = |name: &str, root, fields| HirpdagSchemaType {
+            name: name.to_string(),
+            root,
+            kind: HirpdagSchemaKind::Struct(fields),
+        };
+        HirpdagSchema {
+            types: vec![
+                ty("Leaf", false, vec![field("value", HirpdagSchemaTy::I32)]),
+                ty(
+                    "Node",
+                    true,
+                    vec![field(
+                        "children",
+                        HirpdagSchemaTy::Vec(Box::new(HirpdagSchemaTy::Ref("Leaf".to_string()))),
+                    )],
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let text = r#"{"version":1,"nodes":[
+            {"Leaf":{"value":1}},
+            {"Leaf":{"value":2}},
+            {"Node":{"children":[0,0]}}
+        ],"roots":{"node":[2]}}"#;
+        let archive = hirpdag_inspect_json(text, &schema()).unwrap();
+        assert_eq!(
+            archive.validate(&schema()),
+            vec![HirpdagArchiveProblem::Unreachable { node: 1 }]
+        );
+        assert_eq!(archive.reachable(), vec![true, false, true]);
+        assert_eq!(archive.reference_counts(), vec![2, 0, 1]);
+        assert_eq!(archive.nodes[2].to_string(), "Node { children: [#0, #0] }");
+
+        let text = r#"{"version":1,"nodes":[
+            {"Leaf":{"value":1}},
+            {"Leaf":{"value":1}},
+            {"Node":{"children":[3]}},
+            {"Node":{"children":[2]}}
+        ],"roots":{"node":[0,9]}}"#;
+        let archive = hirpdag_inspect_json(text, &schema()).unwrap();
+        let problems: Vec<String> = archive
+            .validate(&schema())
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                "node 1 duplicates node 0",
+                "node 2 refers forward to node 3",
+                "node 3 refers to node 2 as Leaf, but it is a Node",
+                "Node root 0 is a Leaf",
+                "Node root 9 is out of range",
+                "node 1 is unreachable from the roots",
+                "node 2 is unreachable from the roots",
+                "node 3 is unreachable from the roots",
+            ]
+        );
+
+        // Root list entries may be of any type.
+        let text = r#"{"version":1,"nodes":[
+            {"Leaf":{"value":1}},
+            {"Node":{"children":[0]}}
+        ],"root_list":[{"name":"leaf","node":0},{"name":null,"node":4}]}"#;
+        let archive = hirpdag_inspect_json(text, &schema()).unwrap();
+        let problems: Vec<String> = archive
+            .validate(&schema())
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                "root list entry 1 (node 4) is out of range",
+                "node 1 is unreachable from the roots",
+            ]
+        );
+        assert_eq!(archive.reference_counts(), vec![2, 0]);
+    }
+}
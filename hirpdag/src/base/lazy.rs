@@ -0,0 +1,179 @@
+// ==== Lazy Archives
+//
+// A lazy archive reads the nodes of an indexed binary archive on demand,
+// straight from the archive's bytes: typically a memory-mapped file. The
+// generated `HirpdagLazyArchive`
+// materializes a node into the hashcons table when it is first accessed,
+// after the children it references (children precede their parents, so
+// decoding in node index order resolves every reference).
+//
+// The bytes are any `AsRef<[u8]>`, so mapping the file (with e.g. the
+// `memmap2` crate, whose maps are unsafe to create) is left to the caller.
+// The checksum of a framed payload covers all of it, as does LZ4
+// compression, so opening such an archive reads the whole payload: it is
+// verified, and if compressed decompressed into memory. Only an archive
+// written with neither is read in place, the pages of a mapped file loaded
+// as the nodes on them are read.
+
+use crate::base::frame::hirpdag_unframe_indexed;
+use crate::base::index::HirpdagArchiveIndex;
+use crate::base::serialize::{
+    hirpdag_parse_binary_header, HirpdagBinaryHeader, HirpdagDeserializeError,
+};
+
+/// The payload of an indexed binary archive, addressed by node index.
+pub struct HirpdagLazyPayload<B> {
+    bytes: B,
+    header: HirpdagBinaryHeader,
+    index: HirpdagArchiveIndex,
+    payload: HirpdagLazyBytes,
+}
+
+// Where the archive (after the index) is: in place, or decompressed.
+enum HirpdagLazyBytes {
+    InPlace(std::ops::Range<usize>),
+    Decompressed(Vec<u8>),
+}
+
+impl<B: AsRef<[u8]>> HirpdagLazyPayload<B> {
+    /// Reads the header and index of the archive in `bytes`, and verifies
+    /// its payload, reading all of it if it has a checksum or is
+    /// compressed. Fails with `MissingIndex` for an archive written without
+    /// an index.
+    pub fn new(bytes: B) -> Result<Self, HirpdagDeserializeError> {
+        let all = bytes.as_ref();
+        let (header, rest) = hirpdag_parse_binary_header(all)?;
+        let (index, archive) = hirpdag_unframe_indexed(&header, rest)?;
+        let index = index.ok_or(HirpdagDeserializeError::MissingIndex)?;
+        if index.children.len() != index.offsets.len() {
+            return Err(HirpdagDeserializeError::Format(
+                "the archive index is inconsistent".to_string(),
+            ));
+        }
+        let payload = match archive {
+            std::borrow::Cow::Borrowed(archive) => {
+                let start = archive.as_ptr() as usize - all.as_ptr() as usize;
+                HirpdagLazyBytes::InPlace(start..start + archive.len())
+            }
+            std::borrow::Cow::Owned(archive) => HirpdagLazyBytes::Decompressed(archive),
+        };
+        Ok(Self {
+            bytes,
+            header,
+            index,
+            payload,
+        })
+    }
+
+    pub fn header(&self) -> &HirpdagBinaryHeader {
+        &self.header
+    }
+
+    pub fn index(&self) -> &HirpdagArchiveIndex {
+        &self.index
+    }
+
+    /// The number of nodes in the node table.
+    pub fn len(&self) -> usize {
+        self.index.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.offsets.is_empty()
+    }
+
+    /// The archive from the start of node `node`'s encoding on.
+    pub fn node_bytes(&self, node: u64) -> Result<&[u8], HirpdagDeserializeError> {
+        let archive = match &self.payload {
+            HirpdagLazyBytes::InPlace(range) => &self.bytes.as_ref()[range.clone()],
+            HirpdagLazyBytes::Decompressed(archive) => archive,
+        };
+        let offset = self.index.offsets.get(node as usize).ok_or(
+            HirpdagDeserializeError::InvalidNodeIndex {
+                index: node,
+                at_node: None,
+            },
+        )?;
+        usize::try_from(*offset)
+            .ok()
+            .and_then(|offset| archive.get(offset..))
+            .ok_or(HirpdagDeserializeError::Truncated {
+                at_node: Some(node),
+            })
+    }
+
+    /// The nodes to decode to materialize `node`: it and the nodes it
+    /// reaches, up to those `materialized` already, in node index order.
+    pub fn pending(
+        &self,
+        node: u64,
+        materialized: impl Fn(u64) -> bool,
+    ) -> Result<Vec<u64>, HirpdagDeserializeError> {
+        if node as usize >= self.len() {
+            return Err(HirpdagDeserializeError::InvalidNodeIndex {
+                index: node,
+                at_node: None,
+            });
+        }
+        let mut pending = vec![node];
+        let mut stack = vec![node];
+        let mut seen = std::collections::HashSet::new();
+        while let Some(parent) = stack.pop() {
+            for &child in &self.index.children[parent as usize] {
+                if child >= parent {
+                    return Err(HirpdagDeserializeError::InvalidNodeIndex {
+                        index: child,
+                        at_node: Some(parent),
+                    });
+                }
+                if !materialized(child) && seen.insert(child) {
+                    pending.push(child);
+                    stack.push(child);
+                }
+            }
+        }
+        pending.sort_unstable();
+        Ok(pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::frame::{hirpdag_frame_payload, HIRPDAG_FLAG_CRC32C, HIRPDAG_FLAG_INDEX};
+    use crate::base::serialize::{hirpdag_write_binary_header, HirpdagSchemaFingerprint};
+
+    #[test]
+    fn test_lazy_payload() {
+        let index = HirpdagArchiveIndex {
+            offsets: vec![0, 2, 5],
+            children: vec![vec![], vec![0], vec![0, 1]],
+            roots: vec![vec![2]],
+        };
+        let mut header = HirpdagBinaryHeader::new(HirpdagSchemaFingerprint {
+            hash: 1,
+            name: "test".to_string(),
+        });
+        header.flags = HIRPDAG_FLAG_INDEX | HIRPDAG_FLAG_CRC32C;
+        let mut bytes = hirpdag_write_binary_header(&header).unwrap();
+        bytes.extend(
+            hirpdag_frame_payload(header.flags, Some(&index), None, b"aabbbcc".to_vec()).unwrap(),
+        );
+
+        let payload = HirpdagLazyPayload::new(&bytes[..]).unwrap();
+        assert_eq!(payload.len(), 3);
+        assert_eq!(payload.index(), &index);
+        assert_eq!(payload.node_bytes(1).unwrap(), b"bbbcc");
+        assert_eq!(payload.pending(2, |_| false).unwrap(), vec![0, 1, 2]);
+        assert_eq!(payload.pending(2, |node| node == 1).unwrap(), vec![0, 2]);
+        assert!(payload.pending(3, |_| false).is_err());
+
+        header.flags = 0;
+        let mut plain = hirpdag_write_binary_header(&header).unwrap();
+        plain.extend(b"aabbbcc");
+        assert!(matches!(
+            HirpdagLazyPayload::new(plain),
+            Err(HirpdagDeserializeError::MissingIndex)
+        ));
+    }
+}
@@ -7,6 +7,10 @@ pub type HirpdagMetaHeightType = u16;
 /// Bitfield of user-defined flags propagated upward through the DAG via bitwise OR.
 pub type HirpdagMetaFlagType = u16;
 
+/// Flag set on metavariables and every node above one: see `unify`. The top
+/// bit is reserved for it.
+pub const HIRPDAG_META_FLAG_METAVAR: HirpdagMetaFlagType = 1 << 15;
+
 /// Aggregated structural metadata cached on every interned node.
 ///
 /// Computed bottom-up at intern time via [`HirpdagComputeMeta`] and stored inside
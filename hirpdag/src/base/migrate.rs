@@ -0,0 +1,224 @@
+// ==== Archive Migrations
+//
+// A binary archive written by other hirpdag type definitions has a different
+// schema fingerprint, and is rejected with `SchemaMismatch`. If the archive
+// embeds its schema (`HirpdagSerializeOptions::embed_schema`), registered
+// migrations can bring it to the current definitions instead: the archive is
+// decoded with its own schema into a `HirpdagDynArchive`, each migration on
+// the path from its fingerprint to the current one edits it, and the result
+// is re-encoded with the current schema for the current types to load.
+
+use crate::base::schema::{HirpdagDynArchive, HirpdagSchema};
+use crate::base::serialize::{
+    hirpdag_check_fingerprint, HirpdagBinaryHeader, HirpdagDeserializeError,
+    HirpdagSchemaFingerprint,
+};
+
+type HirpdagMigrationFn = dyn Fn(&mut HirpdagDynArchive) -> Result<(), String> + Send + Sync;
+
+#[derive(Clone)]
+struct HirpdagMigrationStep {
+    from: u64,
+    to: u64,
+    migrate: std::sync::Arc<HirpdagMigrationFn>,
+}
+
+/// Migrations between schema versions, identified by their fingerprint
+/// hashes.
+#[derive(Clone, Default)]
+pub struct HirpdagMigrations {
+    steps: Vec<HirpdagMigrationStep>,
+}
+
+impl std::fmt::Debug for HirpdagMigrations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.steps
+                    .iter()
+                    .map(|s| format!("{:#018x} -> {:#018x}", s.from, s.to)),
+            )
+            .finish()
+    }
+}
+
+impl HirpdagMigrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a migration of archives written by the schema with hash
+    /// `from` to the schema with hash `to`. Migrations chain: an archive is
+    /// migrated along the shortest path of registered migrations to the
+    /// reading schema.
+    ///
+    /// `migrate` edits the decoded archive in place. It must keep the node
+    /// table order (children before parents) and the node indices.
+    pub fn register<F>(&mut self, from: u64, to: u64, migrate: F) -> &mut Self
+    where
+        F: Fn(&mut HirpdagDynArchive) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.steps.push(HirpdagMigrationStep {
+            from,
+            to,
+            migrate: std::sync::Arc::new(migrate),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    // The shortest chain of migrations from `from` to `to`.
+    fn path(&self, from: u64, to: u64) -> Option<Vec<&HirpdagMigrationStep>> {
+        let mut reached: std::collections::HashMap<u64, Option<usize>> =
+            std::collections::HashMap::from([(from, None)]);
+        let mut queue = std::collections::VecDeque::from([from]);
+        while let Some(hash) = queue.pop_front() {
+            if hash == to {
+                let mut path = Vec::new();
+                let mut at = to;
+                while let Some(step) = reached[&at] {
+                    path.push(&self.steps[step]);
+                    at = self.steps[step].from;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (index, step) in self.steps.iter().enumerate() {
+                if step.from == hash && !reached.contains_key(&step.to) {
+                    reached.insert(step.to, Some(index));
+                    queue.push_back(step.to);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn can_migrate(&self, from: u64, to: u64) -> bool {
+        self.path(from, to).is_some()
+    }
+
+    /// Applies the migrations from schema `from` to schema `to`.
+    pub fn migrate(
+        &self,
+        archive: &mut HirpdagDynArchive,
+        from: u64,
+        to: u64,
+    ) -> Result<(), HirpdagDeserializeError> {
+        let path = self.path(from, to).ok_or_else(|| {
+            HirpdagDeserializeError::Migration(format!(
+                "no migration path from schema {:#018x} to {:#018x}",
+                from, to
+            ))
+        })?;
+        for step in path {
+            (step.migrate)(archive).map_err(|e| {
+                HirpdagDeserializeError::Migration(format!(
+                    "{:#018x} -> {:#018x}: {}",
+                    step.from, step.to, e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The schema to decode an archive with `header` by, before migrating it
+    /// to `expected`; `None` if it was written by `expected` itself.
+    pub fn source_schema<'h>(
+        &self,
+        header: &'h HirpdagBinaryHeader,
+        expected: &HirpdagSchemaFingerprint,
+    ) -> Result<Option<&'h HirpdagSchema>, HirpdagDeserializeError> {
+        let found = &header.fingerprint;
+        if found.hash == expected.hash {
+            return Ok(None);
+        }
+        if !self.can_migrate(found.hash, expected.hash) {
+            hirpdag_check_fingerprint(found, expected)?;
+        }
+        match &header.schema {
+            Some(schema) => Ok(Some(schema)),
+            None => Err(HirpdagDeserializeError::Migration(format!(
+                "the archive written by \"{}\" (hash {:#018x}) does not embed its schema",
+                found.name, found.hash
+            ))),
+        }
+    }
+
+    /// Migrates a binary archive payload written by other type definitions
+    /// to `expected`, described by `schema`, returning the migrated payload.
+    /// `None` if the payload was written by `expected` and can be read
+    /// directly.
+    #[cfg(feature = "postcard")]
+    pub fn migrate_payload(
+        &self,
+        header: &HirpdagBinaryHeader,
+        payload: &[u8],
+        expected: &HirpdagSchemaFingerprint,
+        schema: &HirpdagSchema,
+    ) -> Result<Option<Vec<u8>>, HirpdagDeserializeError> {
+        self.migrate_with(header, expected, schema, |schema| {
+            HirpdagDynArchive::from_postcard(schema, header.root_layout(), payload)
+        })
+    }
+
+    /// Streaming variant of [`HirpdagMigrations::migrate_payload`].
+    #[cfg(feature = "postcard")]
+    pub fn migrate_payload_from<R: std::io::Read>(
+        &self,
+        header: &HirpdagBinaryHeader,
+        reader: R,
+        expected: &HirpdagSchemaFingerprint,
+        schema: &HirpdagSchema,
+    ) -> Result<Option<Vec<u8>>, HirpdagDeserializeError> {
+        self.migrate_with(header, expected, schema, |schema| {
+            HirpdagDynArchive::from_postcard_reader(schema, header.root_layout(), reader)
+        })
+    }
+
+    #[cfg(feature = "postcard")]
+    fn migrate_with(
+        &self,
+        header: &HirpdagBinaryHeader,
+        expected: &HirpdagSchemaFingerprint,
+        schema: &HirpdagSchema,
+        decode: impl FnOnce(&HirpdagSchema) -> Result<HirpdagDynArchive, HirpdagDeserializeError>,
+    ) -> Result<Option<Vec<u8>>, HirpdagDeserializeError> {
+        let Some(source) = self.source_schema(header, expected)? else {
+            return Ok(None);
+        };
+        let mut archive = decode(source)?;
+        self.migrate(&mut archive, header.fingerprint.hash, expected.hash)?;
+        let payload = archive
+            .to_postcard(schema)
+            .map_err(|e| HirpdagDeserializeError::Migration(e.to_string()))?;
+        Ok(Some(payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_path() {
+        let mut migrations = HirpdagMigrations::new();
+        migrations
+            .register(1, 2, |_| Ok(()))
+            .register(2, 3, |_| Ok(()))
+            .register(1, 4, |_| Err("unsupported".to_string()))
+            .register(4, 3, |_| Ok(()));
+        assert_eq!(migrations.path(1, 3).map(|p| p.len()), Some(2));
+        assert_eq!(migrations.path(3, 3).map(|p| p.len()), Some(0));
+        assert!(!migrations.can_migrate(3, 1));
+
+        let mut archive = HirpdagDynArchive::default();
+        assert!(migrations.migrate(&mut archive, 1, 3).is_ok());
+        assert!(matches!(
+            migrations.migrate(&mut archive, 1, 4),
+            Err(HirpdagDeserializeError::Migration(_))
+        ));
+    }
+}
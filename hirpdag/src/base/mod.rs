@@ -11,3 +11,65 @@ pub mod serialize;
 pub use self::serialize::*;
 
 pub mod basic_traits;
+
+pub mod egraph;
+pub use self::egraph::*;
+
+pub mod provenance;
+pub use self::provenance::*;
+
+pub mod cache;
+pub use self::cache::*;
+
+pub mod verify;
+pub use self::verify::*;
+
+pub mod schema;
+pub use self::schema::*;
+
+pub mod migrate;
+pub use self::migrate::*;
+
+pub mod inspect;
+pub use self::inspect::*;
+
+pub mod delta;
+pub use self::delta::*;
+
+pub mod frame;
+pub use self::frame::*;
+
+pub mod index;
+pub use self::index::*;
+
+pub mod root_list;
+pub use self::root_list::*;
+
+pub mod side_table;
+pub use self::side_table::*;
+
+pub mod stable_hash;
+pub use self::stable_hash::*;
+
+pub mod store;
+pub use self::store::*;
+
+pub mod dot;
+pub use self::dot::*;
+
+pub mod pretty;
+pub use self::pretty::*;
+
+pub mod sexpr;
+pub use self::sexpr::*;
+
+pub mod diff;
+pub use self::diff::*;
+
+pub mod unify;
+pub use self::unify::*;
+
+#[cfg(feature = "postcard")]
+pub mod lazy;
+#[cfg(feature = "postcard")]
+pub use self::lazy::*;
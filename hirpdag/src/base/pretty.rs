@@ -0,0 +1,222 @@
+// ==== Pretty-printing
+//
+// A derived `Debug` recurses into children, so a node reached along many
+// paths is printed once per path: exponentially often in a chain of
+// diamonds. The printer here is sharing-aware. A first pass counts how
+// often each node is reached; the second prints a node reached more than
+// once in full the first time, labelled `#1 = Add { .. }`, and as `#1`
+// afterwards. Labels number nodes in printing order, so output depends only
+// on the value printed.
+//
+// Generated ref types print through the printer for both `Debug`, always as
+// `Type { field: value, .. }`, and `Display`, in the type's own form if it
+// has one (`#[hirpdag(pretty)]`).
+
+use crate::base::basic_traits::IsNumber;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+/// Implemented by every field type, and the generated ref and enum types, to
+/// print itself to a [`HirpdagPrinter`]. `N` is the module's
+/// `HirpdagNodeRef`.
+pub trait HirpdagPretty<N> {
+    fn hirpdag_print(&self, p: &mut HirpdagPrinter<'_, N>) -> fmt::Result;
+}
+
+/// What [`hirpdag_write_pretty`] prints.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HirpdagPrettyOptions {
+    /// Print nodes at most this many nodes deep (the value printed is at
+    /// depth 0) and `...` in place of deeper ones.
+    pub max_depth: Option<usize>,
+    /// Print at most this many items of a vector, then `...`.
+    pub max_width: Option<usize>,
+    /// Print every node as `Type { field: value, .. }`, ignoring the
+    /// types' own forms.
+    pub debug: bool,
+}
+
+/// Discards its output, for the counting pass.
+struct HirpdagSink;
+
+impl Write for HirpdagSink {
+    fn write_str(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+/// Prints values sharing-aware. Custom forms (`hirpdag_pretty`) write text
+/// with `write!`, and print fields with [`print`](Self::print).
+pub struct HirpdagPrinter<'a, N> {
+    out: &'a mut dyn Write,
+    options: &'a HirpdagPrettyOptions,
+    counting: bool,
+    depth: usize,
+    uses: HashMap<N, usize>,
+    labels: HashMap<N, usize>,
+}
+
+impl<N: Clone + Eq + std::hash::Hash> HirpdagPrinter<'_, N> {
+    pub fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_str(s)
+    }
+
+    pub fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
+        self.out.write_fmt(args)
+    }
+
+    /// Prints `value`.
+    pub fn print<T: HirpdagPretty<N> + ?Sized>(&mut self, value: &T) -> fmt::Result {
+        value.hirpdag_print(self)
+    }
+
+    /// Whether nodes print as `Type { field: value, .. }`.
+    pub fn is_debug(&self) -> bool {
+        self.options.debug
+    }
+
+    /// Prints `node`, whose data `data` prints: in full, labelled if it is
+    /// printed again, or by its label if it already was.
+    pub fn node(&mut self, node: N, data: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        let deep = self.options.max_depth.is_some_and(|max| self.depth >= max);
+        if self.counting {
+            if deep {
+                return Ok(());
+            }
+            let uses = self.uses.entry(node).or_insert(0);
+            *uses += 1;
+            if *uses > 1 {
+                return Ok(());
+            }
+        } else if let Some(label) = self.labels.get(&node) {
+            return write!(self.out, "#{}", label);
+        } else if deep {
+            return self.out.write_str("...");
+        } else if self.uses.get(&node).is_some_and(|uses| *uses > 1) {
+            let label = self.labels.len() + 1;
+            self.labels.insert(node, label);
+            write!(self.out, "#{} = ", label)?;
+        }
+        self.depth += 1;
+        let result = data(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Prints `Type { name: value, .. }`, or `Type` without fields.
+    pub fn fields(
+        &mut self,
+        type_name: &str,
+        fields: &[(&str, &dyn HirpdagPretty<N>)],
+    ) -> fmt::Result {
+        self.out.write_str(type_name)?;
+        for (i, (name, value)) in fields.iter().enumerate() {
+            let sep = if i == 0 { " { " } else { ", " };
+            write!(self.out, "{}{}: ", sep, name)?;
+            value.hirpdag_print(self)?;
+        }
+        if !fields.is_empty() {
+            self.out.write_str(" }")?;
+        }
+        Ok(())
+    }
+
+    /// Prints `Variant(value)`.
+    pub fn variant<T: HirpdagPretty<N> + ?Sized>(&mut self, name: &str, value: &T) -> fmt::Result {
+        write!(self.out, "{}(", name)?;
+        value.hirpdag_print(self)?;
+        self.out.write_str(")")
+    }
+
+    /// Prints `[a, b, ..]`, up to the width limit.
+    pub fn seq<T: HirpdagPretty<N>>(&mut self, items: &[T]) -> fmt::Result {
+        self.out.write_str("[")?;
+        let shown = self.options.max_width.unwrap_or(usize::MAX);
+        for (i, item) in items.iter().enumerate() {
+            if i != 0 {
+                self.out.write_str(", ")?;
+            }
+            if i == shown {
+                self.out.write_str("...")?;
+                break;
+            }
+            item.hirpdag_print(self)?;
+        }
+        self.out.write_str("]")
+    }
+}
+
+/// Writes `value` to `out`, sharing-aware.
+pub fn hirpdag_write_pretty<N, T>(
+    out: &mut dyn Write,
+    value: &T,
+    options: &HirpdagPrettyOptions,
+) -> fmt::Result
+where
+    N: Clone + Eq + std::hash::Hash,
+    T: HirpdagPretty<N> + ?Sized,
+{
+    let mut sink = HirpdagSink;
+    let mut counter = HirpdagPrinter {
+        out: &mut sink,
+        options,
+        counting: true,
+        depth: 0,
+        uses: HashMap::new(),
+        labels: HashMap::new(),
+    };
+    value.hirpdag_print(&mut counter)?;
+    let mut printer = HirpdagPrinter {
+        out,
+        options,
+        counting: false,
+        depth: 0,
+        uses: counter.uses,
+        labels: HashMap::new(),
+    };
+    value.hirpdag_print(&mut printer)
+}
+
+/// `value` printed sharing-aware.
+pub fn hirpdag_pretty<N, T>(value: &T, options: &HirpdagPrettyOptions) -> String
+where
+    N: Clone + Eq + std::hash::Hash,
+    T: HirpdagPretty<N> + ?Sized,
+{
+    let mut out = String::new();
+    hirpdag_write_pretty(&mut out, value, options).expect("writing to a String cannot fail");
+    out
+}
+
+impl<N: Clone + Eq + std::hash::Hash, P: IsNumber + fmt::Debug> HirpdagPretty<N> for P {
+    fn hirpdag_print(&self, p: &mut HirpdagPrinter<'_, N>) -> fmt::Result {
+        write!(p, "{:?}", self)
+    }
+}
+
+impl<N: Clone + Eq + std::hash::Hash> HirpdagPretty<N> for String {
+    fn hirpdag_print(&self, p: &mut HirpdagPrinter<'_, N>) -> fmt::Result {
+        write!(p, "{:?}", self)
+    }
+}
+
+impl<N: Clone + Eq + std::hash::Hash> HirpdagPretty<N> for bool {
+    fn hirpdag_print(&self, p: &mut HirpdagPrinter<'_, N>) -> fmt::Result {
+        write!(p, "{}", self)
+    }
+}
+
+impl<N: Clone + Eq + std::hash::Hash, T: HirpdagPretty<N>> HirpdagPretty<N> for Option<T> {
+    fn hirpdag_print(&self, p: &mut HirpdagPrinter<'_, N>) -> fmt::Result {
+        match self {
+            None => p.write_str("None"),
+            Some(value) => p.variant("Some", value),
+        }
+    }
+}
+
+impl<N: Clone + Eq + std::hash::Hash, T: HirpdagPretty<N>> HirpdagPretty<N> for Vec<T> {
+    fn hirpdag_print(&self, p: &mut HirpdagPrinter<'_, N>) -> fmt::Result {
+        p.seq(self)
+    }
+}
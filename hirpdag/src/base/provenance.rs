@@ -0,0 +1,204 @@
+// ==== Provenance Base
+//
+// Rewrite tracing support shared by all hirpdag modules.
+//
+// The `HirpdagRewriteTraced` wrapper generated per `#[hirpdag_module]` module
+// records one `HirpdagProvenanceStep` for every node whose rewrite changed
+// it. Nodes are recorded as the module's `HirpdagNodeRef` (a ref of any
+// hirpdag type in the module), which implements `HirpdagProvenanceNode`.
+
+/// A node which can be recorded in a [`HirpdagProvenance`].
+pub trait HirpdagProvenanceNode: Clone + Eq + std::hash::Hash {
+    /// The creation id of the node, unique among live nodes of its type.
+    fn hirpdag_provenance_id(&self) -> u64;
+    /// The name of the node's hirpdag type.
+    fn hirpdag_provenance_type_name(&self) -> &'static str;
+}
+
+/// One rewrite which changed a node: `input` was rewritten to `output` by
+/// the `method` of the rewriter type `rewriter`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HirpdagProvenanceStep<N> {
+    pub input: N,
+    pub output: N,
+    /// Type name of the rewriter, as given by `std::any::type_name`.
+    pub rewriter: &'static str,
+    /// The rewrite method, e.g. `rewrite_Expr`.
+    pub method: &'static str,
+}
+
+/// The rewrite steps recorded by a traced rewrite, in the order the
+/// rewrites completed (children before their parents).
+#[derive(Clone, Debug)]
+pub struct HirpdagProvenance<N> {
+    steps: Vec<HirpdagProvenanceStep<N>>,
+    // Index into `steps` of the latest step producing each output.
+    by_output: std::collections::HashMap<N, usize>,
+}
+
+impl<N> Default for HirpdagProvenance<N> {
+    fn default() -> Self {
+        Self {
+            steps: Vec::new(),
+            by_output: std::collections::HashMap::new(),
+        }
+    }
+}
+
+impl<N: HirpdagProvenanceNode> HirpdagProvenance<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that `method` of `rewriter` rewrote `input` to `output`.
+    /// Unchanged nodes and repeats of the previous step are ignored (nested
+    /// wrappers such as `HirpdagRewriteMemoized` report the same rewrite
+    /// twice).
+    pub fn record(&mut self, input: N, output: N, rewriter: &'static str, method: &'static str) {
+        if input == output {
+            return;
+        }
+        if let Some(last) = self.steps.last() {
+            if last.input == input && last.output == output {
+                return;
+            }
+        }
+        self.by_output.insert(output.clone(), self.steps.len());
+        self.steps.push(HirpdagProvenanceStep {
+            input,
+            output,
+            rewriter,
+            method,
+        });
+    }
+
+    /// Appends the steps of `other`.
+    pub fn extend(&mut self, other: Self) {
+        for step in other.steps {
+            self.record(step.input, step.output, step.rewriter, step.method);
+        }
+    }
+
+    /// Every recorded step, in order.
+    pub fn steps(&self) -> &[HirpdagProvenanceStep<N>] {
+        &self.steps
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The latest step which produced `output`, if any.
+    pub fn origin_of(&self, output: &N) -> Option<&HirpdagProvenanceStep<N>> {
+        self.by_output.get(output).map(|&i| &self.steps[i])
+    }
+
+    /// The chain of steps leading to `output`, latest first: the step which
+    /// produced `output`, then the step which produced that step's input,
+    /// and so on back to a node which was not produced by a rewrite.
+    pub fn trace_back(&self, output: &N) -> Vec<&HirpdagProvenanceStep<N>> {
+        let mut chain = Vec::new();
+        let mut visited = std::collections::HashSet::new();
+        let mut node = output;
+        while let Some(&i) = self.by_output.get(node) {
+            if !visited.insert(i) {
+                // A later pass rewrote a node back to an earlier form.
+                break;
+            }
+            let step = &self.steps[i];
+            chain.push(step);
+            node = &step.input;
+        }
+        chain
+    }
+
+    /// The steps as JSON: `{"steps": [{"rewriter", "method", "input",
+    /// "output"}]}`, where nodes are `{"type", "id"}`.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Value {
+        let node = |n: &N| {
+            serde_json::json!({
+                "type": n.hirpdag_provenance_type_name(),
+                "id": n.hirpdag_provenance_id(),
+            })
+        };
+        let steps: Vec<serde_json::Value> = self
+            .steps
+            .iter()
+            .map(|step| {
+                serde_json::json!({
+                    "rewriter": step.rewriter,
+                    "method": step.method,
+                    "input": node(&step.input),
+                    "output": node(&step.output),
+                })
+            })
+            .collect();
+        serde_json::json!({ "steps": steps })
+    }
+
+    /// The steps as a Graphviz DOT digraph, with an edge from each input
+    /// node to its output node labelled by the rewrite method.
+    pub fn to_dot(&self) -> String {
+        use std::fmt::Write;
+        let node = |n: &N| {
+            format!(
+                "\"{}#{}\"",
+                n.hirpdag_provenance_type_name(),
+                n.hirpdag_provenance_id()
+            )
+        };
+        let mut out = String::from("digraph hirpdag_provenance {\n");
+        for step in &self.steps {
+            let label = format!("{}::{}", step.rewriter, step.method).replace('"', "\\\"");
+            writeln!(
+                out,
+                "    {} -> {} [label=\"{}\"];",
+                node(&step.input),
+                node(&step.output),
+                label
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl HirpdagProvenanceNode for u64 {
+        fn hirpdag_provenance_id(&self) -> u64 {
+            *self
+        }
+        fn hirpdag_provenance_type_name(&self) -> &'static str {
+            "u64"
+        }
+    }
+
+    #[test]
+    fn test_provenance_trace_back() {
+        let mut p = HirpdagProvenance::<u64>::new();
+        p.record(1, 1, "R", "rewrite_u64");
+        p.record(1, 2, "R", "rewrite_u64");
+        p.record(1, 2, "R", "rewrite_u64");
+        p.record(2, 3, "S", "rewrite_u64");
+        assert_eq!(p.len(), 2);
+        assert_eq!(p.origin_of(&3).unwrap().rewriter, "S");
+        assert!(p.origin_of(&1).is_none());
+        let chain: Vec<u64> = p.trace_back(&3).iter().map(|s| s.input).collect();
+        assert_eq!(chain, vec![2, 1]);
+        assert_eq!(
+            p.to_dot(),
+            "digraph hirpdag_provenance {\n    \"u64#1\" -> \"u64#2\" [label=\"R::rewrite_u64\"];\n    \"u64#2\" -> \"u64#3\" [label=\"S::rewrite_u64\"];\n}\n"
+        );
+        #[cfg(feature = "json")]
+        assert_eq!(p.to_json()["steps"][1]["input"]["id"], 2);
+    }
+}
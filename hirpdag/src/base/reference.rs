@@ -3,9 +3,12 @@
 use crate::base::meta::HirpdagComputeMeta;
 use crate::base::meta::HirpdagMeta;
 use crate::base::meta::HirpdagMetaFlagType;
+#[cfg(feature = "stable-hash")]
+use crate::base::stable_hash::{HirpdagStableHash, HirpdagStableHasher};
 use hirpdag_hashconsing;
 use hirpdag_hashconsing::BuildTable;
 use hirpdag_hashconsing::Reference;
+use hirpdag_hashconsing::ReferenceWeak;
 use hirpdag_hashconsing::Table;
 
 /// A hash-consed, reference-counted pointer to an interned DAG node.
@@ -101,6 +104,13 @@ where
         R::strong_deref(&self.0).hirpdag_creation_id
     }
 
+    /// Returns the stable hash of this node: a hash of its content, equal
+    /// for structurally equal nodes in any process (see `stable_hash`).
+    #[cfg(feature = "stable-hash")]
+    pub fn hirpdag_stable_hash(&self) -> u64 {
+        R::strong_deref(&self.0).hirpdag_stable_hash
+    }
+
     /// Deep structural comparison of the underlying data, independent of creation order.
     ///
     /// This is O(n) in the size of the DAG. Prefer `cmp` (creation-ID based) for
@@ -112,6 +122,54 @@ where
     }
 }
 
+impl<D, R> HirpdagRef<D, R>
+where
+    D: HirpdagStruct,
+    R: Reference<HirpdagStorage<D>>,
+{
+    /// Returns a weak handle to this node, which does not keep it alive.
+    pub fn hirpdag_downgrade<RW>(&self) -> HirpdagRefWeak<D, R, RW>
+    where
+        RW: ReferenceWeak<HirpdagStorage<D>, R>,
+    {
+        HirpdagRefWeak(RW::weak_downgrade(&self.0), std::marker::PhantomData)
+    }
+}
+
+/// A weak handle to an interned DAG node.
+///
+/// Does not keep the node alive. [`upgrade`](Self::hirpdag_upgrade) returns the node while
+/// some [`HirpdagRef`] to it still exists.
+pub struct HirpdagRefWeak<D: HirpdagStruct, R: Reference<HirpdagStorage<D>>, RW>(
+    RW,
+    // fn() so the auto traits (Send, Sync) follow RW alone.
+    std::marker::PhantomData<fn() -> (D, R)>,
+);
+
+impl<D, R, RW> HirpdagRefWeak<D, R, RW>
+where
+    D: HirpdagStruct,
+    R: Reference<HirpdagStorage<D>>,
+    RW: ReferenceWeak<HirpdagStorage<D>, R>,
+{
+    /// Returns the node if it is still alive.
+    pub fn hirpdag_upgrade(&self) -> Option<HirpdagRef<D, R>> {
+        RW::weak_upgrade(&self.0).map(|r| HirpdagRef(r, std::marker::PhantomData))
+    }
+}
+
+/// A child contributes its stored stable hash.
+#[cfg(feature = "stable-hash")]
+impl<D, R> HirpdagStableHash for HirpdagRef<D, R>
+where
+    D: HirpdagStruct,
+    R: Reference<HirpdagStorage<D>>,
+{
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        hasher.write_u64(self.hirpdag_stable_hash());
+    }
+}
+
 impl<D, R> HirpdagComputeMeta for HirpdagRef<D, R>
 where
     D: HirpdagStruct,
@@ -140,7 +198,8 @@ static HIRPDAG_CREATION_COUNTER: std::sync::atomic::AtomicU64 =
 
 /// The heap allocation stored behind every [`HirpdagRef`].
 ///
-/// Holds the user data `D`, the pre-computed [`HirpdagMeta`], and the creation ID.
+/// Holds the user data `D`, the pre-computed [`HirpdagMeta`], the creation ID and,
+/// with the `stable-hash` feature, the stable hash.
 /// Users never interact with this type directly; access goes through `HirpdagRef::deref`
 /// or `hirpdag_get_meta` / `hirpdag_get_creation_id`.
 pub struct HirpdagStorage<D: HirpdagStruct> {
@@ -148,6 +207,10 @@ pub struct HirpdagStorage<D: HirpdagStruct> {
     /// Monotonically increasing ID assigned at creation time.
     /// Nodes created earlier (and thus potentially depended upon by later nodes) have lower IDs.
     hirpdag_creation_id: u64,
+    /// Content hash computed at creation time, from the data and the
+    /// children's stable hashes.
+    #[cfg(feature = "stable-hash")]
+    hirpdag_stable_hash: u64,
     hirpdag_data: D,
 }
 
@@ -159,6 +222,8 @@ where
         Self {
             hirpdag_meta: self.hirpdag_meta.clone(),
             hirpdag_creation_id: self.hirpdag_creation_id,
+            #[cfg(feature = "stable-hash")]
+            hirpdag_stable_hash: self.hirpdag_stable_hash,
             hirpdag_data: self.hirpdag_data.clone(),
         }
     }
@@ -246,22 +311,37 @@ where
     /// Intern `data`: return an existing node if a structurally equal one is already stored,
     /// otherwise allocate a new one, compute its metadata and assign a creation ID.
     pub fn hirpdag_hashcons(&self, data: D) -> HirpdagRef<D, R> {
+        self.hirpdag_hashcons_fresh(data).0
+    }
+
+    /// Like [`hirpdag_hashcons`](Self::hirpdag_hashcons), also returning whether the node
+    /// was newly inserted (rather than found already interned).
+    pub fn hirpdag_hashcons_fresh(&self, data: D) -> (HirpdagRef<D, R>, bool) {
+        let mut fresh = false;
         let storage = HirpdagStorage::<D> {
             hirpdag_meta: HirpdagMeta::zero(),
             hirpdag_creation_id: 0,
+            #[cfg(feature = "stable-hash")]
+            hirpdag_stable_hash: 0,
             hirpdag_data: data,
         };
         let compute_hirpdag_meta = |s: &mut HirpdagStorage<D>| {
             let meta = s.hirpdag_data.hirpdag_compute_meta();
             s.hirpdag_meta = meta;
+            #[cfg(feature = "stable-hash")]
+            {
+                s.hirpdag_stable_hash = s.hirpdag_data.hirpdag_compute_stable_hash();
+            }
             s.hirpdag_creation_id =
                 HIRPDAG_CREATION_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            fresh = true;
         };
 
-        HirpdagRef(
+        let r = HirpdagRef(
             self.table.get_or_insert(storage, compute_hirpdag_meta),
             std::marker::PhantomData,
-        )
+        );
+        (r, fresh)
     }
 
     /// Empty this type's global interning table so that later construction
@@ -298,4 +378,8 @@ pub trait HirpdagStruct:
     fn hirpdag_flags(&self) -> HirpdagMetaFlagType {
         0
     }
+
+    /// Computes the stable hash of a node holding this data.
+    #[cfg(feature = "stable-hash")]
+    fn hirpdag_compute_stable_hash(&self) -> u64;
 }
@@ -0,0 +1,144 @@
+// ==== Root Lists
+//
+// An archive holds its roots either typed, as the generated
+// `HirpdagArchiveRoots` (one vector per `#[hirpdag(root)]` type), or as a
+// root list: an ordered list of nodes of any struct type, each optionally
+// named. Root lists need no type to be marked as a root, so tools can save
+// snapshots such as `{"main": expr, "env": vars}` of any module. Binary
+// archives with a root list carry `HIRPDAG_FLAG_ROOT_LIST`.
+//
+// The generated `HirpdagRootList` alias holds the module's `HirpdagNodeRef`s;
+// dynamic archives hold node indices.
+
+use crate::base::serialize::HirpdagCollect;
+
+/// One root of a root list.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagRootListEntry<N> {
+    pub name: Option<String>,
+    pub node: N,
+}
+
+/// An ordered list of archive roots, each optionally named. Names need not
+/// be unique.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct HirpdagRootList<N> {
+    entries: Vec<HirpdagRootListEntry<N>>,
+}
+
+impl<N> Default for HirpdagRootList<N> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<N> HirpdagRootList<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an unnamed root.
+    pub fn push(&mut self, node: impl Into<N>) -> &mut Self {
+        self.entries.push(HirpdagRootListEntry {
+            name: None,
+            node: node.into(),
+        });
+        self
+    }
+
+    /// Appends a root named `name`.
+    pub fn push_named(&mut self, name: impl Into<String>, node: impl Into<N>) -> &mut Self {
+        self.entries.push(HirpdagRootListEntry {
+            name: Some(name.into()),
+            node: node.into(),
+        });
+        self
+    }
+
+    /// The first root named `name`.
+    pub fn get(&self, name: &str) -> Option<&N> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name.as_deref() == Some(name))
+            .map(|entry| &entry.node)
+    }
+
+    pub fn entries(&self) -> &[HirpdagRootListEntry<N>] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<HirpdagRootListEntry<N>> {
+        self.entries
+    }
+
+    /// The roots, in order.
+    pub fn nodes(&self) -> impl Iterator<Item = &N> {
+        self.entries.iter().map(|entry| &entry.node)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The list with each root mapped by `f`, names kept.
+    pub fn map<M>(self, mut f: impl FnMut(N) -> M) -> HirpdagRootList<M> {
+        HirpdagRootList {
+            entries: self
+                .entries
+                .into_iter()
+                .map(|entry| HirpdagRootListEntry {
+                    name: entry.name,
+                    node: f(entry.node),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<N> FromIterator<HirpdagRootListEntry<N>> for HirpdagRootList<N> {
+    fn from_iter<I: IntoIterator<Item = HirpdagRootListEntry<N>>>(iter: I) -> Self {
+        Self {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<C, N: HirpdagCollect<C>> HirpdagCollect<C> for HirpdagRootList<N> {
+    fn hirpdag_collect(&self, ctx: &mut C) {
+        for node in self.nodes() {
+            node.hirpdag_collect(ctx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_list() {
+        let mut list: HirpdagRootList<u64> = HirpdagRootList::new();
+        list.push_named("main", 3u64)
+            .push(1u64)
+            .push_named("main", 2u64);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.get("main"), Some(&3));
+        assert_eq!(list.get("env"), None);
+        assert_eq!(list.nodes().copied().collect::<Vec<_>>(), vec![3, 1, 2]);
+        #[cfg(feature = "json")]
+        assert_eq!(
+            serde_json::to_string(&list).unwrap(),
+            r#"[{"name":"main","node":3},{"name":null,"node":1},{"name":"main","node":2}]"#
+        );
+        let doubled = list.clone().map(|node| node * 2);
+        assert_eq!(doubled.get("main"), Some(&6));
+        assert_eq!(doubled.entries()[1].name, None);
+    }
+}
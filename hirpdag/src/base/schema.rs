@@ -0,0 +1,1520 @@
+// ==== Schema Description and Dynamic Archives
+//
+// A `HirpdagSchema` describes the hirpdag types of a module: what the
+// schema fingerprint hashes, in a form that can be embedded in a binary
+// archive and read back without the generated Rust types.
+//
+// Given the schema an archive was written with, `HirpdagDynArchive` decodes
+// its node table into dynamic values (type names, named field values, node
+// indices). Migrations edit these values, and `HirpdagDynArchive::to_json`
+// re-encodes them as a JSON archive for the current types to load.
+
+use crate::base::root_list::HirpdagRootList;
+use crate::base::serialize::HirpdagRootLayout;
+use serde::de::{DeserializeSeed, Error as _};
+
+/// The hirpdag types of a module, in declaration order.
+///
+/// Declaration order is part of the binary wire format: struct types are
+/// tagged by their position among the struct types, root vectors are written
+/// in the order of the root types, and enum variants by their position.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagSchema {
+    pub types: Vec<HirpdagSchemaType>,
+}
+
+/// One `#[hirpdag]` struct or enum.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagSchemaType {
+    pub name: String,
+    /// Marked `#[hirpdag(root)]`.
+    pub root: bool,
+    pub kind: HirpdagSchemaKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HirpdagSchemaKind {
+    /// A hashconsed struct: an entry in the node table.
+    Struct(Vec<HirpdagSchemaField>),
+    /// An enum: inline payload of the node containing it. Every variant has
+    /// exactly one payload value.
+    Enum(Vec<HirpdagSchemaVariant>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagSchemaField {
+    pub name: String,
+    pub ty: HirpdagSchemaTy,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagSchemaVariant {
+    pub name: String,
+    pub ty: HirpdagSchemaTy,
+}
+
+/// The type of a field or variant payload.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HirpdagSchemaTy {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Isize,
+    F32,
+    F64,
+    Char,
+    String,
+    Option(Box<HirpdagSchemaTy>),
+    Vec(Box<HirpdagSchemaTy>),
+    /// A reference to a node of the named hirpdag struct type, encoded as
+    /// its index in the node table.
+    Ref(String),
+    /// A value of the named hirpdag enum type, stored inline.
+    Enum(String),
+    /// Any other type, by its Rust spelling. Opaque values cannot be decoded
+    /// without the Rust type.
+    Opaque(String),
+}
+
+impl HirpdagSchema {
+    pub fn get(&self, name: &str) -> Option<&HirpdagSchemaType> {
+        self.types.iter().find(|t| t.name == name)
+    }
+
+    /// The struct types, in node table tag order.
+    pub fn struct_types(&self) -> impl Iterator<Item = &HirpdagSchemaType> {
+        self.types
+            .iter()
+            .filter(|t| matches!(t.kind, HirpdagSchemaKind::Struct(_)))
+    }
+
+    /// The root types, in archive roots order.
+    pub fn root_types(&self) -> impl Iterator<Item = &HirpdagSchemaType> {
+        self.struct_types().filter(|t| t.root)
+    }
+}
+
+/// The snake_case name of a type's vector in the archive roots, e.g.
+/// "MessageA" -> "message_a". Must match the generated
+/// `HirpdagArchiveRoots` field names.
+pub fn hirpdag_roots_field_name(type_name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in type_name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i != 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// A decoded field value. Integers of every width are widened.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HirpdagDynValue {
+    Bool(bool),
+    Unsigned(u128),
+    Signed(i128),
+    Float(f64),
+    Char(char),
+    String(String),
+    Option(Option<Box<HirpdagDynValue>>),
+    Seq(Vec<HirpdagDynValue>),
+    /// A node table index.
+    Ref(u64),
+    /// A value of a hirpdag enum type.
+    Variant {
+        enum_name: String,
+        variant: String,
+        value: Box<HirpdagDynValue>,
+    },
+}
+
+impl HirpdagDynValue {
+    /// Calls `f` on this value and every value nested in it, outermost first.
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut HirpdagDynValue)) {
+        f(self);
+        match self {
+            Self::Option(Some(inner)) => inner.visit_mut(f),
+            Self::Seq(items) => items.iter_mut().for_each(|item| item.visit_mut(f)),
+            Self::Variant { value, .. } => value.visit_mut(f),
+            _ => {}
+        }
+    }
+
+    /// Calls `f` on this value and every value nested in it, outermost first.
+    pub fn visit(&self, f: &mut impl FnMut(&HirpdagDynValue)) {
+        f(self);
+        match self {
+            Self::Option(Some(inner)) => inner.visit(f),
+            Self::Seq(items) => items.iter().for_each(|item| item.visit(f)),
+            Self::Variant { value, .. } => value.visit(f),
+            _ => {}
+        }
+    }
+}
+
+/// A decoded node table entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HirpdagDynNode {
+    pub type_name: String,
+    /// The fields, in declaration order.
+    pub fields: Vec<(String, HirpdagDynValue)>,
+}
+
+impl HirpdagDynNode {
+    pub fn field(&self, name: &str) -> Option<&HirpdagDynValue> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut HirpdagDynValue> {
+        self.fields
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// The node table indices this node refers to, in field order.
+    pub fn children(&self) -> Vec<u64> {
+        let mut children = Vec::new();
+        for (_, value) in &self.fields {
+            value.visit(&mut |v| {
+                if let HirpdagDynValue::Ref(index) = v {
+                    children.push(*index);
+                }
+            });
+        }
+        children
+    }
+}
+
+/// An archive decoded without its Rust types: the node table (children
+/// before parents) and the root node indices of each root type, or the root
+/// list.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HirpdagDynArchive {
+    pub nodes: Vec<HirpdagDynNode>,
+    /// (root type name, node indices), in schema order. Empty for an
+    /// archive with a root list.
+    pub roots: Vec<(String, Vec<u64>)>,
+    /// The root list of an archive written with one, instead of typed roots.
+    pub root_list: Option<HirpdagRootList<u64>>,
+}
+
+impl HirpdagDynArchive {
+    /// Decodes an archive payload (everything after the binary header, or a
+    /// whole JSON archive) written with `schema`. `layout` selects the roots
+    /// of formats which encode structs as sequences, such as binary archives
+    /// (see [`HirpdagBinaryHeader::root_layout`](crate::base::HirpdagBinaryHeader::root_layout));
+    /// formats with field names, such as JSON, hold either.
+    pub fn decode<'de, D: serde::Deserializer<'de>>(
+        schema: &HirpdagSchema,
+        layout: HirpdagRootLayout,
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        HirpdagDynArchiveSeed { schema, layout }.deserialize(deserializer)
+    }
+
+    /// How the archive holds its roots.
+    pub fn root_layout(&self) -> HirpdagRootLayout {
+        match self.root_list {
+            Some(_) => HirpdagRootLayout::List,
+            None => HirpdagRootLayout::Typed,
+        }
+    }
+
+    /// The node index of every root, typed roots first.
+    pub fn root_nodes(&self) -> impl Iterator<Item = u64> + '_ {
+        let typed = self.roots.iter().flat_map(|(_, indices)| indices.iter());
+        let list = self.root_list.iter().flat_map(|list| list.nodes());
+        typed.chain(list).copied()
+    }
+
+    /// Decodes a binary archive payload (see [`hirpdag_parse_binary_header`](crate::base::hirpdag_parse_binary_header))
+    /// holding its roots as `layout`.
+    #[cfg(feature = "postcard")]
+    pub fn from_postcard(
+        schema: &HirpdagSchema,
+        layout: HirpdagRootLayout,
+        payload: &[u8],
+    ) -> Result<Self, crate::base::HirpdagDeserializeError> {
+        crate::base::hirpdag_de_reset();
+        let mut deserializer = postcard::Deserializer::from_bytes(payload);
+        let archive = Self::decode(schema, layout, &mut deserializer).map_err(|e| {
+            crate::base::hirpdag_de_refine(crate::base::hirpdag_postcard_de_error(e))
+        })?;
+        if !deserializer.finalize().map_or(true, |rest| rest.is_empty()) {
+            return Err(crate::base::HirpdagDeserializeError::TrailingBytes {
+                at_node: crate::base::hirpdag_de_node(),
+            });
+        }
+        Ok(archive)
+    }
+
+    /// Streaming variant of [`HirpdagDynArchive::from_postcard`].
+    #[cfg(feature = "postcard")]
+    pub fn from_postcard_reader<R: std::io::Read>(
+        schema: &HirpdagSchema,
+        layout: HirpdagRootLayout,
+        reader: R,
+    ) -> Result<Self, crate::base::HirpdagDeserializeError> {
+        crate::base::hirpdag_de_reset();
+        crate::base::hirpdag_postcard_seed_from_reader(
+            HirpdagDynArchiveSeed { schema, layout },
+            reader,
+        )
+        .map_err(crate::base::hirpdag_de_refine)
+    }
+
+    /// Decodes a JSON archive.
+    #[cfg(feature = "json")]
+    pub fn from_json(
+        schema: &HirpdagSchema,
+        text: &str,
+    ) -> Result<Self, crate::base::HirpdagDeserializeError> {
+        crate::base::hirpdag_de_reset();
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let archive = Self::decode(schema, HirpdagRootLayout::Typed, &mut deserializer)
+            .map_err(|e| crate::base::hirpdag_de_refine(crate::base::hirpdag_json_de_error(e)))?;
+        deserializer
+            .end()
+            .map_err(|_| crate::base::HirpdagDeserializeError::TrailingBytes {
+                at_node: crate::base::hirpdag_de_node(),
+            })?;
+        Ok(archive)
+    }
+
+    /// Encodes the archive in the JSON archive format, which the generated
+    /// `hirpdag_deserialize_json` reads by type, field and variant names.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&HirpdagDynArchiveJson(self))
+            .expect("dynamic archives always encode as JSON")
+    }
+
+    /// Adds `field` with `value` to every node of `type_name` which does not
+    /// have it yet.
+    pub fn add_field(&mut self, type_name: &str, field: &str, value: HirpdagDynValue) {
+        for node in self.nodes.iter_mut().filter(|n| n.type_name == type_name) {
+            if node.field(field).is_none() {
+                node.fields.push((field.to_string(), value.clone()));
+            }
+        }
+    }
+
+    pub fn remove_field(&mut self, type_name: &str, field: &str) {
+        for node in self.nodes.iter_mut().filter(|n| n.type_name == type_name) {
+            node.fields.retain(|(n, _)| n != field);
+        }
+    }
+
+    pub fn rename_field(&mut self, type_name: &str, from: &str, to: &str) {
+        for node in self.nodes.iter_mut().filter(|n| n.type_name == type_name) {
+            for (name, _) in node.fields.iter_mut().filter(|(n, _)| n == from) {
+                *name = to.to_string();
+            }
+        }
+    }
+
+    /// Renames a variant of the enum `enum_name`, wherever it occurs.
+    pub fn rename_variant(&mut self, enum_name: &str, from: &str, to: &str) {
+        self.visit_values_mut(&mut |v| {
+            if let HirpdagDynValue::Variant {
+                enum_name: e,
+                variant,
+                ..
+            } = v
+            {
+                if e == enum_name && variant == from {
+                    *variant = to.to_string();
+                }
+            }
+        });
+    }
+
+    /// Renames a struct or enum type.
+    pub fn rename_type(&mut self, from: &str, to: &str) {
+        for node in self.nodes.iter_mut().filter(|n| n.type_name == from) {
+            node.type_name = to.to_string();
+        }
+        for (name, _) in self.roots.iter_mut().filter(|(n, _)| n == from) {
+            *name = to.to_string();
+        }
+        self.visit_values_mut(&mut |v| {
+            if let HirpdagDynValue::Variant { enum_name, .. } = v {
+                if enum_name == from {
+                    *enum_name = to.to_string();
+                }
+            }
+        });
+    }
+
+    /// Calls `f` on every field value of every node, nested values included.
+    pub fn visit_values_mut(&mut self, f: &mut impl FnMut(&mut HirpdagDynValue)) {
+        for node in &mut self.nodes {
+            for (_, value) in &mut node.fields {
+                value.visit_mut(f);
+            }
+        }
+    }
+}
+
+// ==== Decoding
+//
+// Dynamic decoding reads what the generated types' derived impls write. serde
+// names structs, fields and variants with `&'static str`, which a runtime
+// schema does not have, so structs and enum values are read without them:
+// as maps keyed by name in human-readable formats such as JSON, and as
+// tuples in compact formats such as postcard, which write a struct as its
+// fields in order and an enum value as its variant index then its payload.
+
+/// Reads the `len` fields of a struct with `visitor`.
+fn hirpdag_dyn_struct<'de, D, V>(
+    deserializer: D,
+    len: usize,
+    visitor: V,
+) -> Result<V::Value, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    V: serde::de::Visitor<'de>,
+{
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_map(visitor)
+    } else {
+        deserializer.deserialize_tuple(len, visitor)
+    }
+}
+
+/// Decodes an identifier given either as an index (compact formats) or a
+/// name (self-describing formats) into an index into `names`.
+struct HirpdagIdentSeed<'s> {
+    names: Vec<&'s str>,
+}
+
+impl<'de> DeserializeSeed<'de> for HirpdagIdentSeed<'_> {
+    type Value = usize;
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for HirpdagIdentSeed<'_> {
+    type Value = usize;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "one of {:?}", self.names)
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<usize, E> {
+        match usize::try_from(v) {
+            Ok(index) if index < self.names.len() => Ok(index),
+            _ => Err(E::custom(format!(
+                "invalid index {} (expected {})",
+                v,
+                HirpdagExpecting(&self)
+            ))),
+        }
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<usize, E> {
+        self.names.iter().position(|n| *n == v).ok_or_else(|| {
+            E::custom(format!(
+                "unknown name `{}`, expected one of {:?}",
+                v, self.names
+            ))
+        })
+    }
+}
+
+struct HirpdagExpecting<'a, V>(&'a V);
+
+impl<'de, V: serde::de::Visitor<'de>> std::fmt::Display for HirpdagExpecting<'_, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.expecting(f)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HirpdagDynValueSeed<'s> {
+    schema: &'s HirpdagSchema,
+    ty: &'s HirpdagSchemaTy,
+}
+
+impl<'de> DeserializeSeed<'de> for HirpdagDynValueSeed<'_> {
+    type Value = HirpdagDynValue;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<HirpdagDynValue, D::Error> {
+        use serde::Deserialize;
+        use HirpdagDynValue as V;
+        use HirpdagSchemaTy as T;
+        match self.ty {
+            T::Bool => bool::deserialize(deserializer).map(V::Bool),
+            T::U8 => u8::deserialize(deserializer).map(|v| V::Unsigned(v.into())),
+            T::U16 => u16::deserialize(deserializer).map(|v| V::Unsigned(v.into())),
+            T::U32 => u32::deserialize(deserializer).map(|v| V::Unsigned(v.into())),
+            T::U64 | T::Usize => u64::deserialize(deserializer).map(|v| V::Unsigned(v.into())),
+            T::U128 => u128::deserialize(deserializer).map(V::Unsigned),
+            T::I8 => i8::deserialize(deserializer).map(|v| V::Signed(v.into())),
+            T::I16 => i16::deserialize(deserializer).map(|v| V::Signed(v.into())),
+            T::I32 => i32::deserialize(deserializer).map(|v| V::Signed(v.into())),
+            T::I64 | T::Isize => i64::deserialize(deserializer).map(|v| V::Signed(v.into())),
+            T::I128 => i128::deserialize(deserializer).map(V::Signed),
+            T::F32 => f32::deserialize(deserializer).map(|v| V::Float(v.into())),
+            T::F64 => f64::deserialize(deserializer).map(V::Float),
+            T::Char => char::deserialize(deserializer).map(V::Char),
+            T::String => String::deserialize(deserializer).map(V::String),
+            T::Ref(_) => u64::deserialize(deserializer).map(V::Ref),
+            T::Option(_) => deserializer.deserialize_option(self),
+            T::Vec(_) => deserializer.deserialize_seq(self),
+            T::Enum(name) => match self.schema.get(name).map(|t| &t.kind) {
+                Some(HirpdagSchemaKind::Enum(variants)) => {
+                    let what = format!("a value of enum {}", name);
+                    let (index, value) = HirpdagDynVariantVisitor {
+                        what: &what,
+                        names: variants.iter().map(|v| v.name.as_str()).collect(),
+                        payload: |index: usize| HirpdagDynValueSeed {
+                            schema: self.schema,
+                            ty: &variants[index].ty,
+                        },
+                    }
+                    .deserialize(deserializer)?;
+                    Ok(V::Variant {
+                        enum_name: name.to_string(),
+                        variant: variants[index].name.clone(),
+                        value: Box::new(value),
+                    })
+                }
+                _ => Err(D::Error::custom(format!(
+                    "the schema has no enum type `{}`",
+                    name
+                ))),
+            },
+            T::Opaque(name) => Err(D::Error::custom(format!(
+                "cannot decode a value of opaque type `{}`",
+                name
+            ))),
+        }
+    }
+}
+
+// Visits the contents of `Option` and `Vec` values.
+impl<'de> serde::de::Visitor<'de> for HirpdagDynValueSeed<'_> {
+    type Value = HirpdagDynValue;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a value of type {:?}", self.ty)
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<HirpdagDynValue, E> {
+        Ok(HirpdagDynValue::Option(None))
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<HirpdagDynValue, E> {
+        Ok(HirpdagDynValue::Option(None))
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<HirpdagDynValue, D::Error> {
+        let HirpdagSchemaTy::Option(inner) = self.ty else {
+            return Err(D::Error::custom("unexpected optional value"));
+        };
+        let seed = HirpdagDynValueSeed {
+            schema: self.schema,
+            ty: inner,
+        };
+        Ok(HirpdagDynValue::Option(Some(Box::new(
+            seed.deserialize(deserializer)?,
+        ))))
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> Result<HirpdagDynValue, A::Error> {
+        let HirpdagSchemaTy::Vec(inner) = self.ty else {
+            return Err(A::Error::custom("unexpected sequence"));
+        };
+        let seed = HirpdagDynValueSeed {
+            schema: self.schema,
+            ty: inner,
+        };
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element_seed(seed)? {
+            items.push(item);
+        }
+        Ok(HirpdagDynValue::Seq(items))
+    }
+}
+
+/// Decodes an enum value of newtype variants: the variant, by name or
+/// index, then its payload with the seed `payload` gives for the variant.
+struct HirpdagDynVariantVisitor<'s, F> {
+    what: &'s str,
+    names: Vec<&'s str>,
+    payload: F,
+}
+
+impl<F> HirpdagDynVariantVisitor<'_, F> {
+    /// The index of the variant, and its payload.
+    fn deserialize<'de, D, S>(self, deserializer: D) -> Result<(usize, S::Value), D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        F: FnOnce(usize) -> S,
+        S: DeserializeSeed<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_map(self)
+        } else {
+            deserializer.deserialize_tuple(2, self)
+        }
+    }
+}
+
+impl<'de, F, S> serde::de::Visitor<'de> for HirpdagDynVariantVisitor<'_, F>
+where
+    F: FnOnce(usize) -> S,
+    S: DeserializeSeed<'de>,
+{
+    type Value = (usize, S::Value);
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.what)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let Some(index) = seq.next_element::<u32>()? else {
+            return Err(A::Error::invalid_length(0, &self));
+        };
+        let index = HirpdagIdentSeed { names: self.names }.visit_u64(index.into())?;
+        match seq.next_element_seed((self.payload)(index))? {
+            Some(value) => Ok((index, value)),
+            None => Err(A::Error::custom(format!(
+                "missing payload of {}",
+                self.what
+            ))),
+        }
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let Some(index) = map.next_key_seed(HirpdagIdentSeed {
+            names: self.names.clone(),
+        })?
+        else {
+            return Err(A::Error::invalid_length(0, &self));
+        };
+        let value = map.next_value_seed((self.payload)(index))?;
+        if map.next_key::<serde::de::IgnoredAny>()?.is_some() {
+            return Err(A::Error::custom(format!(
+                "more than one variant in {}",
+                self.what
+            )));
+        }
+        Ok((index, value))
+    }
+}
+
+/// Decodes the fields of a struct type (a node's data) or of the archive
+/// roots: a sequence in field order, or a map keyed by field name. Missing
+/// fields are filled in by `missing`, or are an error if it returns None.
+struct HirpdagDynFieldsVisitor<'s, S, M> {
+    what: &'s str,
+    fields: Vec<(&'s str, S)>,
+    missing: M,
+}
+
+impl<'de, S, M> serde::de::Visitor<'de> for HirpdagDynFieldsVisitor<'_, S, M>
+where
+    S: DeserializeSeed<'de> + Copy,
+    M: Fn(&str) -> Option<S::Value>,
+{
+    type Value = Vec<S::Value>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.what)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::with_capacity(self.fields.len());
+        for (index, (_, seed)) in self.fields.iter().enumerate() {
+            match seq.next_element_seed(*seed)? {
+                Some(value) => values.push(value),
+                None => return Err(A::Error::invalid_length(index, &self)),
+            }
+        }
+        Ok(values)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut values: Vec<Option<S::Value>> = self.fields.iter().map(|_| None).collect();
+        while let Some(key) = map.next_key::<String>()? {
+            let Some(index) = self.fields.iter().position(|(n, _)| *n == key) else {
+                return Err(A::Error::custom(format!(
+                    "unknown field `{}` in {}",
+                    key, self.what
+                )));
+            };
+            values[index] = Some(map.next_value_seed(self.fields[index].1)?);
+        }
+        values
+            .into_iter()
+            .zip(&self.fields)
+            .map(|(value, (name, _))| {
+                value.or_else(|| (self.missing)(name)).ok_or_else(|| {
+                    A::Error::custom(format!("missing field `{}` in {}", name, self.what))
+                })
+            })
+            .collect()
+    }
+}
+
+/// Decodes a `HirpdagArchiveNode`: a struct type tag, then its fields.
+#[derive(Clone, Copy)]
+struct HirpdagDynNodeSeed<'s> {
+    schema: &'s HirpdagSchema,
+}
+
+impl<'de> DeserializeSeed<'de> for HirpdagDynNodeSeed<'_> {
+    type Value = HirpdagDynNode;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<HirpdagDynNode, D::Error> {
+        let types: Vec<(&str, &[HirpdagSchemaField])> = self
+            .schema
+            .struct_types()
+            .map(|t| match &t.kind {
+                HirpdagSchemaKind::Struct(fields) => (t.name.as_str(), &fields[..]),
+                HirpdagSchemaKind::Enum(_) => unreachable!("struct_types only yields structs"),
+            })
+            .collect();
+        let (index, values) = HirpdagDynVariantVisitor {
+            what: "a hirpdag node",
+            names: types.iter().map(|(name, _)| *name).collect(),
+            payload: |index: usize| HirpdagDynStructSeed {
+                schema: self.schema,
+                name: types[index].0,
+                fields: types[index].1,
+            },
+        }
+        .deserialize(deserializer)?;
+        let (name, fields) = types[index];
+        Ok(HirpdagDynNode {
+            type_name: name.to_string(),
+            fields: fields.iter().map(|f| f.name.clone()).zip(values).collect(),
+        })
+    }
+}
+
+struct HirpdagDynStructSeed<'s> {
+    schema: &'s HirpdagSchema,
+    name: &'s str,
+    fields: &'s [HirpdagSchemaField],
+}
+
+impl<'de> DeserializeSeed<'de> for HirpdagDynStructSeed<'_> {
+    type Value = Vec<HirpdagDynValue>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        let what = format!("struct {}", self.name);
+        let visitor = HirpdagDynFieldsVisitor {
+            what: &what,
+            fields: self
+                .fields
+                .iter()
+                .map(|f| {
+                    let seed = HirpdagDynValueSeed {
+                        schema: self.schema,
+                        ty: &f.ty,
+                    };
+                    (f.name.as_str(), seed)
+                })
+                .collect(),
+            // As with derived impls, a missing optional field is None.
+            missing: |name: &str| {
+                self.fields
+                    .iter()
+                    .find(|f| f.name == name && matches!(f.ty, HirpdagSchemaTy::Option(_)))
+                    .map(|_| HirpdagDynValue::Option(None))
+            },
+        };
+        hirpdag_dyn_struct(deserializer, self.fields.len(), visitor)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HirpdagDynNodesSeed<'s> {
+    schema: &'s HirpdagSchema,
+}
+
+impl<'de> DeserializeSeed<'de> for HirpdagDynNodesSeed<'_> {
+    type Value = Vec<HirpdagDynNode>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for HirpdagDynNodesSeed<'_> {
+    type Value = Vec<HirpdagDynNode>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a sequence of hirpdag nodes")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let node_seed = HirpdagDynNodeSeed {
+            schema: self.schema,
+        };
+        let mut nodes = Vec::new();
+        while let Some(node) = seq.next_element_seed(node_seed)? {
+            nodes.push(node);
+        }
+        Ok(nodes)
+    }
+}
+
+/// A root vector: node indices.
+#[derive(Clone, Copy)]
+struct HirpdagDynRootSeed;
+
+impl<'de> DeserializeSeed<'de> for HirpdagDynRootSeed {
+    type Value = Vec<u64>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Vec<u64>, D::Error> {
+        serde::Deserialize::deserialize(deserializer)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HirpdagDynRootsSeed<'s> {
+    schema: &'s HirpdagSchema,
+}
+
+impl<'de> DeserializeSeed<'de> for HirpdagDynRootsSeed<'_> {
+    type Value = Vec<(String, Vec<u64>)>;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        let types: Vec<&HirpdagSchemaType> = self.schema.root_types().collect();
+        let field_names: Vec<String> = types
+            .iter()
+            .map(|t| hirpdag_roots_field_name(&t.name))
+            .collect();
+        let visitor = HirpdagDynFieldsVisitor {
+            what: "the archive roots",
+            fields: field_names
+                .iter()
+                .map(|n| (n.as_str(), HirpdagDynRootSeed))
+                .collect(),
+            // HirpdagArchiveRoots is #[serde(default)].
+            missing: |_: &str| Some(Vec::new()),
+        };
+        let values = hirpdag_dyn_struct(deserializer, field_names.len(), visitor)?;
+        Ok(types.iter().map(|t| t.name.clone()).zip(values).collect())
+    }
+}
+
+/// Decodes a whole `HirpdagArchive` (version, nodes, roots) or
+/// `HirpdagListArchive` (version, nodes, root list).
+pub(crate) struct HirpdagDynArchiveSeed<'s> {
+    schema: &'s HirpdagSchema,
+    layout: HirpdagRootLayout,
+}
+
+impl<'de> DeserializeSeed<'de> for HirpdagDynArchiveSeed<'_> {
+    type Value = HirpdagDynArchive;
+
+    fn deserialize<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<HirpdagDynArchive, D::Error> {
+        let (name, fields) = hirpdag_archive_struct(self.layout);
+        deserializer.deserialize_struct(name, fields, self)
+    }
+}
+
+// The name and fields of the generated archive struct of `layout`.
+fn hirpdag_archive_struct(layout: HirpdagRootLayout) -> (&'static str, &'static [&'static str]) {
+    match layout {
+        HirpdagRootLayout::Typed => ("HirpdagArchive", &["version", "nodes", "roots"]),
+        HirpdagRootLayout::List => ("HirpdagListArchive", &["version", "nodes", "root_list"]),
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for HirpdagDynArchiveSeed<'_> {
+    type Value = HirpdagDynArchive;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a hirpdag archive")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let missing = || A::Error::custom("truncated hirpdag archive");
+        seq.next_element::<crate::base::HirpdagFormatVersion>()?
+            .ok_or_else(missing)?;
+        let nodes = seq
+            .next_element_seed(HirpdagDynNodesSeed {
+                schema: self.schema,
+            })?
+            .ok_or_else(missing)?;
+        if self.layout == HirpdagRootLayout::List {
+            let root_list = seq.next_element()?.ok_or_else(missing)?;
+            return Ok(HirpdagDynArchive {
+                nodes,
+                roots: Vec::new(),
+                root_list: Some(root_list),
+            });
+        }
+        let roots = seq
+            .next_element_seed(HirpdagDynRootsSeed {
+                schema: self.schema,
+            })?
+            .ok_or_else(missing)?;
+        Ok(HirpdagDynArchive {
+            nodes,
+            roots,
+            root_list: None,
+        })
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut version = None;
+        let mut nodes = None;
+        let mut roots = None;
+        let mut root_list = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" => version = Some(map.next_value::<crate::base::HirpdagFormatVersion>()?),
+                "nodes" => {
+                    nodes = Some(map.next_value_seed(HirpdagDynNodesSeed {
+                        schema: self.schema,
+                    })?)
+                }
+                "roots" => {
+                    roots = Some(map.next_value_seed(HirpdagDynRootsSeed {
+                        schema: self.schema,
+                    })?)
+                }
+                "root_list" => root_list = Some(map.next_value()?),
+                // User side-tables are opaque to dynamic archives.
+                "side_tables" => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+                other => {
+                    return Err(A::Error::custom(format!(
+                        "unknown archive field `{}`",
+                        other
+                    )))
+                }
+            }
+        }
+        version.ok_or_else(|| A::Error::missing_field("version"))?;
+        let nodes = nodes.ok_or_else(|| A::Error::missing_field("nodes"))?;
+        match (roots, root_list) {
+            (Some(roots), None) => Ok(HirpdagDynArchive {
+                nodes,
+                roots,
+                root_list: None,
+            }),
+            (None, Some(root_list)) => Ok(HirpdagDynArchive {
+                nodes,
+                roots: Vec::new(),
+                root_list: Some(root_list),
+            }),
+            (None, None) => Err(A::Error::missing_field("roots")),
+            (Some(_), Some(_)) => Err(A::Error::custom(
+                "an archive holds either `roots` or a `root_list`, not both",
+            )),
+        }
+    }
+}
+
+// ==== JSON encoding
+//
+// Mirrors the JSON the derived `Serialize` impls produce: structs as maps,
+// enum values as single-entry maps, `None` as null, refs as indices.
+
+#[cfg(feature = "json")]
+struct HirpdagDynArchiveJson<'a>(&'a HirpdagDynArchive);
+#[cfg(feature = "json")]
+struct HirpdagDynNodeJson<'a>(&'a HirpdagDynNode);
+#[cfg(feature = "json")]
+struct HirpdagDynFieldsJson<'a>(&'a [(String, HirpdagDynValue)]);
+#[cfg(feature = "json")]
+struct HirpdagDynRootsJson<'a>(&'a [(String, Vec<u64>)]);
+
+#[cfg(feature = "json")]
+impl serde::Serialize for HirpdagDynArchiveJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("version", &crate::base::HirpdagFormatVersion)?;
+        map.serialize_entry(
+            "nodes",
+            &self
+                .0
+                .nodes
+                .iter()
+                .map(HirpdagDynNodeJson)
+                .collect::<Vec<_>>(),
+        )?;
+        match &self.0.root_list {
+            Some(root_list) => map.serialize_entry("root_list", root_list)?,
+            None => map.serialize_entry("roots", &HirpdagDynRootsJson(&self.0.roots))?,
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "json")]
+impl serde::Serialize for HirpdagDynNodeJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(&self.0.type_name, &HirpdagDynFieldsJson(&self.0.fields))?;
+        map.end()
+    }
+}
+
+#[cfg(feature = "json")]
+impl serde::Serialize for HirpdagDynFieldsJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "json")]
+impl serde::Serialize for HirpdagDynRootsJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (type_name, indices) in self.0 {
+            map.serialize_entry(&hirpdag_roots_field_name(type_name), indices)?;
+        }
+        map.end()
+    }
+}
+
+impl serde::Serialize for HirpdagDynValue {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        match self {
+            Self::Bool(v) => serializer.serialize_bool(*v),
+            Self::Unsigned(v) => serializer.serialize_u128(*v),
+            Self::Signed(v) => serializer.serialize_i128(*v),
+            Self::Float(v) => serializer.serialize_f64(*v),
+            Self::Char(v) => serializer.serialize_char(*v),
+            Self::String(v) => serializer.serialize_str(v),
+            Self::Option(None) => serializer.serialize_none(),
+            Self::Option(Some(v)) => serializer.serialize_some(v),
+            Self::Seq(items) => serializer.collect_seq(items),
+            Self::Ref(index) => serializer.serialize_u64(*index),
+            Self::Variant { variant, value, .. } => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(variant, value)?;
+                map.end()
+            }
+        }
+    }
+}
+
+// ==== Schema-driven encoding
+//
+// The inverse of dynamic decoding: writes what the derived `Serialize` impls
+// write, with integer widths, variant indices and field order taken from the
+// schema. Compact formats such as postcard depend on these.
+
+/// Writes the fields of a struct as `hirpdag_dyn_struct` reads them.
+fn hirpdag_dyn_serialize_struct<S: serde::Serializer, T: serde::Serialize>(
+    serializer: S,
+    fields: &[(&str, T)],
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::{SerializeMap, SerializeTuple};
+    if serializer.is_human_readable() {
+        let mut map = serializer.serialize_map(Some(fields.len()))?;
+        for (name, value) in fields {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    } else {
+        let mut tuple = serializer.serialize_tuple(fields.len())?;
+        for (_, value) in fields {
+            tuple.serialize_element(value)?;
+        }
+        tuple.end()
+    }
+}
+
+/// Writes an enum value as `HirpdagDynVariantVisitor` reads it.
+fn hirpdag_dyn_serialize_variant<S: serde::Serializer, T: serde::Serialize>(
+    serializer: S,
+    index: usize,
+    name: &str,
+    payload: &T,
+) -> Result<S::Ok, S::Error> {
+    use serde::ser::{SerializeMap, SerializeTuple};
+    if serializer.is_human_readable() {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(name, payload)?;
+        map.end()
+    } else {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&(index as u32))?;
+        tuple.serialize_element(payload)?;
+        tuple.end()
+    }
+}
+
+impl HirpdagDynArchive {
+    /// Encodes the archive as the types of `schema` would, in a
+    /// human-readable format such as JSON or a compact one such as postcard.
+    /// The inverse of [`HirpdagDynArchive::decode`].
+    pub fn encode<S: serde::Serializer>(
+        &self,
+        schema: &HirpdagSchema,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        use serde::Serialize;
+        HirpdagDynArchiveTyped {
+            schema,
+            archive: self,
+        }
+        .serialize(serializer)
+    }
+
+    /// Encodes a binary archive payload, to follow a binary header.
+    #[cfg(feature = "postcard")]
+    pub fn to_postcard(
+        &self,
+        schema: &HirpdagSchema,
+    ) -> Result<Vec<u8>, crate::base::HirpdagSerializeError> {
+        let typed = HirpdagDynArchiveTyped {
+            schema,
+            archive: self,
+        };
+        postcard::to_stdvec(&typed)
+            .map_err(|e| crate::base::HirpdagSerializeError::Format(e.to_string()))
+    }
+}
+
+struct HirpdagDynArchiveTyped<'a> {
+    schema: &'a HirpdagSchema,
+    archive: &'a HirpdagDynArchive,
+}
+
+struct HirpdagDynNodesTyped<'a> {
+    schema: &'a HirpdagSchema,
+    nodes: &'a [HirpdagDynNode],
+}
+
+struct HirpdagDynNodeTyped<'a> {
+    schema: &'a HirpdagSchema,
+    node: &'a HirpdagDynNode,
+}
+
+struct HirpdagDynFieldsTyped<'a> {
+    schema: &'a HirpdagSchema,
+    name: &'a str,
+    fields: &'a [HirpdagSchemaField],
+    node: &'a HirpdagDynNode,
+}
+
+struct HirpdagDynRootsTyped<'a> {
+    schema: &'a HirpdagSchema,
+    roots: &'a [(String, Vec<u64>)],
+}
+
+struct HirpdagDynValueTyped<'a> {
+    schema: &'a HirpdagSchema,
+    ty: &'a HirpdagSchemaTy,
+    value: &'a HirpdagDynValue,
+}
+
+impl serde::Serialize for HirpdagDynArchiveTyped<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let (name, _) = hirpdag_archive_struct(self.archive.root_layout());
+        let mut archive = serializer.serialize_struct(name, 3)?;
+        archive.serialize_field("version", &crate::base::HirpdagFormatVersion)?;
+        archive.serialize_field(
+            "nodes",
+            &HirpdagDynNodesTyped {
+                schema: self.schema,
+                nodes: &self.archive.nodes,
+            },
+        )?;
+        match &self.archive.root_list {
+            Some(root_list) => archive.serialize_field("root_list", root_list)?,
+            None => archive.serialize_field(
+                "roots",
+                &HirpdagDynRootsTyped {
+                    schema: self.schema,
+                    roots: &self.archive.roots,
+                },
+            )?,
+        }
+        archive.end()
+    }
+}
+
+impl serde::Serialize for HirpdagDynNodesTyped<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.nodes.iter().map(|node| HirpdagDynNodeTyped {
+            schema: self.schema,
+            node,
+        }))
+    }
+}
+
+impl serde::Serialize for HirpdagDynNodeTyped<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+        let name = &self.node.type_name;
+        let found = self
+            .schema
+            .struct_types()
+            .enumerate()
+            .find(|(_, t)| &t.name == name);
+        let Some((
+            index,
+            HirpdagSchemaType {
+                kind: HirpdagSchemaKind::Struct(fields),
+                ..
+            },
+        )) = found
+        else {
+            return Err(S::Error::custom(format!(
+                "the schema has no struct type `{}`",
+                name
+            )));
+        };
+        hirpdag_dyn_serialize_variant(
+            serializer,
+            index,
+            name,
+            &HirpdagDynFieldsTyped {
+                schema: self.schema,
+                name,
+                fields,
+                node: self.node,
+            },
+        )
+    }
+}
+
+impl serde::Serialize for HirpdagDynFieldsTyped<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+        if let Some((unknown, _)) = self
+            .node
+            .fields
+            .iter()
+            .find(|(name, _)| !self.fields.iter().any(|f| &f.name == name))
+        {
+            return Err(S::Error::custom(format!(
+                "unknown field `{}` in {}",
+                unknown, self.name
+            )));
+        }
+        // As in decoding, a missing optional field is None.
+        let none = HirpdagDynValue::Option(None);
+        let mut values = Vec::with_capacity(self.fields.len());
+        for field in self.fields {
+            let value = match (self.node.field(&field.name), &field.ty) {
+                (Some(value), _) => value,
+                (None, HirpdagSchemaTy::Option(_)) => &none,
+                (None, _) => {
+                    return Err(S::Error::custom(format!(
+                        "missing field `{}` in {}",
+                        field.name, self.name
+                    )))
+                }
+            };
+            values.push((
+                field.name.as_str(),
+                HirpdagDynValueTyped {
+                    schema: self.schema,
+                    ty: &field.ty,
+                    value,
+                },
+            ));
+        }
+        hirpdag_dyn_serialize_struct(serializer, &values)
+    }
+}
+
+impl serde::Serialize for HirpdagDynRootsTyped<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let types: Vec<&HirpdagSchemaType> = self.schema.root_types().collect();
+        let names: Vec<String> = types
+            .iter()
+            .map(|t| hirpdag_roots_field_name(&t.name))
+            .collect();
+        let fields: Vec<(&str, &[u64])> = types
+            .iter()
+            .zip(&names)
+            .map(|(ty, name)| {
+                let indices = self
+                    .roots
+                    .iter()
+                    .find(|(root, _)| root == &ty.name)
+                    .map_or(&[][..], |(_, indices)| &indices[..]);
+                (name.as_str(), indices)
+            })
+            .collect();
+        hirpdag_dyn_serialize_struct(serializer, &fields)
+    }
+}
+
+impl serde::Serialize for HirpdagDynValueTyped<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+        use HirpdagDynValue as V;
+        use HirpdagSchemaTy as T;
+        let mismatch = || {
+            S::Error::custom(format!(
+                "value {} does not have type {:?}",
+                self.value, self.ty
+            ))
+        };
+        // Integers are narrowed back to the declared width.
+        let int = |value: &HirpdagDynValue| -> Option<i128> {
+            match value {
+                V::Unsigned(v) => i128::try_from(*v).ok(),
+                V::Signed(v) => Some(*v),
+                _ => None,
+            }
+        };
+        macro_rules! narrow {
+            ($method:ident, $t:ty) => {
+                match self.value {
+                    V::Unsigned(v) => <$t>::try_from(*v).ok(),
+                    other => int(other).and_then(|v| <$t>::try_from(v).ok()),
+                }
+                .ok_or_else(mismatch)
+                .and_then(|v| serializer.$method(v))
+            };
+        }
+        match (self.ty, self.value) {
+            (T::Bool, V::Bool(v)) => serializer.serialize_bool(*v),
+            (T::U8, _) => narrow!(serialize_u8, u8),
+            (T::U16, _) => narrow!(serialize_u16, u16),
+            (T::U32, _) => narrow!(serialize_u32, u32),
+            (T::U64 | T::Usize, _) => narrow!(serialize_u64, u64),
+            (T::U128, _) => narrow!(serialize_u128, u128),
+            (T::I8, _) => narrow!(serialize_i8, i8),
+            (T::I16, _) => narrow!(serialize_i16, i16),
+            (T::I32, _) => narrow!(serialize_i32, i32),
+            (T::I64 | T::Isize, _) => narrow!(serialize_i64, i64),
+            (T::I128, _) => narrow!(serialize_i128, i128),
+            (T::F32, V::Float(v)) => serializer.serialize_f32(*v as f32),
+            (T::F64, V::Float(v)) => serializer.serialize_f64(*v),
+            (T::Char, V::Char(v)) => serializer.serialize_char(*v),
+            (T::String, V::String(v)) => serializer.serialize_str(v),
+            (T::Ref(_), V::Ref(index)) => serializer.serialize_u64(*index),
+            (T::Option(_), V::Option(None)) => serializer.serialize_none(),
+            (T::Option(inner), V::Option(Some(value))) => {
+                serializer.serialize_some(&HirpdagDynValueTyped {
+                    schema: self.schema,
+                    ty: inner,
+                    value,
+                })
+            }
+            (T::Vec(inner), V::Seq(items)) => {
+                serializer.collect_seq(items.iter().map(|value| HirpdagDynValueTyped {
+                    schema: self.schema,
+                    ty: inner,
+                    value,
+                }))
+            }
+            (T::Enum(name), V::Variant { variant, value, .. }) => {
+                let Some(HirpdagSchemaKind::Enum(variants)) =
+                    self.schema.get(name).map(|t| &t.kind)
+                else {
+                    return Err(S::Error::custom(format!(
+                        "the schema has no enum type `{}`",
+                        name
+                    )));
+                };
+                let (index, declared) = variants
+                    .iter()
+                    .enumerate()
+                    .find(|(_, v)| &v.name == variant)
+                    .ok_or_else(|| {
+                        S::Error::custom(format!("enum {} has no variant `{}`", name, variant))
+                    })?;
+                hirpdag_dyn_serialize_variant(
+                    serializer,
+                    index,
+                    variant,
+                    &HirpdagDynValueTyped {
+                        schema: self.schema,
+                        ty: &declared.ty,
+                        value,
+                    },
+                )
+            }
+            (T::Opaque(name), _) => Err(S::Error::custom(format!(
+                "cannot encode a value of opaque type `{}`",
+                name
+            ))),
+            _ => Err(mismatch()),
+        }
+    }
+}
+
+// The tests build archives from JSON.
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    fn schema() -> HirpdagSchema {
+        HirpdagSchema {
+            types: vec![
+                HirpdagSchemaType {
+                    name: "Shape".to_string(),
+                    root: false,
+                    kind: HirpdagSchemaKind::Enum(vec![
+                        HirpdagSchemaVariant {
+                            name: "Leaf".to_string(),
+                            ty: HirpdagSchemaTy::I32,
+                        },
+                        HirpdagSchemaVariant {
+                            name: "Pair".to_string(),
+                            ty: HirpdagSchemaTy::Vec(Box::new(HirpdagSchemaTy::Ref(
+                                "Tree".to_string(),
+                            ))),
+                        },
+                    ]),
+                },
+                HirpdagSchemaType {
+                    name: "Tree".to_string(),
+                    root: true,
+                    kind: HirpdagSchemaKind::Struct(vec![
+                        HirpdagSchemaField {
+                            name: "shape".to_string(),
+                            ty: HirpdagSchemaTy::Enum("Shape".to_string()),
+                        },
+                        HirpdagSchemaField {
+                            name: "label".to_string(),
+                            ty: HirpdagSchemaTy::Option(Box::new(HirpdagSchemaTy::String)),
+                        },
+                    ]),
+                },
+            ],
+        }
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_dyn_archive_names_are_not_retained() {
+        // Names of any total length decode, however many schemas are seen.
+        let long = "x".repeat(1 << 21);
+        let mut schema = schema();
+        schema.types[1].name = long.clone();
+        let text = format!(
+            r#"{{"version":1,"nodes":[{{"{}":{{"shape":{{"Leaf":1}},"label":null}}}}],"roots":{{}}}}"#,
+            long
+        );
+        let archive = HirpdagDynArchive::from_json(&schema, &text).unwrap();
+        let payload = archive.to_postcard(&schema).unwrap();
+        let again =
+            HirpdagDynArchive::from_postcard(&schema, HirpdagRootLayout::Typed, &payload).unwrap();
+        assert_eq!(again, archive);
+        assert_eq!(again.nodes[0].type_name, long);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_dyn_archive_json_round_trip() {
+        let text = r#"{"version":1,"nodes":[
+            {"Tree":{"shape":{"Leaf":-3},"label":"a"}},
+            {"Tree":{"shape":{"Pair":[0,0]}}}
+        ],"roots":{"tree":[1]}}"#;
+        let archive = HirpdagDynArchive::from_json(&schema(), text).unwrap();
+        assert_eq!(archive.nodes.len(), 2);
+        assert_eq!(archive.nodes[1].children(), vec![0, 0]);
+        assert_eq!(
+            archive.nodes[1].field("label"),
+            Some(&HirpdagDynValue::Option(None))
+        );
+        assert_eq!(archive.roots, vec![("Tree".to_string(), vec![1])]);
+        let again = HirpdagDynArchive::from_json(&schema(), &archive.to_json()).unwrap();
+        assert_eq!(again, archive);
+        let payload = archive.to_postcard(&schema()).unwrap();
+        let again = HirpdagDynArchive::from_postcard(&schema(), HirpdagRootLayout::Typed, &payload)
+            .unwrap();
+        assert_eq!(again, archive);
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_dyn_archive_root_list() {
+        let text = r#"{"version":1,"nodes":[
+            {"Tree":{"shape":{"Leaf":1},"label":null}}
+        ],"root_list":[{"name":"main","node":0},{"name":null,"node":0}]}"#;
+        let archive = HirpdagDynArchive::from_json(&schema(), text).unwrap();
+        assert_eq!(archive.root_layout(), HirpdagRootLayout::List);
+        assert!(archive.roots.is_empty());
+        assert_eq!(archive.root_nodes().collect::<Vec<_>>(), vec![0, 0]);
+        let again = HirpdagDynArchive::from_json(&schema(), &archive.to_json()).unwrap();
+        assert_eq!(again, archive);
+        let payload = archive.to_postcard(&schema()).unwrap();
+        let again =
+            HirpdagDynArchive::from_postcard(&schema(), HirpdagRootLayout::List, &payload).unwrap();
+        assert_eq!(again, archive);
+
+        let both = r#"{"version":1,"nodes":[],"roots":{},"root_list":[]}"#;
+        assert!(HirpdagDynArchive::from_json(&schema(), both).is_err());
+    }
+
+    #[test]
+    fn test_dyn_archive_edits() {
+        let text =
+            r#"{"version":1,"nodes":[{"Tree":{"shape":{"Leaf":1},"label":null}}],"roots":{}}"#;
+        let mut archive = HirpdagDynArchive::from_json(&schema(), text).unwrap();
+        archive.rename_variant("Shape", "Leaf", "Tip");
+        archive.rename_field("Tree", "label", "name");
+        archive.add_field("Tree", "weight", HirpdagDynValue::Unsigned(1));
+        archive.rename_type("Tree", "Node");
+        assert_eq!(
+            archive.to_json(),
+            r#"{"version":1,"nodes":[{"Node":{"shape":{"Tip":1},"name":null,"weight":1}}],"roots":{"node":[]}}"#
+        );
+    }
+}
@@ -9,8 +9,18 @@
 //   a single forward pass reconstructs everything, forward references are
 //   errors, and cycles are unrepresentable.
 //
+// The binary format prefixes the archive with a header: the magic prefix,
+// the header version, the schema fingerprint, optionally the full schema,
+// for delta archives the id of their base archive, and flags selecting how
+// the payload is stored (see `frame`).
+//
 // This module holds the format-agnostic pieces: the collect traversal trait,
-// the error type, the format version marker, and the binary magic prefix.
+// the error type, the format version marker, and the binary magic prefix. It
+// also holds the `std::io` adapters used by the streaming entry points.
+
+use crate::base::delta::HirpdagArchiveBaseId;
+use crate::base::migrate::HirpdagMigrations;
+use crate::base::schema::HirpdagSchema;
 
 /// Magic prefix identifying a hirpdag binary archive.
 ///
@@ -38,6 +48,8 @@ pub enum HirpdagSerializeError {
     SessionActive,
     /// An underlying format error (postcard/serde_json).
     Format(String),
+    /// Writing to the output failed.
+    Io(String),
 }
 
 impl std::fmt::Display for HirpdagSerializeError {
@@ -48,6 +60,7 @@ impl std::fmt::Display for HirpdagSerializeError {
                 "hirpdag: a serialization session is already active on this thread"
             ),
             Self::Format(msg) => write!(f, "hirpdag: {}", msg),
+            Self::Io(msg) => write!(f, "hirpdag: I/O error: {}", msg),
         }
     }
 }
@@ -70,10 +83,115 @@ pub enum HirpdagDeserializeError {
     /// A deserialization session is already active on this thread.
     /// Sessions are per-thread and not re-entrant.
     SessionActive,
-    /// An underlying format error (postcard/serde_json), including
-    /// unsupported format versions, invalid node indices, node type
-    /// mismatches and truncated input.
+    /// Any other error of the underlying format (postcard/serde_json), such
+    /// as malformed values.
     Format(String),
+    /// The archive's header layout or format version is not one this
+    /// library reads. `at_node` as for `Truncated`.
+    UnsupportedVersion {
+        kind: HirpdagVersionKind,
+        found: u64,
+        at_node: Option<u64>,
+    },
+    /// A node reference is not the index of an already decoded node: it is
+    /// out of range or a forward reference. `at_node` is the index of the
+    /// node being decoded, `None` while decoding the roots.
+    InvalidNodeIndex { index: u64, at_node: Option<u64> },
+    /// A node reference is the index of a node of another type. `at_node` as
+    /// for `InvalidNodeIndex`.
+    NodeTypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+        at_node: Option<u64>,
+    },
+    /// The input ends inside the archive. `at_node` is the index of the node
+    /// being decoded, `None` outside the node table.
+    Truncated { at_node: Option<u64> },
+    /// The input continues after the archive. `at_node` as for
+    /// `Truncated`.
+    TrailingBytes { at_node: Option<u64> },
+    /// A partial read selected a root past the archive's roots of that type.
+    MissingRoot { root: &'static str, position: u64 },
+    /// A migration of an archive written by other type definitions failed.
+    Migration(String),
+    /// Reading from the input failed (other than by reaching its end, which
+    /// is reported like truncated input).
+    Io(String),
+    /// A delta archive was read without its base archive or against another
+    /// one, or a full archive was read as a delta. `None` stands for a full
+    /// archive.
+    BaseMismatch {
+        expected: Option<HirpdagArchiveBaseId>,
+        found: Option<HirpdagArchiveBaseId>,
+    },
+    /// The archive payload does not match its CRC32C checksum: the archive
+    /// is corrupt. Detected before any node is decoded.
+    ChecksumMismatch { expected: u32, found: u32 },
+    /// An archive with typed roots was read as a root list, or the other
+    /// way around.
+    RootLayoutMismatch {
+        expected: HirpdagRootLayout,
+        found: HirpdagRootLayout,
+    },
+    /// Side-tables were asked of an archive written without any.
+    MissingSideTables,
+    /// An archive written without an index was opened for lazy reading.
+    MissingIndex,
+    /// A node store does not hold the node with this stable hash.
+    MissingNode { hash: u64 },
+}
+
+/// Which version of an archive is unsupported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HirpdagVersionKind {
+    /// The binary header layout version (see [`HIRPDAG_HEADER_VERSION`]).
+    Header,
+    /// The archive format version (see [`HIRPDAG_FORMAT_VERSION`]).
+    Format,
+}
+
+/// How an archive holds its roots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HirpdagRootLayout {
+    /// The generated `HirpdagArchiveRoots`: one vector per root type.
+    Typed,
+    /// A [`HirpdagRootList`](crate::base::HirpdagRootList): roots of any type, optionally named.
+    List,
+}
+
+impl HirpdagRootLayout {
+    /// The binary header flags marking this layout.
+    pub fn flags(self) -> u32 {
+        match self {
+            Self::Typed => 0,
+            Self::List => crate::base::frame::HIRPDAG_FLAG_ROOT_LIST,
+        }
+    }
+}
+
+impl std::fmt::Display for HirpdagRootLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Typed => write!(f, "typed roots"),
+            Self::List => write!(f, "a root list"),
+        }
+    }
+}
+
+// " in node 3", locating an error in the node table, or nothing.
+fn hirpdag_node_site(at_node: &Option<u64>) -> String {
+    match at_node {
+        Some(node) => format!(" in node {}", node),
+        None => String::new(),
+    }
+}
+
+// " in node 3" / " in the roots", locating a reference error.
+fn hirpdag_reference_site(at_node: &Option<u64>) -> String {
+    match at_node {
+        Some(node) => format!(" in node {}", node),
+        None => " in the roots".to_string(),
+    }
 }
 
 impl std::fmt::Display for HirpdagDeserializeError {
@@ -96,12 +214,164 @@ impl std::fmt::Display for HirpdagDeserializeError {
                 "hirpdag: a deserialization session is already active on this thread"
             ),
             Self::Format(msg) => write!(f, "hirpdag: {}", msg),
+            Self::UnsupportedVersion {
+                kind: HirpdagVersionKind::Header,
+                found,
+                at_node,
+            } => write!(
+                f,
+                "hirpdag: unsupported header version {} (supported: 1 to {}){}",
+                found,
+                HIRPDAG_HEADER_VERSION,
+                hirpdag_node_site(at_node)
+            ),
+            Self::UnsupportedVersion {
+                kind: HirpdagVersionKind::Format,
+                found,
+                at_node,
+            } => write!(
+                f,
+                "hirpdag: unsupported format version {} (supported: {}){}",
+                found,
+                HIRPDAG_FORMAT_VERSION,
+                hirpdag_node_site(at_node)
+            ),
+            Self::InvalidNodeIndex { index, at_node } => write!(
+                f,
+                "hirpdag: node index {} is invalid (out of range or forward reference){}",
+                index,
+                hirpdag_reference_site(at_node)
+            ),
+            Self::NodeTypeMismatch {
+                expected,
+                found,
+                at_node,
+            } => write!(
+                f,
+                "hirpdag: node type mismatch: expected {}, found {}{}",
+                expected,
+                found,
+                hirpdag_reference_site(at_node)
+            ),
+            Self::Truncated {
+                at_node: Some(node),
+            } => {
+                write!(f, "hirpdag: the input ends inside node {}", node)
+            }
+            Self::Truncated { at_node: None } => {
+                write!(f, "hirpdag: the input ends inside the archive")
+            }
+            Self::TrailingBytes { at_node } => write!(
+                f,
+                "hirpdag: unexpected input after the archive{}",
+                hirpdag_node_site(at_node)
+            ),
+            Self::MissingRoot { root, position } => write!(
+                f,
+                "hirpdag: the archive has no {} root at position {}",
+                root, position
+            ),
+            Self::Migration(msg) => write!(f, "hirpdag: migration failed: {}", msg),
+            Self::Io(msg) => write!(f, "hirpdag: I/O error: {}", msg),
+            Self::BaseMismatch { expected, found } => match (expected, found) {
+                (Some(expected), Some(found)) => write!(
+                    f,
+                    "hirpdag: base mismatch: delta archive was written against base {} \
+                     but is being applied to base {}",
+                    found, expected
+                ),
+                (None, Some(found)) => write!(
+                    f,
+                    "hirpdag: archive is a delta against base {} and needs that base",
+                    found
+                ),
+                (Some(expected), None) => write!(
+                    f,
+                    "hirpdag: archive is not a delta (expected a delta against base {})",
+                    expected
+                ),
+                (None, None) => write!(f, "hirpdag: base mismatch"),
+            },
+            Self::ChecksumMismatch { expected, found } => write!(
+                f,
+                "hirpdag: checksum mismatch: the archive payload has CRC32C {:#010x}, \
+                 but its header records {:#010x}",
+                found, expected
+            ),
+            Self::RootLayoutMismatch { expected, found } => write!(
+                f,
+                "hirpdag: root layout mismatch: the archive holds {} but is being read as {}",
+                found, expected
+            ),
+            Self::MissingSideTables => write!(f, "hirpdag: the archive holds no side-tables"),
+            Self::MissingIndex => write!(
+                f,
+                "hirpdag: the archive has no index, which reading nodes lazily needs"
+            ),
+            Self::MissingNode { hash } => write!(
+                f,
+                "hirpdag: the node store holds no node with stable hash {:#018x}",
+                hash
+            ),
         }
     }
 }
 
 impl std::error::Error for HirpdagDeserializeError {}
 
+/// Options for the generated `hirpdag_serialize*_with_options` entry
+/// points.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HirpdagSerializeOptions {
+    /// Embed the module's full [`HirpdagSchema`] in the binary header, so
+    /// later versions of the types can migrate the archive (see
+    /// [`HirpdagMigrations`]) without the old types compiled in.
+    pub embed_schema: bool,
+    /// Append a CRC32C checksum of the payload, verified before decoding.
+    pub checksum: bool,
+    /// LZ4 compress the payload (needs the `lz4` feature).
+    pub compress: bool,
+    /// Write an index of the node table, so that the generated
+    /// `hirpdag_deserialize_partial` can read selected roots without
+    /// decoding the whole archive.
+    pub index: bool,
+    /// Sort each vector of typed roots by a hash of its content, so equal
+    /// roots give byte-identical archives whatever order they were built or
+    /// listed in. A root list keeps its order.
+    pub canonical: bool,
+}
+
+impl HirpdagSerializeOptions {
+    /// The binary header flags these options select.
+    pub fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.checksum {
+            flags |= crate::base::frame::HIRPDAG_FLAG_CRC32C;
+        }
+        if self.compress {
+            flags |= crate::base::frame::HIRPDAG_FLAG_LZ4;
+        }
+        if self.index {
+            flags |= crate::base::frame::HIRPDAG_FLAG_INDEX;
+        }
+        flags
+    }
+}
+
+/// Options for the generated `hirpdag_deserialize*_with_options` entry
+/// points.
+#[derive(Clone, Debug, Default)]
+pub struct HirpdagDeserializeOptions {
+    /// Rebuild every node through its type's `new()` instead of interning the
+    /// archived data as is. Use this for archives written under an older
+    /// normalizer. Children are renormalized before their parents, so
+    /// parents are rebuilt from renormalized children.
+    pub renormalize: bool,
+    /// Migrations applied to binary archives written by other versions of
+    /// the types. Such archives must embed their schema.
+    pub migrations: HirpdagMigrations,
+}
+
 /// Identifies the set of hirpdag type definitions that wrote a binary
 /// archive.
 ///
@@ -121,37 +391,204 @@ pub struct HirpdagSchemaFingerprint {
     pub name: String,
 }
 
-/// Writes the binary archive header: magic prefix, then the schema
-/// fingerprint. The archive payload is appended after this.
+/// Version of the binary header layout written by this library.
+///
+/// Version 1 headers hold only the fingerprint, directly after the magic
+/// prefix; they are still read. Later headers start with their version, a
+/// small varint, where version 1 starts with the fingerprint hash. Header
+/// versions stay below `2^16`, and a fingerprint hash below that is taken
+/// for a header version (a 1 in 2^48 chance).
+pub const HIRPDAG_HEADER_VERSION: u32 = 2;
+
+#[cfg(feature = "postcard")]
+const HIRPDAG_HEADER_VERSION_LIMIT: u64 = 1 << 16;
+
+/// The binary archive header, between the magic prefix and the payload.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HirpdagBinaryHeader {
+    pub fingerprint: HirpdagSchemaFingerprint,
+    /// The full description of the writer's types, if it embedded one.
+    pub schema: Option<HirpdagSchema>,
+    /// The base archive of a delta archive; `None` for a full archive.
+    #[serde(default)]
+    pub base: Option<HirpdagArchiveBaseId>,
+    /// How the payload is stored: `HIRPDAG_FLAG_*` bits.
+    #[serde(default)]
+    pub flags: u32,
+}
+
+impl HirpdagBinaryHeader {
+    pub fn new(fingerprint: HirpdagSchemaFingerprint) -> Self {
+        Self {
+            fingerprint,
+            schema: None,
+            base: None,
+            flags: 0,
+        }
+    }
+
+    /// How the archive holds its roots, as its flags record.
+    pub fn root_layout(&self) -> HirpdagRootLayout {
+        if self.flags & crate::base::frame::HIRPDAG_FLAG_ROOT_LIST != 0 {
+            HirpdagRootLayout::List
+        } else {
+            HirpdagRootLayout::Typed
+        }
+    }
+}
+
+/// Writes the binary archive header: magic prefix, header version, then the
+/// header. The archive payload is appended after this.
+#[cfg(feature = "postcard")]
 pub fn hirpdag_write_binary_header(
-    fingerprint: &HirpdagSchemaFingerprint,
+    header: &HirpdagBinaryHeader,
 ) -> Result<Vec<u8>, HirpdagSerializeError> {
     let mut bytes = Vec::new();
-    bytes.extend_from_slice(HIRPDAG_MAGIC);
-    let fp = postcard::to_stdvec(fingerprint)
-        .map_err(|e| HirpdagSerializeError::Format(e.to_string()))?;
-    bytes.extend_from_slice(&fp);
+    hirpdag_write_binary_header_to(&mut bytes, header)?;
     Ok(bytes)
 }
 
+/// Streaming variant of [`hirpdag_write_binary_header`].
+#[cfg(feature = "postcard")]
+pub fn hirpdag_write_binary_header_to<W: std::io::Write>(
+    mut writer: W,
+    header: &HirpdagBinaryHeader,
+) -> Result<(), HirpdagSerializeError> {
+    writer
+        .write_all(HIRPDAG_MAGIC)
+        .map_err(|e| HirpdagSerializeError::Io(e.to_string()))?;
+    hirpdag_postcard_to_writer(&HIRPDAG_HEADER_VERSION, &mut writer)?;
+    hirpdag_postcard_to_writer(header, writer)
+}
+
+/// Parses the binary archive header, without checking it against any
+/// schema, and returns it with the remaining archive payload.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_parse_binary_header(
+    bytes: &[u8],
+) -> Result<(HirpdagBinaryHeader, &[u8]), HirpdagDeserializeError> {
+    let (_, header, rest) = hirpdag_parse_versioned_header(bytes)?;
+    Ok((header, rest))
+}
+
+/// [`hirpdag_parse_binary_header`], also returning the header version.
+#[cfg(feature = "postcard")]
+pub(crate) fn hirpdag_parse_versioned_header(
+    bytes: &[u8],
+) -> Result<(u32, HirpdagBinaryHeader, &[u8]), HirpdagDeserializeError> {
+    let rest = hirpdag_strip_magic(bytes)?;
+    let (lead, rest): (u64, &[u8]) =
+        postcard::take_from_bytes(rest).map_err(hirpdag_postcard_de_error)?;
+    if lead >= HIRPDAG_HEADER_VERSION_LIMIT {
+        let (name, rest): (String, &[u8]) =
+            postcard::take_from_bytes(rest).map_err(hirpdag_postcard_de_error)?;
+        return Ok((1, hirpdag_header_v1(lead, name), rest));
+    }
+    hirpdag_check_header_version(lead)?;
+    let (header, rest) = postcard::take_from_bytes(rest).map_err(hirpdag_postcard_de_error)?;
+    Ok((lead as u32, header, rest))
+}
+
+/// Streaming variant of [`hirpdag_parse_binary_header`]: leaves `reader`
+/// positioned at the start of the archive payload.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_parse_binary_header_from<R: std::io::Read>(
+    reader: R,
+) -> Result<HirpdagBinaryHeader, HirpdagDeserializeError> {
+    Ok(hirpdag_parse_versioned_header_from(reader)?.1)
+}
+
+/// [`hirpdag_parse_binary_header_from`], also returning the header version.
+#[cfg(feature = "postcard")]
+pub(crate) fn hirpdag_parse_versioned_header_from<R: std::io::Read>(
+    mut reader: R,
+) -> Result<(u32, HirpdagBinaryHeader), HirpdagDeserializeError> {
+    let mut magic = [0u8; HIRPDAG_MAGIC.len()];
+    match reader.read_exact(&mut magic) {
+        Ok(()) if &magic == HIRPDAG_MAGIC => {}
+        Ok(()) => return Err(HirpdagDeserializeError::BadMagic),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Err(HirpdagDeserializeError::BadMagic)
+        }
+        Err(e) => return Err(HirpdagDeserializeError::Io(e.to_string())),
+    }
+    let lead: u64 = hirpdag_postcard_from_reader(&mut reader)?;
+    if lead >= HIRPDAG_HEADER_VERSION_LIMIT {
+        let name: String = hirpdag_postcard_from_reader(reader)?;
+        return Ok((1, hirpdag_header_v1(lead, name)));
+    }
+    hirpdag_check_header_version(lead)?;
+    let header = hirpdag_postcard_from_reader(reader)?;
+    Ok((lead as u32, header))
+}
+
+#[cfg(feature = "postcard")]
+fn hirpdag_header_v1(hash: u64, name: String) -> HirpdagBinaryHeader {
+    HirpdagBinaryHeader::new(HirpdagSchemaFingerprint { hash, name })
+}
+
+#[cfg(feature = "postcard")]
+fn hirpdag_check_header_version(version: u64) -> Result<(), HirpdagDeserializeError> {
+    if version == u64::from(HIRPDAG_HEADER_VERSION) {
+        return Ok(());
+    }
+    Err(HirpdagDeserializeError::UnsupportedVersion {
+        kind: HirpdagVersionKind::Header,
+        found: version,
+        at_node: hirpdag_de_node(),
+    })
+}
+
 /// Validates the binary archive header (magic prefix and schema fingerprint)
 /// and returns the remaining archive payload.
+#[cfg(feature = "postcard")]
 pub fn hirpdag_read_binary_header<'a>(
     bytes: &'a [u8],
     expected: &HirpdagSchemaFingerprint,
 ) -> Result<&'a [u8], HirpdagDeserializeError> {
-    let payload = hirpdag_strip_magic(bytes)?;
-    let (found, rest): (HirpdagSchemaFingerprint, &[u8]) = postcard::take_from_bytes(payload)
-        .map_err(|e| HirpdagDeserializeError::Format(e.to_string()))?;
+    let (header, rest) = hirpdag_parse_binary_header(bytes)?;
+    hirpdag_check_fingerprint(&header.fingerprint, expected)?;
+    Ok(rest)
+}
+
+/// Streaming variant of [`hirpdag_read_binary_header`]: validates the header
+/// and leaves `reader` positioned at the start of the archive payload.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_read_binary_header_from<R: std::io::Read>(
+    reader: R,
+    expected: &HirpdagSchemaFingerprint,
+) -> Result<(), HirpdagDeserializeError> {
+    let header = hirpdag_parse_binary_header_from(reader)?;
+    hirpdag_check_fingerprint(&header.fingerprint, expected)
+}
+
+/// Fails with `SchemaMismatch` unless `found` is the `expected` schema.
+pub fn hirpdag_check_fingerprint(
+    found: &HirpdagSchemaFingerprint,
+    expected: &HirpdagSchemaFingerprint,
+) -> Result<(), HirpdagDeserializeError> {
     if found.hash != expected.hash {
         return Err(HirpdagDeserializeError::SchemaMismatch {
             expected_hash: expected.hash,
             expected_name: expected.name.clone(),
             found_hash: found.hash,
-            found_name: found.name,
+            found_name: found.name.clone(),
         });
     }
-    Ok(rest)
+    Ok(())
+}
+
+/// Fails with `RootLayoutMismatch` unless the archive of `header` holds its
+/// roots as `expected`.
+pub fn hirpdag_check_root_layout(
+    header: &HirpdagBinaryHeader,
+    expected: HirpdagRootLayout,
+) -> Result<(), HirpdagDeserializeError> {
+    let found = header.root_layout();
+    if found != expected {
+        return Err(HirpdagDeserializeError::RootLayoutMismatch { expected, found });
+    }
+    Ok(())
 }
 
 /// Strips and validates the binary archive magic prefix.
@@ -179,15 +616,102 @@ impl<'de> serde::Deserialize<'de> for HirpdagFormatVersion {
     fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         let version = u32::deserialize(deserializer)?;
         if version != HIRPDAG_FORMAT_VERSION {
-            return Err(serde::de::Error::custom(format!(
-                "unsupported hirpdag format version {} (supported: {})",
-                version, HIRPDAG_FORMAT_VERSION
-            )));
+            return Err(hirpdag_de_raise(
+                HirpdagDeserializeError::UnsupportedVersion {
+                    kind: HirpdagVersionKind::Format,
+                    found: u64::from(version),
+                    at_node: hirpdag_de_node(),
+                },
+            ));
         }
         Ok(Self)
     }
 }
 
+// ==== Structured decoding errors
+//
+// serde's `Deserialize` impls can only fail with a message, which the format
+// wraps in its own error. The decoding of versions and node references
+// records the structured error it raises here, and the entry points report
+// it instead of the format's error. The index of the node being decoded is
+// tracked here too, to locate errors raised by the format itself.
+
+std::thread_local! {
+    static HIRPDAG_DE_ERROR: std::cell::RefCell<Option<HirpdagDeserializeError>> =
+        const { std::cell::RefCell::new(None) };
+    static HIRPDAG_DE_NODE: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+}
+
+/// Raises `error` from a serde `Deserialize` impl: records it for
+/// [`hirpdag_de_refine`], and returns a serde error with its message.
+#[doc(hidden)]
+pub fn hirpdag_de_raise<E: serde::de::Error>(error: HirpdagDeserializeError) -> E {
+    let message = error.to_string();
+    HIRPDAG_DE_ERROR.with(|slot| *slot.borrow_mut() = Some(error));
+    E::custom(message)
+}
+
+/// Sets the index of the node being decoded; `None` outside the node table.
+#[doc(hidden)]
+pub fn hirpdag_de_set_node(index: Option<u64>) {
+    HIRPDAG_DE_NODE.with(|node| node.set(index));
+}
+
+/// The index of the node being decoded.
+#[doc(hidden)]
+pub fn hirpdag_de_node() -> Option<u64> {
+    HIRPDAG_DE_NODE.with(|node| node.get())
+}
+
+/// Clears the recorded decoding state, before and after decoding an archive.
+#[doc(hidden)]
+pub fn hirpdag_de_reset() {
+    HIRPDAG_DE_ERROR.with(|slot| *slot.borrow_mut() = None);
+    hirpdag_de_set_node(None);
+}
+
+/// The error to report for `error`, raised while decoding an archive: the
+/// structured error recorded by [`hirpdag_de_raise`] if any, else `error`,
+/// locating truncation at the node being decoded.
+#[doc(hidden)]
+pub fn hirpdag_de_refine(error: HirpdagDeserializeError) -> HirpdagDeserializeError {
+    if let Some(recorded) = HIRPDAG_DE_ERROR.with(|slot| slot.borrow_mut().take()) {
+        return recorded;
+    }
+    match error {
+        HirpdagDeserializeError::Truncated { at_node: None } => {
+            HirpdagDeserializeError::Truncated {
+                at_node: hirpdag_de_node(),
+            }
+        }
+        other => other,
+    }
+}
+
+/// Converts a postcard decoding error: running out of input is `Truncated`.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_postcard_de_error(error: postcard::Error) -> HirpdagDeserializeError {
+    match error {
+        postcard::Error::DeserializeUnexpectedEnd => {
+            HirpdagDeserializeError::Truncated { at_node: None }
+        }
+        other => HirpdagDeserializeError::Format(other.to_string()),
+    }
+}
+
+/// Converts a serde_json decoding error: running out of input is
+/// `Truncated`, and failing to read it `Io`.
+#[cfg(feature = "json")]
+pub fn hirpdag_json_de_error(error: serde_json::Error) -> HirpdagDeserializeError {
+    if error.is_io() {
+        HirpdagDeserializeError::Io(error.to_string())
+    } else if error.is_eof() {
+        HirpdagDeserializeError::Truncated { at_node: None }
+    } else {
+        HirpdagDeserializeError::Format(error.to_string())
+    }
+}
+
 /// Traversal trait used by the serialization collect phase to register every
 /// unique node reachable from the roots, children first (post-order DFS).
 ///
@@ -207,6 +731,30 @@ impl<C> HirpdagCollect<C> for String {
     fn hirpdag_collect(&self, _ctx: &mut C) {}
 }
 
+impl<C> HirpdagCollect<C> for bool {
+    fn hirpdag_collect(&self, _ctx: &mut C) {}
+}
+
+// Tuples collect their elements in order, so that several side-tables can
+// be written as one.
+macro_rules! hirpdag_collect_tuple {
+    ($($name:ident),*) => {
+        impl<C, $($name: HirpdagCollect<C>),*> HirpdagCollect<C> for ($($name,)*) {
+            #[allow(non_snake_case, unused_variables)]
+            fn hirpdag_collect(&self, ctx: &mut C) {
+                let ($($name,)*) = self;
+                $($name.hirpdag_collect(ctx);)*
+            }
+        }
+    };
+}
+
+hirpdag_collect_tuple!();
+hirpdag_collect_tuple!(T0);
+hirpdag_collect_tuple!(T0, T1);
+hirpdag_collect_tuple!(T0, T1, T2);
+hirpdag_collect_tuple!(T0, T1, T2, T3);
+
 impl<C, T: HirpdagCollect<C>> HirpdagCollect<C> for Option<T> {
     fn hirpdag_collect(&self, ctx: &mut C) {
         if let Some(inner) = self {
@@ -222,3 +770,152 @@ impl<C, T: HirpdagCollect<C>> HirpdagCollect<C> for Vec<T> {
         }
     }
 }
+
+// ==== Streaming postcard adapters
+//
+// postcard's own `std::io` flavors report every I/O failure as a generic
+// postcard error, and its reader needs a scratch buffer large enough for
+// everything it decodes. These adapters keep the I/O error, and decode owned
+// data through a scratch buffer sized for the largest single value.
+
+#[cfg(feature = "postcard")]
+struct HirpdagIoWriter<W> {
+    writer: W,
+    error: Option<std::io::Error>,
+}
+
+#[cfg(feature = "postcard")]
+impl<W: std::io::Write> postcard::ser_flavors::Flavor for HirpdagIoWriter<W> {
+    type Output = ();
+
+    fn try_extend(&mut self, data: &[u8]) -> postcard::Result<()> {
+        self.writer.write_all(data).map_err(|e| {
+            self.error = Some(e);
+            postcard::Error::SerializeBufferFull
+        })
+    }
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.try_extend(&[data])
+    }
+
+    fn finalize(self) -> postcard::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serializes `value` with postcard straight into `writer`.
+///
+/// Writes are issued per encoded value, so `writer` should be buffered (e.g.
+/// a `std::io::BufWriter`).
+#[cfg(feature = "postcard")]
+pub fn hirpdag_postcard_to_writer<T, W>(value: &T, writer: W) -> Result<(), HirpdagSerializeError>
+where
+    T: serde::Serialize + ?Sized,
+    W: std::io::Write,
+{
+    let mut serializer = postcard::Serializer {
+        output: HirpdagIoWriter {
+            writer,
+            error: None,
+        },
+    };
+    value
+        .serialize(&mut serializer)
+        .map_err(|e| match serializer.output.error.take() {
+            Some(io) => HirpdagSerializeError::Io(io.to_string()),
+            None => HirpdagSerializeError::Format(e.to_string()),
+        })
+}
+
+#[cfg(feature = "postcard")]
+struct HirpdagIoReader<'r, R> {
+    reader: R,
+    scratch: Vec<u8>,
+    error: &'r mut Option<std::io::Error>,
+}
+
+#[cfg(feature = "postcard")]
+impl<R: std::io::Read> HirpdagIoReader<'_, R> {
+    fn fill(&mut self, ct: usize) -> postcard::Result<()> {
+        use std::io::Read;
+        // Grown as bytes arrive, so a corrupt length cannot allocate up front.
+        self.scratch.clear();
+        let read = (&mut self.reader)
+            .take(ct as u64)
+            .read_to_end(&mut self.scratch)
+            .map_err(|e| {
+                *self.error = Some(e);
+                postcard::Error::DeserializeUnexpectedEnd
+            })?;
+        if read != ct {
+            return Err(postcard::Error::DeserializeUnexpectedEnd);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl<'de, R: std::io::Read + 'de> postcard::de_flavors::Flavor<'de> for HirpdagIoReader<'de, R> {
+    type Remainder = ();
+    type Source = R;
+
+    fn pop(&mut self) -> postcard::Result<u8> {
+        self.fill(1)?;
+        Ok(self.scratch[0])
+    }
+
+    fn try_take_n(&mut self, _ct: usize) -> postcard::Result<&'de [u8]> {
+        // Borrowed data would have to outlive the whole stream.
+        Err(postcard::Error::SerdeDeCustom)
+    }
+
+    fn try_take_n_temp<'a>(&'a mut self, ct: usize) -> postcard::Result<&'a [u8]>
+    where
+        'de: 'a,
+    {
+        self.fill(ct)?;
+        Ok(&self.scratch)
+    }
+
+    fn finalize(self) -> postcard::Result<()> {
+        Ok(())
+    }
+}
+
+/// Deserializes one postcard-encoded value from `reader`, reading exactly its
+/// encoded bytes (so further values can be read after it).
+///
+/// Reads are issued per encoded value, so `reader` should be buffered (e.g. a
+/// `std::io::BufReader`). Values borrowing from the input (`&str`, `&[u8]`)
+/// are not supported.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_postcard_from_reader<T, R>(reader: R) -> Result<T, HirpdagDeserializeError>
+where
+    T: serde::de::DeserializeOwned,
+    R: std::io::Read,
+{
+    hirpdag_postcard_seed_from_reader(std::marker::PhantomData::<T>, reader)
+}
+
+/// [`hirpdag_postcard_from_reader`] for a `DeserializeSeed`.
+#[cfg(feature = "postcard")]
+pub fn hirpdag_postcard_seed_from_reader<S, R, V>(
+    seed: S,
+    reader: R,
+) -> Result<V, HirpdagDeserializeError>
+where
+    S: for<'de> serde::de::DeserializeSeed<'de, Value = V>,
+    R: std::io::Read,
+{
+    let mut error = None;
+    let result = seed.deserialize(&mut postcard::Deserializer::from_flavor(HirpdagIoReader {
+        reader,
+        scratch: Vec::new(),
+        error: &mut error,
+    }));
+    result.map_err(|e| match error {
+        Some(io) => HirpdagDeserializeError::Io(io.to_string()),
+        None => hirpdag_postcard_de_error(e),
+    })
+}
@@ -0,0 +1,579 @@
+// ==== S-expression Text Format
+//
+// A hand-writable text form of nodes, for test fixtures and golden outputs:
+//
+//     (let [($1 (Expr (Num 1)))]
+//       (Expr (Add [$1 (Expr (Var "x")) $1])))
+//
+// A struct node is `(Type field ..)`, with its fields in declaration order;
+// an enum value is `(Variant value)`; a vector is `[item ..]`; an option is
+// `None` or `(Some value)`; strings, numbers and booleans are written as in
+// Rust. `(let [($name value) ..] body)` binds nodes to names, which later
+// bindings and the body refer to as `$name`. `;` starts a comment.
+//
+// Reading builds nodes through their types' `new`, so normalizers apply.
+// Writing binds every node reached more than once, children first, so
+// sharing survives a round trip.
+
+use crate::base::basic_traits::IsNumber;
+use std::collections::HashMap;
+
+/// A syntax or type error in S-expression text, at a 1-based line and
+/// column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HirpdagSexprError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for HirpdagSexprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hirpdag: S-expression error at {}:{}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for HirpdagSexprError {}
+
+/// A parsed S-expression, before it is read as a type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HirpdagSexprKind {
+    /// A symbol, number or boolean.
+    Atom(String),
+    Str(String),
+    /// `( .. )`
+    List(Vec<HirpdagSexpr>),
+    /// `[ .. ]`
+    Vector(Vec<HirpdagSexpr>),
+}
+
+/// A parsed S-expression and where it starts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HirpdagSexpr {
+    pub kind: HirpdagSexprKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl HirpdagSexpr {
+    /// An error at this expression.
+    pub fn error(&self, message: impl Into<String>) -> HirpdagSexprError {
+        HirpdagSexprError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    /// The head symbol and arguments of a list `(head arg ..)`.
+    pub fn head(&self) -> Result<(&str, &[HirpdagSexpr]), HirpdagSexprError> {
+        if let HirpdagSexprKind::List(items) = &self.kind {
+            if let Some(HirpdagSexpr {
+                kind: HirpdagSexprKind::Atom(head),
+                ..
+            }) = items.first()
+            {
+                return Ok((head, &items[1..]));
+            }
+        }
+        Err(self.error("expected a list starting with a name"))
+    }
+
+    /// The arguments of `(head arg ..)`, which must have `arity` of them.
+    pub fn form(&self, head: &str, arity: usize) -> Result<&[HirpdagSexpr], HirpdagSexprError> {
+        let (found, args) = self.head()?;
+        if found != head {
+            return Err(self.error(format!("expected `{}`, found `{}`", head, found)));
+        }
+        if args.len() != arity {
+            return Err(self.error(format!(
+                "`{}` takes {} values, found {}",
+                head,
+                arity,
+                args.len()
+            )));
+        }
+        Ok(args)
+    }
+}
+
+/// Parses `text` as a single S-expression.
+pub fn hirpdag_parse_sexpr(text: &str) -> Result<HirpdagSexpr, HirpdagSexprError> {
+    let mut parser = HirpdagSexprParser {
+        chars: text.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let sexpr = parser.parse()?;
+    parser.skip_space();
+    if parser.chars.peek().is_some() {
+        return Err(parser.error("expected the end of the text"));
+    }
+    Ok(sexpr)
+}
+
+struct HirpdagSexprParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl HirpdagSexprParser<'_> {
+    fn error(&self, message: &str) -> HirpdagSexprError {
+        HirpdagSexprError {
+            line: self.line,
+            column: self.column,
+            message: message.to_string(),
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_space(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == ';' {
+                while self.next().is_some_and(|c| c != '\n') {}
+            } else if c.is_whitespace() {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn parse(&mut self) -> Result<HirpdagSexpr, HirpdagSexprError> {
+        self.skip_space();
+        let (line, column) = (self.line, self.column);
+        let kind = match self.chars.peek() {
+            None => return Err(self.error("unexpected end of text")),
+            Some('(') => HirpdagSexprKind::List(self.parse_items(')')?),
+            Some('[') => HirpdagSexprKind::Vector(self.parse_items(']')?),
+            Some(')') | Some(']') => return Err(self.error("unexpected closing bracket")),
+            Some('"') => HirpdagSexprKind::Str(self.parse_string()?),
+            Some(_) => {
+                let mut atom = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_whitespace() || "()[]\";".contains(c) {
+                        break;
+                    }
+                    atom.push(c);
+                    self.next();
+                }
+                HirpdagSexprKind::Atom(atom)
+            }
+        };
+        Ok(HirpdagSexpr { kind, line, column })
+    }
+
+    /// Parses the items of a list or vector, up to `close`.
+    fn parse_items(&mut self, close: char) -> Result<Vec<HirpdagSexpr>, HirpdagSexprError> {
+        self.next();
+        let mut items = Vec::new();
+        loop {
+            self.skip_space();
+            match self.chars.peek() {
+                Some(&c) if c == close => {
+                    self.next();
+                    return Ok(items);
+                }
+                None => return Err(self.error(&format!("expected `{}`", close))),
+                Some(_) => items.push(self.parse()?),
+            }
+        }
+    }
+
+    /// Parses a string with Rust's escapes.
+    fn parse_string(&mut self) -> Result<String, HirpdagSexprError> {
+        self.next();
+        let mut out = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(out),
+                Some('\\') => out.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('\'') => '\'',
+                    Some('u') => self.parse_unicode_escape()?,
+                    _ => return Err(self.error("unknown string escape")),
+                }),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    /// Parses the `{XXXX}` of a `\u{XXXX}` escape.
+    fn parse_unicode_escape(&mut self) -> Result<char, HirpdagSexprError> {
+        let invalid = |parser: &Self| parser.error("invalid unicode escape");
+        if self.next() != Some('{') {
+            return Err(invalid(self));
+        }
+        let mut digits = String::new();
+        loop {
+            match self.next() {
+                Some('}') => break,
+                Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+                _ => return Err(invalid(self)),
+            }
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| invalid(self))
+    }
+}
+
+// ==== Reading
+
+/// Implemented by every field type, and the generated types, to be read
+/// from an S-expression. `N` is the module's `HirpdagNodeRef`.
+pub trait HirpdagSexprRead<N>: Sized {
+    fn hirpdag_sexpr_read(
+        sexpr: &HirpdagSexpr,
+        env: &mut HirpdagSexprEnv<N>,
+    ) -> Result<Self, HirpdagSexprError>;
+}
+
+/// The nodes bound by the enclosing `let`s.
+pub struct HirpdagSexprEnv<N> {
+    bindings: HashMap<String, N>,
+}
+
+impl<N> Default for HirpdagSexprEnv<N> {
+    fn default() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+}
+
+impl<N: Clone + HirpdagSexprRead<N>> HirpdagSexprEnv<N> {
+    /// Reads `sexpr` as `T`, within the bindings of the `let`s around it.
+    pub fn read_let<T>(
+        &mut self,
+        sexpr: &HirpdagSexpr,
+        read: impl FnOnce(&HirpdagSexpr, &mut Self) -> Result<T, HirpdagSexprError>,
+    ) -> Result<T, HirpdagSexprError> {
+        let args = match sexpr.head() {
+            Ok(("let", args)) => args,
+            _ => return read(sexpr, self),
+        };
+        let (bindings, body) = match args {
+            [bindings, body] => (bindings, body),
+            _ => return Err(sexpr.error("expected `(let [($name value) ..] body)`")),
+        };
+        let bindings = match &bindings.kind {
+            HirpdagSexprKind::Vector(bindings) => bindings,
+            _ => return Err(bindings.error("expected a vector of bindings")),
+        };
+        let outer = self.bindings.clone();
+        for binding in bindings {
+            let (name, value) = match &binding.kind {
+                HirpdagSexprKind::List(items) => match &items[..] {
+                    [HirpdagSexpr {
+                        kind: HirpdagSexprKind::Atom(name),
+                        ..
+                    }, value]
+                        if name.starts_with('$') =>
+                    {
+                        (name, value)
+                    }
+                    _ => return Err(binding.error("expected a binding `($name value)`")),
+                },
+                _ => return Err(binding.error("expected a binding `($name value)`")),
+            };
+            let node = N::hirpdag_sexpr_read(value, self)?;
+            self.bindings.insert(name.clone(), node);
+        }
+        let result = self.read_let(body, read);
+        self.bindings = outer;
+        result
+    }
+
+    /// Reads `sexpr` as a node: a bound `$name`, a `let`, or else whatever
+    /// `read` reads.
+    pub fn read_node(
+        &mut self,
+        sexpr: &HirpdagSexpr,
+        read: impl FnOnce(&HirpdagSexpr, &mut Self) -> Result<N, HirpdagSexprError>,
+    ) -> Result<N, HirpdagSexprError> {
+        self.read_let(sexpr, |sexpr, env| match &sexpr.kind {
+            HirpdagSexprKind::Atom(name) if name.starts_with('$') => env
+                .bindings
+                .get(name)
+                .cloned()
+                .ok_or_else(|| sexpr.error(format!("`{}` is not bound", name))),
+            _ => read(sexpr, env),
+        })
+    }
+}
+
+/// Reads the whole of `text` as a `T`.
+pub fn hirpdag_sexpr_read_str<N, T>(text: &str) -> Result<T, HirpdagSexprError>
+where
+    N: Clone + HirpdagSexprRead<N>,
+    T: HirpdagSexprRead<N>,
+{
+    let sexpr = hirpdag_parse_sexpr(text)?;
+    let mut env = HirpdagSexprEnv::default();
+    env.read_let(&sexpr, T::hirpdag_sexpr_read)
+}
+
+impl<N, P: IsNumber + std::str::FromStr> HirpdagSexprRead<N> for P {
+    fn hirpdag_sexpr_read(
+        sexpr: &HirpdagSexpr,
+        _env: &mut HirpdagSexprEnv<N>,
+    ) -> Result<Self, HirpdagSexprError> {
+        match &sexpr.kind {
+            HirpdagSexprKind::Atom(atom) => atom.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| sexpr.error(format!("expected {}", std::any::type_name::<P>())))
+    }
+}
+
+impl<N> HirpdagSexprRead<N> for String {
+    fn hirpdag_sexpr_read(
+        sexpr: &HirpdagSexpr,
+        _env: &mut HirpdagSexprEnv<N>,
+    ) -> Result<Self, HirpdagSexprError> {
+        match &sexpr.kind {
+            HirpdagSexprKind::Str(s) => Ok(s.clone()),
+            _ => Err(sexpr.error("expected a string")),
+        }
+    }
+}
+
+impl<N> HirpdagSexprRead<N> for bool {
+    fn hirpdag_sexpr_read(
+        sexpr: &HirpdagSexpr,
+        _env: &mut HirpdagSexprEnv<N>,
+    ) -> Result<Self, HirpdagSexprError> {
+        match &sexpr.kind {
+            HirpdagSexprKind::Atom(atom) if atom == "true" => Ok(true),
+            HirpdagSexprKind::Atom(atom) if atom == "false" => Ok(false),
+            _ => Err(sexpr.error("expected `true` or `false`")),
+        }
+    }
+}
+
+impl<N, T: HirpdagSexprRead<N>> HirpdagSexprRead<N> for Option<T> {
+    fn hirpdag_sexpr_read(
+        sexpr: &HirpdagSexpr,
+        env: &mut HirpdagSexprEnv<N>,
+    ) -> Result<Self, HirpdagSexprError> {
+        if let HirpdagSexprKind::Atom(atom) = &sexpr.kind {
+            if atom == "None" {
+                return Ok(None);
+            }
+        }
+        let args = sexpr.form("Some", 1)?;
+        Ok(Some(T::hirpdag_sexpr_read(&args[0], env)?))
+    }
+}
+
+impl<N, T: HirpdagSexprRead<N>> HirpdagSexprRead<N> for Vec<T> {
+    fn hirpdag_sexpr_read(
+        sexpr: &HirpdagSexpr,
+        env: &mut HirpdagSexprEnv<N>,
+    ) -> Result<Self, HirpdagSexprError> {
+        match &sexpr.kind {
+            HirpdagSexprKind::Vector(items) => items
+                .iter()
+                .map(|item| T::hirpdag_sexpr_read(item, env))
+                .collect(),
+            _ => Err(sexpr.error("expected a vector")),
+        }
+    }
+}
+
+// ==== Writing
+
+/// Implemented by every field type, and the generated types, to be written
+/// as an S-expression.
+pub trait HirpdagSexprWrite<N> {
+    fn hirpdag_sexpr_write(&self, w: &mut HirpdagSexprWriter<N>);
+}
+
+/// Writes S-expressions, binding nodes reached more than once.
+pub struct HirpdagSexprWriter<N> {
+    out: String,
+    counting: bool,
+    uses: HashMap<N, usize>,
+    names: HashMap<N, usize>,
+    bindings: Vec<String>,
+}
+
+impl<N: Clone + Eq + std::hash::Hash> HirpdagSexprWriter<N> {
+    /// Writes `text` as it is.
+    pub fn text(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    /// Writes `(head arg ..)`.
+    pub fn form(&mut self, head: &str, args: &[&dyn HirpdagSexprWrite<N>]) {
+        self.out.push('(');
+        self.out.push_str(head);
+        for arg in args {
+            self.out.push(' ');
+            arg.hirpdag_sexpr_write(self);
+        }
+        self.out.push(')');
+    }
+
+    /// Writes `[item ..]`.
+    pub fn seq<T: HirpdagSexprWrite<N>>(&mut self, items: &[T]) {
+        self.out.push('[');
+        for (i, item) in items.iter().enumerate() {
+            if i != 0 {
+                self.out.push(' ');
+            }
+            item.hirpdag_sexpr_write(self);
+        }
+        self.out.push(']');
+    }
+
+    /// Writes `node`, whose data `data` writes: in place, or as `$name` if
+    /// it is shared, with its data in a binding.
+    pub fn node(&mut self, node: N, data: impl FnOnce(&mut Self)) {
+        if self.counting {
+            let uses = self.uses.entry(node).or_insert(0);
+            *uses += 1;
+            if *uses == 1 {
+                data(self);
+            }
+            return;
+        }
+        if self.uses.get(&node).is_some_and(|uses| *uses > 1) {
+            if !self.names.contains_key(&node) {
+                // Children are bound while writing the data, before it.
+                let outer = std::mem::take(&mut self.out);
+                data(self);
+                let value = std::mem::replace(&mut self.out, outer);
+                let name = self.bindings.len() + 1;
+                self.bindings.push(format!("(${} {})", name, value));
+                self.names.insert(node.clone(), name);
+            }
+            let name = self.names[&node];
+            self.out.push_str(&format!("${}", name));
+        } else {
+            data(self);
+        }
+    }
+}
+
+/// `value` as S-expression text, with a `let` binding each node reached
+/// more than once.
+pub fn hirpdag_sexpr_write_string<N, T>(value: &T) -> String
+where
+    N: Clone + Eq + std::hash::Hash,
+    T: HirpdagSexprWrite<N> + ?Sized,
+{
+    let mut w = HirpdagSexprWriter {
+        out: String::new(),
+        counting: true,
+        uses: HashMap::new(),
+        names: HashMap::new(),
+        bindings: Vec::new(),
+    };
+    value.hirpdag_sexpr_write(&mut w);
+    w.out.clear();
+    w.counting = false;
+    value.hirpdag_sexpr_write(&mut w);
+    if w.bindings.is_empty() {
+        return w.out;
+    }
+    format!("(let [\n  {}\n]\n  {})", w.bindings.join("\n  "), w.out)
+}
+
+impl<N, P: IsNumber + std::fmt::Debug> HirpdagSexprWrite<N> for P {
+    fn hirpdag_sexpr_write(&self, w: &mut HirpdagSexprWriter<N>) {
+        w.out.push_str(&format!("{:?}", self));
+    }
+}
+
+impl<N> HirpdagSexprWrite<N> for String {
+    fn hirpdag_sexpr_write(&self, w: &mut HirpdagSexprWriter<N>) {
+        w.out.push_str(&format!("{:?}", self));
+    }
+}
+
+impl<N> HirpdagSexprWrite<N> for bool {
+    fn hirpdag_sexpr_write(&self, w: &mut HirpdagSexprWriter<N>) {
+        w.out.push_str(if *self { "true" } else { "false" });
+    }
+}
+
+impl<N: Clone + Eq + std::hash::Hash, T: HirpdagSexprWrite<N>> HirpdagSexprWrite<N> for Option<T> {
+    fn hirpdag_sexpr_write(&self, w: &mut HirpdagSexprWriter<N>) {
+        match self {
+            None => w.text("None"),
+            Some(value) => w.form("Some", &[value]),
+        }
+    }
+}
+
+impl<N: Clone + Eq + std::hash::Hash, T: HirpdagSexprWrite<N>> HirpdagSexprWrite<N> for Vec<T> {
+    fn hirpdag_sexpr_write(&self, w: &mut HirpdagSexprWriter<N>) {
+        w.seq(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sexpr() {
+        let sexpr = hirpdag_parse_sexpr("(a [1 \"x\\u{41}\\n\"] ; note\n  b)").unwrap();
+        let atom = |s: &str, line, column| HirpdagSexpr {
+            kind: HirpdagSexprKind::Atom(s.to_string()),
+            line,
+            column,
+        };
+        assert_eq!(
+            sexpr.kind,
+            HirpdagSexprKind::List(vec![
+                atom("a", 1, 2),
+                HirpdagSexpr {
+                    kind: HirpdagSexprKind::Vector(vec![
+                        atom("1", 1, 5),
+                        HirpdagSexpr {
+                            kind: HirpdagSexprKind::Str("xA\n".to_string()),
+                            line: 1,
+                            column: 7,
+                        },
+                    ]),
+                    line: 1,
+                    column: 4,
+                },
+                atom("b", 2, 3),
+            ])
+        );
+
+        let error = |text| hirpdag_parse_sexpr(text).unwrap_err();
+        assert_eq!(error("(a\n  [b)").message, "unexpected closing bracket");
+        assert_eq!((error("(a\n  [b)").line, error("(a\n  [b)").column), (2, 5));
+        assert_eq!(error("a b").message, "expected the end of the text");
+        assert_eq!(error("\"\\q\"").message, "unknown string escape");
+        assert_eq!(error("(a").message, "expected `)`");
+    }
+}
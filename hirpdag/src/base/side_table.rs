@@ -0,0 +1,162 @@
+// ==== Side-Tables
+//
+// Analyses keep results beside the DAG, in maps keyed by node reference
+// (see the techniques chapter). A `HirpdagSideTable` is such a map that can
+// be written alongside an archive: within a (de)serialization session its
+// keys encode as node table indices, like any other node reference, so the
+// table round-trips with the nodes it describes.
+//
+// A table serializes as a sequence of `[key, value]` pairs ordered by key,
+// which for hirpdag references is creation order, so equal tables encode to
+// equal bytes. Binary archives carry side-tables in a payload section marked
+// by `HIRPDAG_FLAG_SIDE_TABLES`; JSON archives under a `side_tables` key.
+
+use crate::base::serialize::HirpdagCollect;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A map from nodes to values, serializable alongside an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HirpdagSideTable<K: Hash + Eq, V> {
+    map: HashMap<K, V>,
+}
+
+impl<K: Hash + Eq, V> Default for HirpdagSideTable<K, V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> HirpdagSideTable<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of `key`, returning its previous value.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.map.insert(key, value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.map.get_mut(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The entries, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter()
+    }
+
+    pub fn into_map(self) -> HashMap<K, V> {
+        self.map
+    }
+}
+
+impl<K: Hash + Eq, V> From<HashMap<K, V>> for HirpdagSideTable<K, V> {
+    fn from(map: HashMap<K, V>) -> Self {
+        Self { map }
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<(K, V)> for HirpdagSideTable<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self {
+            map: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Collects the keys and the values, in key order.
+impl<C, K, V> HirpdagCollect<C> for HirpdagSideTable<K, V>
+where
+    K: Hash + Eq + Ord + HirpdagCollect<C>,
+    V: HirpdagCollect<C>,
+{
+    fn hirpdag_collect(&self, ctx: &mut C) {
+        for (key, value) in self.sorted() {
+            key.hirpdag_collect(ctx);
+            value.hirpdag_collect(ctx);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Ord, V> HirpdagSideTable<K, V> {
+    // The entries, ordered by key.
+    fn sorted(&self) -> Vec<(&K, &V)> {
+        let mut entries: Vec<_> = self.map.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+}
+
+impl<K, V> serde::Serialize for HirpdagSideTable<K, V>
+where
+    K: Hash + Eq + Ord + serde::Serialize,
+    V: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.sorted())
+    }
+}
+
+/// Reads the pairs back into a map. Keys merged by re-normalization on load
+/// keep the value of their last entry.
+impl<'de, K, V> serde::Deserialize<'de> for HirpdagSideTable<K, V>
+where
+    K: Hash + Eq + serde::Deserialize<'de>,
+    V: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<(K, V)>::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_table() {
+        let mut table: HirpdagSideTable<u64, String> = HirpdagSideTable::new();
+        table.insert(7, "seven".to_string());
+        table.insert(2, "two".to_string());
+        assert_eq!(
+            table.insert(7, "sept".to_string()),
+            Some("seven".to_string())
+        );
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&2).map(String::as_str), Some("two"));
+        assert!(!table.contains_key(&3));
+        #[cfg(feature = "json")]
+        {
+            let text = serde_json::to_string(&table).unwrap();
+            assert_eq!(text, r#"[[2,"two"],[7,"sept"]]"#);
+            let read: HirpdagSideTable<u64, String> = serde_json::from_str(&text).unwrap();
+            assert_eq!(read, table);
+        }
+        assert_eq!(table.remove(&2), Some("two".to_string()));
+        assert_eq!(table.into_map().len(), 1);
+    }
+}
@@ -0,0 +1,165 @@
+// ==== Stable Hashes
+//
+// `HirpdagRef`'s `Hash` uses the creation id, which differs between runs.
+// A stable hash identifies a node by content instead: a Merkle hash of its
+// type name, its field data, and its children's stable hashes, so equal
+// subgraphs hash equally in any process. It suits on-disk cache keys and
+// comparing DAGs across processes.
+//
+// With the `stable-hash` feature, every node's stable hash is computed once
+// at intern time and stored beside its metadata. The algorithm is fixed
+// (FNV-1a over a little-endian encoding, with lengths and tags so distinct
+// values never encode alike), so hashes may be persisted. Renaming a type or
+// an enum variant changes the hashes of its nodes and their ancestors.
+
+/// An FNV-1a 64-bit hasher, stable across Rust releases and platforms
+/// (unlike std's DefaultHasher).
+#[derive(Clone, Debug)]
+pub struct HirpdagStableHasher {
+    hash: u64,
+}
+
+impl Default for HirpdagStableHasher {
+    fn default() -> Self {
+        Self {
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl HirpdagStableHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= u64::from(*byte);
+            self.hash = self.hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    /// Writes the length, then the bytes, of `value`.
+    pub fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write(value.as_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// Implemented by every field type to feed its value to a node's stable
+/// hash. Child references contribute their own stored stable hash, so
+/// hashing a node is O(1) in the size of its subgraph.
+pub trait HirpdagStableHash {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher);
+}
+
+impl HirpdagStableHash for String {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        hasher.write_str(self);
+    }
+}
+
+impl HirpdagStableHash for &str {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        hasher.write_str(self);
+    }
+}
+
+impl<T: HirpdagStableHash> HirpdagStableHash for Option<T> {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        match self {
+            None => hasher.write(&[0]),
+            Some(value) => {
+                hasher.write(&[1]);
+                value.hirpdag_stable_hash_into(hasher);
+            }
+        }
+    }
+}
+
+impl<T: HirpdagStableHash> HirpdagStableHash for Vec<T> {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        hasher.write_u64(self.len() as u64);
+        for value in self {
+            value.hirpdag_stable_hash_into(hasher);
+        }
+    }
+}
+
+// Numbers hash their little-endian bytes. `usize` and `isize` widen to 64
+// bits, and floats hash their bit patterns, so the encoding does not depend
+// on the platform.
+macro_rules! hirpdag_stable_hash_number {
+    ($($t:ty),*) => {
+        $(
+            impl HirpdagStableHash for $t {
+                fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+                    hasher.write(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+hirpdag_stable_hash_number!(i8, i16, i32, i64, i128, u8, u16, u32, u64, u128);
+
+impl HirpdagStableHash for isize {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        (*self as i64).hirpdag_stable_hash_into(hasher);
+    }
+}
+
+impl HirpdagStableHash for usize {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        (*self as u64).hirpdag_stable_hash_into(hasher);
+    }
+}
+
+impl HirpdagStableHash for f32 {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        self.to_bits().hirpdag_stable_hash_into(hasher);
+    }
+}
+
+impl HirpdagStableHash for f64 {
+    fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+        self.to_bits().hirpdag_stable_hash_into(hasher);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash<T: HirpdagStableHash>(value: &T) -> u64 {
+        let mut hasher = HirpdagStableHasher::new();
+        value.hirpdag_stable_hash_into(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        let mut hasher = HirpdagStableHasher::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        assert_eq!(hash(&7usize), hash(&7u64));
+        assert_ne!(hash(&7u32), hash(&7u64));
+        assert_eq!(hash(&"ab"), hash(&"ab".to_string()));
+        // Lengths keep adjacent strings apart.
+        assert_ne!(
+            hash(&vec!["ab".to_string(), String::new()]),
+            hash(&vec!["a".to_string(), "b".to_string()])
+        );
+        assert_ne!(hash(&None::<u8>), hash(&Some(0u8)));
+        assert_ne!(hash(&0.0f64), hash(&-0.0f64));
+    }
+}
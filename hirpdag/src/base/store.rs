@@ -0,0 +1,365 @@
+// ==== Node Stores
+//
+// A node store is a content-addressed, on-disk set of nodes: each node is
+// written once, keyed by its stable hash (see `stable_hash`), and references
+// its children by their stable hashes. Archives and runs writing to the same
+// store share its nodes, and a node is read back with only the nodes it
+// reaches. The generated `HirpdagNodeStore` encodes nodes; this module holds
+// the directory layout:
+//
+//   index           the magic prefix, the layout version and the schema
+//                   fingerprint, then one fixed size entry per node: its
+//                   stable hash, and the pack, offset and length of its
+//                   record
+//   packs/N.pack    records, appended by one writer; each writer session
+//                   starts a new pack
+//
+// A record is the number of children, the children's stable hashes (u32 and
+// u64s, little endian), then the node's encoding, in which references are
+// positions in that list of children.
+//
+// Records are written, and synced, before their index entries, so the index
+// only names complete records. An incomplete trailing index entry (a write
+// in progress, or interrupted) is ignored, and cut off by the next writer.
+// One process at a time may write to a store; any number may read it.
+
+use crate::base::serialize::{
+    hirpdag_check_fingerprint, HirpdagDeserializeError, HirpdagSchemaFingerprint,
+    HirpdagSerializeError,
+};
+use std::io::{Read, Seek, Write};
+
+/// Magic prefix of a node store's index file. As [`HIRPDAG_MAGIC`], with
+/// `HPDS` naming the file.
+///
+/// [`HIRPDAG_MAGIC`]: crate::base::HIRPDAG_MAGIC
+pub const HIRPDAG_STORE_MAGIC: &[u8; 8] = b"\x89HPDS\r\x1a\n";
+
+/// Version of the node store layout written by this library.
+pub const HIRPDAG_STORE_VERSION: u32 = 1;
+
+// Stable hash, pack, offset and length.
+const HIRPDAG_STORE_ENTRY_LEN: usize = 8 + 4 + 8 + 8;
+
+/// A node as stored: its stable hash, its children's stable hashes, and its
+/// encoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HirpdagStoreRecord {
+    pub hash: u64,
+    pub children: Vec<u64>,
+    pub node: Vec<u8>,
+}
+
+// Where a node's record is.
+#[derive(Clone, Copy, Debug)]
+struct HirpdagStoreEntry {
+    pack: u32,
+    offset: u64,
+    len: u64,
+}
+
+/// A node store directory: its index, read into memory, and its packs.
+pub struct HirpdagStoreDir {
+    dir: std::path::PathBuf,
+    index: std::collections::HashMap<u64, HirpdagStoreEntry>,
+    // The length of the index file's header.
+    header_len: u64,
+    // The pack this writer appends to, and its length.
+    writing: Option<(u32, std::fs::File, u64)>,
+}
+
+impl HirpdagStoreDir {
+    /// Opens the store in `dir` for nodes of the schema `fingerprint`,
+    /// creating it if `dir` holds no store. Fails with `SchemaMismatch` for a
+    /// store of another schema.
+    pub fn open(
+        dir: &std::path::Path,
+        fingerprint: &HirpdagSchemaFingerprint,
+    ) -> Result<Self, HirpdagDeserializeError> {
+        std::fs::create_dir_all(dir.join("packs")).map_err(hirpdag_store_de_io)?;
+        let index_path = dir.join("index");
+        if !index_path.exists() {
+            let mut header = Vec::new();
+            header.extend_from_slice(HIRPDAG_STORE_MAGIC);
+            header.extend_from_slice(&HIRPDAG_STORE_VERSION.to_le_bytes());
+            header.extend_from_slice(&fingerprint.hash.to_le_bytes());
+            header.extend_from_slice(&(fingerprint.name.len() as u32).to_le_bytes());
+            header.extend_from_slice(fingerprint.name.as_bytes());
+            std::fs::write(&index_path, header).map_err(hirpdag_store_de_io)?;
+        }
+        let bytes = std::fs::read(&index_path).map_err(hirpdag_store_de_io)?;
+        let (found, entries) = hirpdag_parse_store_header(&bytes)?;
+        hirpdag_check_fingerprint(&found, fingerprint)?;
+        let index = entries
+            .chunks_exact(HIRPDAG_STORE_ENTRY_LEN)
+            .map(|entry| {
+                let field = |range: std::ops::Range<usize>| {
+                    let mut bytes = [0; 8];
+                    bytes[..range.len()].copy_from_slice(&entry[range]);
+                    u64::from_le_bytes(bytes)
+                };
+                let location = HirpdagStoreEntry {
+                    pack: field(8..12) as u32,
+                    offset: field(12..20),
+                    len: field(20..28),
+                };
+                (field(0..8), location)
+            })
+            .collect();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            index,
+            header_len: (bytes.len() - entries.len()) as u64,
+            writing: None,
+        })
+    }
+
+    /// Whether the store holds the node with stable hash `hash`.
+    pub fn contains(&self, hash: u64) -> bool {
+        self.index.contains_key(&hash)
+    }
+
+    /// The number of nodes stored.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Reads the record of the node with stable hash `hash`. Fails with
+    /// `MissingNode` if the store does not hold it.
+    pub fn read(&self, hash: u64) -> Result<HirpdagStoreRecord, HirpdagDeserializeError> {
+        let entry = self
+            .index
+            .get(&hash)
+            .ok_or(HirpdagDeserializeError::MissingNode { hash })?;
+        let mut pack =
+            std::fs::File::open(self.pack_path(entry.pack)).map_err(hirpdag_store_de_io)?;
+        pack.seek(std::io::SeekFrom::Start(entry.offset))
+            .map_err(hirpdag_store_de_io)?;
+        // Grown as data arrives, so a corrupt length cannot allocate up front.
+        let mut bytes = Vec::new();
+        pack.take(entry.len)
+            .read_to_end(&mut bytes)
+            .map_err(hirpdag_store_de_io)?;
+        let truncated = HirpdagDeserializeError::Truncated { at_node: None };
+        if bytes.len() as u64 != entry.len {
+            return Err(truncated);
+        }
+        let (count, rest) = bytes.split_at_checked(4).ok_or(truncated.clone())?;
+        let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+        let (children, node) = count
+            .checked_mul(8)
+            .and_then(|len| rest.split_at_checked(len))
+            .ok_or(truncated)?;
+        Ok(HirpdagStoreRecord {
+            hash,
+            children: children
+                .chunks_exact(8)
+                .map(|child| u64::from_le_bytes(child.try_into().unwrap()))
+                .collect(),
+            node: node.to_vec(),
+        })
+    }
+
+    /// Writes `records` the store does not hold yet. The first write of a
+    /// store opened in this process starts a new pack.
+    pub fn write(&mut self, records: &[HirpdagStoreRecord]) -> Result<(), HirpdagSerializeError> {
+        let mut seen = std::collections::HashSet::new();
+        let records: Vec<_> = records
+            .iter()
+            .filter(|record| !self.contains(record.hash) && seen.insert(record.hash))
+            .collect();
+        if records.is_empty() {
+            return Ok(());
+        }
+        if self.writing.is_none() {
+            let pack = self.next_pack()?;
+            let file = std::fs::OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(self.pack_path(pack))
+                .map_err(hirpdag_store_ser_io)?;
+            self.writing = Some((pack, file, 0));
+        }
+        let (pack, file, len) = self.writing.as_mut().unwrap();
+        let mut data = Vec::new();
+        let mut entries = Vec::new();
+        let mut located = Vec::new();
+        for record in &records {
+            let offset = *len + data.len() as u64;
+            data.extend_from_slice(&(record.children.len() as u32).to_le_bytes());
+            for child in &record.children {
+                data.extend_from_slice(&child.to_le_bytes());
+            }
+            data.extend_from_slice(&record.node);
+            let entry = HirpdagStoreEntry {
+                pack: *pack,
+                offset,
+                len: *len + data.len() as u64 - offset,
+            };
+            entries.extend_from_slice(&record.hash.to_le_bytes());
+            entries.extend_from_slice(&entry.pack.to_le_bytes());
+            entries.extend_from_slice(&entry.offset.to_le_bytes());
+            entries.extend_from_slice(&entry.len.to_le_bytes());
+            located.push((record.hash, entry));
+        }
+        if let Err(error) = file.write_all(&data).and_then(|()| file.sync_data()) {
+            // The pack's length is unknown now: the next write starts another.
+            self.writing = None;
+            return Err(hirpdag_store_ser_io(error));
+        }
+        *len += data.len() as u64;
+        self.append_index(&entries).map_err(hirpdag_store_ser_io)?;
+        self.index.extend(located);
+        Ok(())
+    }
+
+    // Appends entries to the index file, first cutting off an incomplete
+    // trailing entry.
+    fn append_index(&self, entries: &[u8]) -> std::io::Result<()> {
+        let mut index = std::fs::OpenOptions::new()
+            .write(true)
+            .open(self.dir.join("index"))?;
+        let size = index.metadata()?.len();
+        let whole = size - (size - self.header_len) % HIRPDAG_STORE_ENTRY_LEN as u64;
+        index.set_len(whole)?;
+        index.seek(std::io::SeekFrom::Start(whole))?;
+        index.write_all(entries)?;
+        index.sync_data()
+    }
+
+    fn pack_path(&self, pack: u32) -> std::path::PathBuf {
+        self.dir.join("packs").join(format!("{}.pack", pack))
+    }
+
+    // One past the highest pack number in the packs directory, including
+    // packs of interrupted writes.
+    fn next_pack(&self) -> Result<u32, HirpdagSerializeError> {
+        let mut next = 0;
+        for entry in std::fs::read_dir(self.dir.join("packs")).map_err(hirpdag_store_ser_io)? {
+            let name = entry.map_err(hirpdag_store_ser_io)?.file_name();
+            let pack = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".pack"))
+                .and_then(|number| number.parse::<u32>().ok());
+            if let Some(pack) = pack {
+                next = next.max(pack + 1);
+            }
+        }
+        Ok(next)
+    }
+}
+
+// Splits an index file into the fingerprint of its header and its entries.
+fn hirpdag_parse_store_header(
+    bytes: &[u8],
+) -> Result<(HirpdagSchemaFingerprint, &[u8]), HirpdagDeserializeError> {
+    let truncated = HirpdagDeserializeError::Truncated { at_node: None };
+    let rest = bytes
+        .strip_prefix(HIRPDAG_STORE_MAGIC)
+        .ok_or(HirpdagDeserializeError::BadMagic)?;
+    let (version, rest) = rest.split_at_checked(4).ok_or(truncated.clone())?;
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    if version != HIRPDAG_STORE_VERSION {
+        return Err(HirpdagDeserializeError::Format(format!(
+            "unsupported node store version {} (supported: {})",
+            version, HIRPDAG_STORE_VERSION
+        )));
+    }
+    let (hash, rest) = rest.split_at_checked(8).ok_or(truncated.clone())?;
+    let (name_len, rest) = rest.split_at_checked(4).ok_or(truncated.clone())?;
+    let name_len = u32::from_le_bytes(name_len.try_into().unwrap()) as usize;
+    let (name, entries) = rest.split_at_checked(name_len).ok_or(truncated)?;
+    let fingerprint = HirpdagSchemaFingerprint {
+        hash: u64::from_le_bytes(hash.try_into().unwrap()),
+        name: String::from_utf8_lossy(name).into_owned(),
+    };
+    Ok((fingerprint, entries))
+}
+
+fn hirpdag_store_de_io(error: std::io::Error) -> HirpdagDeserializeError {
+    HirpdagDeserializeError::Io(error.to_string())
+}
+
+fn hirpdag_store_ser_io(error: std::io::Error) -> HirpdagSerializeError {
+    HirpdagSerializeError::Io(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_dir() {
+        let dir = std::env::temp_dir().join(format!("hirpdag_store_dir_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let fingerprint = HirpdagSchemaFingerprint {
+            hash: 1,
+            name: "test".to_string(),
+        };
+        let leaf = HirpdagStoreRecord {
+            hash: 7,
+            children: vec![],
+            node: b"leaf".to_vec(),
+        };
+        let parent = HirpdagStoreRecord {
+            hash: 8,
+            children: vec![7, 7],
+            node: b"parent".to_vec(),
+        };
+
+        let mut store = HirpdagStoreDir::open(&dir, &fingerprint).unwrap();
+        assert!(store.is_empty());
+        store.write(&[leaf.clone(), parent.clone()]).unwrap();
+        store.write(std::slice::from_ref(&leaf)).unwrap();
+        assert_eq!(store.read(8).unwrap(), parent);
+        assert_eq!(
+            store.read(9),
+            Err(HirpdagDeserializeError::MissingNode { hash: 9 })
+        );
+
+        // An interrupted index write is ignored, then cut off.
+        let index = dir.join("index");
+        let mut bytes = std::fs::read(&index).unwrap();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        std::fs::write(&index, bytes).unwrap();
+        let mut store = HirpdagStoreDir::open(&dir, &fingerprint).unwrap();
+        assert_eq!(store.len(), 2);
+        let other = HirpdagStoreRecord {
+            hash: 9,
+            children: vec![8],
+            node: vec![],
+        };
+        store.write(std::slice::from_ref(&other)).unwrap();
+        let store = HirpdagStoreDir::open(&dir, &fingerprint).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.read(7).unwrap(), leaf);
+        assert_eq!(store.read(9).unwrap(), other);
+        assert_eq!(std::fs::read_dir(dir.join("packs")).unwrap().count(), 2);
+
+        // The last entry (node 9) claiming more than its pack holds.
+        let mut bytes = std::fs::read(&index).unwrap();
+        let end = bytes.len();
+        bytes[end - 8..].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&index, bytes).unwrap();
+        let store = HirpdagStoreDir::open(&dir, &fingerprint).unwrap();
+        assert_eq!(
+            store.read(9),
+            Err(HirpdagDeserializeError::Truncated { at_node: None })
+        );
+        assert_eq!(store.read(7).unwrap(), leaf);
+
+        let other_schema = HirpdagSchemaFingerprint {
+            hash: 2,
+            name: "other".to_string(),
+        };
+        assert!(matches!(
+            HirpdagStoreDir::open(&dir, &other_schema),
+            Err(HirpdagDeserializeError::SchemaMismatch { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
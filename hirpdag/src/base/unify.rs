@@ -0,0 +1,258 @@
+// ==== Matching and Unification
+//
+// First-order matching and unification over nodes. An enum variant marked
+// `#[hirpdag(metavar)]` makes a metavariable of every node holding a value
+// of it in a field; the metavariable is that node, so two occurrences of
+// `Meta("x")` are the same metavariable.
+//
+// A node is flagged `HIRPDAG_META_FLAG_METAVAR` in its meta if any
+// metavariable is below it, so ground subterms are known in O(1). Matching
+// a ground pattern, or unifying two ground terms, is a pointer comparison,
+// as hash-consing makes equal terms identical.
+//
+// Unification builds a triangular substitution: a binding's term may hold
+// metavariables bound later. Applying it (`hirpdag_substitute`, a
+// generated rewriter) follows bindings until no bound metavariable is
+// left; the occurs check keeps this finite.
+
+use crate::base::basic_traits::IsNumber;
+use std::collections::{HashMap, HashSet};
+
+/// Implemented by the module's `HirpdagNodeRef` to match and unify nodes of
+/// any type.
+pub trait HirpdagTerm: Clone + Eq + std::hash::Hash {
+    /// Whether the node is a metavariable.
+    fn hirpdag_is_metavar(&self) -> bool;
+
+    /// Whether no metavariable is in the node or below it.
+    fn hirpdag_is_ground(&self) -> bool;
+
+    /// The name of the node's type.
+    fn hirpdag_term_type(&self) -> &'static str;
+
+    /// Unifies the data of two nodes, which fails if their types differ.
+    fn hirpdag_unify_data(&self, other: &Self, u: &mut HirpdagUnifier<Self>) -> bool;
+
+    /// Adds the node's children to `out`.
+    fn hirpdag_term_children(&self, out: &mut Vec<Self>);
+}
+
+/// Implemented by every field type, and the generated types, to be matched
+/// and unified.
+pub trait HirpdagUnify<N> {
+    /// Unifies `self` with `other`, or matches it against `other` as a
+    /// pattern, adding to the substitution in `u`.
+    fn hirpdag_unify_with(&self, other: &Self, u: &mut HirpdagUnifier<N>) -> bool;
+
+    /// Whether a node holding this value is a metavariable.
+    fn hirpdag_is_metavar(&self) -> bool {
+        false
+    }
+
+    /// Adds the nodes in this value to `out`.
+    fn hirpdag_term_children(&self, _out: &mut Vec<N>) {}
+}
+
+/// Metavariables bound to terms of their type.
+#[derive(Clone, Debug)]
+pub struct HirpdagSubstitution<N> {
+    bindings: HashMap<N, N>,
+}
+
+impl<N: Eq + std::hash::Hash> PartialEq for HirpdagSubstitution<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bindings == other.bindings
+    }
+}
+
+impl<N: Eq + std::hash::Hash> Eq for HirpdagSubstitution<N> {}
+
+impl<N> Default for HirpdagSubstitution<N> {
+    fn default() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+}
+
+impl<N: HirpdagTerm> HirpdagSubstitution<N> {
+    /// The term bound to `var`.
+    pub fn get(&self, var: &N) -> Option<&N> {
+        self.bindings.get(var)
+    }
+
+    /// Binds `var` to `term`, replacing any binding it had.
+    pub fn insert(&mut self, var: N, term: N) {
+        self.bindings.insert(var, term);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bindings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&N, &N)> {
+        self.bindings.iter()
+    }
+
+    /// `node`, or if it is a bound metavariable, what it is bound to,
+    /// followed through bound metavariables.
+    pub fn resolve(&self, node: &N) -> N {
+        let mut node = node;
+        while let Some(term) = self.bindings.get(node) {
+            node = term;
+        }
+        node.clone()
+    }
+}
+
+/// Matches or unifies terms, building a substitution.
+pub struct HirpdagUnifier<N> {
+    subst: HirpdagSubstitution<N>,
+    /// Matching binds metavariables of the pattern only, and compares terms
+    /// without resolving them.
+    matching: bool,
+}
+
+impl<N: HirpdagTerm> HirpdagUnifier<N> {
+    /// The substitution which makes `pattern` equal to `term`, binding
+    /// metavariables in `pattern` only. Metavariables in `term` are
+    /// ordinary nodes.
+    pub fn match_term<T: HirpdagUnify<N>>(pattern: &T, term: &T) -> Option<HirpdagSubstitution<N>> {
+        let mut u = Self {
+            subst: HirpdagSubstitution::default(),
+            matching: true,
+        };
+        if pattern.hirpdag_unify_with(term, &mut u) {
+            Some(u.subst)
+        } else {
+            None
+        }
+    }
+
+    /// A most general substitution which makes `a` and `b` equal, binding
+    /// metavariables in both.
+    pub fn unify<T: HirpdagUnify<N>>(a: &T, b: &T) -> Option<HirpdagSubstitution<N>> {
+        let mut u = Self {
+            subst: HirpdagSubstitution::default(),
+            matching: false,
+        };
+        if a.hirpdag_unify_with(b, &mut u) {
+            Some(u.subst)
+        } else {
+            None
+        }
+    }
+
+    /// Matches or unifies two nodes.
+    pub fn nodes(&mut self, a: &N, b: &N) -> bool {
+        if self.matching {
+            if a.hirpdag_is_metavar() {
+                return match self.subst.get(a) {
+                    Some(term) => term == b,
+                    None => self.bind(a, b),
+                };
+            }
+            if a.hirpdag_is_ground() {
+                return a == b;
+            }
+            return a.hirpdag_unify_data(b, self);
+        }
+        let (a, b) = (self.subst.resolve(a), self.subst.resolve(b));
+        if a == b {
+            return true;
+        }
+        if a.hirpdag_is_metavar() {
+            return !self.occurs(&a, &b) && self.bind(&a, &b);
+        }
+        if b.hirpdag_is_metavar() {
+            return !self.occurs(&b, &a) && self.bind(&b, &a);
+        }
+        if a.hirpdag_is_ground() && b.hirpdag_is_ground() {
+            return false;
+        }
+        a.hirpdag_unify_data(&b, self)
+    }
+
+    fn bind(&mut self, var: &N, term: &N) -> bool {
+        if var.hirpdag_term_type() != term.hirpdag_term_type() {
+            return false;
+        }
+        self.subst.insert(var.clone(), term.clone());
+        true
+    }
+
+    /// Whether `var` occurs in `term` under the substitution.
+    fn occurs(&self, var: &N, term: &N) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack = vec![term.clone()];
+        while let Some(node) = stack.pop() {
+            let node = self.subst.resolve(&node);
+            if node == *var {
+                return true;
+            }
+            if node.hirpdag_is_ground() || !seen.insert(node.clone()) {
+                continue;
+            }
+            node.hirpdag_term_children(&mut stack);
+        }
+        false
+    }
+}
+
+impl<N, P: IsNumber + PartialEq> HirpdagUnify<N> for P {
+    fn hirpdag_unify_with(&self, other: &Self, _u: &mut HirpdagUnifier<N>) -> bool {
+        self == other
+    }
+}
+
+impl<N> HirpdagUnify<N> for String {
+    fn hirpdag_unify_with(&self, other: &Self, _u: &mut HirpdagUnifier<N>) -> bool {
+        self == other
+    }
+}
+
+impl<N> HirpdagUnify<N> for bool {
+    fn hirpdag_unify_with(&self, other: &Self, _u: &mut HirpdagUnifier<N>) -> bool {
+        self == other
+    }
+}
+
+impl<N, T: HirpdagUnify<N>> HirpdagUnify<N> for Option<T> {
+    fn hirpdag_unify_with(&self, other: &Self, u: &mut HirpdagUnifier<N>) -> bool {
+        match (self, other) {
+            (None, None) => true,
+            (Some(a), Some(b)) => a.hirpdag_unify_with(b, u),
+            _ => false,
+        }
+    }
+
+    fn hirpdag_is_metavar(&self) -> bool {
+        self.as_ref().is_some_and(|x| x.hirpdag_is_metavar())
+    }
+
+    fn hirpdag_term_children(&self, out: &mut Vec<N>) {
+        if let Some(x) = self {
+            x.hirpdag_term_children(out);
+        }
+    }
+}
+
+impl<N, T: HirpdagUnify<N>> HirpdagUnify<N> for Vec<T> {
+    fn hirpdag_unify_with(&self, other: &Self, u: &mut HirpdagUnifier<N>) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .zip(other)
+                .all(|(a, b)| a.hirpdag_unify_with(b, u))
+    }
+
+    fn hirpdag_term_children(&self, out: &mut Vec<N>) {
+        for x in self {
+            x.hirpdag_term_children(out);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// ==== Normalizer Verification Base
+//
+// Types with a `normalizer` promise that every interned node is in normal
+// form, but `spawn` and deserialization intern nodes without going through
+// `new()`. In verification mode, every freshly interned node of a normalizer
+// type is rebuilt through `new()` and compared with itself; a node which
+// normalizes to something else violates the normal form.
+
+/// What happens when a freshly interned node is not in normal form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HirpdagVerifyMode {
+    /// No verification (the default). Costs one atomic load per freshly
+    /// interned node of a normalizer type.
+    Off,
+    /// Violations are recorded; see [`hirpdag_take_verify_violations`].
+    Record,
+    /// The first violation panics.
+    Panic,
+}
+
+static HIRPDAG_VERIFY_MODE: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+static HIRPDAG_VERIFY_VIOLATIONS: std::sync::Mutex<Vec<HirpdagNormalViolation>> =
+    std::sync::Mutex::new(Vec::new());
+
+std::thread_local! {
+    // Set while a normalizer runs for verification, so the nodes it interns
+    // are not themselves verified (which would recurse).
+    static HIRPDAG_VERIFYING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Sets the verification mode for all threads.
+pub fn hirpdag_set_verify_mode(mode: HirpdagVerifyMode) {
+    let value = match mode {
+        HirpdagVerifyMode::Off => 0,
+        HirpdagVerifyMode::Record => 1,
+        HirpdagVerifyMode::Panic => 2,
+    };
+    HIRPDAG_VERIFY_MODE.store(value, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn hirpdag_verify_mode() -> HirpdagVerifyMode {
+    match HIRPDAG_VERIFY_MODE.load(std::sync::atomic::Ordering::Relaxed) {
+        0 => HirpdagVerifyMode::Off,
+        1 => HirpdagVerifyMode::Record,
+        _ => HirpdagVerifyMode::Panic,
+    }
+}
+
+/// A node which was interned although its normalizer rewrites it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HirpdagNormalViolation {
+    /// The hirpdag type of the node.
+    pub type_name: &'static str,
+    /// The interned node (`Debug` output).
+    pub node: String,
+    /// What `new()` builds from the node's fields (`Debug` output).
+    pub normalized: String,
+}
+
+impl std::fmt::Display for HirpdagNormalViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hirpdag: {} node is not in normal form: {} normalizes to {}",
+            self.type_name, self.node, self.normalized
+        )
+    }
+}
+
+/// Removes and returns the violations recorded so far, from all threads.
+pub fn hirpdag_take_verify_violations() -> Vec<HirpdagNormalViolation> {
+    std::mem::take(
+        &mut *HIRPDAG_VERIFY_VIOLATIONS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner),
+    )
+}
+
+/// Verifies a freshly interned node. Called by the generated
+/// `hirpdag_hashcons` of normalizer types; `check` returns the node and its
+/// renormalized form.
+#[doc(hidden)]
+pub fn hirpdag_verify_normal<N, F>(type_name: &'static str, check: F)
+where
+    N: PartialEq + std::fmt::Debug,
+    F: FnOnce() -> (N, N),
+{
+    let mode = hirpdag_verify_mode();
+    if mode == HirpdagVerifyMode::Off || HIRPDAG_VERIFYING.with(|v| v.get()) {
+        return;
+    }
+
+    struct Verifying;
+    impl Drop for Verifying {
+        fn drop(&mut self) {
+            HIRPDAG_VERIFYING.with(|v| v.set(false));
+        }
+    }
+    HIRPDAG_VERIFYING.with(|v| v.set(true));
+    let verifying = Verifying;
+    let (node, normalized) = check();
+    drop(verifying);
+
+    if node == normalized {
+        return;
+    }
+    let violation = HirpdagNormalViolation {
+        type_name,
+        node: format!("{:?}", node),
+        normalized: format!("{:?}", normalized),
+    };
+    match mode {
+        HirpdagVerifyMode::Panic => panic!("{}", violation),
+        _ => HIRPDAG_VERIFY_VIOLATIONS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(violation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_mode_round_trip() {
+        for mode in [
+            HirpdagVerifyMode::Record,
+            HirpdagVerifyMode::Panic,
+            HirpdagVerifyMode::Off,
+        ] {
+            hirpdag_set_verify_mode(mode);
+            assert_eq!(hirpdag_verify_mode(), mode);
+        }
+        // Off skips the check entirely.
+        hirpdag_verify_normal::<u32, _>("T", || unreachable!());
+    }
+}
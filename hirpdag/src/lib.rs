@@ -48,6 +48,8 @@ pub use lazy_static::lazy_static;
 
 // Re-exported for use by hirpdag_derive generated code, so that user crates
 // do not need to declare these dependencies themselves.
+#[cfg(feature = "postcard")]
 pub use postcard;
 pub use serde;
+#[cfg(feature = "json")]
 pub use serde_json;
@@ -0,0 +1,357 @@
+//! `hirpdag-archive`: inspect, validate and convert hirpdag archives without
+//! the Rust types that wrote them.
+//!
+//! Archives written with `HirpdagSerializeOptions { embed_schema: true }`
+//! are self-describing. For others, pass `--header` with a JSON
+//! `HirpdagBinaryHeader` holding the writer's schema: the output of
+//! `hirpdag-archive header` on a self-describing archive, or
+//! `serde_json::to_string(&HirpdagBinaryHeader { schema:
+//! Some(hirpdag_schema()), ..HirpdagBinaryHeader::new(hirpdag_schema_fingerprint())
+//! })` in the writing program.
+//!
+//! A delta archive numbers its nodes after those of its base archive, which
+//! this tool does not load, so `validate` and `stats` reject deltas.
+
+#![forbid(unsafe_code)]
+
+use hirpdag::base::{
+    hirpdag_check_fingerprint, hirpdag_frame_payload, hirpdag_inspect, hirpdag_inspect_json,
+    hirpdag_inspect_with_schema, hirpdag_parse_binary_header, hirpdag_write_binary_header,
+    HirpdagBinaryHeader, HirpdagDynArchive, HirpdagInspection, HirpdagSchema, HIRPDAG_FLAG_CRC32C,
+    HIRPDAG_FLAG_INDEX, HIRPDAG_FLAG_LZ4, HIRPDAG_FLAG_ROOT_LIST, HIRPDAG_FLAG_SIDE_TABLES,
+    HIRPDAG_FORMAT_VERSION,
+};
+use std::fmt::Write as _;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: hirpdag-archive <command> <input> [--header <file>] [-o <file>]
+
+commands:
+  info <archive>       header, format version, node counts per type, root counts
+                       (or the root list)
+  header <archive>     the archive header (fingerprint and schema) as JSON
+  to-json <archive>    convert a binary archive to a JSON archive
+  from-json <json>     convert a JSON archive to a binary archive (needs --header)
+  validate <archive>   check node index, ordering, type and sharing invariants
+  stats <archive>      reachability and sharing statistics
+
+options:
+  --header <file>      header JSON describing the writer's schema, for archives
+                       which do not embed it
+  -o, --output <file>  write the output to a file instead of stdout
+";
+
+struct Args {
+    command: String,
+    input: String,
+    header: Option<String>,
+    output: Option<String>,
+}
+
+/// A command failure: the message, and whether it is a usage error.
+struct Failure {
+    message: String,
+    usage: bool,
+}
+
+impl Failure {
+    fn usage(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            usage: true,
+        }
+    }
+}
+
+impl<E: std::fmt::Display> From<E> for Failure {
+    fn from(e: E) -> Self {
+        Self {
+            message: e.to_string(),
+            usage: false,
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, Failure> {
+    let mut positional = Vec::new();
+    let mut header = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| Failure::usage(format!("{} needs a file argument", name)))
+        };
+        match arg.as_str() {
+            "--header" => header = Some(value("--header")?),
+            "-o" | "--output" => output = Some(value("--output")?),
+            "-h" | "--help" => return Err(Failure::usage("")),
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(Failure::usage(format!("unknown option {}", flag)))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let [command, input]: [String; 2] = positional
+        .try_into()
+        .map_err(|_| Failure::usage("expected a command and one input file"))?;
+    Ok(Args {
+        command,
+        input,
+        header,
+        output,
+    })
+}
+
+/// Reads a file, or stdin for `-`.
+fn read_input(path: &str) -> Result<Vec<u8>, Failure> {
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    if path == "-" {
+        std::io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(bytes)
+}
+
+fn write_output(path: Option<&str>, bytes: &[u8]) -> Result<(), Failure> {
+    use std::io::Write;
+    match path {
+        Some(path) => std::fs::write(path, bytes).map_err(|e| format!("{}: {}", path, e))?,
+        None => std::io::stdout().write_all(bytes)?,
+    }
+    Ok(())
+}
+
+fn read_header(args: &Args) -> Result<Option<HirpdagBinaryHeader>, Failure> {
+    let Some(path) = &args.header else {
+        return Ok(None);
+    };
+    let text = read_input(path)?;
+    let header = hirpdag::serde_json::from_slice(&text)
+        .map_err(|e| format!("{}: not a hirpdag header: {}", path, e))?;
+    Ok(Some(header))
+}
+
+fn header_schema(header: &HirpdagBinaryHeader) -> Result<&HirpdagSchema, Failure> {
+    header
+        .schema
+        .as_ref()
+        .ok_or_else(|| Failure::from("the --header has no schema"))
+}
+
+fn inspect(args: &Args) -> Result<HirpdagInspection, Failure> {
+    let bytes = read_input(&args.input)?;
+    let Some(header) = read_header(args)? else {
+        return Ok(hirpdag_inspect(&bytes)?);
+    };
+    let (found, _) = hirpdag_parse_binary_header(&bytes)?;
+    hirpdag_check_fingerprint(&found.fingerprint, &header.fingerprint)?;
+    Ok(hirpdag_inspect_with_schema(
+        &bytes,
+        header_schema(&header)?,
+    )?)
+}
+
+/// [`inspect`], for commands which need the whole node table.
+fn inspect_full(args: &Args) -> Result<HirpdagInspection, Failure> {
+    let inspection = inspect(args)?;
+    if let Some(base) = &inspection.header.base {
+        return Err(Failure::from(format!(
+            "{} is a delta archive against base {}",
+            args.input, base
+        )));
+    }
+    Ok(inspection)
+}
+
+fn info(inspection: &HirpdagInspection) -> String {
+    let HirpdagInspection {
+        header_version,
+        header,
+        schema,
+        archive,
+    } = inspection;
+    let mut out = String::new();
+    let _ = writeln!(out, "header version: {}", header_version);
+    let _ = writeln!(out, "format version: {}", HIRPDAG_FORMAT_VERSION);
+    let _ = writeln!(out, "schema: {}", header.fingerprint.name);
+    let _ = writeln!(out, "schema hash: {:#018x}", header.fingerprint.hash);
+    let _ = writeln!(out, "schema embedded: {}", header.schema.is_some());
+    let _ = writeln!(out, "checksum: {}", header.flags & HIRPDAG_FLAG_CRC32C != 0);
+    let _ = writeln!(out, "compressed: {}", header.flags & HIRPDAG_FLAG_LZ4 != 0);
+    let _ = writeln!(out, "indexed: {}", header.flags & HIRPDAG_FLAG_INDEX != 0);
+    let _ = writeln!(
+        out,
+        "side-tables: {}",
+        header.flags & HIRPDAG_FLAG_SIDE_TABLES != 0
+    );
+    if let Some(base) = &header.base {
+        let _ = writeln!(out, "delta against base: {}", base);
+    }
+    let _ = writeln!(out, "nodes: {}", archive.nodes.len());
+    for ty in schema.struct_types() {
+        let count = archive
+            .nodes
+            .iter()
+            .filter(|n| n.type_name == ty.name)
+            .count();
+        let _ = writeln!(out, "  {}: {}", ty.name, count);
+    }
+    let _ = writeln!(out, "roots: {}", archive.root_nodes().count());
+    for (name, indices) in &archive.roots {
+        let _ = writeln!(out, "  {}: {}", name, indices.len());
+    }
+    // Root list entries by name (or position), with their node's type.
+    for (position, entry) in archive
+        .root_list
+        .iter()
+        .flat_map(|l| l.entries())
+        .enumerate()
+    {
+        let label = entry
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("#{}", position));
+        let type_name = archive
+            .nodes
+            .get(entry.node as usize)
+            .map_or("(out of range)", |node| node.type_name.as_str());
+        let _ = writeln!(out, "  {}: {}", label, type_name);
+    }
+    out
+}
+
+fn stats(archive: &HirpdagDynArchive) -> String {
+    let reachable = archive.reachable();
+    let counts = archive.reference_counts();
+    let references: usize = archive.nodes.iter().map(|n| n.children().len()).sum();
+    let reached = reachable.iter().filter(|r| **r).count();
+    let shared = counts.iter().filter(|c| **c > 1).count();
+
+    // Children precede parents, so one forward pass sizes every subtree.
+    // Forward and out of range references (see `validate`) count as empty.
+    let mut tree_size = vec![0u128; archive.nodes.len()];
+    let mut height = vec![0u64; archive.nodes.len()];
+    for (index, node) in archive.nodes.iter().enumerate() {
+        let children = node.children();
+        let child = |i: &u64| (*i as usize) < index;
+        tree_size[index] = children
+            .iter()
+            .filter(|i| child(i))
+            .fold(1u128, |size, i| size.saturating_add(tree_size[*i as usize]));
+        height[index] = 1 + children
+            .iter()
+            .filter(|i| child(i))
+            .map(|i| height[*i as usize])
+            .max()
+            .unwrap_or(0);
+    }
+    let root_indices = || {
+        archive
+            .root_nodes()
+            .filter_map(|i| usize::try_from(i).ok())
+            .filter(|i| *i < archive.nodes.len())
+    };
+    let expanded = root_indices().fold(0u128, |size, i| size.saturating_add(tree_size[i]));
+    let depth = root_indices().map(|i| height[i]).max().unwrap_or(0);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "nodes: {}", archive.nodes.len());
+    let _ = writeln!(out, "reachable: {}", reached);
+    let _ = writeln!(out, "unreachable: {}", archive.nodes.len() - reached);
+    let _ = writeln!(out, "references: {}", references);
+    let _ = writeln!(out, "shared nodes: {}", shared);
+    let _ = writeln!(
+        out,
+        "max references to one node: {}",
+        counts.iter().max().copied().unwrap_or(0)
+    );
+    let _ = writeln!(out, "depth: {}", depth);
+    let _ = writeln!(out, "expanded tree size: {}", expanded);
+    if reached > 0 {
+        let _ = writeln!(
+            out,
+            "sharing factor: {:.2}",
+            expanded as f64 / reached as f64
+        );
+    }
+    out
+}
+
+fn run(args: &Args) -> Result<ExitCode, Failure> {
+    let output = args.output.as_deref();
+    match args.command.as_str() {
+        "info" => write_output(output, info(&inspect(args)?).as_bytes())?,
+        "header" => {
+            let bytes = read_input(&args.input)?;
+            let (header, _) = hirpdag_parse_binary_header(&bytes)?;
+            let mut text = hirpdag::serde_json::to_string_pretty(&header)?;
+            text.push('\n');
+            write_output(output, text.as_bytes())?;
+        }
+        "to-json" => {
+            let mut text = inspect(args)?.archive.to_json();
+            text.push('\n');
+            write_output(output, text.as_bytes())?;
+        }
+        "from-json" => {
+            let mut header =
+                read_header(args)?.ok_or_else(|| Failure::usage("from-json needs --header"))?;
+            // The index is an optional aid to partial reads, and is not
+            // rebuilt from JSON. Side-tables are opaque to this tool, and
+            // not carried through JSON.
+            header.flags &= !(HIRPDAG_FLAG_INDEX | HIRPDAG_FLAG_SIDE_TABLES);
+            let text = String::from_utf8(read_input(&args.input)?)?;
+            let archive = hirpdag_inspect_json(&text, header_schema(&header)?)?;
+            // The JSON decides how the archive holds its roots.
+            header.flags &= !HIRPDAG_FLAG_ROOT_LIST;
+            header.flags |= archive.root_layout().flags();
+            let schema = header_schema(&header)?;
+            let mut bytes = hirpdag_write_binary_header(&header)?;
+            bytes.extend(hirpdag_frame_payload(
+                header.flags,
+                None,
+                None,
+                archive.to_postcard(schema)?,
+            )?);
+            write_output(output, &bytes)?;
+        }
+        "validate" => {
+            let inspection = inspect_full(args)?;
+            let problems = inspection.archive.validate(&inspection.schema);
+            let mut text = String::new();
+            for problem in &problems {
+                let _ = writeln!(text, "{}", problem);
+            }
+            if problems.is_empty() {
+                text.push_str("ok\n");
+            }
+            write_output(output, text.as_bytes())?;
+            if !problems.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        "stats" => write_output(output, stats(&inspect_full(args)?.archive).as_bytes())?,
+        other => return Err(Failure::usage(format!("unknown command {}", other))),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)).and_then(|args| run(&args)) {
+        Ok(code) => code,
+        Err(Failure { message, usage }) => {
+            if !message.is_empty() {
+                eprintln!("hirpdag-archive: {}", message);
+            }
+            if usage {
+                eprint!("{}", USAGE);
+                ExitCode::from(2)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// Runs the hirpdag-archive binary on archives written by a test module.
+
+use hirpdag::base::{HirpdagBinaryHeader, HirpdagSerializeOptions};
+use hirpdag::*;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+#[hirpdag_module]
+mod tree {
+    #[hirpdag(root)]
+    struct Tree {
+        label: String,
+        kids: Vec<Tree>,
+    }
+
+    #[hirpdag(root)]
+    struct Forest {
+        trees: Vec<Tree>,
+    }
+}
+
+use tree::*;
+
+fn roots() -> HirpdagArchiveRoots {
+    let leaf = Tree::new("leaf".to_string(), vec![]);
+    let pair = Tree::new("pair".to_string(), vec![leaf.clone(), leaf.clone()]);
+    let top = Tree::new("top".to_string(), vec![pair.clone(), pair, leaf]);
+    HirpdagArchiveRoots {
+        tree: vec![top.clone()],
+        forest: vec![Forest::new(vec![top])],
+    }
+}
+
+/// A scratch directory per test, under the target directory.
+fn scratch(test: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(test);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_hirpdag-archive"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn write_archive(dir: &Path, embed_schema: bool) -> PathBuf {
+    let options = HirpdagSerializeOptions {
+        embed_schema,
+        ..Default::default()
+    };
+    let path = dir.join("archive.bin");
+    std::fs::write(
+        &path,
+        hirpdag_serialize_with_options(&roots(), &options).unwrap(),
+    )
+    .unwrap();
+    path
+}
+
+#[test]
+fn info_and_stats() {
+    let dir = scratch("info_and_stats");
+    let archive = write_archive(&dir, true);
+
+    let info = stdout(&run(&[Path::new("info"), &archive]));
+    assert!(info.contains("header version: 2\n"), "{}", info);
+    assert!(info.contains("schema embedded: true\n"), "{}", info);
+    assert!(info.contains("checksum: false\n"), "{}", info);
+    assert!(info.contains("indexed: false\n"), "{}", info);
+    assert!(info.contains("side-tables: false\n"), "{}", info);
+    assert!(
+        info.contains(&format!(
+            "schema hash: {:#018x}\n",
+            hirpdag_schema_fingerprint().hash
+        )),
+        "{}",
+        info
+    );
+    assert!(
+        info.contains("nodes: 4\n  Tree: 3\n  Forest: 1\n"),
+        "{}",
+        info
+    );
+    assert!(
+        info.contains("roots: 2\n  Tree: 1\n  Forest: 1\n"),
+        "{}",
+        info
+    );
+
+    let stats = stdout(&run(&[Path::new("stats"), &archive]));
+    assert!(stats.contains("reachable: 4\n"), "{}", stats);
+    assert!(stats.contains("shared nodes: 3\n"), "{}", stats);
+    assert!(stats.contains("depth: 4\n"), "{}", stats);
+    // top: 1 + 2 * pair (3) + leaf = 8; forest: 1 + top.
+    assert!(stats.contains("expanded tree size: 17\n"), "{}", stats);
+
+    let validate = stdout(&run(&[Path::new("validate"), &archive]));
+    assert_eq!(validate, "ok\n");
+}
+
+#[test]
+fn json_round_trip() {
+    let dir = scratch("json_round_trip");
+    let archive = write_archive(&dir, true);
+    let json = dir.join("archive.json");
+    let header = dir.join("header.json");
+    let again = dir.join("again.bin");
+
+    stdout(&run(&[
+        Path::new("to-json"),
+        &archive,
+        Path::new("-o"),
+        &json,
+    ]));
+    let text = std::fs::read_to_string(&json).unwrap();
+    assert_eq!(hirpdag_deserialize_json(&text).unwrap(), roots());
+
+    stdout(&run(&[
+        Path::new("header"),
+        &archive,
+        Path::new("-o"),
+        &header,
+    ]));
+    stdout(&run(&[
+        Path::new("from-json"),
+        &json,
+        Path::new("--header"),
+        &header,
+        Path::new("-o"),
+        &again,
+    ]));
+    let bytes = std::fs::read(&again).unwrap();
+    assert_eq!(bytes, std::fs::read(&archive).unwrap());
+    assert_eq!(hirpdag_deserialize(&bytes).unwrap(), roots());
+}
+
+#[test]
+fn schema_from_header_file() {
+    let dir = scratch("schema_from_header_file");
+    let archive = write_archive(&dir, false);
+
+    let output = run(&[Path::new("info"), &archive]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not embed its schema"));
+
+    let header = dir.join("header.json");
+    let described = HirpdagBinaryHeader {
+        schema: Some(hirpdag_schema()),
+        ..HirpdagBinaryHeader::new(hirpdag_schema_fingerprint())
+    };
+    std::fs::write(&header, hirpdag::serde_json::to_string(&described).unwrap()).unwrap();
+    let info = stdout(&run(&[
+        Path::new("info"),
+        &archive,
+        Path::new("--header"),
+        &header,
+    ]));
+    assert!(info.contains("schema embedded: false\n"), "{}", info);
+    assert!(info.contains("nodes: 4\n"), "{}", info);
+}
+
+#[test]
+fn validate_reports_problems() {
+    let dir = scratch("validate_reports_problems");
+    let archive = write_archive(&dir, true);
+    let json = dir.join("broken.json");
+    let header = dir.join("header.json");
+    let broken = dir.join("broken.bin");
+
+    // A root pointing past the node table.
+    let text = r#"{"version":1,"nodes":[{"Tree":{"label":"x","kids":[]}}],"roots":{"tree":[0,5],"forest":[]}}"#;
+    std::fs::write(&json, text).unwrap();
+    stdout(&run(&[
+        Path::new("header"),
+        &archive,
+        Path::new("-o"),
+        &header,
+    ]));
+    stdout(&run(&[
+        Path::new("from-json"),
+        &json,
+        Path::new("--header"),
+        &header,
+        Path::new("-o"),
+        &broken,
+    ]));
+    let output = run(&[Path::new("validate"), &broken]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Tree root 5 is out of range\n"
+    );
+}
+
+#[test]
+fn delta_archives() {
+    let dir = scratch("delta_archives");
+    let (_, base) = hirpdag_serialize_as_base(&roots(), None, &Default::default()).unwrap();
+    let mut more = roots();
+    more.tree.push(Tree::new("new".to_string(), vec![]));
+    let delta = dir.join("delta.bin");
+    std::fs::write(&delta, hirpdag_serialize_delta(&more, &base).unwrap()).unwrap();
+    let header = dir.join("header.json");
+    let described = HirpdagBinaryHeader {
+        schema: Some(hirpdag_schema()),
+        ..HirpdagBinaryHeader::new(hirpdag_schema_fingerprint())
+    };
+    std::fs::write(&header, hirpdag::serde_json::to_string(&described).unwrap()).unwrap();
+
+    let info = stdout(&run(&[
+        Path::new("info"),
+        &delta,
+        Path::new("--header"),
+        &header,
+    ]));
+    assert!(
+        info.contains(&format!("delta against base: {}\n", base.id())),
+        "{}",
+        info
+    );
+    assert!(info.contains("nodes: 1\n"), "{}", info);
+
+    let output = run(&[
+        Path::new("validate"),
+        &delta,
+        Path::new("--header"),
+        &header,
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is a delta archive"));
+}
+
+#[test]
+fn root_list_archives() {
+    let dir = scratch("root_list_archives");
+    let roots = roots();
+    let mut list = HirpdagRootList::new();
+    list.push_named("top", roots.tree[0].clone())
+        .push(roots.forest[0].clone());
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        ..Default::default()
+    };
+    let archive = dir.join("list.bin");
+    std::fs::write(
+        &archive,
+        hirpdag_serialize_list_with_options(&list, &options).unwrap(),
+    )
+    .unwrap();
+
+    let info = stdout(&run(&[Path::new("info"), &archive]));
+    assert!(
+        info.contains("roots: 2\n  top: Tree\n  #1: Forest\n"),
+        "{}",
+        info
+    );
+    assert_eq!(stdout(&run(&[Path::new("validate"), &archive])), "ok\n");
+
+    // Converting through JSON keeps the root list.
+    let json = dir.join("list.json");
+    let header = dir.join("header.json");
+    let again = dir.join("again.bin");
+    stdout(&run(&[
+        Path::new("to-json"),
+        &archive,
+        Path::new("-o"),
+        &json,
+    ]));
+    stdout(&run(&[
+        Path::new("header"),
+        &archive,
+        Path::new("-o"),
+        &header,
+    ]));
+    stdout(&run(&[
+        Path::new("from-json"),
+        &json,
+        Path::new("--header"),
+        &header,
+        Path::new("-o"),
+        &again,
+    ]));
+    let bytes = std::fs::read(&again).unwrap();
+    assert_eq!(bytes, std::fs::read(&archive).unwrap());
+    assert_eq!(hirpdag_deserialize_list(&bytes).unwrap(), list);
+}
+
+#[test]
+fn usage_errors() {
+    assert_eq!(run(&[]).status.code(), Some(2));
+    assert_eq!(
+        run(&[Path::new("frobnicate"), Path::new("x")])
+            .status
+            .code(),
+        Some(2)
+    );
+    let output = run(&[Path::new("info"), Path::new("/nonexistent/archive.bin")]);
+    assert_eq!(output.status.code(), Some(1));
+}
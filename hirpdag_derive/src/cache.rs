@@ -0,0 +1,204 @@
+// Generation of the per-module persistent rewrite cache.
+
+use proc_macro2::{Ident, Span};
+
+/// Generates the module-level persistent rewrite cache: the
+/// `HirpdagWeakKey` impl of every struct type, `HirpdagRewriteCache` (one
+/// `HirpdagRewriteCacheTable` per struct type) and the
+/// `HirpdagRewriteCached` wrapper.
+///
+/// `types` is (name, is_struct) for every `#[hirpdag]` type in the module.
+/// Enum types are inline payload and are not cached.
+pub(crate) fn get_cache_items(types: &[(String, bool)]) -> proc_macro2::TokenStream {
+    if !types.iter().any(|(_, is_struct)| *is_struct) {
+        return proc_macro2::TokenStream::new();
+    }
+
+    let mut weak_key_impls = proc_macro2::TokenStream::new();
+    let mut table_fields = proc_macro2::TokenStream::new();
+    let mut table_new = proc_macro2::TokenStream::new();
+    let mut table_methods = proc_macro2::TokenStream::new();
+    let mut clear_calls = proc_macro2::TokenStream::new();
+    let mut purge_terms = Vec::new();
+    let mut len_terms = Vec::new();
+    let mut stats_terms = Vec::new();
+    let mut cached_methods = proc_macro2::TokenStream::new();
+
+    for (name, is_struct) in types {
+        let ref_name = Ident::new(name, Span::call_site());
+        let rewrite_method_name = Ident::new(&format!("rewrite_{}", name), Span::call_site());
+        if !*is_struct {
+            cached_methods.extend(quote! {
+                #[allow(non_snake_case)]
+                fn #rewrite_method_name(&self, x: &#ref_name) -> #ref_name {
+                    self.rewriter.#rewrite_method_name(x)
+                }
+            });
+            continue;
+        }
+        let struct_name = Ident::new(&format!("HirpdagStruct{}", name), Span::call_site());
+        let table_name = Ident::new(&format!("cache_{}", name), Span::call_site());
+        let get_or_rewrite = Ident::new(&format!("get_or_rewrite_{}", name), Span::call_site());
+        let invalidate = Ident::new(&format!("invalidate_{}", name), Span::call_site());
+        let table_accessor = Ident::new(&format!("table_{}", name), Span::call_site());
+
+        weak_key_impls.extend(quote! {
+            impl hirpdag::base::HirpdagWeakKey for #ref_name {
+                type Weak = hirpdag::base::HirpdagRefWeak<
+                    #struct_name,
+                    ImplRef<HirpdagStorage<#struct_name>>,
+                    ImplRefWeak<HirpdagStorage<#struct_name>>,
+                >;
+
+                fn hirpdag_key_id(&self) -> u64 {
+                    self.0.hirpdag_get_creation_id()
+                }
+
+                fn hirpdag_key_downgrade(&self) -> Self::Weak {
+                    self.0.hirpdag_downgrade()
+                }
+
+                fn hirpdag_key_upgrade(weak: &Self::Weak) -> Option<Self> {
+                    weak.hirpdag_upgrade().map(#ref_name)
+                }
+            }
+        });
+
+        table_fields.extend(quote! {
+            #table_name: hirpdag::base::HirpdagRewriteCacheTable<#ref_name>,
+        });
+        table_new.extend(quote! {
+            #table_name: hirpdag::base::HirpdagRewriteCacheTable::new(capacity),
+        });
+        table_methods.extend(quote! {
+            /// The cached rewrite of `x`, or else `rewrite(x)`, which is
+            /// cached. A rewriter can call this from its `rewrite_X` method
+            /// to cache every node it rewrites, children included.
+            #[allow(non_snake_case)]
+            pub fn #get_or_rewrite<F: FnOnce(&#ref_name) -> #ref_name>(
+                &self,
+                x: &#ref_name,
+                rewrite: F,
+            ) -> #ref_name {
+                self.#table_name.get_or_insert_with(x, rewrite)
+            }
+
+            /// Removes the cached rewrite of `x`. Returns whether there was one.
+            #[allow(non_snake_case)]
+            pub fn #invalidate(&self, x: &#ref_name) -> bool {
+                self.#table_name.invalidate(x)
+            }
+
+            #[allow(non_snake_case)]
+            pub fn #table_accessor(&self) -> &hirpdag::base::HirpdagRewriteCacheTable<#ref_name> {
+                &self.#table_name
+            }
+        });
+        clear_calls.extend(quote! { self.#table_name.clear(); });
+        purge_terms.push(quote! { self.#table_name.purge() });
+        len_terms.push(quote! { self.#table_name.len() });
+        stats_terms.push(quote! { self.#table_name.stats() });
+
+        cached_methods.extend(quote! {
+            #[allow(non_snake_case)]
+            fn #rewrite_method_name(&self, x: &#ref_name) -> #ref_name {
+                self.cache
+                    .#get_or_rewrite(x, |x| self.rewriter.#rewrite_method_name(x))
+            }
+        });
+    }
+
+    quote! {
+        // ==== Persistent rewrite caches
+
+        #weak_key_impls
+
+        /// Rewrite results of one rewrite function, reusable across rewrite
+        /// calls, passes and threads.
+        ///
+        /// Entries are keyed weakly on the input node, so they do not keep
+        /// inputs alive and are dropped after their input dies. With a
+        /// capacity, each type's table evicts its least recently used
+        /// entries. A cache must only be used with one rewrite function:
+        /// entries record what that function returned.
+        #[allow(non_snake_case)]
+        pub struct HirpdagRewriteCache {
+            #table_fields
+        }
+
+        impl Default for HirpdagRewriteCache {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        #[allow(dead_code)]
+        impl HirpdagRewriteCache {
+            /// An unbounded cache.
+            pub fn new() -> Self {
+                Self::with_capacity(None)
+            }
+
+            /// A cache holding at most `capacity` entries per type, or
+            /// unbounded.
+            pub fn with_capacity(capacity: Option<usize>) -> Self {
+                Self {
+                    #table_new
+                }
+            }
+
+            #table_methods
+
+            /// Removes every entry.
+            pub fn clear(&self) {
+                #clear_calls
+            }
+
+            /// Removes the entries whose input node died. Returns how many.
+            pub fn purge(&self) -> usize {
+                0 #(+ #purge_terms)*
+            }
+
+            /// Number of entries, across all types.
+            pub fn len(&self) -> usize {
+                0 #(+ #len_terms)*
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.len() == 0
+            }
+
+            /// Counters, summed across all types.
+            pub fn stats(&self) -> hirpdag::base::HirpdagCacheStats {
+                hirpdag::base::HirpdagCacheStats::default() #(+ #stats_terms)*
+            }
+        }
+
+        /// Wraps a `HirpdagRewriter`, answering `rewrite_X` calls from a
+        /// shared `HirpdagRewriteCache` and caching the rewriter's results.
+        ///
+        /// Only calls made on the wrapper are cached; recursive calls the
+        /// inner rewriter makes on itself (e.g. through `default_rewrite`)
+        /// are not. To cache those as well, have the rewriter call
+        /// `HirpdagRewriteCache::get_or_rewrite_X` itself.
+        pub struct HirpdagRewriteCached<Rewriter: HirpdagRewriter> {
+            rewriter: Rewriter,
+            cache: std::sync::Arc<HirpdagRewriteCache>,
+        }
+
+        #[allow(dead_code)]
+        impl<Rewriter: HirpdagRewriter> HirpdagRewriteCached<Rewriter> {
+            pub fn new(rewriter: Rewriter, cache: std::sync::Arc<HirpdagRewriteCache>) -> Self {
+                Self { rewriter, cache }
+            }
+
+            pub fn cache(&self) -> &std::sync::Arc<HirpdagRewriteCache> {
+                &self.cache
+            }
+        }
+
+        impl<Rewriter: HirpdagRewriter> HirpdagRewriter for HirpdagRewriteCached<Rewriter> {
+            #cached_methods
+        }
+    }
+}
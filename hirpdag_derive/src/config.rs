@@ -10,6 +10,13 @@ pub enum HirpdagArg {
     /// generated HirpdagArchiveRoots struct.
     Root,
 
+    /// Display form will be defined by user (`hirpdag_pretty`).
+    Pretty,
+
+    /// This enum variant makes a metavariable of any node holding it, for
+    /// matching and unification. Only valid on enum variants.
+    Metavar,
+
     /// Hashconsing strong reference type specified by user.
     ReferenceType(String),
 
@@ -183,6 +190,8 @@ impl syn::parse::Parse for HirpdagArg {
         let arg_handler = match arg_name.as_str() {
             "normalizer" => Handler::Flag(|| Ok(Self::Normalizer)),
             "root" => Handler::Flag(|| Ok(Self::Root)),
+            "pretty" => Handler::Flag(|| Ok(Self::Pretty)),
+            "metavar" => Handler::Flag(|| Ok(Self::Metavar)),
             "reference_type" => {
                 Handler::String(|s: &syn::LitStr| Ok(Self::ReferenceType(s.value())))
             }
@@ -258,6 +267,8 @@ impl syn::parse::Parse for HirpdagArgs {
 pub struct HirpdagConfig {
     normalizer: bool,
     root: bool,
+    pretty: bool,
+    metavar: bool,
     types: ConfigTypes,
 }
 
@@ -266,6 +277,8 @@ impl HirpdagConfig {
         Self {
             normalizer: false,
             root: false,
+            pretty: false,
+            metavar: false,
             types: preset_types(DEFAULT_PRESET).expect("default preset is known"),
         }
     }
@@ -278,6 +291,8 @@ impl HirpdagConfig {
             match a {
                 HirpdagArg::Normalizer => config.normalizer = true,
                 HirpdagArg::Root => config.root = true,
+                HirpdagArg::Pretty => config.pretty = true,
+                HirpdagArg::Metavar => config.metavar = true,
                 HirpdagArg::ReferenceType(name) => config.types.reference_type = name.clone(),
                 HirpdagArg::ReferenceWeakType(name) => {
                     config.types.reference_weak_type = name.clone()
@@ -302,6 +317,12 @@ impl HirpdagConfig {
     pub fn is_root(&self) -> bool {
         self.root
     }
+    pub fn has_pretty(&self) -> bool {
+        self.pretty
+    }
+    pub fn is_metavar(&self) -> bool {
+        self.metavar
+    }
     pub fn reference_type(&self) -> TokenStream {
         self.types.reference_type.parse().unwrap()
     }
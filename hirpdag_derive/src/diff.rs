@@ -0,0 +1,227 @@
+// Generation of the per-module structural diff.
+
+use proc_macro2::{Ident, Span};
+
+/// The `HirpdagDiff` impls of a struct type: its data is diffed and patched
+/// field by field, and its ref is replaced as a whole where its data cannot
+/// be diffed.
+pub(crate) fn get_struct_diff_items(
+    ref_name: &Ident,
+    struct_name: &Ident,
+    fields_named: &syn::FieldsNamed,
+) -> proc_macro2::TokenStream {
+    let field_names: Vec<&Ident> = fields_named
+        .named
+        .iter()
+        .map(|t| t.ident.as_ref().unwrap())
+        .collect();
+    let field_strs: Vec<String> = field_names.iter().map(|f| f.to_string()).collect();
+    quote! {
+        impl hirpdag::base::HirpdagDiff<HirpdagNodeRef> for #struct_name {
+            fn hirpdag_diff(
+                &self,
+                new: &Self,
+                d: &mut hirpdag::base::HirpdagDiffer<HirpdagNodeRef>,
+            ) -> bool {
+                true #(&& d.nested(
+                    hirpdag::base::HirpdagPathSegment::Field(#field_strs),
+                    &self.#field_names,
+                    &new.#field_names,
+                ))*
+            }
+
+            fn hirpdag_patch(
+                &self,
+                steps: &[hirpdag::base::HirpdagPatchStep<'_, HirpdagNodeRef>],
+            ) -> Result<Self, hirpdag::base::HirpdagPatchError> {
+                hirpdag::base::HirpdagPatchStep::check(
+                    steps,
+                    &[#(hirpdag::base::HirpdagPathSegment::Field(#field_strs)),*],
+                )?;
+                Ok(Self {
+                    #(#field_names: hirpdag::base::HirpdagPatchStep::nested(
+                        steps,
+                        hirpdag::base::HirpdagPathSegment::Field(#field_strs),
+                        &self.#field_names,
+                    )?,)*
+                })
+            }
+        }
+
+        impl hirpdag::base::HirpdagDiff<HirpdagNodeRef> for #ref_name {
+            fn hirpdag_diff(
+                &self,
+                new: &Self,
+                d: &mut hirpdag::base::HirpdagDiffer<HirpdagNodeRef>,
+            ) -> bool {
+                if self != new {
+                    d.or_replace(
+                        |d| (**self).hirpdag_diff(&**new, d),
+                        || hirpdag::base::HirpdagChangeKind::Replaced {
+                            old: HirpdagNodeRef::from(self.clone()),
+                            new: HirpdagNodeRef::from(new.clone()),
+                        },
+                    );
+                }
+                true
+            }
+
+            /// Nodes along changed paths are rebuilt from their patched data
+            /// through the hashcons path, as when loading an archive: the
+            /// data comes from already-normalized nodes.
+            fn hirpdag_patch(
+                &self,
+                steps: &[hirpdag::base::HirpdagPatchStep<'_, HirpdagNodeRef>],
+            ) -> Result<Self, hirpdag::base::HirpdagPatchError> {
+                let current = HirpdagNodeRef::from(self.clone());
+                if let Some(node) = hirpdag::base::HirpdagPatchStep::replacement(steps, &current)? {
+                    return <#ref_name as std::convert::TryFrom<HirpdagNodeRef>>::try_from(node)
+                        .map_err(|_| steps[0].error("the replacing node has the wrong type"));
+                }
+                let data = (**self).hirpdag_patch(steps)?;
+                Ok(#ref_name(hirpdag::base::HirpdagStruct::hirpdag_hashcons(data)))
+            }
+
+            fn hirpdag_node(&self) -> Option<HirpdagNodeRef> {
+                Some(HirpdagNodeRef::from(self.clone()))
+            }
+
+            fn hirpdag_from_node(node: &HirpdagNodeRef) -> Option<Self> {
+                <#ref_name as std::convert::TryFrom<HirpdagNodeRef>>::try_from(node.clone()).ok()
+            }
+        }
+    }
+}
+
+/// The `HirpdagDiff` impl of an enum type: values of the same variant are
+/// diffed below the variant's name, and otherwise cannot be.
+pub(crate) fn get_enum_diff_items(
+    name: &Ident,
+    input_enum: &syn::DataEnum,
+) -> proc_macro2::TokenStream {
+    let mut variants_diff = proc_macro2::TokenStream::new();
+    let mut variants_patch = proc_macro2::TokenStream::new();
+    for t in &input_enum.variants {
+        let variant = &t.ident;
+        let variant_str = variant.to_string();
+        variants_diff.extend(quote! {
+            (#variant(a), #variant(b)) => d.nested(
+                hirpdag::base::HirpdagPathSegment::Variant(#variant_str),
+                a,
+                b,
+            ),
+        });
+        variants_patch.extend(quote! {
+            #variant(x) => {
+                let segment = hirpdag::base::HirpdagPathSegment::Variant(#variant_str);
+                hirpdag::base::HirpdagPatchStep::check(steps, &[segment])?;
+                Ok(#variant(hirpdag::base::HirpdagPatchStep::nested(steps, segment, x)?))
+            }
+        });
+    }
+    quote! {
+        impl hirpdag::base::HirpdagDiff<HirpdagNodeRef> for #name {
+            fn hirpdag_diff(
+                &self,
+                new: &Self,
+                d: &mut hirpdag::base::HirpdagDiffer<HirpdagNodeRef>,
+            ) -> bool {
+                use #name::*;
+                match (self, new) {
+                    #variants_diff
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }
+            }
+
+            fn hirpdag_patch(
+                &self,
+                steps: &[hirpdag::base::HirpdagPatchStep<'_, HirpdagNodeRef>],
+            ) -> Result<Self, hirpdag::base::HirpdagPatchError> {
+                use #name::*;
+                match self {
+                    #variants_patch
+                }
+            }
+        }
+    }
+}
+
+/// `HirpdagDiff` for `HirpdagNodeRef`, replacing a node by one of another
+/// type, the `HirpdagPatch` alias, and the `hirpdag_diff` entry point.
+///
+/// `struct_types` is (name, is_root) for each struct type in the module.
+pub(crate) fn get_diff_items(struct_types: &[(String, bool)]) -> proc_macro2::TokenStream {
+    let mut diff_arms = proc_macro2::TokenStream::new();
+    let mut patch_arms = proc_macro2::TokenStream::new();
+    for (name, _) in struct_types {
+        let ref_name = Ident::new(name, Span::call_site());
+        diff_arms.extend(quote! {
+            (HirpdagNodeRef::#ref_name(a), HirpdagNodeRef::#ref_name(b)) => a.hirpdag_diff(b, d),
+        });
+        patch_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => Ok(HirpdagNodeRef::#ref_name(x.hirpdag_patch(steps)?)),
+        });
+    }
+    quote! {
+        // ==== Structural diff
+
+        impl hirpdag::base::HirpdagDiff<HirpdagNodeRef> for HirpdagNodeRef {
+            fn hirpdag_diff(
+                &self,
+                new: &Self,
+                d: &mut hirpdag::base::HirpdagDiffer<HirpdagNodeRef>,
+            ) -> bool {
+                use hirpdag::base::HirpdagDiff;
+                match (self, new) {
+                    #diff_arms
+                    #[allow(unreachable_patterns)]
+                    _ => {
+                        d.change(hirpdag::base::HirpdagChangeKind::Replaced {
+                            old: self.clone(),
+                            new: new.clone(),
+                        });
+                        true
+                    }
+                }
+            }
+
+            fn hirpdag_patch(
+                &self,
+                steps: &[hirpdag::base::HirpdagPatchStep<'_, HirpdagNodeRef>],
+            ) -> Result<Self, hirpdag::base::HirpdagPatchError> {
+                use hirpdag::base::HirpdagDiff;
+                if let Some(node) = hirpdag::base::HirpdagPatchStep::replacement(steps, self)? {
+                    return Ok(node);
+                }
+                match self {
+                    #patch_arms
+                }
+            }
+
+            fn hirpdag_node(&self) -> Option<HirpdagNodeRef> {
+                Some(self.clone())
+            }
+
+            fn hirpdag_from_node(node: &HirpdagNodeRef) -> Option<Self> {
+                Some(node.clone())
+            }
+        }
+
+        /// The changes from one version of a value to another, to be applied
+        /// to the old version with `apply`.
+        #[allow(dead_code)]
+        pub type HirpdagPatch = hirpdag::base::HirpdagPatch<HirpdagNodeRef>;
+
+        /// The changes from `old` to `new`: a node, a vector of nodes, or
+        /// any field type holding nodes. `None` if they differ outside any
+        /// node, as two different enum values do.
+        #[allow(dead_code)]
+        pub fn hirpdag_diff<T: hirpdag::base::HirpdagDiff<HirpdagNodeRef>>(
+            old: &T,
+            new: &T,
+        ) -> Option<HirpdagPatch> {
+            hirpdag::base::HirpdagPatch::between(old, new)
+        }
+    }
+}
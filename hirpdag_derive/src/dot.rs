@@ -0,0 +1,184 @@
+// Generation of the per-module Graphviz export.
+
+use proc_macro2::{Ident, Span};
+
+/// The `HirpdagDotField` impls of a struct type: its ref is an edge, and its
+/// data describes each field under the field's name.
+pub(crate) fn get_struct_dot_field_items(
+    ref_name: &Ident,
+    struct_name: &Ident,
+    fields_named: &syn::FieldsNamed,
+) -> proc_macro2::TokenStream {
+    let fields_dot: proc_macro2::TokenStream = fields_named
+        .named
+        .iter()
+        .map(|t| t.ident.as_ref().unwrap())
+        .map(|field_name| {
+            let field_str = field_name.to_string();
+            quote! { fields.nested(#field_str, &self.#field_name); }
+        })
+        .collect();
+    quote! {
+        impl hirpdag::base::HirpdagDotField<HirpdagNodeRef> for #ref_name {
+            fn hirpdag_dot_field(&self, fields: &mut hirpdag::base::HirpdagDotFields<HirpdagNodeRef>) {
+                fields.edge(HirpdagNodeRef::from(self.clone()));
+            }
+        }
+
+        impl hirpdag::base::HirpdagDotField<HirpdagNodeRef> for #struct_name {
+            fn hirpdag_dot_field(&self, fields: &mut hirpdag::base::HirpdagDotFields<HirpdagNodeRef>) {
+                #fields_dot
+            }
+        }
+    }
+}
+
+/// The `HirpdagDotField` impl of an enum type: the active variant's payload,
+/// under the variant's name.
+pub(crate) fn get_enum_dot_field_items(
+    name: &Ident,
+    input_enum: &syn::DataEnum,
+) -> proc_macro2::TokenStream {
+    let variants_dot: proc_macro2::TokenStream = input_enum
+        .variants
+        .iter()
+        .map(|t| {
+            let variant = &t.ident;
+            let variant_str = variant.to_string();
+            quote! { #variant(x) => fields.nested(#variant_str, x), }
+        })
+        .collect();
+    quote! {
+        impl hirpdag::base::HirpdagDotField<HirpdagNodeRef> for #name {
+            fn hirpdag_dot_field(&self, fields: &mut hirpdag::base::HirpdagDotFields<HirpdagNodeRef>) {
+                use #name::*;
+                match self {
+                    #variants_dot
+                }
+            }
+        }
+    }
+}
+
+/// Generates the module-level export items: `HirpdagDotNode` for
+/// `HirpdagNodeRef`, the `HirpdagDotOptions` alias, and the entry points for
+/// root lists and, if the module has root types, typed roots.
+///
+/// `struct_types` is (name, is_root) for each struct type in the module.
+pub(crate) fn get_dot_items(struct_types: &[(String, bool)]) -> proc_macro2::TokenStream {
+    let mut type_name_arms = proc_macro2::TokenStream::new();
+    let mut meta_arms = proc_macro2::TokenStream::new();
+    let mut fields_arms = proc_macro2::TokenStream::new();
+    let mut roots_labelled = proc_macro2::TokenStream::new();
+    for (name, is_root) in struct_types {
+        let ref_name = Ident::new(name, Span::call_site());
+        type_name_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(_) => #name,
+        });
+        meta_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => x.0.hirpdag_get_meta().clone(),
+        });
+        fields_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => {
+                hirpdag::base::HirpdagDotField::hirpdag_dot_field(&**x, fields)
+            }
+        });
+        if *is_root {
+            let field_str = crate::to_snake_case(name);
+            let field_name = Ident::new(&field_str, Span::call_site());
+            roots_labelled.extend(quote! {
+                for (position, root) in roots.#field_name.iter().enumerate() {
+                    labelled.push((
+                        format!("{}[{}]", #field_str, position),
+                        HirpdagNodeRef::from(root.clone()),
+                    ));
+                }
+            });
+        }
+    }
+
+    let roots_items = if struct_types.iter().any(|(_, is_root)| *is_root) {
+        quote! {
+            /// Draws the given roots and every node reachable from them as a
+            /// Graphviz DOT digraph, each unique node once.
+            #[allow(dead_code)]
+            pub fn hirpdag_to_dot(roots: &HirpdagArchiveRoots) -> String {
+                hirpdag_to_dot_with_options(roots, &Default::default())
+            }
+
+            /// [`hirpdag_to_dot`] with options, e.g. to limit the depth, draw
+            /// only some types or highlight nodes.
+            #[allow(dead_code)]
+            pub fn hirpdag_to_dot_with_options(
+                roots: &HirpdagArchiveRoots,
+                options: &HirpdagDotOptions,
+            ) -> String {
+                let mut labelled = Vec::new();
+                #roots_labelled
+                hirpdag::base::hirpdag_dot(&labelled, options)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        // ==== Graphviz Export
+
+        impl hirpdag::base::HirpdagDotNode for HirpdagNodeRef {
+            fn hirpdag_dot_type_name(&self) -> &'static str {
+                match self {
+                    #type_name_arms
+                }
+            }
+
+            fn hirpdag_dot_meta(&self) -> hirpdag::base::HirpdagMeta {
+                match self {
+                    #meta_arms
+                }
+            }
+
+            fn hirpdag_dot_fields(&self, fields: &mut hirpdag::base::HirpdagDotFields<Self>) {
+                match self {
+                    #fields_arms
+                }
+            }
+        }
+
+        /// Options of [`hirpdag_list_to_dot_with_options`], highlighting
+        /// nodes of this module.
+        #[allow(dead_code)]
+        pub type HirpdagDotOptions = hirpdag::base::HirpdagDotOptions<HirpdagNodeRef>;
+
+        #roots_items
+
+        /// Draws the given root list and every node reachable from it as a
+        /// Graphviz DOT digraph. Roots are labelled by name, or position if
+        /// unnamed.
+        #[allow(dead_code)]
+        pub fn hirpdag_list_to_dot(root_list: &HirpdagRootList) -> String {
+            hirpdag_list_to_dot_with_options(root_list, &Default::default())
+        }
+
+        /// [`hirpdag_list_to_dot`] with options.
+        #[allow(dead_code)]
+        pub fn hirpdag_list_to_dot_with_options(
+            root_list: &HirpdagRootList,
+            options: &HirpdagDotOptions,
+        ) -> String {
+            let labelled: Vec<_> = root_list
+                .entries()
+                .iter()
+                .enumerate()
+                .map(|(position, entry)| {
+                    let label = entry
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("#{}", position));
+                    (label, entry.node.clone())
+                })
+                .collect();
+            hirpdag::base::hirpdag_dot(&labelled, options)
+        }
+    }
+}
@@ -0,0 +1,666 @@
+// Generation of the per-module e-graph (equality saturation) machinery.
+
+use proc_macro2::{Ident, Span};
+
+/// Per struct type identifiers used by the generated e-graph.
+struct EGraphType {
+    ref_name: Ident,
+    classes: Ident,
+    best: Ident,
+    rewrite: Ident,
+    add: Ident,
+    insert: Ident,
+    leader: Ident,
+    lookup: Ident,
+    union: Ident,
+    class_members: Ident,
+    extract: Ident,
+    variants: Ident,
+    repair: Ident,
+    apply: Ident,
+    cost: Ident,
+}
+
+impl EGraphType {
+    fn new(name: &str) -> Self {
+        let ident = |s: String| Ident::new(&s, Span::call_site());
+        Self {
+            ref_name: ident(name.to_string()),
+            classes: ident(format!("classes_{}", name)),
+            best: ident(format!("best_{}", name)),
+            rewrite: ident(format!("rewrite_{}", name)),
+            add: ident(format!("add_{}", name)),
+            insert: ident(format!("hirpdag_insert_{}", name)),
+            leader: ident(format!("hirpdag_leader_{}", name)),
+            lookup: ident(format!("lookup_{}", name)),
+            union: ident(format!("union_{}", name)),
+            class_members: ident(format!("class_members_{}", name)),
+            extract: ident(format!("extract_{}", name)),
+            variants: ident(format!("hirpdag_variants_{}", name)),
+            repair: ident(format!("hirpdag_repair_{}", name)),
+            apply: ident(format!("apply_{}", name)),
+            cost: ident(format!("cost_{}", name)),
+        }
+    }
+}
+
+/// Generates the module-level e-graph: `HirpdagEGraph` (union-find over
+/// e-classes of every hashconsed struct type, rebuilding and saturation),
+/// the `HirpdagEGraphRule` and `HirpdagEGraphCost` traits, and the internal
+/// rewriters used to canonicalize and extract nodes.
+///
+/// `struct_names` are the hashconsed struct types of the module. Enum types
+/// are inline payload, not e-nodes; their fields are canonicalized through
+/// the enum's `default_rewrite`.
+pub(crate) fn get_egraph_items(struct_names: &[String]) -> proc_macro2::TokenStream {
+    if struct_names.is_empty() {
+        return proc_macro2::TokenStream::new();
+    }
+    let types: Vec<EGraphType> = struct_names.iter().map(|n| EGraphType::new(n)).collect();
+
+    let mut classes_fields = proc_macro2::TokenStream::new();
+    let mut classes_new = proc_macro2::TokenStream::new();
+    let mut number_of_classes = Vec::new();
+    let mut number_of_nodes = Vec::new();
+    let mut type_methods = proc_macro2::TokenStream::new();
+    let mut repair_arms = proc_macro2::TokenStream::new();
+    let mut class_of_arms = proc_macro2::TokenStream::new();
+    let mut run_steps = proc_macro2::TokenStream::new();
+    let mut extract_steps = proc_macro2::TokenStream::new();
+    let mut extractor_methods = proc_macro2::TokenStream::new();
+    let mut choices_methods = proc_macro2::TokenStream::new();
+    let mut adder_methods = proc_macro2::TokenStream::new();
+    let mut leaders_methods = proc_macro2::TokenStream::new();
+    let mut best_fields = proc_macro2::TokenStream::new();
+    let mut best_methods = proc_macro2::TokenStream::new();
+    let mut rule_methods = proc_macro2::TokenStream::new();
+    let mut rule_blanket_methods = proc_macro2::TokenStream::new();
+    let mut cost_methods = proc_macro2::TokenStream::new();
+
+    for t in &types {
+        let EGraphType {
+            ref_name,
+            classes,
+            best,
+            rewrite,
+            add,
+            insert,
+            leader,
+            lookup,
+            union,
+            class_members,
+            extract,
+            variants,
+            repair,
+            apply,
+            cost,
+        } = t;
+        let msg_wrong_type = format!("hirpdag e-class is not a {} e-class", ref_name);
+
+        classes_fields.extend(quote! {
+            #classes: hirpdag::base::HirpdagEClasses<#ref_name>,
+        });
+        classes_new.extend(quote! {
+            #classes: hirpdag::base::HirpdagEClasses::new(),
+        });
+        number_of_classes.push(quote! { self.#classes.number_of_classes() });
+        number_of_nodes.push(quote! { self.#classes.number_of_nodes() });
+
+        type_methods.extend(quote! {
+            /// Adds `x`, and recursively its children, returning its e-class.
+            /// Nodes the e-graph already knows are not traversed again.
+            #[allow(non_snake_case)]
+            pub fn #add(&mut self, x: &#ref_name) -> hirpdag::base::HirpdagEClassId {
+                let adder = HirpdagEGraphAdder {
+                    egraph: std::cell::RefCell::new(self),
+                };
+                let added = adder.#rewrite(x);
+                let egraph = adder.egraph.into_inner();
+                let id = egraph.#classes.get(&added).expect("added node is known");
+                egraph.find(id)
+            }
+
+            /// Returns the e-class of `x` if it is represented in the e-graph.
+            #[allow(non_snake_case)]
+            pub fn #lookup(&self, x: &#ref_name) -> Option<hirpdag::base::HirpdagEClassId> {
+                if let Some(id) = self.#classes.get(x) {
+                    return Some(self.find(id));
+                }
+                let leaders = HirpdagEGraphLeaders::new(self);
+                let canonical = x.default_rewrite(&leaders);
+                if leaders.missing.get() {
+                    return None;
+                }
+                self.#classes.get(&canonical).map(|id| self.find(id))
+            }
+
+            /// Merges two e-classes of this type. Returns false if they were
+            /// already the same class. The representative of the merged
+            /// class is the one with the fewest nodes. Call `rebuild`
+            /// afterwards to restore congruence.
+            #[allow(non_snake_case)]
+            pub fn #union(
+                &mut self,
+                a: hirpdag::base::HirpdagEClassId,
+                b: hirpdag::base::HirpdagEClassId,
+            ) -> bool {
+                assert!(
+                    self.#classes.contains_class(self.find(a))
+                        && self.#classes.contains_class(self.find(b)),
+                    #msg_wrong_type
+                );
+                // Keep the class whose representative is smaller, so rules
+                // see the simplest known form of each child.
+                let size = |id| {
+                    self.#classes
+                        .leader(self.find(id))
+                        .map_or(u32::MAX, |x| x.hirpdag_compute_meta().get_count())
+                };
+                let (a, b) = if size(b) < size(a) { (b, a) } else { (a, b) };
+                match self.unionfind.union(a, b) {
+                    Some((root, merged)) => {
+                        self.#classes.merge(root, merged);
+                        if let Some(parents) = self.parents.remove(&merged) {
+                            self.parents.entry(root).or_default().extend(parents);
+                        }
+                        self.pending.push(root);
+                        true
+                    }
+                    None => false,
+                }
+            }
+
+            /// The e-nodes of the class of `id`. Their children are the
+            /// representatives of the child classes at the time each e-node
+            /// was added.
+            #[allow(non_snake_case)]
+            pub fn #class_members(&self, id: hirpdag::base::HirpdagEClassId) -> &[#ref_name] {
+                self.#classes.members(self.find(id))
+            }
+
+            /// Extracts the cheapest term of the class of `id` under `cost`,
+            /// as an ordinary interned node. Returns `None` if the class has
+            /// no finite term. Costs every class; use `extractor` to extract
+            /// several terms.
+            #[allow(non_snake_case)]
+            pub fn #extract<C: HirpdagEGraphCost>(
+                &self,
+                id: hirpdag::base::HirpdagEClassId,
+                cost: &C,
+            ) -> Option<#ref_name> {
+                self.extractor(cost).#extract(id)
+            }
+
+            /// `x` with its children replaced by every combination of the
+            /// e-nodes of their classes, the representatives first.
+            #[allow(non_snake_case)]
+            fn #variants(&self, x: &#ref_name) -> Vec<#ref_name> {
+                let mut out = Vec::new();
+                let mut choice = Vec::new();
+                loop {
+                    let choices = HirpdagEGraphChoices {
+                        egraph: self,
+                        choice: &choice,
+                        sizes: std::cell::RefCell::new(Vec::new()),
+                    };
+                    out.push(x.default_rewrite(&choices));
+                    let sizes = choices.sizes.into_inner();
+                    choice.resize(sizes.len(), 0);
+                    // The next combination, last child first.
+                    match (0..sizes.len()).rev().find(|&i| choice[i] + 1 < sizes[i]) {
+                        Some(i) => {
+                            choice[i] += 1;
+                            choice[i + 1..].iter_mut().for_each(|c| *c = 0);
+                        }
+                        None => return out,
+                    }
+                }
+            }
+
+            /// Re-canonicalizes the e-node `x` after a child class merged,
+            /// merging its class with that of the result.
+            #[allow(non_snake_case)]
+            fn #repair(&mut self, x: &#ref_name) {
+                let Some(id) = self.#classes.get(x) else {
+                    return;
+                };
+                let canonical = x.default_rewrite(&HirpdagEGraphLeaders::new(self));
+                if canonical != *x {
+                    let canonical_id = self.#add(&canonical);
+                    self.#union(id, canonical_id);
+                }
+            }
+
+            /// The representative of the class `x` is known in.
+            #[allow(non_snake_case)]
+            fn #leader(&self, x: &#ref_name) -> Option<#ref_name> {
+                let id = self.find(self.#classes.get(x)?);
+                self.#classes.leader(id).cloned()
+            }
+
+            /// Records `canonical` (the e-node of `x`, whose children are
+            /// class representatives) and returns its class representative.
+            #[allow(non_snake_case)]
+            fn #insert(&mut self, x: &#ref_name, canonical: #ref_name) -> #ref_name {
+                let id = match self.#classes.get(&canonical) {
+                    Some(id) => self.find(id),
+                    None => {
+                        let id = self.unionfind.make_set();
+                        self.#classes.insert_class(id, canonical.clone());
+                        self.hirpdag_add_parent(HirpdagNodeRef::from(canonical.clone()));
+                        id
+                    }
+                };
+                if *x != canonical {
+                    self.#classes.insert_known(x.clone(), id);
+                }
+                self.#classes
+                    .leader(id)
+                    .cloned()
+                    .expect("e-class has a representative")
+            }
+        });
+
+        repair_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => self.#repair(x),
+        });
+
+        class_of_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => self.#classes.get(x),
+        });
+
+        run_steps.extend(quote! {
+            let matches: Vec<(hirpdag::base::HirpdagEClassId, #ref_name)> = self
+                .#classes
+                .iter()
+                .flat_map(|(id, x)| self.#variants(x).into_iter().map(move |v| (id, v)))
+                .flat_map(|(id, v)| {
+                    rules
+                        .iter()
+                        .filter_map(move |rule| rule.#apply(&v).map(|y| (id, y)))
+                })
+                .collect();
+            for (id, y) in matches {
+                let y_id = self.#add(&y);
+                changed |= self.#union(id, y_id);
+            }
+        });
+
+        extract_steps.extend(quote! {
+            for (id, x) in self.#classes.iter() {
+                let (candidate, missing) = {
+                    let rewriter = HirpdagEGraphBestRewriter {
+                        egraph: self,
+                        best: &best,
+                        missing: std::cell::Cell::new(false),
+                    };
+                    let candidate = x.default_rewrite(&rewriter);
+                    (candidate, rewriter.missing.get())
+                };
+                if missing {
+                    continue;
+                }
+                let c = cost.#cost(&candidate);
+                if best.#best.get(&id).map_or(true, |(old, _)| c < *old) {
+                    best.#best.insert(id, (c, candidate));
+                    changed = true;
+                }
+            }
+        });
+
+        adder_methods.extend(quote! {
+            #[allow(non_snake_case)]
+            fn #rewrite(&self, x: &#ref_name) -> #ref_name {
+                if let Some(leader) = self.egraph.borrow().#leader(x) {
+                    return leader;
+                }
+                let canonical = x.default_rewrite(self);
+                self.egraph.borrow_mut().#insert(x, canonical)
+            }
+        });
+
+        leaders_methods.extend(quote! {
+            #[allow(non_snake_case)]
+            fn #rewrite(&self, x: &#ref_name) -> #ref_name {
+                if let Some(leader) = self.egraph.#leader(x) {
+                    return leader;
+                }
+                match self
+                    .egraph
+                    .#lookup(x)
+                    .and_then(|id| self.egraph.#classes.leader(id))
+                {
+                    Some(leader) => leader.clone(),
+                    None => {
+                        self.missing.set(true);
+                        x.clone()
+                    }
+                }
+            }
+        });
+
+        best_fields.extend(quote! {
+            #best: std::collections::HashMap<hirpdag::base::HirpdagEClassId, (u64, #ref_name)>,
+        });
+
+        extractor_methods.extend(quote! {
+            /// The cheapest term of the class of `id`, as an ordinary
+            /// interned node. Returns `None` if the class has no finite term.
+            #[allow(non_snake_case)]
+            pub fn #extract(&self, id: hirpdag::base::HirpdagEClassId) -> Option<#ref_name> {
+                self.best.#best.get(&self.egraph.find(id)).map(|(_, x)| x.clone())
+            }
+        });
+
+        choices_methods.extend(quote! {
+            #[allow(non_snake_case)]
+            fn #rewrite(&self, x: &#ref_name) -> #ref_name {
+                let mut sizes = self.sizes.borrow_mut();
+                let position = sizes.len();
+                let members = self
+                    .egraph
+                    .#classes
+                    .get(x)
+                    .map_or(&[][..], |id| self.egraph.#class_members(id));
+                sizes.push(members.len().max(1));
+                let choice = self.choice.get(position).copied().unwrap_or(0);
+                members.get(choice).cloned().unwrap_or_else(|| x.clone())
+            }
+        });
+
+        best_methods.extend(quote! {
+            #[allow(non_snake_case)]
+            fn #rewrite(&self, x: &#ref_name) -> #ref_name {
+                let best = self
+                    .egraph
+                    .#classes
+                    .get(x)
+                    .and_then(|id| self.best.#best.get(&self.egraph.find(id)));
+                match best {
+                    Some((_, b)) => b.clone(),
+                    None => {
+                        self.missing.set(true);
+                        x.clone()
+                    }
+                }
+            }
+        });
+
+        rule_methods.extend(quote! {
+            /// Returns a node equivalent to the e-node `x`, or `None` if the
+            /// rule does not apply.
+            #[allow(non_snake_case)]
+            fn #apply(&self, x: &#ref_name) -> Option<#ref_name>;
+        });
+
+        rule_blanket_methods.extend(quote! {
+            #[allow(non_snake_case)]
+            fn #apply(&self, x: &#ref_name) -> Option<#ref_name> {
+                let y = self.#rewrite(x);
+                if y == *x {
+                    None
+                } else {
+                    Some(y)
+                }
+            }
+        });
+
+        cost_methods.extend(quote! {
+            #[allow(non_snake_case)]
+            fn #cost(&self, x: &#ref_name) -> u64 {
+                u64::from(x.hirpdag_compute_meta().get_count())
+            }
+        });
+    }
+
+    quote! {
+        // ==== Equality saturation
+        //
+        // An e-node is an interned node whose child refs are the
+        // representatives of their e-classes, so congruent e-nodes are
+        // pointer-equal and the hash-consing table does the "hashcons" part
+        // of the e-graph. Canonicalizing a node is a `default_rewrite` that
+        // maps each child to its class representative.
+
+        /// An e-graph over the hirpdag types of this module.
+        ///
+        /// Add nodes with `add_Foo`, saturate with `run`, and extract the
+        /// cheapest equivalent term with `extract_Foo`. Canonicalization
+        /// rebuilds nodes with `new`, so normalizers still apply to every
+        /// e-node.
+        pub struct HirpdagEGraph {
+            unionfind: hirpdag::base::HirpdagUnionFind,
+            #classes_fields
+            /// The e-nodes with a child in each class, by canonical id.
+            parents: std::collections::HashMap<hirpdag::base::HirpdagEClassId, Vec<HirpdagNodeRef>>,
+            /// Classes merged since the last `rebuild`.
+            pending: Vec<hirpdag::base::HirpdagEClassId>,
+        }
+
+        impl Default for HirpdagEGraph {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        #[allow(dead_code)]
+        impl HirpdagEGraph {
+            pub fn new() -> Self {
+                Self {
+                    unionfind: hirpdag::base::HirpdagUnionFind::new(),
+                    #classes_new
+                    parents: std::collections::HashMap::new(),
+                    pending: Vec::new(),
+                }
+            }
+
+            /// The canonical id of the class containing `id`.
+            pub fn find(&self, id: hirpdag::base::HirpdagEClassId) -> hirpdag::base::HirpdagEClassId {
+                self.unionfind.find(id)
+            }
+
+            /// Number of e-classes, across all types.
+            pub fn number_of_classes(&self) -> usize {
+                0 #(+ #number_of_classes)*
+            }
+
+            /// Number of e-nodes, across all types.
+            pub fn number_of_nodes(&self) -> usize {
+                0 #(+ #number_of_nodes)*
+            }
+
+            #type_methods
+
+            /// Restores congruence after unions: e-nodes with a child in a
+            /// merged class are re-canonicalized, and classes whose
+            /// canonical e-nodes coincide are merged, until nothing changes.
+            pub fn rebuild(&mut self) {
+                while !self.pending.is_empty() {
+                    let mut pending: Vec<hirpdag::base::HirpdagEClassId> =
+                        std::mem::take(&mut self.pending)
+                            .into_iter()
+                            .map(|id| self.find(id))
+                            .collect();
+                    pending.sort();
+                    pending.dedup();
+                    for id in pending {
+                        let parents = self.parents.get(&self.find(id)).cloned().unwrap_or_default();
+                        for parent in &parents {
+                            self.hirpdag_repair(parent);
+                        }
+                    }
+                }
+            }
+
+            fn hirpdag_repair(&mut self, node: &HirpdagNodeRef) {
+                match node {
+                    #repair_arms
+                }
+            }
+
+            /// The class of `node`, if it is known.
+            fn hirpdag_class_of(&self, node: &HirpdagNodeRef) -> Option<hirpdag::base::HirpdagEClassId> {
+                let id = match node {
+                    #class_of_arms
+                };
+                id.map(|id| self.find(id))
+            }
+
+            /// Records the new e-node `node` as a parent of its child classes.
+            fn hirpdag_add_parent(&mut self, node: HirpdagNodeRef) {
+                let mut children = Vec::new();
+                hirpdag::base::HirpdagTerm::hirpdag_term_children(&node, &mut children);
+                for child in children {
+                    if let Some(id) = self.hirpdag_class_of(&child) {
+                        self.parents.entry(id).or_default().push(node.clone());
+                    }
+                }
+            }
+
+            /// Applies `rules` to every e-node, adding each result to the
+            /// e-node's class, then rebuilds; repeats until saturation or
+            /// `iter_limit` iterations.
+            pub fn run(
+                &mut self,
+                rules: &[&dyn HirpdagEGraphRule],
+                iter_limit: usize,
+            ) -> hirpdag::base::HirpdagEGraphStop {
+                self.rebuild();
+                for _ in 0..iter_limit {
+                    let nodes_before = self.number_of_nodes();
+                    let mut changed = false;
+                    #run_steps
+                    self.rebuild();
+                    if !changed && self.number_of_nodes() == nodes_before {
+                        return hirpdag::base::HirpdagEGraphStop::Saturated;
+                    }
+                }
+                hirpdag::base::HirpdagEGraphStop::IterationLimit
+            }
+
+            /// The cheapest term of every class under `cost`, computed once to
+            /// extract any number of terms while the e-graph is unchanged.
+            pub fn extractor<C: HirpdagEGraphCost>(&self, cost: &C) -> HirpdagEGraphExtractor<'_> {
+                HirpdagEGraphExtractor {
+                    egraph: self,
+                    best: self.hirpdag_extract_best(cost),
+                }
+            }
+
+            /// Cheapest term of every class, computed to a fixpoint: a class
+            /// gets a term once all child classes of one of its e-nodes have
+            /// one, and costs only ever decrease.
+            fn hirpdag_extract_best<C: HirpdagEGraphCost>(&self, cost: &C) -> HirpdagEGraphBest {
+                let mut best = HirpdagEGraphBest::default();
+                loop {
+                    let mut changed = false;
+                    #extract_steps
+                    if !changed {
+                        return best;
+                    }
+                }
+            }
+        }
+
+        /// A rewrite rule for `HirpdagEGraph::run`.
+        ///
+        /// Implemented for every `HirpdagRewriter`: the rule applies to an
+        /// e-node when rewriting it produces a different node, and the
+        /// result is added to the e-node's class.
+        ///
+        /// Each e-node is given to the rules once per combination of the
+        /// e-nodes of its child classes, so a rule matching one level below
+        /// the node finds any e-node of the child class, not only its
+        /// representative. Further down, it sees representatives only.
+        pub trait HirpdagEGraphRule {
+            #rule_methods
+        }
+
+        impl<R: HirpdagRewriter> HirpdagEGraphRule for R {
+            #rule_blanket_methods
+        }
+
+        /// Cost function for extracting terms from a `HirpdagEGraph`. Each
+        /// method is given a fully built candidate term; the default cost is
+        /// its `HirpdagMeta` node count.
+        pub trait HirpdagEGraphCost {
+            #cost_methods
+        }
+
+        /// The default `HirpdagEGraphCost`: fewest nodes.
+        pub struct HirpdagEGraphMetaCount;
+
+        impl HirpdagEGraphCost for HirpdagEGraphMetaCount {}
+
+        /// Adds nodes to the e-graph, children first.
+        struct HirpdagEGraphAdder<'a> {
+            egraph: std::cell::RefCell<&'a mut HirpdagEGraph>,
+        }
+
+        impl<'a> HirpdagRewriter for HirpdagEGraphAdder<'a> {
+            #adder_methods
+        }
+
+        /// Maps each child to its class representative without adding
+        /// anything; `missing` is set if a child is not in the e-graph.
+        struct HirpdagEGraphLeaders<'a> {
+            egraph: &'a HirpdagEGraph,
+            missing: std::cell::Cell<bool>,
+        }
+
+        impl<'a> HirpdagEGraphLeaders<'a> {
+            fn new(egraph: &'a HirpdagEGraph) -> Self {
+                Self {
+                    egraph,
+                    missing: std::cell::Cell::new(false),
+                }
+            }
+        }
+
+        impl<'a> HirpdagRewriter for HirpdagEGraphLeaders<'a> {
+            #leaders_methods
+        }
+
+        #[derive(Default)]
+        struct HirpdagEGraphBest {
+            #best_fields
+        }
+
+        /// The cheapest term of every class of a `HirpdagEGraph` under a
+        /// cost function; see `HirpdagEGraph::extractor`.
+        pub struct HirpdagEGraphExtractor<'a> {
+            egraph: &'a HirpdagEGraph,
+            best: HirpdagEGraphBest,
+        }
+
+        #[allow(dead_code)]
+        impl HirpdagEGraphExtractor<'_> {
+            #extractor_methods
+        }
+
+        /// Maps the children of a node to e-nodes of their classes: the
+        /// `choice[i]`th member for the `i`th child visited, recording the
+        /// size of each child's class in `sizes`.
+        struct HirpdagEGraphChoices<'a> {
+            egraph: &'a HirpdagEGraph,
+            choice: &'a [usize],
+            sizes: std::cell::RefCell<Vec<usize>>,
+        }
+
+        impl<'a> HirpdagRewriter for HirpdagEGraphChoices<'a> {
+            #choices_methods
+        }
+
+        /// Maps each child to the best term found so far for its class;
+        /// `missing` is set if a child class has none yet.
+        struct HirpdagEGraphBestRewriter<'a> {
+            egraph: &'a HirpdagEGraph,
+            best: &'a HirpdagEGraphBest,
+            missing: std::cell::Cell<bool>,
+        }
+
+        impl<'a> HirpdagRewriter for HirpdagEGraphBestRewriter<'a> {
+            #best_methods
+        }
+    }
+}
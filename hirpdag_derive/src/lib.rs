@@ -7,7 +7,17 @@ extern crate syn;
 extern crate proc_macro;
 extern crate proc_macro2;
 
+mod cache;
 mod config;
+mod diff;
+mod dot;
+mod egraph;
+mod pretty;
+mod provenance;
+mod schema;
+mod sexpr;
+mod strategy;
+mod unify;
 
 use crate::config::{HirpdagArgs, HirpdagConfig};
 
@@ -27,6 +37,10 @@ struct DataTypeEntry {
     /// module, in declaration order, are hashed into the schema fingerprint
     /// embedded in binary archives.
     definition: String,
+    /// Field names and types of a struct, or variant names and payload
+    /// types of an enum, in declaration order. Described by the generated
+    /// `hirpdag_schema()`.
+    members: Vec<(String, syn::Type)>,
 }
 
 /// FNV-1a 64-bit hash. Implemented here (rather than using std's
@@ -118,6 +132,10 @@ fn expand_module_items(
         if let Some(attr) = take_hirpdag_attr(&mut item) {
             let args = parse_hirpdag_args(&attr)?;
             let type_config = HirpdagConfig::from(&args);
+            let metavars = match &mut item {
+                syn::Item::Enum(e) => take_metavar_variants(e)?,
+                _ => Vec::new(),
+            };
             let input: syn::DeriveInput = match item {
                 syn::Item::Struct(s) => s.into(),
                 syn::Item::Enum(e) => e.into(),
@@ -125,7 +143,9 @@ fn expand_module_items(
             };
             body.extend(match &input.data {
                 syn::Data::Struct(s) => expand_hirpdag_struct(&type_config, &input, s, &mut types),
-                syn::Data::Enum(e) => expand_hirpdag_enum(&type_config, &input, e, &mut types),
+                syn::Data::Enum(e) => {
+                    expand_hirpdag_enum(&type_config, &input, e, &metavars, &mut types)
+                }
                 _ => unreachable!(),
             });
         } else {
@@ -148,6 +168,36 @@ fn take_hirpdag_attr(item: &mut syn::Item) -> Option<syn::Attribute> {
     Some(attrs.remove(position))
 }
 
+/// Removes the `#[hirpdag(metavar)]` attributes of an enum's variants, and
+/// returns the variants they marked.
+fn take_metavar_variants(item: &mut syn::ItemEnum) -> syn::Result<Vec<Ident>> {
+    let mut metavars = Vec::new();
+    for variant in item.variants.iter_mut() {
+        let position = match variant
+            .attrs
+            .iter()
+            .position(|a| a.path().is_ident("hirpdag"))
+        {
+            Some(position) => position,
+            None => continue,
+        };
+        let attr = variant.attrs.remove(position);
+        let config = HirpdagConfig::from(&parse_hirpdag_args(&attr)?);
+        if !config.is_metavar()
+            || config.has_normalizer()
+            || config.is_root()
+            || config.has_pretty()
+        {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "enum variants only take `#[hirpdag(metavar)]`",
+            ));
+        }
+        metavars.push(variant.ident.clone());
+    }
+    Ok(metavars)
+}
+
 fn parse_hirpdag_args(attr: &syn::Attribute) -> syn::Result<HirpdagArgs> {
     match &attr.meta {
         syn::Meta::Path(_) => syn::parse2(proc_macro2::TokenStream::new()),
@@ -254,6 +304,33 @@ fn get_fields_compute_meta(fields_named: &syn::FieldsNamed) -> proc_macro2::Toke
         .collect()
 }
 
+/// The struct's `hirpdag_compute_stable_hash`, with the `stable-hash`
+/// feature: hashes the type name, then each field in declaration order.
+fn get_compute_stable_hash(
+    name: &str,
+    fields_named: &syn::FieldsNamed,
+) -> proc_macro2::TokenStream {
+    if !cfg!(feature = "stable-hash") {
+        return quote! {};
+    }
+    let fields_stable_hash: proc_macro2::TokenStream = fields_named
+        .named
+        .iter()
+        .map(|t| t.ident.as_ref().unwrap())
+        .map(|field_name| {
+            quote! { HirpdagStableHash::hirpdag_stable_hash_into(&self.#field_name, &mut hasher); }
+        })
+        .collect();
+    quote! {
+        fn hirpdag_compute_stable_hash(&self) -> u64 {
+            let mut hasher = HirpdagStableHasher::new();
+            hasher.write_str(#name);
+            #fields_stable_hash
+            hasher.finish()
+        }
+    }
+}
+
 /// Body of a struct's `default_rewrite`.
 ///
 /// Each field is rewritten into a local, then the rewritten values are compared
@@ -443,6 +520,33 @@ fn get_default_normalizer(
     }
 }
 
+/// The body of the generated `HirpdagStruct::hirpdag_hashcons`. Types with a
+/// normalizer verify freshly interned nodes when normalizer verification is
+/// enabled (see `hirpdag::base::HirpdagVerifyMode`).
+fn get_hashcons_body(
+    config: &HirpdagConfig,
+    name: &str,
+    table_name: &Ident,
+) -> proc_macro2::TokenStream {
+    if !config.has_normalizer() {
+        return quote! {
+            #table_name.hirpdag_hashcons(self)
+        };
+    }
+    let ref_name = Ident::new(name, Span::call_site());
+    quote! {
+        let (r, fresh) = #table_name.hirpdag_hashcons_fresh(self);
+        if fresh {
+            hirpdag::base::hirpdag_verify_normal(#name, || {
+                let node = #ref_name(r.clone());
+                let normalized = #ref_name::hirpdag_renormalize((*r).clone());
+                (node, normalized)
+            });
+        }
+        r
+    }
+}
+
 fn expand_hirpdag_struct(
     config: &HirpdagConfig,
     input: &syn::DeriveInput,
@@ -454,6 +558,10 @@ fn expand_hirpdag_struct(
     let name_str = name.to_string();
     let name_uppercase_str = name_str.to_ascii_uppercase();
 
+    if config.is_metavar() {
+        panic!("`#[hirpdag(metavar)]` can only be applied to enum variants");
+    }
+
     types.push(DataTypeEntry {
         name: name_str.clone(),
         is_struct: true,
@@ -463,6 +571,11 @@ fn expand_hirpdag_struct(
             config.is_root(),
             get_fields_named(input_struct),
         ),
+        members: get_fields_named(input_struct)
+            .named
+            .iter()
+            .map(|f| (f.ident.as_ref().unwrap().to_string(), f.ty.clone()))
+            .collect(),
     });
 
     let hirpdag_ref_name_str = name_str.to_string();
@@ -474,9 +587,7 @@ fn expand_hirpdag_struct(
     let hirpdag_table_name_str = format!("HIRPDAG_TABLE_{}", name_uppercase_str);
     let hirpdag_table_name = Ident::new(&hirpdag_table_name_str, Span::call_site());
 
-    let hirpdag_rewrite_method_name_str = format!("rewrite_{}", name_str);
-    let hirpdag_rewrite_method_name =
-        Ident::new(&hirpdag_rewrite_method_name_str, Span::call_site());
+    let traced_rewrite_body = provenance::get_traced_rewrite_body(&name_str);
 
     let hirpdag_builder_name_str = format!("{}Builder", name_str);
     let hirpdag_builder_name = Ident::new(&hirpdag_builder_name_str, Span::call_site());
@@ -486,8 +597,39 @@ fn expand_hirpdag_struct(
     let fields_parameters = get_fields_parameters(fields_named);
     let fields_list = get_fields_list(fields_named);
     let fields_compute_meta = get_fields_compute_meta(fields_named);
+    let compute_stable_hash = get_compute_stable_hash(&name_str, fields_named);
+    let ref_stable_hash = if cfg!(feature = "stable-hash") {
+        quote! {
+            impl HirpdagStableHash for #hirpdag_ref_name {
+                fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+                    self.0.hirpdag_stable_hash_into(hasher)
+                }
+            }
+
+            impl #hirpdag_ref_name {
+                /// A hash of this node's content, equal for structurally
+                /// equal nodes in any process.
+                pub fn hirpdag_stable_hash(&self) -> u64 {
+                    self.0.hirpdag_stable_hash()
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
     let default_rewrite_body = get_default_rewrite_body(fields_named);
     let fields_collect = get_fields_collect(fields_named);
+    let dot_field_items =
+        dot::get_struct_dot_field_items(&hirpdag_ref_name, &hirpdag_struct_name, fields_named);
+    let pretty_items =
+        pretty::get_struct_pretty_items(&hirpdag_ref_name, fields_named, config.has_pretty());
+    let sexpr_items =
+        sexpr::get_struct_sexpr_items(&hirpdag_ref_name, &hirpdag_struct_name, fields_named);
+    let diff_items =
+        diff::get_struct_diff_items(&hirpdag_ref_name, &hirpdag_struct_name, fields_named);
+    let unify_items =
+        unify::get_struct_unify_items(&hirpdag_ref_name, &hirpdag_struct_name, fields_named);
+    let metavar_flags = unify::get_struct_metavar_flags(fields_named);
 
     let msg_outside_ser_session = format!(
         "hirpdag ref {} serialized outside a hirpdag serialization session",
@@ -501,7 +643,6 @@ fn expand_hirpdag_struct(
         "hirpdag ref {} deserialized outside a hirpdag deserialization session",
         name_str
     );
-    let msg_node_type_mismatch = format!("hirpdag node type mismatch: expected {}", name_str);
 
     let builder_field_declarations = get_builder_field_declarations(fields_named);
     let builder_setters = get_builder_setters(fields_named);
@@ -510,6 +651,7 @@ fn expand_hirpdag_struct(
     let builder_build_args = get_builder_build_args(fields_named);
 
     let default_normalizer = get_default_normalizer(config, fields_named);
+    let hashcons_body = get_hashcons_body(config, &name_str, &hirpdag_table_name);
 
     quote! {
         use hirpdag::base::*;
@@ -525,8 +667,12 @@ fn expand_hirpdag_struct(
             type ReferenceStorageStruct = ImplRef<HirpdagStorage<#hirpdag_struct_name>>;
             fn hirpdag_hashcons(self) ->
             HirpdagRef<#hirpdag_struct_name, ImplRef<HirpdagStorage<#hirpdag_struct_name>>> {
-                #hirpdag_table_name.hirpdag_hashcons(self)
+                #hashcons_body
             }
+
+            #metavar_flags
+
+            #compute_stable_hash
         }
 
         impl HirpdagComputeMeta for #hirpdag_struct_name {
@@ -551,7 +697,7 @@ fn expand_hirpdag_struct(
                 );
         }
 
-        #[derive(Hash, Clone, Debug, PartialEq, Eq)]
+        #[derive(Hash, Clone, PartialEq, Eq)]
         pub struct #hirpdag_ref_name(HirpdagRef<#hirpdag_struct_name, ImplRef<HirpdagStorage<#hirpdag_struct_name>>>);
 
         impl std::ops::Deref for #hirpdag_ref_name {
@@ -586,6 +732,8 @@ fn expand_hirpdag_struct(
             }
         }
 
+        #ref_stable_hash
+
         impl #hirpdag_ref_name {
             fn spawn(#fields_parameters) -> Self {
                 let data = #hirpdag_struct_name { #fields_list };
@@ -604,6 +752,14 @@ fn expand_hirpdag_struct(
             // If normalizer is not provided, generate one.
             #default_normalizer
 
+            /// Builds a node from interned data through `new`, applying the
+            /// normalizer (if any) again.
+            #[doc(hidden)]
+            pub fn hirpdag_renormalize(data: #hirpdag_struct_name) -> Self {
+                let #hirpdag_struct_name { #fields_list } = data;
+                Self::new(#fields_list)
+            }
+
             #[allow(non_snake_case)]
             pub fn default_rewrite<T: HirpdagRewriter>(&self, rewriter: &T) -> Self {
                 #default_rewrite_body
@@ -651,7 +807,7 @@ fn expand_hirpdag_struct(
 
         impl<T: HirpdagRewriter> HirpdagRewritable<T> for #hirpdag_ref_name {
             fn hirpdag_rewrite(&self, rewriter: &T) -> Self {
-                rewriter.#hirpdag_rewrite_method_name(self)
+                #traced_rewrite_body
             }
         }
 
@@ -697,17 +853,22 @@ fn expand_hirpdag_struct(
                     // ever references nodes that are already reconstructed.
                     // A forward reference is indistinguishable from an
                     // out-of-range index here, and both are rejected.
-                    if index >= nodes.len() as u64 {
-                        return Err(<D::Error as hirpdag::serde::de::Error>::custom(format!(
-                            "hirpdag node index {} is invalid (out of range or forward reference)",
-                            index
-                        )));
-                    }
-                    match &nodes[index as usize] {
-                        HirpdagNodeRef::#hirpdag_ref_name(r) => Ok(r.clone()),
+                    // Partial reads leave the nodes they skip empty.
+                    match nodes.get(index as usize).and_then(Option::as_ref) {
+                        Some(HirpdagNodeRef::#hirpdag_ref_name(r)) => Ok(r.clone()),
                         #[allow(unreachable_patterns)]
-                        _ => Err(<D::Error as hirpdag::serde::de::Error>::custom(
-                            #msg_node_type_mismatch,
+                        Some(found) => Err(hirpdag::base::hirpdag_de_raise(
+                            hirpdag::base::HirpdagDeserializeError::NodeTypeMismatch {
+                                expected: #name_str,
+                                found: found.hirpdag_type_name(),
+                                at_node: hirpdag::base::hirpdag_de_node(),
+                            },
+                        )),
+                        None => Err(hirpdag::base::hirpdag_de_raise(
+                            hirpdag::base::HirpdagDeserializeError::InvalidNodeIndex {
+                                index,
+                                at_node: hirpdag::base::hirpdag_de_node(),
+                            },
                         )),
                     }
                 })
@@ -717,15 +878,17 @@ fn expand_hirpdag_struct(
         impl hirpdag::base::HirpdagCollect<HirpdagCollectCtx> for #hirpdag_ref_name {
             fn hirpdag_collect(&self, ctx: &mut HirpdagCollectCtx) {
                 let creation_id = self.0.hirpdag_get_creation_id();
-                if ctx.seen.contains_key(&creation_id) {
+                if let Some(index) = ctx.seen.get(&creation_id) {
+                    if let Some(children) = &mut ctx.children {
+                        children.push(*index);
+                    }
                     return;
                 }
                 // Post-order DFS: register children before their parent so
                 // every child's node index is smaller than its parent's.
                 hirpdag::base::HirpdagCollect::hirpdag_collect(&(**self), ctx);
-                let index = ctx.nodes.len() as u64;
-                ctx.nodes
-                    .push(HirpdagArchiveNode::#hirpdag_ref_name((**self).clone()));
+                let index = ctx.base_nodes + ctx.nodes.len() as u64;
+                ctx.nodes.push(HirpdagNodeRef::#hirpdag_ref_name(self.clone()));
                 ctx.seen.insert(creation_id, index);
             }
         }
@@ -735,6 +898,16 @@ fn expand_hirpdag_struct(
                 #fields_collect
             }
         }
+
+        #dot_field_items
+
+        #pretty_items
+
+        #sexpr_items
+
+        #diff_items
+
+        #unify_items
     }
 }
 
@@ -764,6 +937,38 @@ fn get_variants_compute_meta(input_enum: &syn::DataEnum) -> proc_macro2::TokenSt
         .collect()
 }
 
+/// The enum's `HirpdagStableHash` impl, with the `stable-hash` feature:
+/// hashes the variant name, then the payload.
+fn get_enum_stable_hash(name: &Ident, input_enum: &syn::DataEnum) -> proc_macro2::TokenStream {
+    if !cfg!(feature = "stable-hash") {
+        return quote! {};
+    }
+    let variants_stable_hash: proc_macro2::TokenStream = input_enum
+        .variants
+        .iter()
+        .map(|t| {
+            let variant = &t.ident;
+            let variant_str = variant.to_string();
+            quote! {
+                #variant(x) => {
+                    hasher.write_str(#variant_str);
+                    x.hirpdag_stable_hash_into(hasher);
+                }
+            }
+        })
+        .collect();
+    quote! {
+        impl HirpdagStableHash for #name {
+            fn hirpdag_stable_hash_into(&self, hasher: &mut HirpdagStableHasher) {
+                use #name::*;
+                match self {
+                    #variants_stable_hash
+                }
+            }
+        }
+    }
+}
+
 fn get_variants_collect(input_enum: &syn::DataEnum) -> proc_macro2::TokenStream {
     //let variants_collect = quote! {
     //    Foo(x) => hirpdag::base::HirpdagCollect::hirpdag_collect(x, ctx),
@@ -802,6 +1007,7 @@ fn expand_hirpdag_enum(
     config: &HirpdagConfig,
     input: &syn::DeriveInput,
     input_enum: &syn::DataEnum,
+    metavars: &[Ident],
     types: &mut Vec<DataTypeEntry>,
 ) -> proc_macro2::TokenStream {
     let name: &Ident = &input.ident;
@@ -811,12 +1017,23 @@ fn expand_hirpdag_enum(
     if config.is_root() {
         panic!("`#[hirpdag(root)]` can only be applied to structs; enums are not hashconsed");
     }
+    if config.is_metavar() {
+        panic!("`#[hirpdag(metavar)]` can only be applied to enum variants");
+    }
 
     types.push(DataTypeEntry {
         name: name_str.clone(),
         is_struct: false,
         is_root: false,
         definition: get_definition_string_enum(&name_str, input_enum),
+        members: input_enum
+            .variants
+            .iter()
+            .filter_map(|v| {
+                let ty = v.fields.iter().next()?.ty.clone();
+                Some((v.ident.to_string(), ty))
+            })
+            .collect(),
     });
 
     let hirpdag_rewrite_method_name_str = format!("rewrite_{}", name_str);
@@ -827,6 +1044,12 @@ fn expand_hirpdag_enum(
     let variants_compute_meta = get_variants_compute_meta(input_enum);
     let variants_rewrite = get_variants_rewrite(input_enum);
     let variants_collect = get_variants_collect(input_enum);
+    let enum_stable_hash = get_enum_stable_hash(name, input_enum);
+    let dot_field_items = dot::get_enum_dot_field_items(name, input_enum);
+    let pretty_items = pretty::get_enum_pretty_items(name, input_enum, config.has_pretty());
+    let sexpr_items = sexpr::get_enum_sexpr_items(name, input_enum);
+    let diff_items = diff::get_enum_diff_items(name, input_enum);
+    let unify_items = unify::get_enum_unify_items(name, input_enum, metavars);
 
     quote! {
         use hirpdag::base::*;
@@ -847,6 +1070,8 @@ fn expand_hirpdag_enum(
             }
         }
 
+        #enum_stable_hash
+
         impl #name {
             #[allow(non_snake_case)]
             pub fn default_rewrite<T: HirpdagRewriter>(&self, rewriter: &T) -> Self {
@@ -876,6 +1101,16 @@ fn expand_hirpdag_enum(
                 }
             }
         }
+
+        #dot_field_items
+
+        #pretty_items
+
+        #sexpr_items
+
+        #diff_items
+
+        #unify_items
     }
 }
 
@@ -1043,7 +1278,26 @@ fn expand_hirpdag_end(config: &HirpdagConfig, types: &[DataTypeEntry]) -> proc_m
         name
     };
 
-    let serialization_items = get_serialization_items(&struct_types, schema_hash, &schema_name);
+    let schema_fn = schema::get_schema_fn(types);
+    let serialization_items =
+        get_serialization_items(&struct_types, schema_hash, &schema_name, schema_fn);
+
+    let all_types: Vec<(String, bool)> = types
+        .iter()
+        .map(|entry| (entry.name.clone(), entry.is_struct))
+        .collect();
+    let provenance_items = provenance::get_provenance_items(&all_types);
+    let cache_items = cache::get_cache_items(&all_types);
+    let strategy_items = strategy::get_strategy_items(&all_types);
+    let rule_methods = strategy::get_rule_methods(&all_types);
+
+    let struct_names: Vec<String> = struct_types.iter().map(|(name, _)| name.clone()).collect();
+    let egraph_items = egraph::get_egraph_items(&struct_names);
+    let dot_items = dot::get_dot_items(&struct_types);
+    let pretty_items = pretty::get_pretty_items(&struct_types);
+    let sexpr_items = sexpr::get_sexpr_items(&struct_types);
+    let diff_items = diff::get_diff_items(&struct_types);
+    let unify_items = unify::get_unify_items(&struct_types);
 
     let reference_type: proc_macro2::TokenStream = config.reference_type();
     let reference_weak_type: proc_macro2::TokenStream = config.reference_weak_type();
@@ -1068,6 +1322,8 @@ fn expand_hirpdag_end(config: &HirpdagConfig, types: &[DataTypeEntry]) -> proc_m
         pub trait HirpdagRewriter: std::marker::Sized {
             #rewrite_methods
 
+            #rule_methods
+
             fn rewrite<T: HirpdagRewritable<Self>>(&self, x: &T) -> T {
                 x.hirpdag_rewrite(self)
             }
@@ -1103,6 +1359,24 @@ fn expand_hirpdag_end(config: &HirpdagConfig, types: &[DataTypeEntry]) -> proc_m
         }
 
         #serialization_items
+
+        #provenance_items
+
+        #cache_items
+
+        #strategy_items
+
+        #egraph_items
+
+        #dot_items
+
+        #pretty_items
+
+        #sexpr_items
+
+        #diff_items
+
+        #unify_items
     }
 }
 
@@ -1125,19 +1399,21 @@ fn to_snake_case(name: &str) -> String {
 
 /// Generates the module-level serialization machinery: the archive node enum,
 /// the HirpdagArchiveRoots struct (one vector per `#[hirpdag(root)]` type),
-/// the collect context, the thread-local (de)serialization sessions, and the
-/// public entry points.
+/// the HirpdagRootList alias, the collect context, the thread-local
+/// (de)serialization sessions, and the public entry points.
 ///
 /// `struct_types` is (name, is_root) for each hashconsed struct type.
 ///
-/// The session/collect infrastructure is generated whenever the module has
-/// struct types (the per-struct impls generated by `#[hirpdag]` refer to it).
-/// The HirpdagArchiveRoots struct and the entry points are only generated
-/// when at least one type is marked `#[hirpdag(root)]`.
+/// The session/collect infrastructure and the root list entry points are
+/// generated whenever the module has struct types (the per-struct impls
+/// generated by `#[hirpdag]` refer to it). The HirpdagArchiveRoots struct and
+/// its entry points are only generated when at least one type is marked
+/// `#[hirpdag(root)]`.
 fn get_serialization_items(
     struct_types: &[(String, bool)],
     schema_hash: u64,
     schema_name: &str,
+    schema_fn: proc_macro2::TokenStream,
 ) -> proc_macro2::TokenStream {
     if struct_types.is_empty() {
         // No hashconsed types in this module; nothing to serialize.
@@ -1147,9 +1423,18 @@ fn get_serialization_items(
 
     let mut archive_variants = proc_macro2::TokenStream::new();
     let mut noderef_variants = proc_macro2::TokenStream::new();
+    let mut noderef_from = proc_macro2::TokenStream::new();
+    let mut noderef_type_name_arms = proc_macro2::TokenStream::new();
+    let mut noderef_creation_id_arms = proc_macro2::TokenStream::new();
+    let mut noderef_stable_hash_arms = proc_macro2::TokenStream::new();
+    let mut archive_node_arms = proc_macro2::TokenStream::new();
     let mut intern_arms = proc_macro2::TokenStream::new();
-    let mut roots_field_declarations = proc_macro2::TokenStream::new();
-    let mut roots_fields_collect = proc_macro2::TokenStream::new();
+    let mut noderef_children_arms = proc_macro2::TokenStream::new();
+    let mut noderef_collect_arms = proc_macro2::TokenStream::new();
+    let mut noderef_serialize_arms = proc_macro2::TokenStream::new();
+    let mut noderef_try_from = proc_macro2::TokenStream::new();
+    let mut roots = RootsTokens::default();
+    let mut root_field = 0usize;
 
     for (name, is_root) in struct_types {
         let ref_name = Ident::new(name, Span::call_site());
@@ -1161,37 +1446,149 @@ fn get_serialization_items(
         noderef_variants.extend(quote! {
             #ref_name(#ref_name),
         });
+        noderef_from.extend(quote! {
+            impl From<#ref_name> for HirpdagNodeRef {
+                fn from(x: #ref_name) -> Self {
+                    HirpdagNodeRef::#ref_name(x)
+                }
+            }
+        });
+        noderef_type_name_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(_) => #name,
+        });
+        noderef_creation_id_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => x.0.hirpdag_get_creation_id(),
+        });
+        noderef_stable_hash_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => x.hirpdag_stable_hash(),
+        });
+        archive_node_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => HirpdagArchiveNode::#ref_name((**x).clone()),
+        });
+        noderef_children_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => hirpdag::base::HirpdagCollect::hirpdag_collect(&(**x), ctx),
+        });
+        noderef_collect_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => hirpdag::base::HirpdagCollect::hirpdag_collect(x, ctx),
+        });
+        noderef_serialize_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => hirpdag::serde::Serialize::serialize(x, serializer),
+        });
+        noderef_try_from.extend(quote! {
+            impl std::convert::TryFrom<HirpdagNodeRef> for #ref_name {
+                type Error = HirpdagNodeRef;
+
+                fn try_from(node: HirpdagNodeRef) -> Result<Self, HirpdagNodeRef> {
+                    match node {
+                        HirpdagNodeRef::#ref_name(x) => Ok(x),
+                        #[allow(unreachable_patterns)]
+                        other => Err(other),
+                    }
+                }
+            }
+        });
         // Nodes are re-interned through the normal hashcons path (not the
         // normalizing constructor: the archived data was produced from
         // already-normalized nodes). This merges with any nodes already live
-        // in the process and restores sharing exactly.
+        // in the process and restores sharing exactly. With the renormalize
+        // option, nodes are rebuilt through `new` instead, for archives
+        // written under an older normalizer.
         intern_arms.extend(quote! {
-            HirpdagArchiveNode::#ref_name(data) => HirpdagNodeRef::#ref_name(#ref_name(
-                hirpdag::base::HirpdagStruct::hirpdag_hashcons(data),
-            )),
+            HirpdagArchiveNode::#ref_name(data) => HirpdagNodeRef::#ref_name(if renormalize {
+                #ref_name::hirpdag_renormalize(data)
+            } else {
+                #ref_name(hirpdag::base::HirpdagStruct::hirpdag_hashcons(data))
+            }),
         });
 
         if *is_root {
-            let field_name = Ident::new(&to_snake_case(name), Span::call_site());
-            roots_field_declarations.extend(quote! {
+            let field_str = to_snake_case(name);
+            let field_name = Ident::new(&field_str, Span::call_site());
+            roots.declarations.extend(quote! {
                 pub #field_name: Vec<#ref_name>,
             });
-            roots_fields_collect.extend(quote! {
+            roots.collect.extend(quote! {
                 for root in &self.#field_name {
                     hirpdag::base::HirpdagCollect::hirpdag_collect(root, ctx);
                 }
             });
+            roots.index.extend(quote! {
+                roots.#field_name
+                    .iter()
+                    .map(|root| seen[&root.0.hirpdag_get_creation_id()])
+                    .collect(),
+            });
+            roots.sort.extend(quote! {
+                roots
+                    .#field_name
+                    .sort_by_key(|root| hashes[&root.0.hirpdag_get_creation_id()]);
+            });
+            roots.selection_declarations.extend(quote! {
+                pub #field_name: Vec<usize>,
+            });
+            roots.selection_nodes.extend(quote! {
+                for position in &self.#field_name {
+                    nodes.push(index.root(#root_field, #field_str, *position)?);
+                }
+            });
+            roots.selection_resolve.extend(quote! {
+                #field_name: self
+                    .#field_name
+                    .iter()
+                    .map(|position| {
+                        let node = index.root(#root_field, #field_str, *position)?;
+                        match nodes.get(node as usize).and_then(Option::as_ref) {
+                            Some(HirpdagNodeRef::#ref_name(r)) => Ok(r.clone()),
+                            #[allow(unreachable_patterns)]
+                            Some(found) => Err(hirpdag::base::HirpdagDeserializeError::NodeTypeMismatch {
+                                expected: #name,
+                                found: found.hirpdag_type_name(),
+                                at_node: None,
+                            }),
+                            None => Err(hirpdag::base::HirpdagDeserializeError::InvalidNodeIndex {
+                                index: node,
+                                at_node: None,
+                            }),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?,
+            });
+            roots.selection_select.extend(quote! {
+                #field_name: self
+                    .#field_name
+                    .iter()
+                    .map(|position| {
+                        roots.#field_name.get(*position).cloned().ok_or(
+                            hirpdag::base::HirpdagDeserializeError::MissingRoot {
+                                root: #field_str,
+                                position: *position as u64,
+                            },
+                        )
+                    })
+                    .collect::<Result<_, _>>()?,
+            });
+            root_field += 1;
         }
     }
 
-    let roots_items = get_serialization_roots_items(
-        has_roots,
-        roots_field_declarations,
-        roots_fields_collect,
-        intern_arms,
-        schema_hash,
-        schema_name,
-    );
+    let archive_items =
+        get_serialization_archive_items(intern_arms, schema_hash, schema_name, schema_fn);
+    let list_items = get_serialization_list_items();
+    let roots_items = get_serialization_roots_items(has_roots, roots);
+
+    let noderef_stable_hash = if cfg!(feature = "stable-hash") {
+        quote! {
+            /// A hash of the node's content, equal for structurally equal
+            /// nodes in any process.
+            pub fn hirpdag_stable_hash(&self) -> u64 {
+                match self {
+                    #noderef_stable_hash_arms
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
 
     quote! {
         // ==== Serialization
@@ -1209,135 +1606,993 @@ fn get_serialization_items(
             #archive_variants
         }
 
-        /// A reconstructed node of any hirpdag type in this module.
-        /// Internal to the deserialization session: node references resolve
-        /// their u64 index against a vector of these.
-        #[derive(Clone, Debug)]
+        /// A node of any hirpdag struct type in this module.
+        ///
+        /// Deserialization resolves node references (u64 indices) against a
+        /// vector of these; rewrite provenance records its steps with them.
+        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
         #[allow(dead_code)]
-        enum HirpdagNodeRef {
+        pub enum HirpdagNodeRef {
             #noderef_variants
         }
 
+        #noderef_from
+
+        #[allow(dead_code)]
+        impl HirpdagNodeRef {
+            /// The name of the node's hirpdag type.
+            pub fn hirpdag_type_name(&self) -> &'static str {
+                match self {
+                    #noderef_type_name_arms
+                }
+            }
+
+            /// The creation id of the node, unique among live nodes of its
+            /// type.
+            pub fn hirpdag_creation_id(&self) -> u64 {
+                match self {
+                    #noderef_creation_id_arms
+                }
+            }
+
+            #noderef_stable_hash
+
+            /// Collects the node's data (but not the node itself): with every
+            /// child already collected, records the children's indices.
+            fn hirpdag_collect_data(&self, ctx: &mut HirpdagCollectCtx) {
+                match self {
+                    #noderef_children_arms
+                }
+            }
+        }
+
+        impl hirpdag::base::HirpdagProvenanceNode for HirpdagNodeRef {
+            fn hirpdag_provenance_id(&self) -> u64 {
+                self.hirpdag_creation_id()
+            }
+
+            fn hirpdag_provenance_type_name(&self) -> &'static str {
+                self.hirpdag_type_name()
+            }
+        }
+
+        /// The node table entry of a node. Built one node at a time while
+        /// writing, so the node table never holds a copy of every node's data.
+        #[allow(dead_code)]
+        fn hirpdag_archive_node(node: &HirpdagNodeRef) -> HirpdagArchiveNode {
+            match node {
+                #archive_node_arms
+            }
+        }
+
         /// Collect phase state: dedup map from node creation id to node table
-        /// index, and the node table itself in post-order DFS order.
+        /// index, and the node table itself (as references to the nodes) in
+        /// post-order DFS order. For a delta archive, the base's nodes are
+        /// pre-registered and the node table starts after them.
         #[doc(hidden)]
         pub struct HirpdagCollectCtx {
             seen: std::collections::HashMap<u64, u64>,
-            nodes: Vec<HirpdagArchiveNode>,
+            nodes: Vec<HirpdagNodeRef>,
+            base_nodes: u64,
+            /// When set, the indices of already collected nodes reached.
+            children: Option<Vec<u64>>,
         }
 
         impl HirpdagCollectCtx {
-            fn new() -> Self {
+            fn new(base: &[HirpdagNodeRef]) -> Self {
                 Self {
-                    seen: std::collections::HashMap::new(),
+                    seen: base
+                        .iter()
+                        .enumerate()
+                        .map(|(index, node)| (node.hirpdag_creation_id(), index as u64))
+                        .collect(),
                     nodes: Vec::new(),
+                    base_nodes: base.len() as u64,
+                    children: None,
                 }
             }
         }
 
         // serde's traits carry no user state, so the ref index resolution
         // state lives in thread-local sessions scoped to the entry points
-        // below. Sessions are per-thread and not re-entrant.
+        // below. Sessions are per-thread and not re-entrant. The
+        // deserialization session starts with the base's nodes when reading
+        // a delta archive.
         std::thread_local! {
             static HIRPDAG_SER_SESSION: std::cell::RefCell<
                 Option<std::collections::HashMap<u64, u64>>,
             > = std::cell::RefCell::new(None);
-            static HIRPDAG_DE_SESSION: std::cell::RefCell<Option<Vec<HirpdagNodeRef>>> =
+            static HIRPDAG_DE_SESSION: std::cell::RefCell<Option<Vec<Option<HirpdagNodeRef>>>> =
                 std::cell::RefCell::new(None);
+            static HIRPDAG_DE_RENORMALIZE: std::cell::Cell<bool> =
+                const { std::cell::Cell::new(false) };
+        }
+
+        #noderef_try_from
+
+        impl hirpdag::base::HirpdagCollect<HirpdagCollectCtx> for HirpdagNodeRef {
+            fn hirpdag_collect(&self, ctx: &mut HirpdagCollectCtx) {
+                match self {
+                    #noderef_collect_arms
+                }
+            }
+        }
+
+        // A node of any type serializes as its node table index, like a ref
+        // of its type.
+        impl hirpdag::serde::Serialize for HirpdagNodeRef {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: hirpdag::serde::Serializer,
+            {
+                match self {
+                    #noderef_serialize_arms
+                }
+            }
+        }
+
+        impl<'de> hirpdag::serde::Deserialize<'de> for HirpdagNodeRef {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: hirpdag::serde::Deserializer<'de>,
+            {
+                let index: u64 =
+                    <u64 as hirpdag::serde::Deserialize>::deserialize(deserializer)?;
+                HIRPDAG_DE_SESSION.with(|cell| {
+                    let borrow = cell.borrow();
+                    let nodes = borrow.as_ref().ok_or_else(|| {
+                        <D::Error as hirpdag::serde::de::Error>::custom(
+                            "hirpdag node deserialized outside a hirpdag deserialization session",
+                        )
+                    })?;
+                    nodes.get(index as usize).and_then(Option::as_ref).cloned().ok_or_else(|| {
+                        hirpdag::base::hirpdag_de_raise(
+                            hirpdag::base::HirpdagDeserializeError::InvalidNodeIndex {
+                                index,
+                                at_node: hirpdag::base::hirpdag_de_node(),
+                            },
+                        )
+                    })
+                })
+            }
         }
 
+        #archive_items
+
+        #list_items
+
         #roots_items
     }
 }
 
-/// Generates the roots-dependent serialization items: the HirpdagArchiveRoots
-/// struct, the session guards, the archive container and the public entry
-/// points. Empty when no type in the module is marked `#[hirpdag(root)]`.
-fn get_serialization_roots_items(
-    has_roots: bool,
-    roots_field_declarations: proc_macro2::TokenStream,
-    roots_fields_collect: proc_macro2::TokenStream,
+/// Per root type tokens of the roots items, one field or statement each.
+#[derive(Default)]
+struct RootsTokens {
+    /// `HirpdagArchiveRoots` field declarations.
+    declarations: proc_macro2::TokenStream,
+    /// Collect statements for the roots.
+    collect: proc_macro2::TokenStream,
+    /// The roots' node indices (`HirpdagArchiveIndex::roots` entries).
+    index: proc_macro2::TokenStream,
+    /// Statements sorting the roots by content hash.
+    sort: proc_macro2::TokenStream,
+    /// `HirpdagRootSelection` field declarations.
+    selection_declarations: proc_macro2::TokenStream,
+    /// Statements pushing the selected roots' node indices.
+    selection_nodes: proc_macro2::TokenStream,
+    /// `HirpdagArchiveRoots` fields of the selected roots, from a node table.
+    selection_resolve: proc_macro2::TokenStream,
+    /// `HirpdagArchiveRoots` fields of the selected roots, from all roots.
+    selection_select: proc_macro2::TokenStream,
+}
+
+/// Generates the archive machinery shared by the typed roots and the root
+/// list: the schema fingerprint and description, the session guards, the
+/// node table, and the collect, encode and decode helpers, generic over the
+/// archive layout.
+fn get_serialization_archive_items(
     intern_arms: proc_macro2::TokenStream,
     schema_hash: u64,
     schema_name: &str,
+    schema_fn: proc_macro2::TokenStream,
 ) -> proc_macro2::TokenStream {
-    if !has_roots {
-        return proc_macro2::TokenStream::new();
-    }
-
-    quote! {
-        /// The schema fingerprint embedded in (and verified against) the
-        /// header of binary archives written by this module.
-        #[allow(dead_code)]
-        fn hirpdag_schema_fingerprint() -> hirpdag::base::HirpdagSchemaFingerprint {
-            hirpdag::base::HirpdagSchemaFingerprint {
-                hash: #schema_hash,
-                name: #schema_name.to_string(),
+    // The node store keys nodes by their stable hash.
+    let store_items = if cfg!(feature = "stable-hash") {
+        quote! {
+            /// A content-addressed store of this module's nodes, in a
+            /// directory: see [`hirpdag::base::HirpdagStoreDir`]. Each node is
+            /// written once, and read back with the nodes it reaches.
+            #[allow(dead_code)]
+            pub struct HirpdagNodeStore {
+                dir: hirpdag::base::HirpdagStoreDir,
+                options: hirpdag::base::HirpdagDeserializeOptions,
             }
-        }
 
-        /// The roots of a serialized archive: one vector per
-        /// `#[hirpdag(root)]` type. Input of the serialize entry points and
-        /// output of the deserialize entry points.
-        ///
-        /// Implements `Default`, so a subset of root types can be set with
-        /// struct update syntax:
-        /// `HirpdagArchiveRoots { foo: vec![x], ..Default::default() }`.
-        #[derive(Clone, Debug, Default, PartialEq, Eq)]
-        #[derive(hirpdag::serde::Serialize, hirpdag::serde::Deserialize)]
-        #[serde(crate = "hirpdag::serde", default)]
-        #[allow(dead_code)]
-        pub struct HirpdagArchiveRoots {
-            #roots_field_declarations
-        }
+            #[allow(dead_code)]
+            impl HirpdagNodeStore {
+                /// Opens the store in `dir`, creating it if there is none.
+                /// Fails with `SchemaMismatch` for a store of other types.
+                pub fn open(
+                    dir: impl AsRef<std::path::Path>,
+                ) -> Result<Self, hirpdag::base::HirpdagDeserializeError> {
+                    Self::open_with_options(dir, &Default::default())
+                }
 
-        impl hirpdag::base::HirpdagCollect<HirpdagCollectCtx> for HirpdagArchiveRoots {
-            fn hirpdag_collect(&self, ctx: &mut HirpdagCollectCtx) {
-                #roots_fields_collect
-            }
-        }
+                /// [`HirpdagNodeStore::open`] with options for reading
+                /// nodes, e.g. to renormalize them. Migrations do not apply.
+                pub fn open_with_options(
+                    dir: impl AsRef<std::path::Path>,
+                    options: &hirpdag::base::HirpdagDeserializeOptions,
+                ) -> Result<Self, hirpdag::base::HirpdagDeserializeError> {
+                    let dir = hirpdag::base::HirpdagStoreDir::open(
+                        dir.as_ref(),
+                        &hirpdag_schema_fingerprint(),
+                    )?;
+                    Ok(Self {
+                        dir,
+                        options: options.clone(),
+                    })
+                }
 
-        struct HirpdagSerSessionGuard;
+                /// Whether the store holds the node with stable hash `hash`.
+                pub fn contains(&self, hash: u64) -> bool {
+                    self.dir.contains(hash)
+                }
 
-        impl HirpdagSerSessionGuard {
-            fn open(
-                index_map: std::collections::HashMap<u64, u64>,
-            ) -> Result<Self, hirpdag::base::HirpdagSerializeError> {
-                HIRPDAG_SER_SESSION.with(|cell| {
-                    let mut borrow = cell.borrow_mut();
-                    if borrow.is_some() {
-                        return Err(hirpdag::base::HirpdagSerializeError::SessionActive);
-                    }
-                    *borrow = Some(index_map);
-                    Ok(HirpdagSerSessionGuard)
-                })
-            }
-        }
+                /// The number of nodes stored.
+                pub fn len(&self) -> usize {
+                    self.dir.len()
+                }
 
-        impl Drop for HirpdagSerSessionGuard {
-            fn drop(&mut self) {
-                HIRPDAG_SER_SESSION.with(|cell| *cell.borrow_mut() = None);
-            }
-        }
+                pub fn is_empty(&self) -> bool {
+                    self.dir.is_empty()
+                }
+
+                /// Writes `node` and the nodes it reaches that the store does
+                /// not hold yet, and returns its stable hash.
+                pub fn put(
+                    &mut self,
+                    node: impl Into<HirpdagNodeRef>,
+                ) -> Result<u64, hirpdag::base::HirpdagSerializeError> {
+                    let node = node.into();
+                    let hash = node.hirpdag_stable_hash();
+                    if self.dir.contains(hash) {
+                        return Ok(hash);
+                    }
+                    let mut ctx = HirpdagCollectCtx::new(&[]);
+                    hirpdag::base::HirpdagCollect::hirpdag_collect(&node, &mut ctx);
+                    let nodes = std::mem::take(&mut ctx.nodes);
+                    let mut records = Vec::new();
+                    for node in &nodes {
+                        let hash = node.hirpdag_stable_hash();
+                        if self.dir.contains(hash) {
+                            continue;
+                        }
+                        // Collecting a node's data again, with all nodes
+                        // collected, only reaches its children.
+                        ctx.children = Some(Vec::new());
+                        node.hirpdag_collect_data(&mut ctx);
+                        let mut children = ctx.children.take().unwrap_or_default();
+                        children.sort_unstable();
+                        children.dedup();
+                        // References encode as positions in the children.
+                        let positions = children
+                            .iter()
+                            .enumerate()
+                            .map(|(position, &child)| {
+                                (nodes[child as usize].hirpdag_creation_id(), position as u64)
+                            })
+                            .collect();
+                        let session = HirpdagSerSessionGuard::open(positions)?;
+                        let bytes = hirpdag::postcard::to_stdvec(&hirpdag_archive_node(node))
+                            .map_err(|e| hirpdag::base::HirpdagSerializeError::Format(e.to_string()))?;
+                        drop(session);
+                        records.push(hirpdag::base::HirpdagStoreRecord {
+                            hash,
+                            children: children
+                                .iter()
+                                .map(|&child| nodes[child as usize].hirpdag_stable_hash())
+                                .collect(),
+                            node: bytes,
+                        });
+                    }
+                    self.dir.write(&records)?;
+                    Ok(hash)
+                }
+
+                /// Reads the node with stable hash `hash`, interning it and
+                /// the nodes it reaches. Fails with `MissingNode` if the store
+                /// does not hold it or one of those.
+                pub fn get(
+                    &self,
+                    hash: u64,
+                ) -> Result<HirpdagNodeRef, hirpdag::base::HirpdagDeserializeError> {
+                    let mut read = std::collections::HashMap::new();
+                    let mut interned: std::collections::HashMap<u64, HirpdagNodeRef> =
+                        std::collections::HashMap::new();
+                    // Depth first: a node is interned once its children are.
+                    let mut stack = vec![hash];
+                    while let Some(&top) = stack.last() {
+                        if interned.contains_key(&top) {
+                            stack.pop();
+                            continue;
+                        }
+                        let Some(record) = read.remove(&top) else {
+                            let record: hirpdag::base::HirpdagStoreRecord = self.dir.read(top)?;
+                            stack.extend(
+                                record
+                                    .children
+                                    .iter()
+                                    .filter(|child| !interned.contains_key(child)),
+                            );
+                            read.insert(top, record);
+                            continue;
+                        };
+                        // A child still pending is one of the node's ancestors.
+                        let children = record
+                            .children
+                            .iter()
+                            .map(|child| {
+                                interned.get(child).cloned().ok_or_else(|| {
+                                    hirpdag::base::HirpdagDeserializeError::Format(format!(
+                                        "the node store holds a cycle through stable hash {:#018x}",
+                                        child
+                                    ))
+                                })
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let data: HirpdagArchiveNode = {
+                            let session = HirpdagDeSessionGuard::open_on(&self.options, children)?;
+                            hirpdag::postcard::from_bytes(&record.node).map_err(|e| {
+                                session.refine(hirpdag::base::hirpdag_postcard_de_error(e))
+                            })?
+                        };
+                        let node = hirpdag_intern(data, self.options.renormalize);
+                        if !self.options.renormalize && node.hirpdag_stable_hash() != top {
+                            return Err(hirpdag::base::HirpdagDeserializeError::Format(format!(
+                                "the node stored under stable hash {:#018x} does not match it",
+                                top
+                            )));
+                        }
+                        interned.insert(top, node);
+                        stack.pop();
+                    }
+                    Ok(interned.remove(&hash).expect("the node is interned last"))
+                }
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+
+    let binary_items = if cfg!(feature = "postcard") {
+        quote! {
+            /// The binary header written by this module for an archive of
+            /// `layout`.
+            fn hirpdag_binary_header(
+                options: &hirpdag::base::HirpdagSerializeOptions,
+                layout: hirpdag::base::HirpdagRootLayout,
+            ) -> hirpdag::base::HirpdagBinaryHeader {
+                hirpdag::base::HirpdagBinaryHeader {
+                    fingerprint: hirpdag_schema_fingerprint(),
+                    schema: options.embed_schema.then(hirpdag_schema),
+                    base: None,
+                    flags: options.flags() | layout.flags(),
+                }
+            }
+
+            /// Content hashes of `nodes`, given children first, by creation
+            /// id: a hash of each node's encoding, with its references
+            /// encoded as the content hashes of the nodes they reference.
+            /// Structurally equal nodes hash equally however they were built.
+            fn hirpdag_content_hashes<'a>(
+                nodes: impl Iterator<Item = &'a HirpdagNodeRef>,
+            ) -> Result<std::collections::HashMap<u64, u64>, hirpdag::base::HirpdagSerializeError>
+            {
+                let session = HirpdagSerSessionGuard::open(std::collections::HashMap::new())?;
+                for node in nodes {
+                    let bytes = hirpdag::postcard::to_stdvec(&hirpdag_archive_node(node))
+                        .map_err(|e| hirpdag::base::HirpdagSerializeError::Format(e.to_string()))?;
+                    let mut hasher = hirpdag::base::HirpdagStableHasher::new();
+                    hasher.write(&bytes);
+                    session.insert(node.hirpdag_creation_id(), hasher.finish());
+                }
+                Ok(session.take_index_map())
+            }
+
+            /// `roots` in canonical order: see
+            /// [`hirpdag::base::HirpdagSerializeOptions::canonical`].
+            fn hirpdag_canonical_as<A: HirpdagArchiveLayout>(
+                roots: &A::Roots,
+                base: &[HirpdagNodeRef],
+            ) -> Result<A::Roots, hirpdag::base::HirpdagSerializeError> {
+                let (archive, _) = hirpdag_collect_archive_on::<A, ()>(roots, &(), base);
+                let hashes = hirpdag_content_hashes(base.iter().chain(archive.nodes()))?;
+                Ok(A::canonical_roots(roots, &hashes))
+            }
+
+            /// [`hirpdag_collect_archive_on`] for a binary archive, with the
+            /// roots in canonical order and an index if `options` ask for
+            /// them.
+            fn hirpdag_collect_binary<A, T>(
+                roots: &A::Roots,
+                side_tables: &T,
+                base: &[HirpdagNodeRef],
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<
+                (
+                    A,
+                    std::collections::HashMap<u64, u64>,
+                    Option<hirpdag::base::HirpdagArchiveIndex>,
+                ),
+                hirpdag::base::HirpdagSerializeError,
+            >
+            where
+                A: HirpdagArchiveLayout,
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx>,
+            {
+                let canonical;
+                let roots = if options.canonical {
+                    canonical = hirpdag_canonical_as::<A>(roots, base)?;
+                    &canonical
+                } else {
+                    roots
+                };
+                let (archive, seen) = hirpdag_collect_archive_on::<A, T>(roots, side_tables, base);
+                if !options.index {
+                    return Ok((archive, seen, None));
+                }
+                // Collecting a node's data again, with all nodes collected,
+                // only reaches its children.
+                let mut ctx = HirpdagCollectCtx {
+                    seen,
+                    nodes: Vec::new(),
+                    base_nodes: 0,
+                    children: None,
+                };
+                let children = archive
+                    .nodes()
+                    .iter()
+                    .map(|node| {
+                        ctx.children = Some(Vec::new());
+                        node.hirpdag_collect_data(&mut ctx);
+                        let mut children = ctx.children.take().unwrap_or_default();
+                        children.sort_unstable();
+                        children.dedup();
+                        children
+                    })
+                    .collect();
+                let index = hirpdag::base::HirpdagArchiveIndex {
+                    offsets: Vec::new(),
+                    children,
+                    roots: A::root_indices(archive.roots(), &ctx.seen),
+                };
+                Ok((archive, ctx.seen, Some(index)))
+            }
+
+            /// Encodes a binary archive: the header, then the payload framed as
+            /// the header's flags select, with `index` completed by the node
+            /// offsets if given, and the encoded `side_tables` if given. Runs
+            /// inside the serialization session.
+            fn hirpdag_write_archive<A: HirpdagArchiveLayout>(
+                header: &hirpdag::base::HirpdagBinaryHeader,
+                archive: &A,
+                index: Option<hirpdag::base::HirpdagArchiveIndex>,
+                side_tables: Option<&[u8]>,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError> {
+                let format = |e: hirpdag::postcard::Error| {
+                    hirpdag::base::HirpdagSerializeError::Format(e.to_string())
+                };
+                let (payload, index) = match index {
+                    None => (hirpdag::postcard::to_stdvec(archive).map_err(format)?, None),
+                    Some(mut index) => {
+                        // The archive's encoding, field by field, recording
+                        // where each node starts.
+                        let mut payload =
+                            hirpdag::postcard::to_stdvec(&hirpdag::base::HirpdagFormatVersion)
+                                .map_err(format)?;
+                        payload = hirpdag::postcard::to_extend(&archive.nodes().len(), payload)
+                            .map_err(format)?;
+                        for node in archive.nodes() {
+                            index.offsets.push(payload.len() as u64);
+                            payload =
+                                hirpdag::postcard::to_extend(&hirpdag_archive_node(node), payload)
+                                    .map_err(format)?;
+                        }
+                        payload =
+                            hirpdag::postcard::to_extend(archive.roots(), payload).map_err(format)?;
+                        (payload, Some(index))
+                    }
+                };
+                let mut bytes = hirpdag::base::hirpdag_write_binary_header(header)?;
+                bytes.extend(hirpdag::base::hirpdag_frame_payload(
+                    header.flags,
+                    index.as_ref(),
+                    side_tables,
+                    payload,
+                )?);
+                Ok(bytes)
+            }
+
+            /// Migrates an unframed binary archive payload to this module's
+            /// types if needed, and decodes it.
+            fn hirpdag_deserialize_migrated<A: HirpdagArchiveLayout>(
+                header: &hirpdag::base::HirpdagBinaryHeader,
+                payload: &[u8],
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<A::Roots, hirpdag::base::HirpdagDeserializeError> {
+                match options.migrations.migrate_payload(
+                    header,
+                    payload,
+                    &hirpdag_schema_fingerprint(),
+                    &hirpdag_schema(),
+                )? {
+                    Some(migrated) => hirpdag_deserialize_payload::<A>(&migrated, options),
+                    None => hirpdag_deserialize_payload::<A>(payload, options),
+                }
+            }
+
+            /// Decodes a binary archive payload written by this module.
+            fn hirpdag_deserialize_payload<A: HirpdagArchiveLayout>(
+                payload: &[u8],
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<A::Roots, hirpdag::base::HirpdagDeserializeError> {
+                let session = HirpdagDeSessionGuard::open(options)?;
+                let archive = hirpdag_decode_binary::<A>(&session, payload)?;
+                Ok(archive.into_roots())
+            }
+
+            /// Decodes a whole binary archive payload inside `session`.
+            fn hirpdag_decode_binary<A: HirpdagArchiveLayout>(
+                session: &HirpdagDeSessionGuard,
+                payload: &[u8],
+            ) -> Result<A, hirpdag::base::HirpdagDeserializeError> {
+                let (archive, rest): (A, &[u8]) =
+                    hirpdag::postcard::take_from_bytes(payload).map_err(|e| {
+                        session.refine(hirpdag::base::hirpdag_postcard_de_error(e))
+                    })?;
+                if !rest.is_empty() {
+                    return Err(hirpdag::base::HirpdagDeserializeError::TrailingBytes {
+                        at_node: hirpdag::base::hirpdag_de_node(),
+                    });
+                }
+                Ok(archive)
+            }
+
+            /// An indexed binary archive read lazily, from bytes such as a
+            /// memory-mapped file: a node is decoded and interned on first
+            /// access, after the nodes it references, and kept from then on.
+            /// Other nodes are never decoded. Each access opens a
+            /// deserialization session on the calling thread.
+            #[allow(dead_code)]
+            pub struct HirpdagLazyArchive<B> {
+                payload: hirpdag::base::HirpdagLazyPayload<B>,
+                nodes: std::cell::RefCell<Vec<Option<HirpdagNodeRef>>>,
+                options: hirpdag::base::HirpdagDeserializeOptions,
+            }
+
+            #[allow(dead_code)]
+            impl<B: AsRef<[u8]>> HirpdagLazyArchive<B> {
+                /// Opens the archive in `bytes`, written by this module with an
+                /// index (see `HirpdagSerializeOptions::index`). Reads its header
+                /// and index and verifies any checksum, but decodes no node.
+                /// An archive with a checksum or compression is read whole
+                /// to verify or decompress it; only others are read in place.
+                pub fn open(bytes: B) -> Result<Self, hirpdag::base::HirpdagDeserializeError> {
+                    Self::open_with_options(bytes, &Default::default())
+                }
+
+                /// [`HirpdagLazyArchive::open`] with options, e.g. to
+                /// re-normalize nodes as they are materialized. Archives written
+                /// by other type definitions are not migrated.
+                pub fn open_with_options(
+                    bytes: B,
+                    options: &hirpdag::base::HirpdagDeserializeOptions,
+                ) -> Result<Self, hirpdag::base::HirpdagDeserializeError> {
+                    let payload = hirpdag::base::HirpdagLazyPayload::new(bytes)?;
+                    let header = payload.header();
+                    hirpdag::base::hirpdag_check_fingerprint(
+                        &header.fingerprint,
+                        &hirpdag_schema_fingerprint(),
+                    )?;
+                    hirpdag::base::hirpdag_check_base(header.base.as_ref(), None)?;
+                    let nodes = std::cell::RefCell::new(vec![None; payload.len()]);
+                    Ok(Self {
+                        payload,
+                        nodes,
+                        options: options.clone(),
+                    })
+                }
+
+                pub fn header(&self) -> &hirpdag::base::HirpdagBinaryHeader {
+                    self.payload.header()
+                }
+
+                /// The number of nodes in the node table.
+                pub fn len(&self) -> usize {
+                    self.payload.len()
+                }
+
+                pub fn is_empty(&self) -> bool {
+                    self.payload.is_empty()
+                }
+
+                /// The node index of each root: per root type, in the order of
+                /// the `HirpdagArchiveRoots` fields, or for a root list, one
+                /// vector in list order.
+                pub fn root_nodes(&self) -> &[Vec<u64>] {
+                    &self.payload.index().roots
+                }
+
+                /// The nodes `node` references, from the index: nothing is
+                /// decoded.
+                pub fn children(&self, node: u64) -> Option<&[u64]> {
+                    self.payload
+                        .index()
+                        .children
+                        .get(node as usize)
+                        .map(Vec::as_slice)
+                }
+
+                /// Whether `node` has been materialized.
+                pub fn is_materialized(&self, node: u64) -> bool {
+                    matches!(self.nodes.borrow().get(node as usize), Some(Some(_)))
+                }
+
+                /// The number of nodes materialized so far.
+                pub fn materialized(&self) -> usize {
+                    self.nodes.borrow().iter().flatten().count()
+                }
+
+                /// Node `node` of the node table, materialized (with the nodes
+                /// it references) on first access.
+                pub fn get(
+                    &self,
+                    node: u64,
+                ) -> Result<HirpdagNodeRef, hirpdag::base::HirpdagDeserializeError> {
+                    if let Some(Some(found)) = self.nodes.borrow().get(node as usize) {
+                        return Ok(found.clone());
+                    }
+                    let mut nodes = self.nodes.borrow_mut();
+                    let pending = self
+                        .payload
+                        .pending(node, |child| nodes[child as usize].is_some())?;
+                    // The session decodes into the materialized node table, so
+                    // references to earlier materialized nodes resolve.
+                    let session = HirpdagDeSessionGuard::open(&self.options)?;
+                    session.swap_nodes(&mut nodes);
+                    let decoded = pending.iter().try_for_each(|&pending_node| {
+                        hirpdag::base::hirpdag_de_set_node(Some(pending_node));
+                        let bytes = self.payload.node_bytes(pending_node)?;
+                        let (data, _): (HirpdagArchiveNode, &[u8]) =
+                            hirpdag::postcard::take_from_bytes(bytes).map_err(|e| {
+                                session.refine(hirpdag::base::hirpdag_postcard_de_error(e))
+                            })?;
+                        session.set_node(
+                            pending_node as usize,
+                            hirpdag_intern(data, self.options.renormalize),
+                        );
+                        Ok(())
+                    });
+                    hirpdag::base::hirpdag_de_set_node(None);
+                    session.swap_nodes(&mut nodes);
+                    decoded?;
+                    nodes[node as usize].clone().ok_or(
+                        hirpdag::base::HirpdagDeserializeError::InvalidNodeIndex {
+                            index: node,
+                            at_node: None,
+                        },
+                    )
+                }
+            }
+
+            #store_items
+
+            /// Encodes a binary archive of `roots` carrying `side_tables`.
+            fn hirpdag_serialize_tables_as<A, T>(
+                roots: &A::Roots,
+                side_tables: &T,
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError>
+            where
+                A: HirpdagArchiveLayout,
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                let (archive, index_map, index) =
+                    hirpdag_collect_binary::<A, T>(roots, side_tables, &[], options)?;
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                let side_tables = hirpdag::postcard::to_stdvec(side_tables)
+                    .map_err(|e| hirpdag::base::HirpdagSerializeError::Format(e.to_string()))?;
+                let mut header = hirpdag_binary_header(options, A::LAYOUT);
+                header.flags |= hirpdag::base::HIRPDAG_FLAG_SIDE_TABLES;
+                hirpdag_write_archive(&header, &archive, index, Some(&side_tables))
+            }
+
+            /// Decodes a binary archive carrying side-tables, and returns its
+            /// roots and side-tables. The side-tables are decoded after the
+            /// node table, in the same session.
+            fn hirpdag_deserialize_tables_as<A, T>(
+                bytes: &[u8],
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<(A::Roots, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                A: HirpdagArchiveLayout,
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                let (header, rest) = hirpdag::base::hirpdag_parse_binary_header(bytes)?;
+                hirpdag::base::hirpdag_check_base(header.base.as_ref(), None)?;
+                hirpdag::base::hirpdag_check_root_layout(&header, A::LAYOUT)?;
+                let (side_tables, payload) = hirpdag::base::hirpdag_unframe_side_tables(&header, rest)?
+                    .ok_or(hirpdag::base::HirpdagDeserializeError::MissingSideTables)?;
+                let migrated = options.migrations.migrate_payload(
+                    &header,
+                    &payload,
+                    &hirpdag_schema_fingerprint(),
+                    &hirpdag_schema(),
+                )?;
+                let session = HirpdagDeSessionGuard::open(options)?;
+                let archive =
+                    hirpdag_decode_binary::<A>(&session, migrated.as_deref().unwrap_or(&payload))?;
+                let (side_tables, rest): (T, &[u8]) =
+                    hirpdag::postcard::take_from_bytes(&side_tables).map_err(|e| {
+                        session.refine(hirpdag::base::hirpdag_postcard_de_error(e))
+                    })?;
+                if !rest.is_empty() {
+                    return Err(hirpdag::base::HirpdagDeserializeError::TrailingBytes {
+                        at_node: hirpdag::base::hirpdag_de_node(),
+                    });
+                }
+                Ok((archive.into_roots(), side_tables))
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+    let json_items = if cfg!(feature = "json") {
+        quote! {
+            /// Encodes the archive of `roots` as JSON.
+            fn hirpdag_serialize_json_as<A: HirpdagArchiveLayout>(
+                roots: &A::Roots,
+            ) -> Result<String, hirpdag::base::HirpdagSerializeError> {
+                let (archive, index_map) = hirpdag_collect_archive::<A>(roots);
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                hirpdag::serde_json::to_string(&archive)
+                    .map_err(|e| hirpdag::base::HirpdagSerializeError::Format(e.to_string()))
+            }
+
+            /// Encodes the archive of `roots` as JSON, with `side_tables` as
+            /// its last field.
+            fn hirpdag_serialize_tables_json_as<A, T>(
+                roots: &A::Roots,
+                side_tables: &T,
+            ) -> Result<String, hirpdag::base::HirpdagSerializeError>
+            where
+                A: HirpdagArchiveLayout,
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                let format =
+                    |e: hirpdag::serde_json::Error| hirpdag::base::HirpdagSerializeError::Format(e.to_string());
+                let (archive, index_map) = hirpdag_collect_archive_on::<A, T>(roots, side_tables, &[]);
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                // The archive is a JSON object: the side-tables go before its
+                // closing brace.
+                let mut text = hirpdag::serde_json::to_string(&archive).map_err(format)?;
+                debug_assert!(text.ends_with('}'));
+                text.pop();
+                text.push_str(",\"side_tables\":");
+                text.push_str(&hirpdag::serde_json::to_string(side_tables).map_err(format)?);
+                text.push('}');
+                Ok(text)
+            }
+
+            /// Decodes a JSON archive carrying side-tables, and returns its
+            /// roots and side-tables.
+            fn hirpdag_deserialize_tables_json_as<A, T>(
+                text: &str,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<(A::Roots, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                A: HirpdagArchiveLayout,
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                // The side-tables reference the node table, wherever the
+                // fields are in the text: the archive is parsed first, and
+                // then decoded nodes first.
+                let mut deserializer = hirpdag::serde_json::Deserializer::from_str(text);
+                let mut value: hirpdag::serde_json::Value =
+                    hirpdag::serde::Deserialize::deserialize(&mut deserializer)
+                        .map_err(hirpdag::base::hirpdag_json_de_error)?;
+                deserializer
+                    .end()
+                    .map_err(|_| hirpdag::base::HirpdagDeserializeError::TrailingBytes {
+                        at_node: hirpdag::base::hirpdag_de_node(),
+                    })?;
+                let side_tables = value
+                    .as_object_mut()
+                    .and_then(|fields| fields.remove("side_tables"))
+                    .ok_or(hirpdag::base::HirpdagDeserializeError::MissingSideTables)?;
+                let session = HirpdagDeSessionGuard::open(options)?;
+                let refine = |e| session.refine(hirpdag::base::hirpdag_json_de_error(e));
+                let archive: A = hirpdag::serde_json::from_value(value).map_err(refine)?;
+                let side_tables: T = hirpdag::serde_json::from_value(side_tables).map_err(refine)?;
+                Ok((archive.into_roots(), side_tables))
+            }
+
+            /// Decodes a JSON archive, and returns its roots.
+            fn hirpdag_deserialize_json_as<A: HirpdagArchiveLayout>(
+                text: &str,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<A::Roots, hirpdag::base::HirpdagDeserializeError> {
+                let session = HirpdagDeSessionGuard::open(options)?;
+                let mut deserializer = hirpdag::serde_json::Deserializer::from_str(text);
+                let archive: A =
+                    hirpdag::serde::Deserialize::deserialize(&mut deserializer).map_err(|e| {
+                        session.refine(hirpdag::base::hirpdag_json_de_error(e))
+                    })?;
+                deserializer
+                    .end()
+                    .map_err(|_| hirpdag::base::HirpdagDeserializeError::TrailingBytes {
+                        at_node: hirpdag::base::hirpdag_de_node(),
+                    })?;
+                Ok(archive.into_roots())
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+
+    quote! {
+        /// The schema fingerprint embedded in (and verified against) the
+        /// header of binary archives written by this module. Migrations are
+        /// registered between fingerprint hashes.
+        #[allow(dead_code)]
+        pub fn hirpdag_schema_fingerprint() -> hirpdag::base::HirpdagSchemaFingerprint {
+            hirpdag::base::HirpdagSchemaFingerprint {
+                hash: #schema_hash,
+                name: #schema_name.to_string(),
+            }
+        }
+
+        #schema_fn
+
+        struct HirpdagSerSessionGuard;
+
+        impl HirpdagSerSessionGuard {
+            fn open(
+                index_map: std::collections::HashMap<u64, u64>,
+            ) -> Result<Self, hirpdag::base::HirpdagSerializeError> {
+                HIRPDAG_SER_SESSION.with(|cell| {
+                    let mut borrow = cell.borrow_mut();
+                    if borrow.is_some() {
+                        return Err(hirpdag::base::HirpdagSerializeError::SessionActive);
+                    }
+                    *borrow = Some(index_map);
+                    Ok(HirpdagSerSessionGuard)
+                })
+            }
+
+            /// Encodes references to the node with `creation_id` as `index`.
+            #[allow(dead_code)]
+            fn insert(&self, creation_id: u64, index: u64) {
+                HIRPDAG_SER_SESSION.with(|cell| {
+                    if let Some(index_map) = cell.borrow_mut().as_mut() {
+                        index_map.insert(creation_id, index);
+                    }
+                })
+            }
+
+            /// Takes the index map.
+            #[allow(dead_code)]
+            fn take_index_map(&self) -> std::collections::HashMap<u64, u64> {
+                HIRPDAG_SER_SESSION.with(|cell| cell.borrow_mut().take().unwrap_or_default())
+            }
+        }
+
+        impl Drop for HirpdagSerSessionGuard {
+            fn drop(&mut self) {
+                HIRPDAG_SER_SESSION.with(|cell| *cell.borrow_mut() = None);
+            }
+        }
 
         struct HirpdagDeSessionGuard;
 
         impl HirpdagDeSessionGuard {
-            fn open() -> Result<Self, hirpdag::base::HirpdagDeserializeError> {
+            fn open(
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<Self, hirpdag::base::HirpdagDeserializeError> {
+                Self::open_on(options, Vec::new())
+            }
+
+            /// Opens a session whose node table starts with `base`.
+            fn open_on(
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+                base: Vec<HirpdagNodeRef>,
+            ) -> Result<Self, hirpdag::base::HirpdagDeserializeError> {
                 HIRPDAG_DE_SESSION.with(|cell| {
                     let mut borrow = cell.borrow_mut();
                     if borrow.is_some() {
                         return Err(hirpdag::base::HirpdagDeserializeError::SessionActive);
                     }
-                    *borrow = Some(Vec::new());
+                    *borrow = Some(base.into_iter().map(Some).collect());
+                    HIRPDAG_DE_RENORMALIZE.with(|r| r.set(options.renormalize));
+                    hirpdag::base::hirpdag_de_reset();
                     Ok(HirpdagDeSessionGuard)
                 })
             }
+
+            /// Takes the reconstructed node table, base nodes included.
+            #[allow(dead_code)]
+            fn take_nodes(&self) -> Vec<HirpdagNodeRef> {
+                HIRPDAG_DE_SESSION.with(|cell| {
+                    cell.borrow_mut()
+                        .take()
+                        .unwrap_or_default()
+                        .into_iter()
+                        .flatten()
+                        .collect()
+                })
+            }
+
+            /// Sets node `index` of the node table, leaving any nodes skipped
+            /// before it empty.
+            #[allow(dead_code)]
+            fn put_node(&self, index: usize, node: HirpdagNodeRef) {
+                HIRPDAG_DE_SESSION.with(|cell| {
+                    if let Some(nodes) = cell.borrow_mut().as_mut() {
+                        nodes.resize(index, None);
+                        nodes.push(Some(node));
+                    }
+                })
+            }
+
+            /// Sets node `index` of a node table already holding it.
+            #[allow(dead_code)]
+            fn set_node(&self, index: usize, node: HirpdagNodeRef) {
+                HIRPDAG_DE_SESSION.with(|cell| {
+                    if let Some(slot) = cell.borrow_mut().as_mut().and_then(|nodes| nodes.get_mut(index)) {
+                        *slot = Some(node);
+                    }
+                })
+            }
+
+            /// Swaps the node table with `nodes`.
+            #[allow(dead_code)]
+            fn swap_nodes(&self, nodes: &mut Vec<Option<HirpdagNodeRef>>) {
+                HIRPDAG_DE_SESSION.with(|cell| {
+                    if let Some(table) = cell.borrow_mut().as_mut() {
+                        std::mem::swap(table, nodes);
+                    }
+                })
+            }
+
+            /// Runs `f` on the node table.
+            #[allow(dead_code)]
+            fn with_nodes<T>(&self, f: impl FnOnce(&[Option<HirpdagNodeRef>]) -> T) -> T {
+                HIRPDAG_DE_SESSION.with(|cell| f(cell.borrow().as_deref().unwrap_or_default()))
+            }
+
+            /// The error to report for `error`, raised while decoding in this
+            /// session: see [`hirpdag::base::hirpdag_de_refine`].
+            fn refine(
+                &self,
+                error: hirpdag::base::HirpdagDeserializeError,
+            ) -> hirpdag::base::HirpdagDeserializeError {
+                hirpdag::base::hirpdag_de_refine(error)
+            }
         }
 
         impl Drop for HirpdagDeSessionGuard {
             fn drop(&mut self) {
                 HIRPDAG_DE_SESSION.with(|cell| *cell.borrow_mut() = None);
+                HIRPDAG_DE_RENORMALIZE.with(|r| r.set(false));
+                hirpdag::base::hirpdag_de_reset();
+            }
+        }
+
+        /// Interns a decoded node table entry.
+        fn hirpdag_intern(node: HirpdagArchiveNode, renormalize: bool) -> HirpdagNodeRef {
+            match node {
+                #intern_arms
             }
         }
 
@@ -1345,7 +2600,7 @@ fn get_serialization_roots_items(
         /// interns each node into the hashcons table as soon as it is
         /// decoded, so later nodes (and the roots) can resolve references to
         /// it in a single forward pass.
-        struct HirpdagNodeSeq(Vec<HirpdagArchiveNode>);
+        struct HirpdagNodeSeq(Vec<HirpdagNodeRef>);
 
         impl hirpdag::serde::Serialize for HirpdagNodeSeq {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -1355,7 +2610,7 @@ fn get_serialization_roots_items(
                 use hirpdag::serde::ser::SerializeSeq;
                 let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
                 for node in &self.0 {
-                    seq.serialize_element(node)?;
+                    seq.serialize_element(&hirpdag_archive_node(node))?;
                 }
                 seq.end()
             }
@@ -1382,12 +2637,18 @@ fn get_serialization_roots_items(
                     where
                         A: hirpdag::serde::de::SeqAccess<'de>,
                     {
-                        while let Some(node) =
-                            seq.next_element::<HirpdagArchiveNode>()?
-                        {
-                            let reconstructed = match node {
-                                #intern_arms
+                        let renormalize = HIRPDAG_DE_RENORMALIZE.with(|r| r.get());
+                        loop {
+                            // Locates errors at the node about to be decoded
+                            // (its index in the session's table).
+                            let index = HIRPDAG_DE_SESSION
+                                .with(|cell| cell.borrow().as_ref().map(|nodes| nodes.len() as u64));
+                            hirpdag::base::hirpdag_de_set_node(index);
+                            let node = match seq.next_element::<HirpdagArchiveNode>()? {
+                                Some(node) => node,
+                                None => break,
                             };
+                            let reconstructed = hirpdag_intern(node, renormalize);
                             HIRPDAG_DE_SESSION.with(|cell| -> Result<(), A::Error> {
                                 cell.borrow_mut()
                                     .as_mut()
@@ -1396,10 +2657,11 @@ fn get_serialization_roots_items(
                                             "hirpdag nodes deserialized outside a hirpdag deserialization session",
                                         )
                                     })?
-                                    .push(reconstructed);
+                                    .push(Some(reconstructed));
                                 Ok(())
                             })?;
                         }
+                        hirpdag::base::hirpdag_de_set_node(None);
                         // The reconstructed refs live in the session; the
                         // archive value itself carries nothing further.
                         Ok(HirpdagNodeSeq(Vec::new()))
@@ -1410,85 +2672,1010 @@ fn get_serialization_roots_items(
             }
         }
 
+        /// An archive struct: version, node table, then the roots held as
+        /// `Roots` (see [`hirpdag::base::HirpdagRootLayout`]). The collect,
+        /// encode and decode helpers are generic over it.
+        #[allow(dead_code)]
+        trait HirpdagArchiveLayout:
+            hirpdag::serde::Serialize + hirpdag::serde::de::DeserializeOwned
+        {
+            type Roots: hirpdag::base::HirpdagCollect<HirpdagCollectCtx>
+                + hirpdag::serde::Serialize
+                + Clone;
+
+            const LAYOUT: hirpdag::base::HirpdagRootLayout;
+
+            fn new(nodes: Vec<HirpdagNodeRef>, roots: Self::Roots) -> Self;
+
+            fn nodes(&self) -> &[HirpdagNodeRef];
+
+            fn roots(&self) -> &Self::Roots;
+
+            fn into_roots(self) -> Self::Roots;
+
+            /// The node index of each root (`HirpdagArchiveIndex::roots`).
+            fn root_indices(
+                roots: &Self::Roots,
+                seen: &std::collections::HashMap<u64, u64>,
+            ) -> Vec<Vec<u64>>;
+
+            /// The roots in canonical order, given the content hash of every
+            /// node by creation id.
+            fn canonical_roots(
+                roots: &Self::Roots,
+                hashes: &std::collections::HashMap<u64, u64>,
+            ) -> Self::Roots;
+        }
+
+        /// Runs the collect phase: post-order DFS from each root, registering
+        /// every unique reachable node exactly once, children first.
+        #[allow(dead_code)]
+        fn hirpdag_collect_archive<A: HirpdagArchiveLayout>(
+            roots: &A::Roots,
+        ) -> (A, std::collections::HashMap<u64, u64>) {
+            hirpdag_collect_archive_on::<A, ()>(roots, &(), &[])
+        }
+
+        /// [`hirpdag_collect_archive`], also collecting the nodes of
+        /// `side_tables` (after the roots'), and for a delta archive: nodes
+        /// of `base` keep their indices and are left out of the node table.
+        #[allow(dead_code)]
+        fn hirpdag_collect_archive_on<A, T>(
+            roots: &A::Roots,
+            side_tables: &T,
+            base: &[HirpdagNodeRef],
+        ) -> (A, std::collections::HashMap<u64, u64>)
+        where
+            A: HirpdagArchiveLayout,
+            T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx>,
+        {
+            let mut ctx = HirpdagCollectCtx::new(base);
+            hirpdag::base::HirpdagCollect::hirpdag_collect(roots, &mut ctx);
+            hirpdag::base::HirpdagCollect::hirpdag_collect(side_tables, &mut ctx);
+            (A::new(ctx.nodes, roots.clone()), ctx.seen)
+        }
+
+        #binary_items
+
+        #json_items
+    }
+}
+
+/// Generates the root list items: the `HirpdagRootList` alias, its archive
+/// struct and its entry points. Generated for every module with struct
+/// types, whether or not any is marked `#[hirpdag(root)]`.
+fn get_serialization_list_items() -> proc_macro2::TokenStream {
+    let binary_items = if cfg!(feature = "postcard") {
+        quote! {
+            /// Serializes a root list (and every node reachable from it) into
+            /// the hirpdag binary archive format, like [`hirpdag_serialize`]
+            /// does typed roots. The header marks the archive as holding a
+            /// root list (`HIRPDAG_FLAG_ROOT_LIST`).
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_list(
+                list: &HirpdagRootList,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError> {
+                hirpdag_serialize_list_with_options(list, &Default::default())
+            }
+
+            /// [`hirpdag_serialize_list`] with options, e.g. to embed the
+            /// schema, add a checksum or compress the payload.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_list_with_options(
+                list: &HirpdagRootList,
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError> {
+                let (archive, index_map, index) =
+                    hirpdag_collect_binary::<HirpdagListArchive, ()>(list, &(), &[], options)?;
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                let header = hirpdag_binary_header(options, HirpdagListArchive::LAYOUT);
+                hirpdag_write_archive(&header, &archive, index, None)
+            }
+
+            /// Deserializes a binary archive written by
+            /// [`hirpdag_serialize_list`], re-interning every node through the
+            /// hashcons table, and returns its root list. Fails with
+            /// `RootLayoutMismatch` for an archive with typed roots.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_list(
+                bytes: &[u8],
+            ) -> Result<HirpdagRootList, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_list_with_options(bytes, &Default::default())
+            }
+
+            /// [`hirpdag_deserialize_list`] with options, e.g. to re-normalize
+            /// nodes on load or to migrate archives written by older type
+            /// definitions.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_list_with_options(
+                bytes: &[u8],
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagRootList, hirpdag::base::HirpdagDeserializeError> {
+                let (header, rest) = hirpdag::base::hirpdag_parse_binary_header(bytes)?;
+                hirpdag::base::hirpdag_check_base(header.base.as_ref(), None)?;
+                hirpdag::base::hirpdag_check_root_layout(&header, HirpdagListArchive::LAYOUT)?;
+                let payload = hirpdag::base::hirpdag_unframe_payload(&header, rest)?;
+                hirpdag_deserialize_migrated::<HirpdagListArchive>(&header, &payload, options)
+            }
+
+            /// [`hirpdag_serialize_list`], also writing `side_tables`: maps
+            /// keyed by nodes, such as `HirpdagSideTable`s (or a tuple of
+            /// them). Their nodes are written to the node table too.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_list_tables<T>(
+                list: &HirpdagRootList,
+                side_tables: &T,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError>
+            where
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                hirpdag_serialize_list_tables_with_options(list, side_tables, &Default::default())
+            }
+
+            /// [`hirpdag_serialize_list_tables`] with options.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_list_tables_with_options<T>(
+                list: &HirpdagRootList,
+                side_tables: &T,
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError>
+            where
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                hirpdag_serialize_tables_as::<HirpdagListArchive, T>(list, side_tables, options)
+            }
+
+            /// Deserializes a binary archive written by
+            /// [`hirpdag_serialize_list_tables`], and returns its root list
+            /// and side-tables. Fails with `MissingSideTables` for an archive
+            /// without any.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_list_tables<T>(
+                bytes: &[u8],
+            ) -> Result<(HirpdagRootList, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                hirpdag_deserialize_list_tables_with_options(bytes, &Default::default())
+            }
+
+            /// [`hirpdag_deserialize_list_tables`] with options.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_list_tables_with_options<T>(
+                bytes: &[u8],
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<(HirpdagRootList, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                hirpdag_deserialize_tables_as::<HirpdagListArchive, T>(bytes, options)
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+    let json_items = if cfg!(feature = "json") {
+        quote! {
+            /// JSON (text format) variant of [`hirpdag_serialize_list`].
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_list_json(
+                list: &HirpdagRootList,
+            ) -> Result<String, hirpdag::base::HirpdagSerializeError> {
+                hirpdag_serialize_json_as::<HirpdagListArchive>(list)
+            }
+
+            /// JSON (text format) variant of [`hirpdag_deserialize_list`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_list_json(
+                text: &str,
+            ) -> Result<HirpdagRootList, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_list_json_with_options(text, &Default::default())
+            }
+
+            /// JSON (text format) variant of
+            /// [`hirpdag_deserialize_list_with_options`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_list_json_with_options(
+                text: &str,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagRootList, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_json_as::<HirpdagListArchive>(text, options)
+            }
+
+            /// JSON (text format) variant of [`hirpdag_serialize_list_tables`].
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_list_tables_json<T>(
+                list: &HirpdagRootList,
+                side_tables: &T,
+            ) -> Result<String, hirpdag::base::HirpdagSerializeError>
+            where
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                hirpdag_serialize_tables_json_as::<HirpdagListArchive, T>(list, side_tables)
+            }
+
+            /// JSON (text format) variant of [`hirpdag_deserialize_list_tables`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_list_tables_json<T>(
+                text: &str,
+            ) -> Result<(HirpdagRootList, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                hirpdag_deserialize_tables_json_as::<HirpdagListArchive, T>(text, &Default::default())
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+
+    quote! {
+        /// An ordered list of archive roots of any struct type in this
+        /// module, each optionally named: the input of
+        /// [`hirpdag_serialize_list`] and output of
+        /// [`hirpdag_deserialize_list`]. Nodes convert into
+        /// [`HirpdagNodeRef`] with `From`, and back with `TryFrom`.
+        #[allow(dead_code)]
+        pub type HirpdagRootList = hirpdag::base::HirpdagRootList<HirpdagNodeRef>;
+
+        /// A whole archive holding a root list: version, node table and the
+        /// root list. In JSON, the `root_list` key tells it from a
+        /// `HirpdagArchive`; binary archives mark it in their header.
         #[derive(hirpdag::serde::Serialize, hirpdag::serde::Deserialize)]
         #[serde(crate = "hirpdag::serde")]
-        struct HirpdagArchive {
+        struct HirpdagListArchive {
+            version: hirpdag::base::HirpdagFormatVersion,
+            nodes: HirpdagNodeSeq,
+            root_list: HirpdagRootList,
+        }
+
+        impl HirpdagArchiveLayout for HirpdagListArchive {
+            type Roots = HirpdagRootList;
+
+            const LAYOUT: hirpdag::base::HirpdagRootLayout = hirpdag::base::HirpdagRootLayout::List;
+
+            fn new(nodes: Vec<HirpdagNodeRef>, root_list: HirpdagRootList) -> Self {
+                Self {
+                    version: hirpdag::base::HirpdagFormatVersion,
+                    nodes: HirpdagNodeSeq(nodes),
+                    root_list,
+                }
+            }
+
+            fn nodes(&self) -> &[HirpdagNodeRef] {
+                &self.nodes.0
+            }
+
+            fn roots(&self) -> &HirpdagRootList {
+                &self.root_list
+            }
+
+            fn into_roots(self) -> HirpdagRootList {
+                self.root_list
+            }
+
+            fn root_indices(
+                root_list: &HirpdagRootList,
+                seen: &std::collections::HashMap<u64, u64>,
+            ) -> Vec<Vec<u64>> {
+                vec![root_list
+                    .nodes()
+                    .map(|node| seen[&node.hirpdag_creation_id()])
+                    .collect()]
+            }
+
+            /// A root list's order is the user's: it is kept.
+            fn canonical_roots(
+                root_list: &HirpdagRootList,
+                _hashes: &std::collections::HashMap<u64, u64>,
+            ) -> HirpdagRootList {
+                root_list.clone()
+            }
+        }
+
+        #binary_items
+
+        #json_items
+    }
+}
+
+/// Generates the typed roots items: the HirpdagArchiveRoots struct, its
+/// archive struct and the public entry points. Empty when no type in the
+/// module is marked `#[hirpdag(root)]`.
+fn get_serialization_roots_items(has_roots: bool, roots: RootsTokens) -> proc_macro2::TokenStream {
+    if !has_roots {
+        return proc_macro2::TokenStream::new();
+    }
+    let RootsTokens {
+        declarations: roots_field_declarations,
+        collect: roots_fields_collect,
+        index: roots_fields_index,
+        sort: roots_fields_sort,
+        selection_declarations,
+        selection_nodes,
+        selection_resolve,
+        selection_select,
+    } = roots;
+
+    // The binary and JSON entry points are generated only with the
+    // matching hirpdag features (forwarded to this crate).
+    let binary_items = if cfg!(feature = "postcard") {
+        quote! {
+            /// Serializes the given roots (and every node reachable from them)
+            /// into the hirpdag binary archive format. Each unique node is
+            /// written exactly once, preserving DAG sharing. The header carries a
+            /// fingerprint of this module's type definitions.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize(
+                roots: &HirpdagArchiveRoots,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError> {
+                hirpdag_serialize_with_options(roots, &Default::default())
+            }
+
+            /// [`hirpdag_serialize`] with options, e.g. to embed the schema,
+            /// add a checksum or compress the payload.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_with_options(
+                roots: &HirpdagArchiveRoots,
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError> {
+                let (archive, index_map, index) =
+                    hirpdag_collect_binary::<HirpdagArchive, ()>(roots, &(), &[], options)?;
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                let header = hirpdag_binary_header(options, HirpdagArchive::LAYOUT);
+                hirpdag_write_archive(&header, &archive, index, None)
+            }
+
+            /// `roots` in the order a canonical archive stores them (see
+            /// [`hirpdag::base::HirpdagSerializeOptions::canonical`]), e.g. to
+            /// pass to `hirpdag_serialize_json` for reproducible JSON.
+            #[allow(dead_code)]
+            pub fn hirpdag_canonical_roots(
+                roots: &HirpdagArchiveRoots,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagSerializeError> {
+                hirpdag_canonical_as::<HirpdagArchive>(roots, &[])
+            }
+
+            /// Streaming variant of [`hirpdag_serialize`]: writes the header, the
+            /// node table and the roots to `writer` as they are encoded, so
+            /// memory use is bounded by the collected node references rather
+            /// than the encoded size. `writer` should be buffered.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_to<W: std::io::Write>(
+                roots: &HirpdagArchiveRoots,
+                writer: W,
+            ) -> Result<(), hirpdag::base::HirpdagSerializeError> {
+                hirpdag_serialize_to_with_options(roots, writer, &Default::default())
+            }
+
+            /// Streaming variant of [`hirpdag_serialize_with_options`].
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_to_with_options<W: std::io::Write>(
+                roots: &HirpdagArchiveRoots,
+                mut writer: W,
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<(), hirpdag::base::HirpdagSerializeError> {
+                let (archive, index_map, index) =
+                    hirpdag_collect_binary::<HirpdagArchive, ()>(roots, &(), &[], options)?;
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                let header = hirpdag_binary_header(options, HirpdagArchive::LAYOUT);
+                if header.flags == 0 {
+                    hirpdag::base::hirpdag_write_binary_header_to(&mut writer, &header)?;
+                    hirpdag::base::hirpdag_postcard_to_writer(&archive, &mut writer)?;
+                } else {
+                    // A framed payload is written whole, after its length.
+                    writer
+                        .write_all(&hirpdag_write_archive(&header, &archive, index, None)?)
+                        .map_err(|e| hirpdag::base::HirpdagSerializeError::Io(e.to_string()))?;
+                }
+                writer
+                    .flush()
+                    .map_err(|e| hirpdag::base::HirpdagSerializeError::Io(e.to_string()))
+            }
+
+            /// Deserializes a hirpdag binary archive, re-interning every node
+            /// through the hashcons table, and returns the typed roots. Fails
+            /// with `SchemaMismatch` if the archive was written by different
+            /// hirpdag type definitions (unless migrations to these are given,
+            /// see [`hirpdag_deserialize_with_options`]).
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize(
+                bytes: &[u8],
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_with_options(bytes, &Default::default())
+            }
+
+            /// [`hirpdag_deserialize`] with options, e.g. to re-normalize nodes
+            /// on load or to migrate archives written by older type
+            /// definitions.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_with_options(
+                bytes: &[u8],
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                let (header, rest) = hirpdag::base::hirpdag_parse_binary_header(bytes)?;
+                hirpdag::base::hirpdag_check_base(header.base.as_ref(), None)?;
+                hirpdag::base::hirpdag_check_root_layout(&header, HirpdagArchive::LAYOUT)?;
+                let payload = hirpdag::base::hirpdag_unframe_payload(&header, rest)?;
+                hirpdag_deserialize_migrated::<HirpdagArchive>(&header, &payload, options)
+            }
+
+            /// [`hirpdag_serialize`], also writing `side_tables`: maps keyed by
+            /// nodes, such as `HirpdagSideTable`s (or a tuple of them), with
+            /// their keys encoded as node table indices. Their nodes are
+            /// written to the node table too. Readers not after the
+            /// side-tables skip them.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_tables<T>(
+                roots: &HirpdagArchiveRoots,
+                side_tables: &T,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError>
+            where
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                hirpdag_serialize_tables_with_options(roots, side_tables, &Default::default())
+            }
+
+            /// [`hirpdag_serialize_tables`] with options.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_tables_with_options<T>(
+                roots: &HirpdagArchiveRoots,
+                side_tables: &T,
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError>
+            where
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                hirpdag_serialize_tables_as::<HirpdagArchive, T>(roots, side_tables, options)
+            }
+
+            /// Deserializes a binary archive written by
+            /// [`hirpdag_serialize_tables`], and returns its roots and
+            /// side-tables, with their keys resolved to the re-interned nodes.
+            /// Fails with `MissingSideTables` for an archive without any.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_tables<T>(
+                bytes: &[u8],
+            ) -> Result<(HirpdagArchiveRoots, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                hirpdag_deserialize_tables_with_options(bytes, &Default::default())
+            }
+
+            /// [`hirpdag_deserialize_tables`] with options.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_tables_with_options<T>(
+                bytes: &[u8],
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<(HirpdagArchiveRoots, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                hirpdag_deserialize_tables_as::<HirpdagArchive, T>(bytes, options)
+            }
+
+            /// The roots to read with [`hirpdag_deserialize_partial`]: per root
+            /// type, the positions of the selected roots among the archive's
+            /// roots of that type.
+            ///
+            /// Implements `Default`, so roots of a subset of root types can be
+            /// selected with struct update syntax:
+            /// `HirpdagRootSelection { foo: vec![0, 2], ..Default::default() }`.
+            #[derive(Clone, Debug, Default, PartialEq, Eq)]
+            #[allow(dead_code)]
+            pub struct HirpdagRootSelection {
+                #selection_declarations
+            }
+
+            #[allow(dead_code)]
+            impl HirpdagRootSelection {
+                /// The node indices of the selected roots.
+                fn nodes(
+                    &self,
+                    index: &hirpdag::base::HirpdagArchiveIndex,
+                ) -> Result<Vec<u64>, hirpdag::base::HirpdagDeserializeError> {
+                    let mut nodes = Vec::new();
+                    #selection_nodes
+                    Ok(nodes)
+                }
+
+                /// The selected roots, from a partially read node table.
+                fn resolve(
+                    &self,
+                    index: &hirpdag::base::HirpdagArchiveIndex,
+                    nodes: &[Option<HirpdagNodeRef>],
+                ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                    Ok(HirpdagArchiveRoots {
+                        #selection_resolve
+                    })
+                }
+
+                /// The selected roots, from all roots of an archive.
+                fn select(
+                    &self,
+                    roots: &HirpdagArchiveRoots,
+                ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                    Ok(HirpdagArchiveRoots {
+                        #selection_select
+                    })
+                }
+            }
+
+            /// Deserializes the selected roots of a binary archive, and only the
+            /// nodes reachable from them. The result holds, per root type, the
+            /// selected roots in selection order. Fails with `MissingRoot` if a
+            /// position is past the archive's roots of its type.
+            ///
+            /// With an index (see `HirpdagSerializeOptions::index`), the other
+            /// nodes are skipped without being decoded or interned. Archives
+            /// without an index, and archives which need migrating, are read
+            /// whole.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_partial(
+                bytes: &[u8],
+                selection: &HirpdagRootSelection,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                let (header, rest) = hirpdag::base::hirpdag_parse_binary_header(bytes)?;
+                hirpdag::base::hirpdag_check_base(header.base.as_ref(), None)?;
+                hirpdag::base::hirpdag_check_root_layout(&header, HirpdagArchive::LAYOUT)?;
+                let (index, payload) = hirpdag::base::hirpdag_unframe_indexed(&header, rest)?;
+                match index {
+                    Some(index) if header.fingerprint.hash == hirpdag_schema_fingerprint().hash => {
+                        hirpdag_deserialize_indexed(&payload, &index, selection, options)
+                    }
+                    _ => selection.select(&hirpdag_deserialize_migrated::<HirpdagArchive>(
+                        &header, &payload, options,
+                    )?),
+                }
+            }
+
+            /// Decodes the nodes of an indexed binary archive payload reachable
+            /// from the selected roots.
+            fn hirpdag_deserialize_indexed(
+                payload: &[u8],
+                index: &hirpdag::base::HirpdagArchiveIndex,
+                selection: &HirpdagRootSelection,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                let reachable = index.reachable(&selection.nodes(index)?)?;
+                let session = HirpdagDeSessionGuard::open(options)?;
+                for (node, offset) in index.offsets.iter().enumerate() {
+                    if !reachable[node] {
+                        continue;
+                    }
+                    hirpdag::base::hirpdag_de_set_node(Some(node as u64));
+                    let bytes = <usize as std::convert::TryFrom<u64>>::try_from(*offset)
+                        .ok()
+                        .and_then(|offset| payload.get(offset..))
+                        .ok_or(hirpdag::base::HirpdagDeserializeError::Truncated {
+                            at_node: Some(node as u64),
+                        })?;
+                    let (data, _): (HirpdagArchiveNode, &[u8]) =
+                        hirpdag::postcard::take_from_bytes(bytes).map_err(|e| {
+                            session.refine(hirpdag::base::hirpdag_postcard_de_error(e))
+                        })?;
+                    session.put_node(node, hirpdag_intern(data, options.renormalize));
+                }
+                hirpdag::base::hirpdag_de_set_node(None);
+                session.with_nodes(|nodes| selection.resolve(index, nodes))
+            }
+
+            #[allow(dead_code)]
+            impl<B: AsRef<[u8]>> HirpdagLazyArchive<B> {
+                /// The selected roots, like [`hirpdag_deserialize_partial`]
+                /// returns them, materializing only the nodes they reach.
+                pub fn roots(
+                    &self,
+                    selection: &HirpdagRootSelection,
+                ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                    hirpdag::base::hirpdag_check_root_layout(self.header(), HirpdagArchive::LAYOUT)?;
+                    let index = self.payload.index();
+                    for node in selection.nodes(index)? {
+                        self.get(node)?;
+                    }
+                    selection.resolve(index, &self.nodes.borrow())
+                }
+            }
+
+            /// Streaming variant of [`hirpdag_deserialize`]: decodes nodes from
+            /// `reader` one at a time, reading exactly the archive's bytes.
+            /// `reader` should be buffered.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_from<R: std::io::Read>(
+                reader: R,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_from_with_options(reader, &Default::default())
+            }
+
+            /// Streaming variant of [`hirpdag_deserialize_with_options`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_from_with_options<R: std::io::Read>(
+                mut reader: R,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                let header = hirpdag::base::hirpdag_parse_binary_header_from(&mut reader)?;
+                hirpdag::base::hirpdag_check_base(header.base.as_ref(), None)?;
+                hirpdag::base::hirpdag_check_root_layout(&header, HirpdagArchive::LAYOUT)?;
+                if let Some(payload) =
+                    hirpdag::base::hirpdag_unframe_payload_from(&header, &mut reader)?
+                {
+                    return hirpdag_deserialize_migrated::<HirpdagArchive>(&header, &payload, options);
+                }
+                if let Some(migrated) = options.migrations.migrate_payload_from(
+                    &header,
+                    &mut reader,
+                    &hirpdag_schema_fingerprint(),
+                    &hirpdag_schema(),
+                )? {
+                    return hirpdag_deserialize_payload::<HirpdagArchive>(&migrated, options);
+                }
+                let session = HirpdagDeSessionGuard::open(options)?;
+                let archive: HirpdagArchive = hirpdag::base::hirpdag_postcard_from_reader(reader)
+                    .map_err(|e| session.refine(e))?;
+                Ok(archive.roots)
+            }
+
+            /// The node table of a binary archive, to write and read delta
+            /// archives against. Holds every node of the archive (and of its
+            /// own base), keeping them interned.
+            #[derive(Clone)]
+            #[allow(dead_code)]
+            pub struct HirpdagArchiveBase {
+                id: hirpdag::base::HirpdagArchiveBaseId,
+                nodes: Vec<HirpdagNodeRef>,
+            }
+
+            #[allow(dead_code)]
+            impl HirpdagArchiveBase {
+                fn new(bytes: &[u8], nodes: Vec<HirpdagNodeRef>) -> Self {
+                    Self {
+                        id: hirpdag::base::HirpdagArchiveBaseId::of_archive(
+                            bytes,
+                            nodes.len() as u64,
+                        ),
+                        nodes,
+                    }
+                }
+
+                /// The id recorded in the header of deltas against this base.
+                pub fn id(&self) -> &hirpdag::base::HirpdagArchiveBaseId {
+                    &self.id
+                }
+            }
+
+            /// Serializes the given roots as a delta archive against `base`:
+            /// nodes already in `base` are referenced by their base index, and
+            /// only the others are written. Read it back with
+            /// [`hirpdag_deserialize_delta`] and the same base.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_delta(
+                roots: &HirpdagArchiveRoots,
+                base: &HirpdagArchiveBase,
+            ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError> {
+                Ok(hirpdag_serialize_as_base(roots, Some(base), &Default::default())?.0)
+            }
+
+            /// [`hirpdag_serialize_with_options`], or [`hirpdag_serialize_delta`]
+            /// if `base` is given, also returning the written archive as the
+            /// base of later deltas.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_as_base(
+                roots: &HirpdagArchiveRoots,
+                base: Option<&HirpdagArchiveBase>,
+                options: &hirpdag::base::HirpdagSerializeOptions,
+            ) -> Result<(Vec<u8>, HirpdagArchiveBase), hirpdag::base::HirpdagSerializeError> {
+                let base_nodes = base.map_or(&[][..], |base| &base.nodes[..]);
+                let (archive, index_map, index) =
+                    hirpdag_collect_binary::<HirpdagArchive, ()>(roots, &(), base_nodes, options)?;
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                let header = hirpdag::base::HirpdagBinaryHeader {
+                    base: base.map(|base| base.id),
+                    ..hirpdag_binary_header(options, HirpdagArchive::LAYOUT)
+                };
+                let bytes = hirpdag_write_archive(&header, &archive, index, None)?;
+                let mut nodes = base_nodes.to_vec();
+                nodes.extend(archive.nodes.0);
+                let written = HirpdagArchiveBase::new(&bytes, nodes);
+                Ok((bytes, written))
+            }
+
+            /// Deserializes a delta archive written against `base` by
+            /// [`hirpdag_serialize_delta`]: the base's nodes are taken as
+            /// already loaded, and the delta's nodes are interned after them.
+            /// Fails with `BaseMismatch` for any other base. Deltas are not
+            /// migrated (`options.migrations` do not apply).
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_delta(
+                bytes: &[u8],
+                base: &HirpdagArchiveBase,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                Ok(hirpdag_deserialize_as_base(bytes, Some(base), options)?.0)
+            }
+
+            /// [`hirpdag_deserialize_with_options`], or
+            /// [`hirpdag_deserialize_delta`] if `base` is given, also returning
+            /// the read archive as the base of later deltas.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_as_base(
+                bytes: &[u8],
+                base: Option<&HirpdagArchiveBase>,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<(HirpdagArchiveRoots, HirpdagArchiveBase), hirpdag::base::HirpdagDeserializeError>
+            {
+                let (header, rest) = hirpdag::base::hirpdag_parse_binary_header(bytes)?;
+                hirpdag::base::hirpdag_check_base(header.base.as_ref(), base.map(|base| &base.id))?;
+                hirpdag::base::hirpdag_check_root_layout(&header, HirpdagArchive::LAYOUT)?;
+                let payload = hirpdag::base::hirpdag_unframe_payload(&header, rest)?;
+                let migrated = match base {
+                    // A migrated delta could no longer refer to its base's nodes.
+                    Some(_) => {
+                        hirpdag::base::hirpdag_check_fingerprint(
+                            &header.fingerprint,
+                            &hirpdag_schema_fingerprint(),
+                        )?;
+                        None
+                    }
+                    None => options.migrations.migrate_payload(
+                        &header,
+                        &payload,
+                        &hirpdag_schema_fingerprint(),
+                        &hirpdag_schema(),
+                    )?,
+                };
+                let session = HirpdagDeSessionGuard::open_on(
+                    options,
+                    base.map_or_else(Vec::new, |base| base.nodes.clone()),
+                )?;
+                let archive: HirpdagArchive =
+                    hirpdag_decode_binary(&session, migrated.as_deref().unwrap_or(&payload))?;
+                let read = HirpdagArchiveBase::new(bytes, session.take_nodes());
+                Ok((archive.roots, read))
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+    let json_items = if cfg!(feature = "json") {
+        quote! {
+            /// JSON (text format) variant of [`hirpdag_serialize`].
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_json(
+                roots: &HirpdagArchiveRoots,
+            ) -> Result<String, hirpdag::base::HirpdagSerializeError> {
+                hirpdag_serialize_json_as::<HirpdagArchive>(roots)
+            }
+
+            /// Streaming variant of [`hirpdag_serialize_json`]. `writer` should
+            /// be buffered.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_json_to<W: std::io::Write>(
+                roots: &HirpdagArchiveRoots,
+                mut writer: W,
+            ) -> Result<(), hirpdag::base::HirpdagSerializeError> {
+                let (archive, index_map) = hirpdag_collect_archive::<HirpdagArchive>(roots);
+                let _session = HirpdagSerSessionGuard::open(index_map)?;
+                hirpdag::serde_json::to_writer(&mut writer, &archive).map_err(|e| {
+                    if e.is_io() {
+                        hirpdag::base::HirpdagSerializeError::Io(e.to_string())
+                    } else {
+                        hirpdag::base::HirpdagSerializeError::Format(e.to_string())
+                    }
+                })?;
+                writer
+                    .flush()
+                    .map_err(|e| hirpdag::base::HirpdagSerializeError::Io(e.to_string()))
+            }
+
+            /// JSON (text format) variant of [`hirpdag_deserialize`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_json(
+                text: &str,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_json_with_options(text, &Default::default())
+            }
+
+            /// JSON (text format) variant of [`hirpdag_deserialize_with_options`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_json_with_options(
+                text: &str,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_json_as::<HirpdagArchive>(text, options)
+            }
+
+            /// JSON (text format) variant of [`hirpdag_serialize_tables`]. The
+            /// side-tables are the archive's `side_tables` field.
+            #[allow(dead_code)]
+            pub fn hirpdag_serialize_tables_json<T>(
+                roots: &HirpdagArchiveRoots,
+                side_tables: &T,
+            ) -> Result<String, hirpdag::base::HirpdagSerializeError>
+            where
+                T: hirpdag::base::HirpdagCollect<HirpdagCollectCtx> + hirpdag::serde::Serialize,
+            {
+                hirpdag_serialize_tables_json_as::<HirpdagArchive, T>(roots, side_tables)
+            }
+
+            /// JSON (text format) variant of [`hirpdag_deserialize_tables`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_tables_json<T>(
+                text: &str,
+            ) -> Result<(HirpdagArchiveRoots, T), hirpdag::base::HirpdagDeserializeError>
+            where
+                T: hirpdag::serde::de::DeserializeOwned,
+            {
+                hirpdag_deserialize_tables_json_as::<HirpdagArchive, T>(text, &Default::default())
+            }
+
+            /// Streaming variant of [`hirpdag_deserialize_json`]. `reader`
+            /// should be buffered.
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_json_from<R: std::io::Read>(
+                reader: R,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                hirpdag_deserialize_json_from_with_options(reader, &Default::default())
+            }
+
+            /// Streaming variant of [`hirpdag_deserialize_json_with_options`].
+            #[allow(dead_code)]
+            pub fn hirpdag_deserialize_json_from_with_options<R: std::io::Read>(
+                reader: R,
+                options: &hirpdag::base::HirpdagDeserializeOptions,
+            ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
+                let session = HirpdagDeSessionGuard::open(options)?;
+                let archive: HirpdagArchive = hirpdag::serde_json::from_reader(reader)
+                    .map_err(|e| session.refine(hirpdag::base::hirpdag_json_de_error(e)))?;
+                Ok(archive.roots)
+            }
+        }
+    } else {
+        proc_macro2::TokenStream::new()
+    };
+
+    quote! {
+        /// The roots of a serialized archive: one vector per
+        /// `#[hirpdag(root)]` type. Input of the serialize entry points and
+        /// output of the deserialize entry points.
+        /// For roots of any type, or named roots, see [`HirpdagRootList`].
+        ///
+        /// Implements `Default`, so a subset of root types can be set with
+        /// struct update syntax:
+        /// `HirpdagArchiveRoots { foo: vec![x], ..Default::default() }`.
+        #[derive(Clone, Debug, Default, PartialEq, Eq)]
+        #[derive(hirpdag::serde::Serialize, hirpdag::serde::Deserialize)]
+        #[serde(crate = "hirpdag::serde", default)]
+        #[allow(dead_code)]
+        pub struct HirpdagArchiveRoots {
+            #roots_field_declarations
+        }
+
+        impl hirpdag::base::HirpdagCollect<HirpdagCollectCtx> for HirpdagArchiveRoots {
+            fn hirpdag_collect(&self, ctx: &mut HirpdagCollectCtx) {
+                #roots_fields_collect
+            }
+        }
+
+        /// A whole archive: version, node table and roots. It can only be
+        /// serialized and deserialized inside the session of
+        /// [`hirpdag_serialize_by`] / [`hirpdag_deserialize_by`] (or the
+        /// other entry points); elsewhere, node references fail to resolve.
+        #[derive(hirpdag::serde::Serialize, hirpdag::serde::Deserialize)]
+        #[serde(crate = "hirpdag::serde")]
+        pub struct HirpdagArchive {
             version: hirpdag::base::HirpdagFormatVersion,
             nodes: HirpdagNodeSeq,
             roots: HirpdagArchiveRoots,
         }
 
-        /// Runs the collect phase: post-order DFS from each root, registering
-        /// every unique reachable node exactly once, children first.
-        fn hirpdag_collect_archive(
-            roots: &HirpdagArchiveRoots,
-        ) -> (HirpdagArchive, std::collections::HashMap<u64, u64>) {
-            let mut ctx = HirpdagCollectCtx::new();
-            hirpdag::base::HirpdagCollect::hirpdag_collect(roots, &mut ctx);
-            let archive = HirpdagArchive {
-                version: hirpdag::base::HirpdagFormatVersion,
-                nodes: HirpdagNodeSeq(ctx.nodes),
-                roots: roots.clone(),
-            };
-            (archive, ctx.seen)
+        impl HirpdagArchiveLayout for HirpdagArchive {
+            type Roots = HirpdagArchiveRoots;
+
+            const LAYOUT: hirpdag::base::HirpdagRootLayout = hirpdag::base::HirpdagRootLayout::Typed;
+
+            fn new(nodes: Vec<HirpdagNodeRef>, roots: HirpdagArchiveRoots) -> Self {
+                Self {
+                    version: hirpdag::base::HirpdagFormatVersion,
+                    nodes: HirpdagNodeSeq(nodes),
+                    roots,
+                }
+            }
+
+            fn nodes(&self) -> &[HirpdagNodeRef] {
+                &self.nodes.0
+            }
+
+            fn roots(&self) -> &HirpdagArchiveRoots {
+                &self.roots
+            }
+
+            fn into_roots(self) -> HirpdagArchiveRoots {
+                self.roots
+            }
+
+            fn root_indices(
+                roots: &HirpdagArchiveRoots,
+                seen: &std::collections::HashMap<u64, u64>,
+            ) -> Vec<Vec<u64>> {
+                vec![#roots_fields_index]
+            }
+
+            fn canonical_roots(
+                roots: &HirpdagArchiveRoots,
+                hashes: &std::collections::HashMap<u64, u64>,
+            ) -> HirpdagArchiveRoots {
+                let mut roots = roots.clone();
+                #roots_fields_sort
+                roots
+            }
         }
 
         /// Serializes the given roots (and every node reachable from them)
-        /// into the hirpdag binary archive format. Each unique node is
-        /// written exactly once, preserving DAG sharing. The header carries a
-        /// fingerprint of this module's type definitions.
+        /// with any serde serializer. This writes the format-agnostic
+        /// archive (version, node table, roots) without the binary header,
+        /// so there is no schema fingerprint check on the way back in; read
+        /// it with [`hirpdag_deserialize_with`] and the matching deserializer.
         #[allow(dead_code)]
-        pub fn hirpdag_serialize(
+        pub fn hirpdag_serialize_with<S: hirpdag::serde::Serializer>(
             roots: &HirpdagArchiveRoots,
-        ) -> Result<Vec<u8>, hirpdag::base::HirpdagSerializeError> {
-            let (archive, index_map) = hirpdag_collect_archive(roots);
+            serializer: S,
+        ) -> Result<S::Ok, hirpdag::base::HirpdagSerializeError> {
+            let (archive, index_map) = hirpdag_collect_archive::<HirpdagArchive>(roots);
             let _session = HirpdagSerSessionGuard::open(index_map)?;
-            let payload = hirpdag::postcard::to_stdvec(&archive)
-                .map_err(|e| hirpdag::base::HirpdagSerializeError::Format(e.to_string()))?;
-            let mut bytes =
-                hirpdag::base::hirpdag_write_binary_header(&hirpdag_schema_fingerprint())?;
-            bytes.extend_from_slice(&payload);
-            Ok(bytes)
-        }
-
-        /// Deserializes a hirpdag binary archive, re-interning every node
-        /// through the hashcons table, and returns the typed roots. Fails
-        /// with `SchemaMismatch` if the archive was written by different
-        /// hirpdag type definitions.
+            hirpdag::serde::Serialize::serialize(&archive, serializer)
+                .map_err(|e| hirpdag::base::HirpdagSerializeError::Format(e.to_string()))
+        }
+
+        /// Deserializes an archive written by [`hirpdag_serialize_with`]
+        /// from any serde deserializer, re-interning every node through the
+        /// hashcons table. `options.migrations` do not apply: without the
+        /// binary header the writing schema is unknown.
         #[allow(dead_code)]
-        pub fn hirpdag_deserialize(
-            bytes: &[u8],
+        pub fn hirpdag_deserialize_with<'de, D: hirpdag::serde::Deserializer<'de>>(
+            deserializer: D,
+            options: &hirpdag::base::HirpdagDeserializeOptions,
         ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
-            let payload = hirpdag::base::hirpdag_read_binary_header(
-                bytes,
-                &hirpdag_schema_fingerprint(),
-            )?;
-            let _session = HirpdagDeSessionGuard::open()?;
-            let archive: HirpdagArchive = hirpdag::postcard::from_bytes(payload)
-                .map_err(|e| hirpdag::base::HirpdagDeserializeError::Format(e.to_string()))?;
+            let session = HirpdagDeSessionGuard::open(options)?;
+            let archive: HirpdagArchive = hirpdag::serde::Deserialize::deserialize(deserializer)
+                .map_err(|e| {
+                    session.refine(hirpdag::base::HirpdagDeserializeError::Format(e.to_string()))
+                })?;
             Ok(archive.roots)
         }
 
-        /// JSON (text format) variant of [`hirpdag_serialize`].
+        /// [`hirpdag_serialize_with`] for formats which only offer functions
+        /// serializing a value (e.g. `ciborium::into_writer`): `encode` is
+        /// called on the archive inside the serialization session.
         #[allow(dead_code)]
-        pub fn hirpdag_serialize_json(
+        pub fn hirpdag_serialize_by<T, E: std::fmt::Display>(
             roots: &HirpdagArchiveRoots,
-        ) -> Result<String, hirpdag::base::HirpdagSerializeError> {
-            let (archive, index_map) = hirpdag_collect_archive(roots);
+            encode: impl FnOnce(&HirpdagArchive) -> Result<T, E>,
+        ) -> Result<T, hirpdag::base::HirpdagSerializeError> {
+            let (archive, index_map) = hirpdag_collect_archive::<HirpdagArchive>(roots);
             let _session = HirpdagSerSessionGuard::open(index_map)?;
-            hirpdag::serde_json::to_string(&archive)
+            encode(&archive)
                 .map_err(|e| hirpdag::base::HirpdagSerializeError::Format(e.to_string()))
         }
 
-        /// JSON (text format) variant of [`hirpdag_deserialize`].
+        /// [`hirpdag_deserialize_with`] for formats which only offer functions
+        /// deserializing a value (e.g. `ciborium::from_reader`): `decode` is
+        /// called inside the deserialization session.
         #[allow(dead_code)]
-        pub fn hirpdag_deserialize_json(
-            text: &str,
+        pub fn hirpdag_deserialize_by<E: std::fmt::Display>(
+            decode: impl FnOnce() -> Result<HirpdagArchive, E>,
+            options: &hirpdag::base::HirpdagDeserializeOptions,
         ) -> Result<HirpdagArchiveRoots, hirpdag::base::HirpdagDeserializeError> {
-            let _session = HirpdagDeSessionGuard::open()?;
-            let archive: HirpdagArchive = hirpdag::serde_json::from_str(text)
-                .map_err(|e| hirpdag::base::HirpdagDeserializeError::Format(e.to_string()))?;
+            let session = HirpdagDeSessionGuard::open(options)?;
+            let archive = decode().map_err(|e| {
+                session.refine(hirpdag::base::HirpdagDeserializeError::Format(e.to_string()))
+            })?;
             Ok(archive.roots)
         }
+
+        #binary_items
+
+        #json_items
     }
 }
@@ -0,0 +1,186 @@
+// Generation of the per-type sharing-aware printers.
+
+use proc_macro2::{Ident, Span};
+
+/// The default `hirpdag_pretty`, printing the structural form, unless the
+/// type's attribute says the user defines it.
+fn get_default_pretty(has_pretty: bool) -> proc_macro2::TokenStream {
+    if has_pretty {
+        return quote! {};
+    }
+    quote! {
+        /// Prints the value for `Display`: here as in `Debug`.
+        pub fn hirpdag_pretty(
+            &self,
+            p: &mut hirpdag::base::HirpdagPrinter<'_, HirpdagNodeRef>,
+        ) -> std::fmt::Result {
+            self.hirpdag_print_fields(p)
+        }
+    }
+}
+
+/// `Debug` and `Display` for a ref or enum type, printing sharing-aware.
+fn get_fmt_impls(name: &Ident) -> proc_macro2::TokenStream {
+    quote! {
+        impl std::fmt::Debug for #name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let options = hirpdag::base::HirpdagPrettyOptions {
+                    debug: true,
+                    ..Default::default()
+                };
+                hirpdag::base::hirpdag_write_pretty(f, self, &options)
+            }
+        }
+
+        impl std::fmt::Display for #name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                hirpdag::base::hirpdag_write_pretty(f, self, &Default::default())
+            }
+        }
+    }
+}
+
+/// The printing items of a struct type: `HirpdagPretty` for its ref, which
+/// prints the node once and labels it if shared, and `Debug` and `Display`.
+pub(crate) fn get_struct_pretty_items(
+    ref_name: &Ident,
+    fields_named: &syn::FieldsNamed,
+    has_pretty: bool,
+) -> proc_macro2::TokenStream {
+    let name_str = ref_name.to_string();
+    let fields_print: proc_macro2::TokenStream = fields_named
+        .named
+        .iter()
+        .map(|t| t.ident.as_ref().unwrap())
+        .map(|field_name| {
+            let field_str = field_name.to_string();
+            quote! {
+                (#field_str, &self.#field_name as &dyn hirpdag::base::HirpdagPretty<HirpdagNodeRef>),
+            }
+        })
+        .collect();
+    let default_pretty = get_default_pretty(has_pretty);
+    let fmt_impls = get_fmt_impls(ref_name);
+    quote! {
+        impl hirpdag::base::HirpdagPretty<HirpdagNodeRef> for #ref_name {
+            fn hirpdag_print(
+                &self,
+                p: &mut hirpdag::base::HirpdagPrinter<'_, HirpdagNodeRef>,
+            ) -> std::fmt::Result {
+                p.node(HirpdagNodeRef::from(self.clone()), |p| {
+                    if p.is_debug() {
+                        self.hirpdag_print_fields(p)
+                    } else {
+                        self.hirpdag_pretty(p)
+                    }
+                })
+            }
+        }
+
+        #[allow(dead_code)]
+        impl #ref_name {
+            /// Prints the node's data as `Type { field: value, .. }`.
+            pub fn hirpdag_print_fields(
+                &self,
+                p: &mut hirpdag::base::HirpdagPrinter<'_, HirpdagNodeRef>,
+            ) -> std::fmt::Result {
+                p.fields(#name_str, &[#fields_print])
+            }
+
+            #default_pretty
+        }
+
+        #fmt_impls
+    }
+}
+
+/// The printing items of an enum type: `HirpdagPretty`, printing the active
+/// variant as `Variant(value)`, and `Display`. The enum keeps its derived
+/// `Debug`, whose refs print sharing-aware on their own.
+pub(crate) fn get_enum_pretty_items(
+    name: &Ident,
+    input_enum: &syn::DataEnum,
+    has_pretty: bool,
+) -> proc_macro2::TokenStream {
+    let variants_print: proc_macro2::TokenStream = input_enum
+        .variants
+        .iter()
+        .map(|t| {
+            let variant = &t.ident;
+            let variant_str = variant.to_string();
+            quote! { #variant(x) => p.variant(#variant_str, x), }
+        })
+        .collect();
+    let default_pretty = get_default_pretty(has_pretty);
+    quote! {
+        impl hirpdag::base::HirpdagPretty<HirpdagNodeRef> for #name {
+            fn hirpdag_print(
+                &self,
+                p: &mut hirpdag::base::HirpdagPrinter<'_, HirpdagNodeRef>,
+            ) -> std::fmt::Result {
+                if p.is_debug() {
+                    self.hirpdag_print_fields(p)
+                } else {
+                    self.hirpdag_pretty(p)
+                }
+            }
+        }
+
+        #[allow(dead_code)]
+        impl #name {
+            /// Prints the value as `Variant(value)`.
+            pub fn hirpdag_print_fields(
+                &self,
+                p: &mut hirpdag::base::HirpdagPrinter<'_, HirpdagNodeRef>,
+            ) -> std::fmt::Result {
+                use #name::*;
+                match self {
+                    #variants_print
+                }
+            }
+
+            #default_pretty
+        }
+
+        impl std::fmt::Display for #name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                hirpdag::base::hirpdag_write_pretty(f, self, &Default::default())
+            }
+        }
+    }
+}
+
+/// `HirpdagPretty` and `Display` for `HirpdagNodeRef`, dispatching on the
+/// node's type.
+///
+/// `struct_types` is (name, is_root) for each struct type in the module.
+pub(crate) fn get_pretty_items(struct_types: &[(String, bool)]) -> proc_macro2::TokenStream {
+    let print_arms: proc_macro2::TokenStream = struct_types
+        .iter()
+        .map(|(name, _)| {
+            let ref_name = Ident::new(name, Span::call_site());
+            quote! { HirpdagNodeRef::#ref_name(x) => x.hirpdag_print(p), }
+        })
+        .collect();
+    quote! {
+        // ==== Pretty-printing
+
+        impl hirpdag::base::HirpdagPretty<HirpdagNodeRef> for HirpdagNodeRef {
+            fn hirpdag_print(
+                &self,
+                p: &mut hirpdag::base::HirpdagPrinter<'_, HirpdagNodeRef>,
+            ) -> std::fmt::Result {
+                use hirpdag::base::HirpdagPretty;
+                match self {
+                    #print_arms
+                }
+            }
+        }
+
+        impl std::fmt::Display for HirpdagNodeRef {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                hirpdag::base::hirpdag_write_pretty(f, self, &Default::default())
+            }
+        }
+    }
+}
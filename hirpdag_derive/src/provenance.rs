@@ -0,0 +1,180 @@
+// Generation of the per-module rewrite tracing machinery.
+
+use proc_macro2::{Ident, Span};
+
+/// The generated `HirpdagRewritable::hirpdag_rewrite` body for a struct type:
+/// calls the rewriter and, while a traced rewrite is running on this thread,
+/// records the step if it changed the node.
+pub(crate) fn get_traced_rewrite_body(name: &str) -> proc_macro2::TokenStream {
+    let rewrite_method_name_str = format!("rewrite_{}", name);
+    let rewrite_method_name = Ident::new(&rewrite_method_name_str, Span::call_site());
+    quote! {
+        let traced = hirpdag_trace_active();
+        let output = rewriter.#rewrite_method_name(self);
+        if traced && output != *self {
+            hirpdag_trace_record(
+                self.clone().into(),
+                output.clone().into(),
+                std::any::type_name::<T>(),
+                #rewrite_method_name_str,
+            );
+        }
+        output
+    }
+}
+
+/// Generates the module-level tracing items: the thread-local trace session
+/// and the `HirpdagRewriteTraced` wrapper.
+///
+/// `types` is (name, is_struct) for every `#[hirpdag]` type in the module.
+/// Only struct types are recorded; enum rewrites are forwarded untraced.
+pub(crate) fn get_provenance_items(types: &[(String, bool)]) -> proc_macro2::TokenStream {
+    if !types.iter().any(|(_, is_struct)| *is_struct) {
+        return proc_macro2::TokenStream::new();
+    }
+
+    let mut traced_methods = proc_macro2::TokenStream::new();
+    for (name, is_struct) in types {
+        let ref_name = Ident::new(name, Span::call_site());
+        let rewrite_method_name_str = format!("rewrite_{}", name);
+        let rewrite_method_name = Ident::new(&rewrite_method_name_str, Span::call_site());
+        if *is_struct {
+            traced_methods.extend(quote! {
+                #[allow(non_snake_case)]
+                fn #rewrite_method_name(&self, x: &#ref_name) -> #ref_name {
+                    let session = HirpdagTraceSession::begin();
+                    let output = self.rewriter.#rewrite_method_name(x);
+                    if output != *x {
+                        hirpdag_trace_record(
+                            x.clone().into(),
+                            output.clone().into(),
+                            std::any::type_name::<Rewriter>(),
+                            #rewrite_method_name_str,
+                        );
+                    }
+                    self.provenance.borrow_mut().extend(session.finish());
+                    output
+                }
+            });
+        } else {
+            traced_methods.extend(quote! {
+                #[allow(non_snake_case)]
+                fn #rewrite_method_name(&self, x: &#ref_name) -> #ref_name {
+                    self.rewriter.#rewrite_method_name(x)
+                }
+            });
+        }
+    }
+
+    quote! {
+        // ==== Provenance
+        //
+        // While a `HirpdagRewriteTraced` rewrite runs, the thread-local trace
+        // session collects a step for every struct node rewrite that changed
+        // its node. Nested rewrites are seen through `hirpdag_rewrite`, which
+        // `default_rewrite` calls on every field.
+
+        std::thread_local! {
+            static HIRPDAG_TRACE_SESSION: std::cell::RefCell<
+                Option<hirpdag::base::HirpdagProvenance<HirpdagNodeRef>>,
+            > = const { std::cell::RefCell::new(None) };
+        }
+
+        fn hirpdag_trace_active() -> bool {
+            HIRPDAG_TRACE_SESSION.with(|cell| cell.borrow().is_some())
+        }
+
+        fn hirpdag_trace_record(
+            input: HirpdagNodeRef,
+            output: HirpdagNodeRef,
+            rewriter: &'static str,
+            method: &'static str,
+        ) {
+            HIRPDAG_TRACE_SESSION.with(|cell| {
+                if let Some(provenance) = cell.borrow_mut().as_mut() {
+                    provenance.record(input, output, rewriter, method);
+                }
+            });
+        }
+
+        /// A trace session for one traced rewrite call. Saves the enclosing
+        /// session (if any) and restores it when finished or dropped (on
+        /// panic), so traced rewriters can be nested.
+        struct HirpdagTraceSession {
+            outer: Option<Option<hirpdag::base::HirpdagProvenance<HirpdagNodeRef>>>,
+        }
+
+        impl HirpdagTraceSession {
+            fn begin() -> Self {
+                let outer = HIRPDAG_TRACE_SESSION
+                    .with(|cell| cell.replace(Some(hirpdag::base::HirpdagProvenance::new())));
+                Self { outer: Some(outer) }
+            }
+
+            /// Ends the session, returning its steps. The steps are also
+            /// added to the enclosing session.
+            fn finish(mut self) -> hirpdag::base::HirpdagProvenance<HirpdagNodeRef> {
+                let mut outer = self.outer.take().expect("trace session is open");
+                let provenance = HIRPDAG_TRACE_SESSION
+                    .with(|cell| cell.borrow_mut().take())
+                    .unwrap_or_default();
+                if let Some(outer) = outer.as_mut() {
+                    outer.extend(provenance.clone());
+                }
+                HIRPDAG_TRACE_SESSION.with(|cell| cell.replace(outer));
+                provenance
+            }
+        }
+
+        impl Drop for HirpdagTraceSession {
+            fn drop(&mut self) {
+                if let Some(outer) = self.outer.take() {
+                    HIRPDAG_TRACE_SESSION.with(|cell| cell.replace(outer));
+                }
+            }
+        }
+
+        /// Wraps any `HirpdagRewriter`, recording which rewrite method
+        /// changed which node.
+        ///
+        /// Every struct node rewrite reached through `hirpdag_rewrite` (for
+        /// example from `default_rewrite`) during a call on this wrapper is
+        /// recorded, including rewrites of children. Direct calls of one
+        /// `rewrite_X` method from another are seen only through their
+        /// effect on the caller's node.
+        pub struct HirpdagRewriteTraced<Rewriter: HirpdagRewriter> {
+            rewriter: Rewriter,
+            provenance: std::cell::RefCell<hirpdag::base::HirpdagProvenance<HirpdagNodeRef>>,
+        }
+
+        #[allow(dead_code)]
+        impl<Rewriter: HirpdagRewriter> HirpdagRewriteTraced<Rewriter> {
+            pub fn new(rewriter: Rewriter) -> Self {
+                Self {
+                    rewriter,
+                    provenance: std::cell::RefCell::new(hirpdag::base::HirpdagProvenance::new()),
+                }
+            }
+
+            /// The steps recorded so far, across all calls.
+            pub fn provenance(
+                &self,
+            ) -> std::cell::Ref<'_, hirpdag::base::HirpdagProvenance<HirpdagNodeRef>> {
+                self.provenance.borrow()
+            }
+
+            /// Removes and returns the steps recorded so far.
+            pub fn take_provenance(&self) -> hirpdag::base::HirpdagProvenance<HirpdagNodeRef> {
+                self.provenance.take()
+            }
+
+            pub fn into_inner(self) -> (Rewriter, hirpdag::base::HirpdagProvenance<HirpdagNodeRef>) {
+                (self.rewriter, self.provenance.into_inner())
+            }
+        }
+
+        impl<Rewriter: HirpdagRewriter> HirpdagRewriter for HirpdagRewriteTraced<Rewriter> {
+            #traced_methods
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// Generation of the per-module schema description.
+
+use crate::DataTypeEntry;
+
+/// Generates `hirpdag_schema()`, which describes every `#[hirpdag]` type in
+/// the module (see `hirpdag::base::HirpdagSchema`).
+pub(crate) fn get_schema_fn(types: &[DataTypeEntry]) -> proc_macro2::TokenStream {
+    let struct_names: Vec<&str> = types
+        .iter()
+        .filter(|t| t.is_struct)
+        .map(|t| t.name.as_str())
+        .collect();
+    let enum_names: Vec<&str> = types
+        .iter()
+        .filter(|t| !t.is_struct)
+        .map(|t| t.name.as_str())
+        .collect();
+
+    let type_descriptions = types.iter().map(|entry| {
+        let name = &entry.name;
+        let root = entry.is_root;
+        let members = entry.members.iter().map(|(member, ty)| {
+            let ty = get_schema_ty(ty, &struct_names, &enum_names);
+            if entry.is_struct {
+                quote! {
+                    hirpdag::base::HirpdagSchemaField { name: #member.to_string(), ty: #ty }
+                }
+            } else {
+                quote! {
+                    hirpdag::base::HirpdagSchemaVariant { name: #member.to_string(), ty: #ty }
+                }
+            }
+        });
+        let kind = if entry.is_struct {
+            quote! { hirpdag::base::HirpdagSchemaKind::Struct(vec![#(#members),*]) }
+        } else {
+            quote! { hirpdag::base::HirpdagSchemaKind::Enum(vec![#(#members),*]) }
+        };
+        quote! {
+            hirpdag::base::HirpdagSchemaType {
+                name: #name.to_string(),
+                root: #root,
+                kind: #kind,
+            }
+        }
+    });
+
+    quote! {
+        /// A description of every hirpdag type in this module, which binary
+        /// archives can embed (`HirpdagSerializeOptions::embed_schema`) so
+        /// they can be decoded without these Rust types.
+        #[allow(dead_code)]
+        pub fn hirpdag_schema() -> hirpdag::base::HirpdagSchema {
+            hirpdag::base::HirpdagSchema {
+                types: vec![#(#type_descriptions),*],
+            }
+        }
+    }
+}
+
+/// The `HirpdagSchemaTy` expression describing a field or payload type.
+/// `Box<T>` is described as `T`, which it serializes as.
+fn get_schema_ty(
+    ty: &syn::Type,
+    struct_names: &[&str],
+    enum_names: &[&str],
+) -> proc_macro2::TokenStream {
+    use quote::ToTokens;
+    let opaque = || {
+        let spelling = ty.to_token_stream().to_string();
+        quote! { hirpdag::base::HirpdagSchemaTy::Opaque(#spelling.to_string()) }
+    };
+    let syn::Type::Path(path) = ty else {
+        return opaque();
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return opaque();
+    };
+    let ident = segment.ident.to_string();
+    let arg = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            syn::GenericArgument::Type(arg) => Some(arg),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(arg) = arg {
+        let inner = get_schema_ty(arg, struct_names, enum_names);
+        return match ident.as_str() {
+            "Option" => quote! { hirpdag::base::HirpdagSchemaTy::Option(Box::new(#inner)) },
+            "Vec" => quote! { hirpdag::base::HirpdagSchemaTy::Vec(Box::new(#inner)) },
+            "Box" => inner,
+            _ => opaque(),
+        };
+    }
+    if !segment.arguments.is_none() {
+        return opaque();
+    }
+    let variant = match ident.as_str() {
+        "bool" => "Bool",
+        "u8" => "U8",
+        "u16" => "U16",
+        "u32" => "U32",
+        "u64" => "U64",
+        "u128" => "U128",
+        "usize" => "Usize",
+        "i8" => "I8",
+        "i16" => "I16",
+        "i32" => "I32",
+        "i64" => "I64",
+        "i128" => "I128",
+        "isize" => "Isize",
+        "f32" => "F32",
+        "f64" => "F64",
+        "char" => "Char",
+        "String" => "String",
+        name if struct_names.contains(&name) => {
+            return quote! { hirpdag::base::HirpdagSchemaTy::Ref(#name.to_string()) };
+        }
+        name if enum_names.contains(&name) => {
+            return quote! { hirpdag::base::HirpdagSchemaTy::Enum(#name.to_string()) };
+        }
+        _ => return opaque(),
+    };
+    let variant = proc_macro2::Ident::new(variant, proc_macro2::Span::call_site());
+    quote! { hirpdag::base::HirpdagSchemaTy::#variant }
+}
@@ -0,0 +1,189 @@
+// Generation of the per-module S-expression reader and writer.
+
+use proc_macro2::{Ident, Span};
+
+/// The S-expression items of a struct type: its data reads and writes as
+/// `(Type field ..)`, and its ref reads a node through `new` and writes it
+/// bound by name if it is shared.
+pub(crate) fn get_struct_sexpr_items(
+    ref_name: &Ident,
+    struct_name: &Ident,
+    fields_named: &syn::FieldsNamed,
+) -> proc_macro2::TokenStream {
+    let name_str = ref_name.to_string();
+    let field_names: Vec<&Ident> = fields_named
+        .named
+        .iter()
+        .map(|t| t.ident.as_ref().unwrap())
+        .collect();
+    let arity = field_names.len();
+    let fields_read = field_names.iter().enumerate().map(|(i, field_name)| {
+        quote! {
+            #field_name: hirpdag::base::HirpdagSexprRead::hirpdag_sexpr_read(&args[#i], env)?,
+        }
+    });
+    let msg_wrong_type = format!("expected {}, found {{}}", name_str);
+    quote! {
+        impl hirpdag::base::HirpdagSexprRead<HirpdagNodeRef> for #struct_name {
+            fn hirpdag_sexpr_read(
+                sexpr: &hirpdag::base::HirpdagSexpr,
+                env: &mut hirpdag::base::HirpdagSexprEnv<HirpdagNodeRef>,
+            ) -> Result<Self, hirpdag::base::HirpdagSexprError> {
+                let args = sexpr.form(#name_str, #arity)?;
+                Ok(Self {
+                    #(#fields_read)*
+                })
+            }
+        }
+
+        impl hirpdag::base::HirpdagSexprWrite<HirpdagNodeRef> for #struct_name {
+            fn hirpdag_sexpr_write(&self, w: &mut hirpdag::base::HirpdagSexprWriter<HirpdagNodeRef>) {
+                w.form(#name_str, &[#(&self.#field_names),*]);
+            }
+        }
+
+        impl hirpdag::base::HirpdagSexprRead<HirpdagNodeRef> for #ref_name {
+            fn hirpdag_sexpr_read(
+                sexpr: &hirpdag::base::HirpdagSexpr,
+                env: &mut hirpdag::base::HirpdagSexprEnv<HirpdagNodeRef>,
+            ) -> Result<Self, hirpdag::base::HirpdagSexprError> {
+                let node = env.read_node(sexpr, |sexpr, env| {
+                    let data = #struct_name::hirpdag_sexpr_read(sexpr, env)?;
+                    Ok(HirpdagNodeRef::from(#ref_name::hirpdag_renormalize(data)))
+                })?;
+                <#ref_name as std::convert::TryFrom<HirpdagNodeRef>>::try_from(node).map_err(|other| {
+                    sexpr.error(format!(#msg_wrong_type, other.hirpdag_type_name()))
+                })
+            }
+        }
+
+        impl hirpdag::base::HirpdagSexprWrite<HirpdagNodeRef> for #ref_name {
+            fn hirpdag_sexpr_write(&self, w: &mut hirpdag::base::HirpdagSexprWriter<HirpdagNodeRef>) {
+                w.node(HirpdagNodeRef::from(self.clone()), |w| {
+                    hirpdag::base::HirpdagSexprWrite::hirpdag_sexpr_write(&**self, w)
+                });
+            }
+        }
+    }
+}
+
+/// The S-expression items of an enum type, read and written as the active
+/// variant, `(Variant value)`.
+pub(crate) fn get_enum_sexpr_items(
+    name: &Ident,
+    input_enum: &syn::DataEnum,
+) -> proc_macro2::TokenStream {
+    let mut variants_read = proc_macro2::TokenStream::new();
+    let mut variants_write = proc_macro2::TokenStream::new();
+    let mut variant_strs = Vec::new();
+    for t in &input_enum.variants {
+        let variant = &t.ident;
+        let variant_str = variant.to_string();
+        variants_read.extend(quote! {
+            #variant_str => {
+                let args = sexpr.form(#variant_str, 1)?;
+                Ok(#variant(hirpdag::base::HirpdagSexprRead::hirpdag_sexpr_read(&args[0], env)?))
+            }
+        });
+        variants_write.extend(quote! {
+            #variant(x) => w.form(#variant_str, &[x]),
+        });
+        variant_strs.push(variant_str);
+    }
+    let msg_not_variant = format!(
+        "expected a variant of {}: {}",
+        name,
+        variant_strs.join(", ")
+    );
+    quote! {
+        impl hirpdag::base::HirpdagSexprRead<HirpdagNodeRef> for #name {
+            fn hirpdag_sexpr_read(
+                sexpr: &hirpdag::base::HirpdagSexpr,
+                env: &mut hirpdag::base::HirpdagSexprEnv<HirpdagNodeRef>,
+            ) -> Result<Self, hirpdag::base::HirpdagSexprError> {
+                use #name::*;
+                let (head, _) = sexpr.head().map_err(|_| sexpr.error(#msg_not_variant))?;
+                match head {
+                    #variants_read
+                    _ => Err(sexpr.error(#msg_not_variant)),
+                }
+            }
+        }
+
+        impl hirpdag::base::HirpdagSexprWrite<HirpdagNodeRef> for #name {
+            fn hirpdag_sexpr_write(&self, w: &mut hirpdag::base::HirpdagSexprWriter<HirpdagNodeRef>) {
+                use #name::*;
+                match self {
+                    #variants_write
+                }
+            }
+        }
+    }
+}
+
+/// `HirpdagSexprRead` and `HirpdagSexprWrite` for `HirpdagNodeRef`,
+/// dispatching on the type name, and the module's entry points.
+///
+/// `struct_types` is (name, is_root) for each struct type in the module.
+pub(crate) fn get_sexpr_items(struct_types: &[(String, bool)]) -> proc_macro2::TokenStream {
+    let mut read_arms = proc_macro2::TokenStream::new();
+    let mut write_arms = proc_macro2::TokenStream::new();
+    let mut type_names = Vec::new();
+    for (name, _) in struct_types {
+        let ref_name = Ident::new(name, Span::call_site());
+        read_arms.extend(quote! {
+            #name => Ok(HirpdagNodeRef::from(#ref_name::hirpdag_sexpr_read(sexpr, env)?)),
+        });
+        write_arms.extend(quote! {
+            HirpdagNodeRef::#ref_name(x) => x.hirpdag_sexpr_write(w),
+        });
+        type_names.push(name.clone());
+    }
+    let msg_not_node = format!("expected a node: {}", type_names.join(", "));
+    quote! {
+        // ==== S-expressions
+
+        impl hirpdag::base::HirpdagSexprRead<HirpdagNodeRef> for HirpdagNodeRef {
+            fn hirpdag_sexpr_read(
+                sexpr: &hirpdag::base::HirpdagSexpr,
+                env: &mut hirpdag::base::HirpdagSexprEnv<HirpdagNodeRef>,
+            ) -> Result<Self, hirpdag::base::HirpdagSexprError> {
+                use hirpdag::base::HirpdagSexprRead;
+                env.read_node(sexpr, |sexpr, env| {
+                    let (head, _) = sexpr.head().map_err(|_| sexpr.error(#msg_not_node))?;
+                    match head {
+                        #read_arms
+                        _ => Err(sexpr.error(#msg_not_node)),
+                    }
+                })
+            }
+        }
+
+        impl hirpdag::base::HirpdagSexprWrite<HirpdagNodeRef> for HirpdagNodeRef {
+            fn hirpdag_sexpr_write(&self, w: &mut hirpdag::base::HirpdagSexprWriter<HirpdagNodeRef>) {
+                use hirpdag::base::HirpdagSexprWrite;
+                match self {
+                    #write_arms
+                }
+            }
+        }
+
+        /// Reads S-expression text as a `T`: a node, a vector of nodes, or
+        /// any field type. Nodes are built through their types' `new`.
+        #[allow(dead_code)]
+        pub fn hirpdag_from_sexpr<T: hirpdag::base::HirpdagSexprRead<HirpdagNodeRef>>(
+            text: &str,
+        ) -> Result<T, hirpdag::base::HirpdagSexprError> {
+            hirpdag::base::hirpdag_sexpr_read_str::<HirpdagNodeRef, T>(text)
+        }
+
+        /// `value` as S-expression text, with a `let` binding each node
+        /// reached more than once.
+        #[allow(dead_code)]
+        pub fn hirpdag_to_sexpr<T: hirpdag::base::HirpdagSexprWrite<HirpdagNodeRef> + ?Sized>(
+            value: &T,
+        ) -> String {
+            hirpdag::base::hirpdag_sexpr_write_string::<HirpdagNodeRef, T>(value)
+        }
+    }
+}
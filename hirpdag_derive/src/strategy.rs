@@ -0,0 +1,581 @@
+// Generation of the per-module rewrite strategy combinators.
+
+use proc_macro2::{Ident, Span, TokenStream};
+
+/// Per struct type identifiers used by the generated strategies.
+struct StrategyType {
+    ref_name: Ident,
+    apply: Ident,
+    rewrite: Ident,
+    rule: Ident,
+    all: Ident,
+    one: Ident,
+    memo: Ident,
+}
+
+/// Generates the body of `apply_X` for one combinator and struct type.
+type CombinatorBody<'a> = &'a dyn Fn(&StrategyType) -> TokenStream;
+
+/// The `rule_X` methods of `HirpdagRewriter`, one per struct type in
+/// `types` ((name, is_struct) as for `get_strategy_items`), which
+/// `HirpdagRule` applies.
+pub(crate) fn get_rule_methods(types: &[(String, bool)]) -> TokenStream {
+    types
+        .iter()
+        .filter(|(_, is_struct)| *is_struct)
+        .map(|(name, _)| {
+            let ref_name = Ident::new(name, Span::call_site());
+            let rule = Ident::new(&format!("rule_{}", name), Span::call_site());
+            quote! {
+                /// Rewrites `x` itself, not its children, as a strategy
+                /// rule: the result, or `None` where the rule does not
+                /// apply. Only `HirpdagRule` calls this.
+                #[allow(non_snake_case)]
+                fn #rule(&self, x: &#ref_name) -> Option<#ref_name> {
+                    let _ = x;
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Generates the module-level strategy combinators: the `HirpdagStrategy`
+/// trait, one combinator struct per Stratego-style combinator, the
+/// `HirpdagRule` adapter (rewriter to strategy), the
+/// `HirpdagStrategyRewriter` adapter (strategy to rewriter) and the
+/// one-layer traversals `all` and `one` they are built from.
+///
+/// `types` is (name, is_struct) for every `#[hirpdag]` type in the module.
+/// Strategies apply to struct nodes; enum fields are traversed through
+/// their `default_rewrite` to reach the struct nodes inside them.
+pub(crate) fn get_strategy_items(types: &[(String, bool)]) -> TokenStream {
+    let types: Vec<StrategyType> = types
+        .iter()
+        .filter(|(_, is_struct)| *is_struct)
+        .map(|(name, _)| {
+            let ident = |s: String| Ident::new(&s, Span::call_site());
+            StrategyType {
+                ref_name: ident(name.clone()),
+                apply: ident(format!("apply_{}", name)),
+                rewrite: ident(format!("rewrite_{}", name)),
+                rule: ident(format!("rule_{}", name)),
+                all: ident(format!("hirpdag_strategy_all_{}", name)),
+                one: ident(format!("hirpdag_strategy_one_{}", name)),
+                memo: ident(format!("memo_{}", name)),
+            }
+        })
+        .collect();
+    if types.is_empty() {
+        return TokenStream::new();
+    }
+
+    let impls = |generics: TokenStream, target: TokenStream, body: CombinatorBody| {
+        let methods: TokenStream = types
+            .iter()
+            .map(|t| {
+                let StrategyType {
+                    ref_name, apply, ..
+                } = t;
+                let body = body(t);
+                quote! {
+                    #[allow(non_snake_case)]
+                    fn #apply(&self, x: &#ref_name) -> Option<#ref_name> {
+                        #body
+                    }
+                }
+            })
+            .collect();
+        quote! {
+            impl #generics HirpdagStrategy for #target {
+                #methods
+            }
+        }
+    };
+
+    let trait_methods: TokenStream = types
+        .iter()
+        .map(
+            |StrategyType {
+                 ref_name, apply, ..
+             }| {
+                quote! {
+                    /// Applies the strategy to `x`: the result, or `None` if the
+                    /// strategy fails.
+                    #[allow(non_snake_case)]
+                    fn #apply(&self, x: &#ref_name) -> Option<#ref_name> {
+                        let _ = x;
+                        None
+                    }
+                }
+            },
+        )
+        .collect();
+
+    let traversal_fns: TokenStream = types
+        .iter()
+        .map(
+            |StrategyType {
+                 ref_name, all, one, ..
+             }| {
+                quote! {
+                    #[allow(non_snake_case)]
+                    fn #all<S: HirpdagStrategy>(s: &S, x: &#ref_name) -> Option<#ref_name> {
+                        let all = HirpdagStrategyAll {
+                            strategy: s,
+                            failed: std::cell::Cell::new(false),
+                        };
+                        let y = x.default_rewrite(&all);
+                        if all.failed.get() {
+                            None
+                        } else {
+                            Some(y)
+                        }
+                    }
+
+                    #[allow(non_snake_case)]
+                    fn #one<S: HirpdagStrategy>(s: &S, x: &#ref_name) -> Option<#ref_name> {
+                        let one = HirpdagStrategyOne {
+                            strategy: s,
+                            done: std::cell::Cell::new(false),
+                        };
+                        let y = x.default_rewrite(&one);
+                        if one.done.get() {
+                            Some(y)
+                        } else {
+                            None
+                        }
+                    }
+                }
+            },
+        )
+        .collect();
+
+    let all_methods: TokenStream = types
+        .iter()
+        .map(
+            |StrategyType {
+                 ref_name,
+                 apply,
+                 rewrite,
+                 ..
+             }| {
+                quote! {
+                    #[allow(non_snake_case)]
+                    fn #rewrite(&self, x: &#ref_name) -> #ref_name {
+                        if self.failed.get() {
+                            return x.clone();
+                        }
+                        self.strategy.#apply(x).unwrap_or_else(|| {
+                            self.failed.set(true);
+                            x.clone()
+                        })
+                    }
+                }
+            },
+        )
+        .collect();
+
+    let one_methods: TokenStream = types
+        .iter()
+        .map(
+            |StrategyType {
+                 ref_name,
+                 apply,
+                 rewrite,
+                 ..
+             }| {
+                quote! {
+                    #[allow(non_snake_case)]
+                    fn #rewrite(&self, x: &#ref_name) -> #ref_name {
+                        if self.done.get() {
+                            return x.clone();
+                        }
+                        match self.strategy.#apply(x) {
+                            Some(y) => {
+                                self.done.set(true);
+                                y
+                            }
+                            None => x.clone(),
+                        }
+                    }
+                }
+            },
+        )
+        .collect();
+
+    let strategy_rewriter_methods: TokenStream = types
+        .iter()
+        .map(
+            |StrategyType {
+                 ref_name,
+                 apply,
+                 rewrite,
+                 ..
+             }| {
+                quote! {
+                    #[allow(non_snake_case)]
+                    fn #rewrite(&self, x: &#ref_name) -> #ref_name {
+                        self.0.#apply(x).unwrap_or_else(|| x.clone())
+                    }
+                }
+            },
+        )
+        .collect();
+
+    let memo_fields: TokenStream = types
+        .iter()
+        .map(|StrategyType { ref_name, memo, .. }| {
+            quote! {
+                #memo: std::cell::RefCell<std::collections::HashMap<#ref_name, Option<#ref_name>>>,
+            }
+        })
+        .collect();
+
+    let step_names: Vec<Ident> = ["TopDown", "BottomUp", "Once", "Innermost"]
+        .iter()
+        .map(|name| Ident::new(&format!("Hirpdag{}Step", name), Span::call_site()))
+        .collect();
+
+    let impl_id = impls(
+        quote! {},
+        quote! { HirpdagId },
+        &|_| quote! { Some(x.clone()) },
+    );
+    let impl_fail = impls(quote! {}, quote! { HirpdagFail }, &|_| quote! { None });
+    let impl_rule = impls(
+        quote! { <R: HirpdagRewriter> },
+        quote! { HirpdagRule<R> },
+        &|StrategyType { rule, .. }| quote! { self.0.#rule(x) },
+    );
+    let impl_seq = impls(
+        quote! { <A: HirpdagStrategy, B: HirpdagStrategy> },
+        quote! { HirpdagSeq<A, B> },
+        &|StrategyType { apply, .. }| quote! { self.0.#apply(x).and_then(|y| self.1.#apply(&y)) },
+    );
+    let impl_choice = impls(
+        quote! { <A: HirpdagStrategy, B: HirpdagStrategy> },
+        quote! { HirpdagChoice<A, B> },
+        &|StrategyType { apply, .. }| quote! { self.0.#apply(x).or_else(|| self.1.#apply(x)) },
+    );
+    let impl_try = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagTry<S> },
+        &|StrategyType { apply, .. }| quote! { Some(self.0.#apply(x).unwrap_or_else(|| x.clone())) },
+    );
+    let impl_repeat = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagRepeat<S> },
+        &|StrategyType { apply, .. }| {
+            quote! {
+                let mut x = x.clone();
+                while let Some(y) = self.0.#apply(&x) {
+                    x = y;
+                }
+                Some(x)
+            }
+        },
+    );
+    let impl_all = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagAll<S> },
+        &|StrategyType { all, .. }| quote! { #all(&self.0, x) },
+    );
+    let impl_one = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagOne<S> },
+        &|StrategyType { one, .. }| quote! { #one(&self.0, x) },
+    );
+    // The recursive traversals apply through a step which memoizes its
+    // result per node, so a node shared by several parents is traversed once.
+    let memoized = |target: TokenStream, body: CombinatorBody| {
+        impls(
+            quote! { <'a, S: HirpdagStrategy> },
+            target,
+            &|t: &StrategyType| {
+                let memo = &t.memo;
+                let body = body(t);
+                quote! {
+                    if let Some(y) = self.memo.#memo.borrow().get(x) {
+                        return y.clone();
+                    }
+                    let y = { #body };
+                    self.memo.#memo.borrow_mut().insert(x.clone(), y.clone());
+                    y
+                }
+            },
+        )
+    };
+    let impl_top_down_step = memoized(
+        quote! { HirpdagTopDownStep<'a, S> },
+        &|StrategyType { apply, all, .. }| {
+            quote! { self.strategy.#apply(x).and_then(|y| #all(self, &y)) }
+        },
+    );
+    let impl_bottom_up_step = memoized(
+        quote! { HirpdagBottomUpStep<'a, S> },
+        &|StrategyType { apply, all, .. }| {
+            quote! { #all(self, x).and_then(|y| self.strategy.#apply(&y)) }
+        },
+    );
+    let impl_once_step = memoized(
+        quote! { HirpdagOnceStep<'a, S> },
+        &|StrategyType { apply, one, .. }| {
+            quote! { self.strategy.#apply(x).or_else(|| #one(self, x)) }
+        },
+    );
+    let impl_innermost_step = memoized(
+        quote! { HirpdagInnermostStep<'a, S> },
+        &|StrategyType { apply, all, .. }| {
+            quote! {
+                #all(self, x).and_then(|y| match self.strategy.#apply(&y) {
+                    Some(z) => self.#apply(&z),
+                    None => Some(y),
+                })
+            }
+        },
+    );
+    let impl_top_down = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagTopDown<S> },
+        &|StrategyType { apply, .. }| {
+            quote! { HirpdagTopDownStep::new(&self.0, &Default::default()).#apply(x) }
+        },
+    );
+    let impl_bottom_up = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagBottomUp<S> },
+        &|StrategyType { apply, .. }| {
+            quote! { HirpdagBottomUpStep::new(&self.0, &Default::default()).#apply(x) }
+        },
+    );
+    let impl_once = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagOnce<S> },
+        &|StrategyType { apply, .. }| {
+            quote! { HirpdagOnceStep::new(&self.0, &Default::default()).#apply(x) }
+        },
+    );
+    let impl_innermost = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagInnermost<S> },
+        &|StrategyType { apply, .. }| {
+            quote! { HirpdagInnermostStep::new(&self.0, &Default::default()).#apply(x) }
+        },
+    );
+    let impl_outermost = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { HirpdagOutermost<S> },
+        &|StrategyType { apply, .. }| {
+            quote! {
+                // Where `once` fails on a node is the same in every pass,
+                // so the passes share a memo.
+                let memo = HirpdagStrategyMemo::default();
+                let once = HirpdagOnceStep::new(&self.0, &memo);
+                let mut x = x.clone();
+                while let Some(y) = once.#apply(&x) {
+                    x = y;
+                }
+                Some(x)
+            }
+        },
+    );
+    let impl_ref = impls(
+        quote! { <S: HirpdagStrategy> },
+        quote! { &S },
+        &|StrategyType { apply, .. }| quote! { (**self).#apply(x) },
+    );
+
+    quote! {
+        // ==== Rewrite strategies
+        //
+        // Stratego-style strategy combinators. A strategy either succeeds
+        // with a (possibly unchanged) node or fails. Traversal combinators
+        // are built from the one-layer traversals `all` and `one`, which
+        // apply a strategy to the immediate struct children of a node via
+        // `default_rewrite`.
+        //
+        // The recursive traversals memoize their result per node for the
+        // duration of one application, so a node shared by several parents
+        // is traversed once. Strategies must be pure for this to hold.
+
+        /// A rewrite strategy over the hirpdag types of this module.
+        ///
+        /// Every method defaults to failing, so a strategy only implements
+        /// the types it matches. Compose strategies with the combinator
+        /// structs (`HirpdagSeq`, `HirpdagTopDown`, ...) or the methods
+        /// below, and lift a `HirpdagRewriter` with `HirpdagRule`.
+        pub trait HirpdagStrategy {
+            #trait_methods
+
+            /// `self`, then `next` on its result. Fails if either fails.
+            fn seq<B: HirpdagStrategy>(self, next: B) -> HirpdagSeq<Self, B>
+            where
+                Self: Sized,
+            {
+                HirpdagSeq(self, next)
+            }
+
+            /// `self`, or `other` if `self` fails.
+            fn choice<B: HirpdagStrategy>(self, other: B) -> HirpdagChoice<Self, B>
+            where
+                Self: Sized,
+            {
+                HirpdagChoice(self, other)
+            }
+
+            /// Uses the strategy as a rewriter: nodes it fails on are left
+            /// unchanged.
+            fn into_rewriter(self) -> HirpdagStrategyRewriter<Self>
+            where
+                Self: Sized,
+            {
+                HirpdagStrategyRewriter(self)
+            }
+        }
+
+        /// Always succeeds, leaving the node unchanged.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagId;
+
+        /// Always fails.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagFail;
+
+        /// Lifts a rewriter into a strategy which applies its `rule_X`
+        /// methods: it succeeds where the rule does, and fails on the types
+        /// whose rule is not overridden. The `rewrite_X` methods are not
+        /// used, so the strategy rewrites the node it is given only;
+        /// traversal is up to the other combinators.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagRule<R>(pub R);
+
+        /// `s1; s2`: applies `s1`, then `s2` to its result.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagSeq<A, B>(pub A, pub B);
+
+        /// `s1 <+ s2`: applies `s1`, or `s2` if `s1` fails.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagChoice<A, B>(pub A, pub B);
+
+        /// `try(s)`: applies `s`, leaving the node unchanged if it fails.
+        /// Never fails.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagTry<S>(pub S);
+
+        /// `repeat(s)`: applies `s` until it fails. Never fails. Does not
+        /// terminate if `s` keeps succeeding.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagRepeat<S>(pub S);
+
+        /// `all(s)`: applies `s` to every immediate child. Fails if it
+        /// fails on any child.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagAll<S>(pub S);
+
+        /// `one(s)`: applies `s` to the first immediate child it succeeds
+        /// on. Fails if it fails on every child.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagOne<S>(pub S);
+
+        /// `top_down(s)`: applies `s` to the node, then recursively to the
+        /// children of the result. Fails if `s` fails anywhere; wrap `s` in
+        /// `HirpdagTry` to rewrite where possible.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagTopDown<S>(pub S);
+
+        /// `bottom_up(s)`: applies `s` recursively to the children, then to
+        /// the rebuilt node. Fails if `s` fails anywhere; wrap `s` in
+        /// `HirpdagTry` to rewrite where possible.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagBottomUp<S>(pub S);
+
+        /// `once(s)` (`oncetd`): applies `s` at the first position, in
+        /// pre-order, where it succeeds. Fails if it succeeds nowhere.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagOnce<S>(pub S);
+
+        /// `innermost(s)`: rewrites to a normal form of `s`, reducing
+        /// innermost redexes first. Never fails.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagInnermost<S>(pub S);
+
+        /// `outermost(s)`: rewrites to a normal form of `s`, reducing
+        /// outermost redexes first. Never fails.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagOutermost<S>(pub S);
+
+        /// Uses a strategy as a `HirpdagRewriter`: nodes it fails on are left
+        /// unchanged.
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct HirpdagStrategyRewriter<S>(pub S);
+
+        #impl_id
+        #impl_fail
+        #impl_rule
+        #impl_seq
+        #impl_choice
+        #impl_try
+        #impl_repeat
+        #impl_all
+        #impl_one
+        #impl_top_down
+        #impl_bottom_up
+        #impl_once
+        #impl_innermost
+        #impl_outermost
+        #impl_ref
+        #impl_top_down_step
+        #impl_bottom_up_step
+        #impl_once_step
+        #impl_innermost_step
+
+        impl<S: HirpdagStrategy> HirpdagRewriter for HirpdagStrategyRewriter<S> {
+            #strategy_rewriter_methods
+        }
+
+        #traversal_fns
+
+        /// The result of a traversal step on each node it was applied to.
+        #[derive(Default)]
+        struct HirpdagStrategyMemo {
+            #memo_fields
+        }
+
+        #(
+            /// A recursive traversal applying `strategy`, memoized in `memo`.
+            struct #step_names<'a, S> {
+                strategy: &'a S,
+                memo: &'a HirpdagStrategyMemo,
+            }
+
+            impl<'a, S> #step_names<'a, S> {
+                fn new(strategy: &'a S, memo: &'a HirpdagStrategyMemo) -> Self {
+                    Self { strategy, memo }
+                }
+            }
+        )*
+
+        /// Applies a strategy to every child reached by `default_rewrite`,
+        /// recording whether it failed on any.
+        struct HirpdagStrategyAll<'a, S> {
+            strategy: &'a S,
+            failed: std::cell::Cell<bool>,
+        }
+
+        impl<'a, S: HirpdagStrategy> HirpdagRewriter for HirpdagStrategyAll<'a, S> {
+            #all_methods
+        }
+
+        /// Applies a strategy to the children reached by `default_rewrite`
+        /// until it succeeds on one.
+        struct HirpdagStrategyOne<'a, S> {
+            strategy: &'a S,
+            done: std::cell::Cell<bool>,
+        }
+
+        impl<'a, S: HirpdagStrategy> HirpdagRewriter for HirpdagStrategyOne<'a, S> {
+            #one_methods
+        }
+    }
+}
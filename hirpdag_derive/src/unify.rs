@@ -0,0 +1,290 @@
+// Generation of the per-module matching and unification.
+
+use proc_macro2::{Ident, Span};
+
+/// `hirpdag_flags` for a struct's data: flags the node as a metavariable if
+/// any of its fields is a value of a `#[hirpdag(metavar)]` variant.
+pub(crate) fn get_struct_metavar_flags(
+    fields_named: &syn::FieldsNamed,
+) -> proc_macro2::TokenStream {
+    let field_names: Vec<&Ident> = fields_named
+        .named
+        .iter()
+        .map(|t| t.ident.as_ref().unwrap())
+        .collect();
+    let arity = field_names.len();
+    quote! {
+        fn hirpdag_flags(&self) -> HirpdagMetaFlagType {
+            let metavar: [bool; #arity] = [#(
+                hirpdag::base::HirpdagUnify::<HirpdagNodeRef>::hirpdag_is_metavar(&self.#field_names)
+            ),*];
+            if metavar.iter().any(|m| *m) {
+                hirpdag::base::HIRPDAG_META_FLAG_METAVAR
+            } else {
+                0
+            }
+        }
+    }
+}
+
+/// The `HirpdagUnify` impls of a struct type: its data unifies field by
+/// field, and its ref as a node.
+pub(crate) fn get_struct_unify_items(
+    ref_name: &Ident,
+    struct_name: &Ident,
+    fields_named: &syn::FieldsNamed,
+) -> proc_macro2::TokenStream {
+    let field_names: Vec<&Ident> = fields_named
+        .named
+        .iter()
+        .map(|t| t.ident.as_ref().unwrap())
+        .collect();
+    quote! {
+        impl hirpdag::base::HirpdagUnify<HirpdagNodeRef> for #struct_name {
+            fn hirpdag_unify_with(
+                &self,
+                other: &Self,
+                u: &mut hirpdag::base::HirpdagUnifier<HirpdagNodeRef>,
+            ) -> bool {
+                true #(&& hirpdag::base::HirpdagUnify::<HirpdagNodeRef>::hirpdag_unify_with(
+                    &self.#field_names,
+                    &other.#field_names,
+                    u,
+                ))*
+            }
+
+            fn hirpdag_term_children(&self, out: &mut Vec<HirpdagNodeRef>) {
+                #(hirpdag::base::HirpdagUnify::<HirpdagNodeRef>::hirpdag_term_children(
+                    &self.#field_names,
+                    out,
+                );)*
+            }
+        }
+
+        impl hirpdag::base::HirpdagUnify<HirpdagNodeRef> for #ref_name {
+            fn hirpdag_unify_with(
+                &self,
+                other: &Self,
+                u: &mut hirpdag::base::HirpdagUnifier<HirpdagNodeRef>,
+            ) -> bool {
+                u.nodes(
+                    &HirpdagNodeRef::from(self.clone()),
+                    &HirpdagNodeRef::from(other.clone()),
+                )
+            }
+
+            fn hirpdag_term_children(&self, out: &mut Vec<HirpdagNodeRef>) {
+                out.push(HirpdagNodeRef::from(self.clone()));
+            }
+        }
+    }
+}
+
+/// The `HirpdagUnify` impl of an enum type: values of the same variant unify
+/// if their payloads do. A value of a `metavars` variant makes the node
+/// holding it a metavariable.
+pub(crate) fn get_enum_unify_items(
+    name: &Ident,
+    input_enum: &syn::DataEnum,
+    metavars: &[Ident],
+) -> proc_macro2::TokenStream {
+    let variants: Vec<&Ident> = input_enum.variants.iter().map(|t| &t.ident).collect();
+    let is_metavar = if metavars.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            fn hirpdag_is_metavar(&self) -> bool {
+                matches!(self, #(#name::#metavars(_))|*)
+            }
+        }
+    };
+    quote! {
+        impl hirpdag::base::HirpdagUnify<HirpdagNodeRef> for #name {
+            fn hirpdag_unify_with(
+                &self,
+                other: &Self,
+                u: &mut hirpdag::base::HirpdagUnifier<HirpdagNodeRef>,
+            ) -> bool {
+                use #name::*;
+                match (self, other) {
+                    #((#variants(a), #variants(b)) => {
+                        hirpdag::base::HirpdagUnify::<HirpdagNodeRef>::hirpdag_unify_with(a, b, u)
+                    })*
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }
+            }
+
+            #is_metavar
+
+            fn hirpdag_term_children(&self, out: &mut Vec<HirpdagNodeRef>) {
+                use #name::*;
+                match self {
+                    #(#variants(x) => {
+                        hirpdag::base::HirpdagUnify::<HirpdagNodeRef>::hirpdag_term_children(x, out)
+                    })*
+                }
+            }
+        }
+    }
+}
+
+/// `HirpdagTerm` and `HirpdagUnify` for `HirpdagNodeRef`, the
+/// `HirpdagSubstitution` alias, the `HirpdagSubstituter` rewriter which
+/// applies a substitution, and the entry points.
+///
+/// `struct_types` is (name, is_root) for each struct type in the module.
+pub(crate) fn get_unify_items(struct_types: &[(String, bool)]) -> proc_macro2::TokenStream {
+    let ref_names: Vec<Ident> = struct_types
+        .iter()
+        .map(|(name, _)| Ident::new(name, Span::call_site()))
+        .collect();
+    let rewrite_methods: proc_macro2::TokenStream = ref_names
+        .iter()
+        .map(|ref_name| {
+            let rewrite_method_name =
+                Ident::new(&format!("rewrite_{}", ref_name), Span::call_site());
+            quote! {
+                #[allow(non_snake_case)]
+                fn #rewrite_method_name(&self, x: &#ref_name) -> #ref_name {
+                    let node = HirpdagNodeRef::from(x.clone());
+                    if hirpdag::base::HirpdagTerm::hirpdag_is_ground(&node) {
+                        return x.clone();
+                    }
+                    let to_ref = |node: HirpdagNodeRef| {
+                        <#ref_name as std::convert::TryFrom<HirpdagNodeRef>>::try_from(node)
+                            .unwrap_or_else(|_| {
+                                panic!("hirpdag: metavariable bound to a node of another type")
+                            })
+                    };
+                    if let Some(done) = self.memo.borrow().get(&node) {
+                        return to_ref(done.clone());
+                    }
+                    let result = match self.subst.get(&node) {
+                        // The term may hold bound metavariables itself.
+                        Some(term) => self.rewrite(&to_ref(term.clone())),
+                        None => #ref_name::default_rewrite::<Self>(x, self),
+                    };
+                    self.memo.borrow_mut().insert(node, HirpdagNodeRef::from(result.clone()));
+                    result
+                }
+            }
+        })
+        .collect();
+    quote! {
+        // ==== Matching and unification
+
+        impl hirpdag::base::HirpdagTerm for HirpdagNodeRef {
+            fn hirpdag_is_metavar(&self) -> bool {
+                let flags = match self {
+                    #(HirpdagNodeRef::#ref_names(x) => hirpdag::base::HirpdagStruct::hirpdag_flags(&**x),)*
+                };
+                flags & hirpdag::base::HIRPDAG_META_FLAG_METAVAR != 0
+            }
+
+            fn hirpdag_is_ground(&self) -> bool {
+                let flags = match self {
+                    #(HirpdagNodeRef::#ref_names(x) => x.0.hirpdag_get_meta().get_flags(),)*
+                };
+                flags & hirpdag::base::HIRPDAG_META_FLAG_METAVAR == 0
+            }
+
+            fn hirpdag_term_type(&self) -> &'static str {
+                self.hirpdag_type_name()
+            }
+
+            fn hirpdag_unify_data(
+                &self,
+                other: &Self,
+                u: &mut hirpdag::base::HirpdagUnifier<Self>,
+            ) -> bool {
+                use hirpdag::base::HirpdagUnify;
+                match (self, other) {
+                    #((HirpdagNodeRef::#ref_names(a), HirpdagNodeRef::#ref_names(b)) => {
+                        (**a).hirpdag_unify_with(&**b, u)
+                    })*
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }
+            }
+
+            fn hirpdag_term_children(&self, out: &mut Vec<Self>) {
+                use hirpdag::base::HirpdagUnify;
+                match self {
+                    #(HirpdagNodeRef::#ref_names(x) => (**x).hirpdag_term_children(out),)*
+                }
+            }
+        }
+
+        impl hirpdag::base::HirpdagUnify<HirpdagNodeRef> for HirpdagNodeRef {
+            fn hirpdag_unify_with(
+                &self,
+                other: &Self,
+                u: &mut hirpdag::base::HirpdagUnifier<HirpdagNodeRef>,
+            ) -> bool {
+                u.nodes(self, other)
+            }
+
+            fn hirpdag_term_children(&self, out: &mut Vec<HirpdagNodeRef>) {
+                out.push(self.clone());
+            }
+        }
+
+        /// Metavariables bound to terms, by `hirpdag_match_term` or
+        /// `hirpdag_unify`.
+        #[allow(dead_code)]
+        pub type HirpdagSubstitution = hirpdag::base::HirpdagSubstitution<HirpdagNodeRef>;
+
+        /// Rewrites bound metavariables to their terms, following bindings,
+        /// and leaves ground subterms as they are. Each node is rewritten
+        /// once.
+        pub struct HirpdagSubstituter<'a> {
+            subst: &'a HirpdagSubstitution,
+            memo: std::cell::RefCell<std::collections::HashMap<HirpdagNodeRef, HirpdagNodeRef>>,
+        }
+
+        impl<'a> HirpdagSubstituter<'a> {
+            #[allow(dead_code)]
+            pub fn new(subst: &'a HirpdagSubstitution) -> Self {
+                Self {
+                    subst,
+                    memo: Default::default(),
+                }
+            }
+        }
+
+        impl HirpdagRewriter for HirpdagSubstituter<'_> {
+            #rewrite_methods
+        }
+
+        /// The substitution which makes `pattern` equal to `term`, binding
+        /// metavariables in `pattern` only, if there is one.
+        #[allow(dead_code)]
+        pub fn hirpdag_match_term<T: hirpdag::base::HirpdagUnify<HirpdagNodeRef>>(
+            pattern: &T,
+            term: &T,
+        ) -> Option<HirpdagSubstitution> {
+            hirpdag::base::HirpdagUnifier::match_term(pattern, term)
+        }
+
+        /// A most general substitution which makes `a` and `b` equal, if
+        /// there is one.
+        #[allow(dead_code)]
+        pub fn hirpdag_unify<T: hirpdag::base::HirpdagUnify<HirpdagNodeRef>>(
+            a: &T,
+            b: &T,
+        ) -> Option<HirpdagSubstitution> {
+            hirpdag::base::HirpdagUnifier::unify(a, b)
+        }
+
+        /// `term` with the metavariables bound in `subst` replaced by their
+        /// terms, rebuilt through `new`.
+        #[allow(dead_code)]
+        pub fn hirpdag_substitute<'a, T: HirpdagRewritable<HirpdagSubstituter<'a>>>(
+            subst: &'a HirpdagSubstitution,
+            term: &T,
+        ) -> T {
+            HirpdagSubstituter::new(subst).rewrite(term)
+        }
+    }
+}
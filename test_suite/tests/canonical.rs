@@ -0,0 +1,115 @@
+// Tests for canonical archives, whose bytes depend only on their content.
+
+use hirpdag::base::HirpdagSerializeOptions;
+use hirpdag::*;
+
+#[hirpdag_module]
+mod build {
+    #[hirpdag(root)]
+    struct Target {
+        pub name: String,
+        pub deps: Vec<Target>,
+    }
+
+    #[hirpdag(root)]
+    struct Tool {
+        pub name: String,
+    }
+}
+
+use build::*;
+
+fn target(name: &str, deps: Vec<Target>) -> Target {
+    Target::new(format!("canonical_{}", name), deps)
+}
+
+fn tool(name: &str) -> Tool {
+    Tool::new(format!("canonical_{}", name))
+}
+
+fn canonical() -> HirpdagSerializeOptions {
+    HirpdagSerializeOptions {
+        canonical: true,
+        ..Default::default()
+    }
+}
+
+/// The same build graph, listing its roots in the order given.
+fn graph(reversed: bool) -> HirpdagArchiveRoots {
+    let lib = target("lib", vec![]);
+    let mut targets = vec![
+        target("app", vec![lib.clone()]),
+        target("test", vec![lib.clone(), target("mock", vec![])]),
+        lib,
+    ];
+    let mut tools = vec![tool("cc"), tool("ld")];
+    if reversed {
+        targets.reverse();
+        tools.reverse();
+    }
+    HirpdagArchiveRoots {
+        target: targets,
+        tool: tools,
+    }
+}
+
+#[test]
+fn ignores_root_order() {
+    let (forward, reversed) = (graph(false), graph(true));
+    assert_ne!(
+        hirpdag_serialize(&forward).unwrap(),
+        hirpdag_serialize(&reversed).unwrap()
+    );
+
+    let bytes = hirpdag_serialize_with_options(&forward, &canonical()).unwrap();
+    assert_eq!(
+        bytes,
+        hirpdag_serialize_with_options(&reversed, &canonical()).unwrap()
+    );
+    let read = hirpdag_deserialize(&bytes).unwrap();
+    assert_eq!(read, hirpdag_canonical_roots(&forward).unwrap());
+    for target in &forward.target {
+        assert!(read.target.contains(target));
+    }
+}
+
+#[test]
+fn canonical_roots_serve_json() {
+    let forward = hirpdag_canonical_roots(&graph(false)).unwrap();
+    let reversed = hirpdag_canonical_roots(&graph(true)).unwrap();
+    assert_eq!(forward, reversed);
+    assert_eq!(
+        hirpdag_serialize_json(&forward).unwrap(),
+        hirpdag_serialize_json(&reversed).unwrap()
+    );
+}
+
+#[test]
+fn combines_with_other_options() {
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        checksum: true,
+        compress: true,
+        index: true,
+        canonical: true,
+    };
+    let bytes = hirpdag_serialize_with_options(&graph(false), &options).unwrap();
+    assert_eq!(
+        bytes,
+        hirpdag_serialize_with_options(&graph(true), &options).unwrap()
+    );
+
+    let mut streamed = Vec::new();
+    hirpdag_serialize_to_with_options(&graph(true), &mut streamed, &options).unwrap();
+    assert_eq!(streamed, bytes);
+}
+
+#[test]
+fn keeps_root_list_order() {
+    let roots = graph(false);
+    let mut list = HirpdagRootList::new();
+    list.push_named("tool", roots.tool[1].clone());
+    list.push(roots.target[0].clone());
+    let bytes = hirpdag_serialize_list_with_options(&list, &canonical()).unwrap();
+    assert_eq!(hirpdag_deserialize_list(&bytes).unwrap(), list);
+}
@@ -0,0 +1,105 @@
+// Tests for serializing archives with arbitrary serde formats through
+// `hirpdag_serialize_with` / `hirpdag_deserialize_with`.
+
+use hirpdag::base::{HirpdagDeserializeError, HirpdagDeserializeOptions};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod codec {
+    #[hirpdag(root)]
+    struct Term {
+        pub op: Op,
+        pub weight: Option<i16>,
+    }
+
+    #[hirpdag]
+    enum Op {
+        Lit(u64),
+        Name(String),
+        Apply(Vec<Term>),
+    }
+}
+
+use codec::*;
+
+fn roots() -> HirpdagArchiveRoots {
+    let x = Term::new(Op::Name("codec_x".to_string()), None);
+    let one = Term::new(Op::Lit(1), Some(-3));
+    let sum = Term::new(Op::Apply(vec![x.clone(), one, x.clone()]), Some(7));
+    HirpdagArchiveRoots {
+        term: vec![sum.clone(), x, sum],
+    }
+}
+
+fn no_options() -> HirpdagDeserializeOptions {
+    HirpdagDeserializeOptions::default()
+}
+
+#[test]
+fn cbor_round_trip() {
+    let mut bytes = Vec::new();
+    hirpdag_serialize_by(&roots(), |archive| {
+        ciborium::into_writer(archive, &mut bytes)
+    })
+    .unwrap();
+    let out = hirpdag_deserialize_by(|| ciborium::from_reader(&bytes[..]), &no_options()).unwrap();
+    assert_eq!(out, roots());
+    // Sharing is restored.
+    assert_eq!(out.term[0], out.term[2]);
+}
+
+#[test]
+fn bincode_round_trip() {
+    let mut bytes = Vec::new();
+    hirpdag_serialize_with(
+        &roots(),
+        &mut bincode::Serializer::new(&mut bytes, bincode::DefaultOptions::new()),
+    )
+    .unwrap();
+    let mut deserializer =
+        bincode::Deserializer::from_slice(&bytes, bincode::DefaultOptions::new());
+    let out = hirpdag_deserialize_with(&mut deserializer, &no_options()).unwrap();
+    assert_eq!(out, roots());
+}
+
+#[test]
+fn matches_builtin_formats() {
+    let mut json = Vec::new();
+    hirpdag_serialize_with(&roots(), &mut serde_json::Serializer::new(&mut json)).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        hirpdag_serialize_json(&roots()).unwrap()
+    );
+
+    // The binary format is the postcard archive behind a header.
+    let payload = hirpdag_serialize_by(&roots(), hirpdag::postcard::to_stdvec).unwrap();
+    let bytes = hirpdag_serialize(&roots()).unwrap();
+    assert!(bytes.ends_with(&payload));
+}
+
+#[test]
+fn format_errors() {
+    // Errors of the format are reported as format errors, and errors of the
+    // archive as such.
+    let err = hirpdag_deserialize_by(
+        || -> Result<HirpdagArchive, String> { Err("decoder failed".to_string()) },
+        &no_options(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        HirpdagDeserializeError::Format("decoder failed".to_string())
+    );
+
+    let mut deserializer = serde_json::Deserializer::from_str(
+        r#"{"version":1,"nodes":[{"Term":{"op":{"Lit":1}}}],"roots":{"term":[4]}}"#,
+    );
+    let err = hirpdag_deserialize_with(&mut deserializer, &no_options()).unwrap_err();
+    assert_eq!(
+        err,
+        HirpdagDeserializeError::InvalidNodeIndex {
+            index: 4,
+            at_node: None,
+        }
+    );
+}
@@ -0,0 +1,147 @@
+// Tests for delta archives, which write only the nodes missing from a base
+// archive.
+
+use hirpdag::base::{
+    hirpdag_inspect_with_schema, HirpdagDeserializeError, HirpdagDeserializeOptions,
+};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod ir {
+    #[hirpdag(root)]
+    struct Func {
+        pub name: String,
+        pub body: Vec<Stmt>,
+    }
+
+    #[hirpdag]
+    struct Stmt {
+        pub text: String,
+        pub uses: Vec<Stmt>,
+    }
+}
+
+use ir::*;
+
+fn stmt(text: &str, uses: Vec<Stmt>) -> Stmt {
+    Stmt::new(format!("delta_{}", text), uses)
+}
+
+/// The first compiler run: two functions sharing a statement.
+fn run1() -> HirpdagArchiveRoots {
+    let a = stmt("a", vec![]);
+    let b = stmt("b", vec![a.clone()]);
+    let c = stmt("c", vec![a.clone(), b.clone()]);
+    HirpdagArchiveRoots {
+        func: vec![
+            Func::new("f".to_string(), vec![a.clone(), b]),
+            Func::new("g".to_string(), vec![c]),
+        ],
+    }
+}
+
+/// The second run: `f` is unchanged, `g` gained a statement.
+fn run2() -> HirpdagArchiveRoots {
+    let mut roots = run1();
+    let c = roots.func[1].body[0].clone();
+    let d = stmt("d", vec![c.clone()]);
+    roots.func[1] = Func::new("g".to_string(), vec![c, d]);
+    roots
+}
+
+fn no_options() -> HirpdagDeserializeOptions {
+    HirpdagDeserializeOptions::default()
+}
+
+fn node_count(bytes: &[u8]) -> usize {
+    hirpdag_inspect_with_schema(bytes, &hirpdag_schema())
+        .unwrap()
+        .archive
+        .nodes
+        .len()
+}
+
+#[test]
+fn delta_round_trip() {
+    let (base_bytes, base) = hirpdag_serialize_as_base(&run1(), None, &Default::default()).unwrap();
+    assert_eq!(base_bytes, hirpdag_serialize(&run1()).unwrap());
+    assert_eq!(base.id().nodes, 5);
+
+    // Only the new statement and the new `g` are written.
+    let delta = hirpdag_serialize_delta(&run2(), &base).unwrap();
+    assert_eq!(node_count(&delta), 2);
+    assert!(delta.len() < hirpdag_serialize(&run2()).unwrap().len());
+
+    // A reader loads the base archive, then applies the delta.
+    let (roots, read_base) = hirpdag_deserialize_as_base(&base_bytes, None, &no_options()).unwrap();
+    assert_eq!(roots, run1());
+    assert_eq!(read_base.id(), base.id());
+    let roots = hirpdag_deserialize_delta(&delta, &read_base, &no_options()).unwrap();
+    assert_eq!(roots, run2());
+    assert_eq!(roots.func[0], run1().func[0]);
+}
+
+#[test]
+fn delta_chain() {
+    let (_, base) = hirpdag_serialize_as_base(&run1(), None, &Default::default()).unwrap();
+    let (delta1, base1) =
+        hirpdag_serialize_as_base(&run2(), Some(&base), &Default::default()).unwrap();
+    assert_eq!(base1.id().nodes, 7);
+
+    let mut run3 = run2();
+    run3.func
+        .push(Func::new("h".to_string(), vec![stmt("e", vec![])]));
+    let delta2 = hirpdag_serialize_delta(&run3, &base1).unwrap();
+    assert_eq!(node_count(&delta2), 2);
+
+    let base_bytes = hirpdag_serialize(&run1()).unwrap();
+    let (_, read_base) = hirpdag_deserialize_as_base(&base_bytes, None, &no_options()).unwrap();
+    let (roots, read_base1) =
+        hirpdag_deserialize_as_base(&delta1, Some(&read_base), &no_options()).unwrap();
+    assert_eq!(roots, run2());
+    assert_eq!(read_base1.id(), base1.id());
+    let roots = hirpdag_deserialize_delta(&delta2, &read_base1, &no_options()).unwrap();
+    assert_eq!(roots, run3);
+
+    // The second delta needs the first one applied.
+    assert!(matches!(
+        hirpdag_deserialize_delta(&delta2, &read_base, &no_options()),
+        Err(HirpdagDeserializeError::BaseMismatch { .. })
+    ));
+}
+
+#[test]
+fn base_mismatch() {
+    let (base_bytes, base) = hirpdag_serialize_as_base(&run1(), None, &Default::default()).unwrap();
+    let delta = hirpdag_serialize_delta(&run2(), &base).unwrap();
+
+    assert_eq!(
+        hirpdag_deserialize(&delta),
+        Err(HirpdagDeserializeError::BaseMismatch {
+            expected: None,
+            found: Some(*base.id()),
+        })
+    );
+    assert!(matches!(
+        hirpdag_deserialize_from(&delta[..]),
+        Err(HirpdagDeserializeError::BaseMismatch { .. })
+    ));
+    assert_eq!(
+        hirpdag_deserialize_delta(&base_bytes, &base, &no_options()),
+        Err(HirpdagDeserializeError::BaseMismatch {
+            expected: Some(*base.id()),
+            found: None,
+        })
+    );
+
+    let (_, other) = hirpdag_serialize_as_base(&run2(), None, &Default::default()).unwrap();
+    let err = hirpdag_deserialize_delta(&delta, &other, &no_options()).unwrap_err();
+    assert_eq!(
+        err,
+        HirpdagDeserializeError::BaseMismatch {
+            expected: Some(*other.id()),
+            found: Some(*base.id()),
+        }
+    );
+    assert!(err.to_string().contains("written against base"), "{}", err);
+}
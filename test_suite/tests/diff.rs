@@ -0,0 +1,196 @@
+// Tests for structural diffs and patches.
+
+use hirpdag::base::{HirpdagChangeKind, HirpdagPathSegment};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod doc {
+    #[hirpdag]
+    enum Body {
+        Text(String),
+        Items(Vec<Section>),
+    }
+
+    #[hirpdag]
+    struct Section {
+        pub title: String,
+        pub body: Body,
+    }
+
+    #[hirpdag]
+    struct Note {
+        pub section: Section,
+        pub tags: Vec<u32>,
+    }
+}
+
+use doc::*;
+
+fn text(title: &str, text: &str) -> Section {
+    Section::new(format!("diff_{}", title), Body::Text(text.to_string()))
+}
+
+fn items(title: &str, items: &[&Section]) -> Section {
+    Section::new(
+        format!("diff_{}", title),
+        Body::Items(items.iter().map(|&s| s.clone()).collect()),
+    )
+}
+
+fn paths(patch: &HirpdagPatch) -> Vec<String> {
+    patch
+        .changes
+        .iter()
+        .map(|change| change.path.to_string())
+        .collect()
+}
+
+#[test]
+fn equal_versions_have_no_changes() {
+    let a = items("root", &[&text("a", "1"), &text("b", "2")]);
+    let b = items("root", &[&text("a", "1"), &text("b", "2")]);
+    let patch = hirpdag_diff(&a, &b).unwrap();
+    assert!(patch.is_empty());
+    assert_eq!(patch.apply(&a).unwrap(), b);
+}
+
+#[test]
+fn replaces_the_deepest_changed_node() {
+    let (a, b, c) = (text("a", "1"), text("b", "2"), text("c", "3"));
+    let old = items("root", &[&a, &items("mid", &[&b, &c])]);
+    let b2 = text("b", "changed");
+    let new = items("root", &[&a, &items("mid", &[&b2, &c])]);
+
+    let patch = hirpdag_diff(&old, &new).unwrap();
+    assert_eq!(
+        patch.changes[0].kind,
+        HirpdagChangeKind::Replaced {
+            old: HirpdagNodeRef::from(b),
+            new: HirpdagNodeRef::from(b2),
+        }
+    );
+    assert_eq!(paths(&patch), vec!["body.Items[1].body.Items[0]"]);
+    assert_eq!(
+        patch.changes[0].path.0,
+        vec![
+            HirpdagPathSegment::Field("body"),
+            HirpdagPathSegment::Variant("Items"),
+            HirpdagPathSegment::Index(1),
+            HirpdagPathSegment::Field("body"),
+            HirpdagPathSegment::Variant("Items"),
+            HirpdagPathSegment::Index(0),
+        ]
+    );
+    assert_eq!(patch.apply(&old).unwrap(), new);
+}
+
+#[test]
+fn replaces_nodes_whose_own_data_changed() {
+    let a = text("a", "1");
+    let old = items("root", &[&a]);
+    // The title differs, so the root is replaced, though its child is not.
+    let new = items("renamed", &[&a]);
+    let patch = hirpdag_diff(&old, &new).unwrap();
+    assert_eq!(paths(&patch), vec!["(root)"]);
+    assert_eq!(patch.apply(&old).unwrap(), new);
+
+    // As is a node whose enum changed variant.
+    let old = items("root", &[&items("mid", &[&a])]);
+    let new = items("root", &[&text("mid", "flat")]);
+    assert_eq!(
+        paths(&hirpdag_diff(&old, &new).unwrap()),
+        vec!["body.Items[0]"]
+    );
+
+    // And one whose leaf vector changed.
+    let old = Note::new(a.clone(), vec![1, 2]);
+    let new = Note::new(a, vec![1, 3]);
+    assert_eq!(paths(&hirpdag_diff(&old, &new).unwrap()), vec!["(root)"]);
+}
+
+#[test]
+fn adds_and_removes_vector_items() {
+    let (a, b, c, d) = (
+        text("a", "1"),
+        text("b", "2"),
+        text("c", "3"),
+        text("d", "4"),
+    );
+    let old = items("root", &[&a, &b, &c]);
+    let new = items("root", &[&a, &d, &b, &c]);
+    let patch = hirpdag_diff(&old, &new).unwrap();
+    assert_eq!(
+        patch.changes[0].kind,
+        HirpdagChangeKind::Added(HirpdagNodeRef::from(d.clone()))
+    );
+    assert_eq!(paths(&patch), vec!["body.Items[1]"]);
+    assert_eq!(patch.apply(&old).unwrap(), new);
+
+    // Removing is the reverse.
+    let patch = hirpdag_diff(&new, &old).unwrap();
+    assert_eq!(
+        patch.changes[0].kind,
+        HirpdagChangeKind::Removed(HirpdagNodeRef::from(d.clone()))
+    );
+    assert_eq!(patch.apply(&new).unwrap(), old);
+
+    // Unmatched items are paired up by position, and the rest removed.
+    let new = items("root", &[&d, &c]);
+    let patch = hirpdag_diff(&old, &new).unwrap();
+    assert_eq!(paths(&patch), vec!["body.Items[0]", "body.Items[1]"]);
+    assert!(matches!(
+        patch.changes[1].kind,
+        HirpdagChangeKind::Removed(_)
+    ));
+    assert_eq!(patch.apply(&old).unwrap(), new);
+}
+
+#[test]
+fn diffs_vectors_of_roots() {
+    let (a, b) = (text("a", "1"), text("b", "2"));
+    let old = vec![a.clone(), b.clone()];
+    let new = vec![a.clone(), text("b", "changed"), text("c", "3")];
+    let patch = hirpdag_diff(&old, &new).unwrap();
+    assert_eq!(paths(&patch), vec!["[1]", "[2]"]);
+    assert_eq!(
+        patch.changes[0].kind,
+        HirpdagChangeKind::Replaced {
+            old: HirpdagNodeRef::from(b),
+            new: HirpdagNodeRef::from(new[1].clone()),
+        }
+    );
+    assert_eq!(patch.apply(&old).unwrap(), new);
+
+    // A node may be replaced by one of another type.
+    let old = vec![HirpdagNodeRef::from(a.clone())];
+    let new = vec![HirpdagNodeRef::from(Note::new(a, vec![]))];
+    let patch = hirpdag_diff(&old, &new).unwrap();
+    assert_eq!(paths(&patch), vec!["[0]"]);
+    assert_eq!(patch.apply(&old).unwrap(), new);
+}
+
+#[test]
+fn rejects_other_bases() {
+    let old = items("root", &[&text("a", "1")]);
+    let new = items("root", &[&text("a", "2")]);
+    let patch = hirpdag_diff(&old, &new).unwrap();
+    let other = items("root", &[&text("a", "3")]);
+    let error = patch.apply(&other).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "hirpdag: patch does not apply at body.Items[0]: the replaced node differs"
+    );
+    let error = patch.apply(&text("root", "1")).unwrap_err();
+    assert_eq!(error.message, "no such path");
+}
+
+#[test]
+fn leaf_differences_have_no_patch() {
+    let (a, b) = (Body::Text("a".to_string()), Body::Text("b".to_string()));
+    assert_eq!(hirpdag_diff(&a, &b), None);
+    assert!(hirpdag_diff(&a, &a.clone()).unwrap().is_empty());
+    assert_eq!(hirpdag_diff(&vec![1u32], &vec![2u32]), None);
+    // Below a node, the difference replaces the node.
+    let (old, new) = (text("a", "a"), text("a", "b"));
+    assert_eq!(paths(&hirpdag_diff(&old, &new).unwrap()), vec!["(root)"]);
+}
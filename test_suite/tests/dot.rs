@@ -0,0 +1,186 @@
+// Tests for Graphviz DOT export.
+
+use hirpdag::base::HirpdagComputeMeta;
+use hirpdag::*;
+
+#[hirpdag_module]
+mod ast {
+    #[hirpdag]
+    enum Op {
+        Add(Vec<Term>),
+        Neg(Term),
+        Bind(Binding),
+    }
+
+    #[hirpdag(root)]
+    struct Term {
+        pub name: String,
+        pub op: Option<Op>,
+    }
+
+    #[hirpdag(root)]
+    struct Binding {
+        pub var: String,
+        pub value: Term,
+        pub weight: u32,
+    }
+}
+
+use ast::*;
+
+fn var(name: &str) -> Term {
+    Term::new(format!("dot_{}", name), None)
+}
+
+/// `x + -x`, sharing `x`.
+fn sum() -> Term {
+    let x = var("x");
+    let neg = Term::new("dot_neg".to_string(), Some(Op::Neg(x.clone())));
+    Term::new("dot_sum".to_string(), Some(Op::Add(vec![x, neg])))
+}
+
+fn roots() -> HirpdagArchiveRoots {
+    HirpdagArchiveRoots {
+        term: vec![sum()],
+        binding: vec![],
+    }
+}
+
+#[test]
+fn draws_each_node_once() {
+    assert_eq!(
+        hirpdag_to_dot(&roots()),
+        r#"digraph hirpdag {
+    node [shape=box];
+    r0 [shape=plaintext, label="term[0]"];
+    r0 -> n0;
+    n0 [label="Term\lname = \"dot_sum\"\l"];
+    n0 -> n1 [label="op.Add[0]"];
+    n0 -> n2 [label="op.Add[1]"];
+    n1 [label="Term\lname = \"dot_x\"\lop = None\l"];
+    n2 [label="Term\lname = \"dot_neg\"\l"];
+    n2 -> n1 [label="op.Neg"];
+}
+"#
+    );
+}
+
+#[test]
+fn adds_meta() {
+    let options = HirpdagDotOptions {
+        meta: true,
+        ..Default::default()
+    };
+    let dot = hirpdag_to_dot_with_options(&roots(), &options);
+    let meta = sum().hirpdag_compute_meta();
+    assert!(dot.contains(&format!(
+        "count={} height={} flags=0x0000\\l\"];",
+        meta.get_count(),
+        meta.get_height()
+    )));
+}
+
+#[test]
+fn limits_depth() {
+    let options = HirpdagDotOptions {
+        max_depth: Some(1),
+        ..Default::default()
+    };
+    let dot = hirpdag_to_dot_with_options(&roots(), &options);
+    // `neg` is drawn, but not its edge to `x`.
+    assert!(dot.contains("n2 [label=\"Term\\lname = \\\"dot_neg\\\"\\l\", style=dashed];"));
+    assert!(!dot.contains("n2 ->"));
+    assert!(!dot.contains("n1 [label=\"Term\\lname = \\\"dot_x\\\"\\lop = None\\l\", style"));
+}
+
+#[test]
+fn filters_types() {
+    let binding = Binding::new("dot_y".to_string(), sum(), 3);
+    let roots = HirpdagArchiveRoots {
+        term: vec![],
+        binding: vec![binding],
+    };
+    let options = HirpdagDotOptions {
+        types: Some(vec!["Binding"]),
+        ..Default::default()
+    };
+    assert_eq!(
+        hirpdag_to_dot_with_options(&roots, &options),
+        r#"digraph hirpdag {
+    node [shape=box];
+    r0 [shape=plaintext, label="binding[0]"];
+    r0 -> n0;
+    n0 [label="Binding\lvar = \"dot_y\"\lweight = 3\l"];
+}
+"#
+    );
+
+    // A hidden root passes its references on to the drawn nodes.
+    let options = HirpdagDotOptions {
+        types: Some(vec!["Term"]),
+        ..Default::default()
+    };
+    let mut list = HirpdagRootList::new();
+    list.push_named("y", roots.binding[0].clone());
+    let dot = hirpdag_list_to_dot_with_options(&list, &options);
+    assert!(dot.contains("r0 [shape=plaintext, label=\"y\"];"));
+    assert!(dot.contains("r0 -> n0 [label=\"value\"];"));
+    assert!(dot.contains("n0 [label=\"Term\\lname = \\\"dot_sum\\\"\\l\"];"));
+}
+
+#[test]
+fn filters_types_through_shared_nodes() {
+    // t(n+1) = t(n) + t(n) over a binding: 2^n paths through hidden terms.
+    let inner = Binding::new("dot_inner".to_string(), var("x"), 1);
+    let mut term = Term::new("dot_t".to_string(), Some(Op::Bind(inner)));
+    for _ in 0..40 {
+        term = Term::new("dot_t".to_string(), Some(Op::Add(vec![term.clone(), term])));
+    }
+    let roots = HirpdagArchiveRoots {
+        term: vec![],
+        binding: vec![Binding::new("dot_outer".to_string(), term, 2)],
+    };
+    let options = HirpdagDotOptions {
+        types: Some(vec!["Binding"]),
+        ..Default::default()
+    };
+    let path = format!("value{}.op.Bind", ".op.Add[0]".repeat(40));
+    assert_eq!(
+        hirpdag_to_dot_with_options(&roots, &options),
+        format!(
+            r#"digraph hirpdag {{
+    node [shape=box];
+    r0 [shape=plaintext, label="binding[0]"];
+    r0 -> n0;
+    n0 [label="Binding\lvar = \"dot_outer\"\lweight = 2\l"];
+    n0 -> n1 [label="{} (1099511627776 paths)"];
+    n1 [label="Binding\lvar = \"dot_inner\"\lweight = 1\l"];
+}}
+"#,
+            path
+        )
+    );
+}
+
+#[test]
+fn highlights_nodes() {
+    let x = HirpdagNodeRef::from(var("x"));
+    let options = HirpdagDotOptions {
+        highlight: vec![x].into_iter().collect(),
+        ..Default::default()
+    };
+    let dot = hirpdag_to_dot_with_options(&roots(), &options);
+    assert!(dot.contains("lop = None\\l\", style=filled, fillcolor=yellow];"));
+    assert_eq!(dot.matches("fillcolor").count(), 1);
+}
+
+#[test]
+fn labels_root_list_entries() {
+    let mut list = HirpdagRootList::new();
+    list.push(var("a"));
+    list.push_named("quoted \"b\"", var("b"));
+    let dot = hirpdag_list_to_dot(&list);
+    assert!(dot.contains("r0 [shape=plaintext, label=\"#0\"];"));
+    assert!(dot.contains("r1 [shape=plaintext, label=\"quoted \\\"b\\\"\"];"));
+    assert!(dot.contains("r1 -> n1;"));
+}
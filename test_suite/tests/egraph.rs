@@ -0,0 +1,260 @@
+use hirpdag::base::{HirpdagComputeMeta, HirpdagEGraphStop};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod datamodel {
+    #[hirpdag]
+    struct Expr {
+        // pub so rules defined outside the module can read this field.
+        pub x: ExprKind,
+    }
+
+    #[hirpdag]
+    enum ExprKind {
+        Num(u32),
+        Var(String),
+        Mul(Vec<Expr>),
+        Shl(Vec<Expr>),
+        Div(Vec<Expr>),
+    }
+}
+
+use datamodel::*;
+
+fn num(n: u32) -> Expr {
+    Expr::new(ExprKind::Num(n))
+}
+
+fn var(name: &str) -> Expr {
+    Expr::new(ExprKind::Var(name.to_string()))
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    Expr::new(ExprKind::Mul(vec![a, b]))
+}
+
+fn shl(a: Expr, b: Expr) -> Expr {
+    Expr::new(ExprKind::Shl(vec![a, b]))
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    Expr::new(ExprKind::Div(vec![a, b]))
+}
+
+fn binary(x: &Expr) -> Option<(&ExprKind, &Expr, &Expr)> {
+    match &x.x {
+        ExprKind::Mul(v) | ExprKind::Shl(v) | ExprKind::Div(v) if v.len() == 2 => {
+            Some((&x.x, &v[0], &v[1]))
+        }
+        _ => None,
+    }
+}
+
+// Rules only rewrite the node they are given; the e-graph applies them to
+// every e-node. Returning the node unchanged means the rule does not apply.
+
+// a * b => b * a
+struct MulCommute;
+
+impl HirpdagRewriter for MulCommute {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        match binary(x) {
+            Some((ExprKind::Mul(_), a, b)) => mul(b.clone(), a.clone()),
+            _ => x.clone(),
+        }
+    }
+}
+
+// a * 1 => a
+struct MulOne;
+
+impl HirpdagRewriter for MulOne {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        match binary(x) {
+            Some((ExprKind::Mul(_), a, b)) if *b == num(1) => a.clone(),
+            _ => x.clone(),
+        }
+    }
+}
+
+// a * 2 => a << 1
+struct MulTwoShl;
+
+impl HirpdagRewriter for MulTwoShl {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        match binary(x) {
+            Some((ExprKind::Mul(_), a, b)) if *b == num(2) => shl(a.clone(), num(1)),
+            _ => x.clone(),
+        }
+    }
+}
+
+// (a * b) / c => a * (b / c)
+struct MulDivAssoc;
+
+impl HirpdagRewriter for MulDivAssoc {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        if let Some((ExprKind::Div(_), ab, c)) = binary(x) {
+            if let Some((ExprKind::Mul(_), a, b)) = binary(ab) {
+                return mul(a.clone(), div(b.clone(), c.clone()));
+            }
+        }
+        x.clone()
+    }
+}
+
+// a / a => 1
+struct DivSelf;
+
+impl HirpdagRewriter for DivSelf {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        match binary(x) {
+            Some((ExprKind::Div(_), a, b)) if a == b => num(1),
+            _ => x.clone(),
+        }
+    }
+}
+
+#[test]
+fn egraph_add_lookup() {
+    let mut egraph = HirpdagEGraph::new();
+    let a_times_2 = mul(var("a"), num(2));
+    let id = egraph.add_Expr(&a_times_2);
+    // a, 2, a * 2
+    assert_eq!(egraph.number_of_classes(), 3);
+    assert_eq!(egraph.number_of_nodes(), 3);
+    assert_eq!(egraph.add_Expr(&a_times_2), id);
+    assert_eq!(egraph.lookup_Expr(&a_times_2), Some(id));
+    assert!(egraph.lookup_Expr(&var("a")).is_some());
+    assert_eq!(egraph.lookup_Expr(&var("b")), None);
+    assert_eq!(egraph.class_members_Expr(id), &[a_times_2]);
+}
+
+#[test]
+fn egraph_union_congruence() {
+    let mut egraph = HirpdagEGraph::new();
+    let fa = mul(var("a"), num(3));
+    let fb = mul(var("b"), num(3));
+    let id_fa = egraph.add_Expr(&fa);
+    let id_fb = egraph.add_Expr(&fb);
+    assert_ne!(egraph.find(id_fa), egraph.find(id_fb));
+
+    // a == b implies a * 3 == b * 3 once congruence is restored.
+    let id_a = egraph.lookup_Expr(&var("a")).unwrap();
+    let id_b = egraph.lookup_Expr(&var("b")).unwrap();
+    assert!(egraph.union_Expr(id_a, id_b));
+    assert!(!egraph.union_Expr(id_b, id_a));
+    egraph.rebuild();
+    assert_eq!(egraph.find(id_fa), egraph.find(id_fb));
+}
+
+#[test]
+fn egraph_saturate_extract() {
+    // (a * 2) / 2 => a * (2 / 2) => a * 1 => a
+    let start = div(mul(var("a"), num(2)), num(2));
+
+    let mut egraph = HirpdagEGraph::new();
+    let id = egraph.add_Expr(&start);
+    let rules: [&dyn HirpdagEGraphRule; 5] =
+        [&MulCommute, &MulOne, &MulTwoShl, &MulDivAssoc, &DivSelf];
+    // a == 2 * (a / 2) == 2 * ((a / 2) / 2) == ..., so this never
+    // saturates.
+    let stop = egraph.run(&rules, 4);
+    assert_eq!(stop, HirpdagEGraphStop::IterationLimit);
+
+    // The original term is still represented.
+    assert_eq!(egraph.lookup_Expr(&start), Some(egraph.find(id)));
+    assert_eq!(
+        egraph.lookup_Expr(&shl(var("a"), num(1))),
+        egraph.lookup_Expr(&mul(var("a"), num(2)))
+    );
+
+    let best = egraph.extract_Expr(id, &HirpdagEGraphMetaCount).unwrap();
+    assert_eq!(best, var("a"));
+}
+
+#[test]
+fn egraph_iteration_limit() {
+    let start = div(mul(var("a"), num(2)), num(2));
+    let mut egraph = HirpdagEGraph::new();
+    egraph.add_Expr(&start);
+    let rules: [&dyn HirpdagEGraphRule; 1] = [&MulCommute];
+    assert_eq!(egraph.run(&rules, 0), HirpdagEGraphStop::IterationLimit);
+    assert_eq!(egraph.run(&rules, 8), HirpdagEGraphStop::Saturated);
+}
+
+// Prefer shifts over multiplications.
+struct ShiftCost;
+
+impl HirpdagEGraphCost for ShiftCost {
+    fn cost_Expr(&self, x: &Expr) -> u64 {
+        let own = match &x.x {
+            ExprKind::Mul(_) | ExprKind::Div(_) => 10,
+            _ => 1,
+        };
+        match &x.x {
+            ExprKind::Mul(v) | ExprKind::Shl(v) | ExprKind::Div(v) => {
+                own + v.iter().map(|e| self.cost_Expr(e)).sum::<u64>()
+            }
+            _ => own,
+        }
+    }
+}
+
+#[test]
+fn egraph_custom_cost() {
+    let start = mul(num(2), var("b"));
+    let mut egraph = HirpdagEGraph::new();
+    let id = egraph.add_Expr(&start);
+    let rules: [&dyn HirpdagEGraphRule; 2] = [&MulCommute, &MulTwoShl];
+    egraph.run(&rules, 16);
+    let best = egraph.extract_Expr(id, &ShiftCost).unwrap();
+    assert_eq!(best, shl(var("b"), num(1)));
+    let smallest = egraph.extract_Expr(id, &HirpdagEGraphMetaCount).unwrap();
+    assert_eq!(smallest.hirpdag_compute_meta().get_count(), 3);
+}
+
+#[test]
+fn egraph_rules_match_non_representative_children() {
+    // c == a * 2, so c / 2 == a; the class of c is represented by c.
+    let c = var("c");
+    let a_times_2 = mul(var("a"), num(2));
+    for c_first in [true, false] {
+        let mut egraph = HirpdagEGraph::new();
+        let id = egraph.add_Expr(&div(c.clone(), num(2)));
+        let id_c = egraph.lookup_Expr(&c).unwrap();
+        let id_mul = egraph.add_Expr(&a_times_2);
+        if c_first {
+            egraph.union_Expr(id_c, id_mul);
+        } else {
+            egraph.union_Expr(id_mul, id_c);
+        }
+        egraph.rebuild();
+        assert_eq!(egraph.class_members_Expr(id_c)[0], c);
+
+        // MulDivAssoc matches the Mul below the Div, which is not the
+        // representative of its class.
+        let rules: [&dyn HirpdagEGraphRule; 3] = [&MulDivAssoc, &DivSelf, &MulOne];
+        assert_eq!(egraph.run(&rules, 16), HirpdagEGraphStop::Saturated);
+        assert_eq!(egraph.lookup_Expr(&var("a")), Some(egraph.find(id)));
+    }
+}
+
+#[test]
+fn egraph_extractor_reuses_costs() {
+    let start = div(mul(var("a"), num(2)), num(2));
+    let mut egraph = HirpdagEGraph::new();
+    let id = egraph.add_Expr(&start);
+    let id_mul = egraph.lookup_Expr(&mul(var("a"), num(2))).unwrap();
+    let rules: [&dyn HirpdagEGraphRule; 5] =
+        [&MulCommute, &MulOne, &MulTwoShl, &MulDivAssoc, &DivSelf];
+    egraph.run(&rules, 16);
+
+    let extractor = egraph.extractor(&ShiftCost);
+    assert_eq!(extractor.extract_Expr(id), Some(var("a")));
+    assert_eq!(extractor.extract_Expr(id_mul), Some(shl(var("a"), num(1))));
+    assert_eq!(
+        egraph.extract_Expr(id_mul, &ShiftCost),
+        Some(shl(var("a"), num(1)))
+    );
+}
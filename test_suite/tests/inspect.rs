@@ -0,0 +1,124 @@
+// Tests for decoding archives without their Rust types.
+
+use hirpdag::base::{
+    hirpdag_inspect, hirpdag_inspect_from, hirpdag_inspect_json, hirpdag_inspect_with_schema,
+    hirpdag_parse_binary_header, HirpdagDeserializeError, HirpdagDynValue, HirpdagSerializeOptions,
+    HIRPDAG_HEADER_VERSION,
+};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod shapes {
+    #[hirpdag(root)]
+    struct Scene {
+        name: String,
+        shapes: Vec<Shape>,
+    }
+
+    #[hirpdag]
+    struct Shape {
+        geometry: Geometry,
+        scale: Option<i32>,
+    }
+
+    #[hirpdag]
+    enum Geometry {
+        Circle(u32),
+        Group(Vec<Shape>),
+    }
+}
+
+use shapes::*;
+
+fn scene() -> Scene {
+    let circle = Shape::new(Geometry::Circle(7), None);
+    let group = Shape::new(
+        Geometry::Group(vec![circle.clone(), circle.clone()]),
+        Some(-2),
+    );
+    Scene::new("inspect_scene".to_string(), vec![group, circle])
+}
+
+fn roots() -> HirpdagArchiveRoots {
+    HirpdagArchiveRoots {
+        scene: vec![scene()],
+    }
+}
+
+#[test]
+fn inspect_embedded_schema() {
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        ..Default::default()
+    };
+    let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+    let inspection = hirpdag_inspect(&bytes).unwrap();
+    assert_eq!(inspection.header_version, HIRPDAG_HEADER_VERSION);
+    assert_eq!(inspection.header.fingerprint, hirpdag_schema_fingerprint());
+    assert_eq!(inspection.schema, hirpdag_schema());
+
+    let archive = &inspection.archive;
+    let types: Vec<&str> = archive.nodes.iter().map(|n| n.type_name.as_str()).collect();
+    assert_eq!(types, vec!["Shape", "Shape", "Scene"]);
+    assert_eq!(archive.roots, vec![("Scene".to_string(), vec![2])]);
+    assert_eq!(archive.nodes[1].children(), vec![0, 0]);
+    assert_eq!(archive.nodes[2].children(), vec![1, 0]);
+    assert_eq!(
+        archive.nodes[2].field("name"),
+        Some(&HirpdagDynValue::String("inspect_scene".to_string()))
+    );
+    assert_eq!(
+        archive.nodes[1].to_string(),
+        "Shape { geometry: Geometry::Group([#0, #0]), scale: Some(-2) }"
+    );
+    assert_eq!(archive.validate(&inspection.schema), vec![]);
+    assert_eq!(archive.reference_counts(), vec![3, 1, 1]);
+
+    // Re-encoding by the schema reproduces the payload.
+    let (_, payload) = hirpdag_parse_binary_header(&bytes).unwrap();
+    assert_eq!(archive.to_postcard(&inspection.schema).unwrap(), payload);
+
+    let streamed = hirpdag_inspect_from(&bytes[..], None).unwrap();
+    assert_eq!(streamed, inspection);
+}
+
+#[test]
+fn inspect_with_schema() {
+    let bytes = hirpdag_serialize(&roots()).unwrap();
+    assert!(matches!(
+        hirpdag_inspect(&bytes),
+        Err(HirpdagDeserializeError::Format(msg)) if msg.contains("embed")
+    ));
+    let inspection = hirpdag_inspect_with_schema(&bytes, &hirpdag_schema()).unwrap();
+    assert_eq!(inspection.header.schema, None);
+    assert_eq!(inspection.archive.nodes.len(), 3);
+    assert_eq!(
+        hirpdag_inspect_from(&bytes[..], Some(&hirpdag_schema())).unwrap(),
+        inspection
+    );
+
+    let json = hirpdag_serialize_json(&roots()).unwrap();
+    let archive = hirpdag_inspect_json(&json, &hirpdag_schema()).unwrap();
+    assert_eq!(archive, inspection.archive);
+    assert_eq!(
+        hirpdag_deserialize_json(&archive.to_json()).unwrap(),
+        roots()
+    );
+}
+
+#[test]
+fn inspect_errors() {
+    assert_eq!(
+        hirpdag_inspect(b"not an archive"),
+        Err(HirpdagDeserializeError::BadMagic)
+    );
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        ..Default::default()
+    };
+    let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+    assert!(matches!(
+        hirpdag_inspect(&bytes[..bytes.len() - 1]),
+        Err(HirpdagDeserializeError::Truncated { .. })
+    ));
+}
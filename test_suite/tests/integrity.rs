@@ -0,0 +1,144 @@
+// Tests for checksummed and compressed binary archives.
+
+use hirpdag::base::{
+    hirpdag_inspect, hirpdag_parse_binary_header, HirpdagDeserializeError, HirpdagSerializeOptions,
+    HIRPDAG_FLAG_CRC32C, HIRPDAG_FLAG_LZ4,
+};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod log {
+    #[hirpdag(root)]
+    struct Entry {
+        pub message: String,
+        pub level: u8,
+        pub causes: Vec<Entry>,
+    }
+}
+
+use log::*;
+
+fn roots() -> HirpdagArchiveRoots {
+    let mut entries = Vec::new();
+    for i in 0..40u8 {
+        let causes = entries.iter().rev().take(2).cloned().collect();
+        entries.push(Entry::new(
+            format!("integrity: step {} of a long and repetitive log", i),
+            i % 4,
+            causes,
+        ));
+    }
+    HirpdagArchiveRoots {
+        entry: vec![entries.pop().unwrap()],
+    }
+}
+
+fn options(checksum: bool, compress: bool) -> HirpdagSerializeOptions {
+    HirpdagSerializeOptions {
+        checksum,
+        compress,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn round_trips() {
+    let plain = hirpdag_serialize(&roots()).unwrap();
+    for (checksum, compress) in [(false, false), (true, false), (false, true), (true, true)] {
+        let options = options(checksum, compress);
+        let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+        let (header, _) = hirpdag_parse_binary_header(&bytes).unwrap();
+        assert_eq!(header.flags & HIRPDAG_FLAG_CRC32C != 0, checksum);
+        assert_eq!(header.flags & HIRPDAG_FLAG_LZ4 != 0, compress);
+        assert_eq!(hirpdag_deserialize(&bytes).unwrap(), roots());
+        if compress {
+            assert!(
+                bytes.len() < plain.len() / 2,
+                "{} {}",
+                bytes.len(),
+                plain.len()
+            );
+        }
+
+        // Streaming reads exactly one archive.
+        let mut stream = Vec::new();
+        hirpdag_serialize_to_with_options(&roots(), &mut stream, &options).unwrap();
+        assert_eq!(stream, bytes);
+        stream.extend_from_slice(&plain);
+        let mut reader = &stream[..];
+        assert_eq!(hirpdag_deserialize_from(&mut reader).unwrap(), roots());
+        assert_eq!(hirpdag_deserialize_from(&mut reader).unwrap(), roots());
+        assert!(reader.is_empty());
+
+        let inspection = hirpdag_inspect(
+            &hirpdag_serialize_with_options(
+                &roots(),
+                &HirpdagSerializeOptions {
+                    embed_schema: true,
+                    ..options
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(inspection.archive.nodes.len(), 40);
+    }
+}
+
+#[test]
+fn detects_corruption() {
+    let mut bytes = hirpdag_serialize_with_options(&roots(), &options(true, false)).unwrap();
+    // Change one character of a message.
+    let at = bytes.len() / 2;
+    bytes[at] ^= 0x01;
+    assert!(matches!(
+        hirpdag_deserialize(&bytes),
+        Err(HirpdagDeserializeError::ChecksumMismatch { .. })
+    ));
+    assert!(matches!(
+        hirpdag_deserialize_from(&bytes[..]),
+        Err(HirpdagDeserializeError::ChecksumMismatch { .. })
+    ));
+
+    let compressed = hirpdag_serialize_with_options(&roots(), &options(true, true)).unwrap();
+    let mut corrupt = compressed.clone();
+    let at = corrupt.len() - 10;
+    corrupt[at] ^= 0x40;
+    assert!(matches!(
+        hirpdag_deserialize(&corrupt),
+        Err(HirpdagDeserializeError::ChecksumMismatch { .. })
+    ));
+    assert!(matches!(
+        hirpdag_deserialize(&compressed[..compressed.len() - 1]),
+        Err(HirpdagDeserializeError::Truncated { at_node: None })
+    ));
+}
+
+#[test]
+fn unknown_flags() {
+    let (mut header, _) =
+        hirpdag_parse_binary_header(&hirpdag_serialize(&roots()).unwrap()).unwrap();
+    header.flags = 1 << 9;
+    let bytes = hirpdag::base::hirpdag_write_binary_header(&header).unwrap();
+    let err = hirpdag_deserialize(&bytes).unwrap_err();
+    assert!(err.to_string().contains("flags 0x200"), "{}", err);
+}
+
+#[test]
+fn compressed_deltas() {
+    let options = options(true, true);
+    let (bytes, base) = hirpdag_serialize_as_base(&roots(), None, &options).unwrap();
+    let more = HirpdagArchiveRoots {
+        entry: vec![Entry::new(
+            "integrity: done".to_string(),
+            0,
+            roots().entry.clone(),
+        )],
+    };
+    let (delta, _) = hirpdag_serialize_as_base(&more, Some(&base), &options).unwrap();
+    let (_, read_base) = hirpdag_deserialize_as_base(&bytes, None, &Default::default()).unwrap();
+    assert_eq!(
+        hirpdag_deserialize_delta(&delta, &read_base, &Default::default()).unwrap(),
+        more
+    );
+}
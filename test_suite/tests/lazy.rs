@@ -0,0 +1,141 @@
+// Tests for lazily decoded archives.
+
+use hirpdag::base::{HirpdagDeserializeError, HirpdagSerializeOptions};
+use hirpdag::*;
+use std::convert::TryFrom;
+
+#[hirpdag_module]
+mod docs {
+    #[hirpdag(root)]
+    struct Doc {
+        pub title: String,
+        pub sections: Vec<Section>,
+    }
+
+    #[hirpdag]
+    struct Section {
+        pub text: String,
+        pub cites: Vec<Section>,
+    }
+}
+
+use docs::*;
+
+fn section(text: &str, cites: Vec<Section>) -> Section {
+    Section::new(format!("lazy_{}", text), cites)
+}
+
+/// Two documents sharing a section.
+fn roots() -> HirpdagArchiveRoots {
+    let shared = section("shared", vec![]);
+    let a = Doc::new("a".to_string(), vec![section("a1", vec![]), shared.clone()]);
+    let b = Doc::new("b".to_string(), vec![section("b1", vec![shared])]);
+    HirpdagArchiveRoots { doc: vec![a, b] }
+}
+
+fn indexed() -> HirpdagSerializeOptions {
+    HirpdagSerializeOptions {
+        index: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn materializes_nodes_on_access() {
+    let bytes = hirpdag_serialize_with_options(&roots(), &indexed()).unwrap();
+    let archive = HirpdagLazyArchive::open(&bytes[..]).unwrap();
+    // a1, shared, a, b1, b
+    assert_eq!(archive.len(), 5);
+    assert_eq!(archive.materialized(), 0);
+    assert_eq!(archive.root_nodes(), &[vec![2, 4]]);
+    assert_eq!(archive.children(4), Some(&[3][..]));
+
+    let b = Doc::try_from(archive.get(4).unwrap()).unwrap();
+    assert_eq!(b, roots().doc[1]);
+    assert_eq!(archive.materialized(), 3);
+    assert!(!archive.is_materialized(0));
+    assert!(archive.is_materialized(1));
+
+    // Materialized nodes are reused.
+    let shared = Section::try_from(archive.get(1).unwrap()).unwrap();
+    assert_eq!(shared, b.sections[0].cites[0]);
+    assert_eq!(archive.materialized(), 3);
+    archive.get(2).unwrap();
+    assert_eq!(archive.materialized(), 5);
+
+    assert_eq!(
+        archive.get(5).unwrap_err(),
+        HirpdagDeserializeError::InvalidNodeIndex {
+            index: 5,
+            at_node: None,
+        }
+    );
+}
+
+#[test]
+fn reads_selected_roots() {
+    let options = HirpdagSerializeOptions {
+        checksum: true,
+        compress: true,
+        ..indexed()
+    };
+    let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+    let archive = HirpdagLazyArchive::open(bytes).unwrap();
+    let selection = HirpdagRootSelection { doc: vec![1] };
+    let out = archive.roots(&selection).unwrap();
+    assert_eq!(out.doc, vec![roots().doc[1].clone()]);
+    assert_eq!(archive.materialized(), 3);
+    assert_eq!(
+        archive.roots(&HirpdagRootSelection { doc: vec![2] }),
+        Err(HirpdagDeserializeError::MissingRoot {
+            root: "doc",
+            position: 2,
+        })
+    );
+}
+
+#[test]
+fn needs_an_index() {
+    let bytes = hirpdag_serialize(&roots()).unwrap();
+    assert!(matches!(
+        HirpdagLazyArchive::open(&bytes[..]),
+        Err(HirpdagDeserializeError::MissingIndex)
+    ));
+}
+
+#[test]
+fn rejects_other_schemas() {
+    #[hirpdag_module]
+    mod other {
+        #[hirpdag(root)]
+        struct Note {
+            pub text: String,
+        }
+    }
+
+    let roots = other::HirpdagArchiveRoots {
+        note: vec![other::Note::new("lazy_note".to_string())],
+    };
+    let bytes = other::hirpdag_serialize_with_options(&roots, &indexed()).unwrap();
+    assert!(matches!(
+        HirpdagLazyArchive::open(&bytes[..]),
+        Err(HirpdagDeserializeError::SchemaMismatch { .. })
+    ));
+}
+
+#[test]
+fn reads_root_lists() {
+    let mut list = HirpdagRootList::new();
+    list.push_named("b", roots().doc[1].clone());
+    let bytes = hirpdag_serialize_list_with_options(&list, &indexed()).unwrap();
+    let archive = HirpdagLazyArchive::open(&bytes[..]).unwrap();
+    assert_eq!(archive.root_nodes(), &[vec![2]]);
+    assert_eq!(
+        archive.get(2).unwrap(),
+        HirpdagNodeRef::from(roots().doc[1].clone())
+    );
+    assert!(matches!(
+        archive.roots(&HirpdagRootSelection { doc: vec![0] }),
+        Err(HirpdagDeserializeError::RootLayoutMismatch { .. })
+    ));
+}
@@ -0,0 +1,216 @@
+// Tests for schema descriptions embedded in binary archives, and migrating
+// archives written by older hirpdag type definitions.
+
+use hirpdag::base::{
+    HirpdagDeserializeError, HirpdagDeserializeOptions, HirpdagDynValue, HirpdagMigrations,
+    HirpdagSchemaKind, HirpdagSchemaTy, HirpdagSerializeOptions,
+};
+use hirpdag::*;
+
+/// The types as an older version of the program defined them.
+#[hirpdag_module]
+mod v1 {
+    #[hirpdag(root)]
+    struct Doc {
+        title: String,
+        body: Vec<Para>,
+    }
+
+    #[hirpdag]
+    struct Para {
+        kind: Kind,
+    }
+
+    #[hirpdag]
+    enum Kind {
+        Text(String),
+        Code(String),
+    }
+}
+
+/// The current types: `Doc` gained a field, `Para` an optional field, and
+/// `Kind::Text` was renamed.
+#[hirpdag_module]
+mod v2 {
+    #[hirpdag(root)]
+    struct Doc {
+        pub title: String,
+        pub body: Vec<Para>,
+        pub revision: u32,
+    }
+
+    #[hirpdag]
+    struct Para {
+        pub kind: Kind,
+        pub indent: Option<u8>,
+    }
+
+    #[hirpdag]
+    enum Kind {
+        Prose(String),
+        Code(String),
+    }
+}
+
+fn v1_archive(options: &HirpdagSerializeOptions) -> Vec<u8> {
+    let text = v1::Para::new(v1::Kind::Text("migrate_hello".to_string()));
+    let code = v1::Para::new(v1::Kind::Code("migrate_x = 1".to_string()));
+    let doc = v1::Doc::new("migrate_doc".to_string(), vec![text.clone(), code, text]);
+    let roots = v1::HirpdagArchiveRoots { doc: vec![doc] };
+    v1::hirpdag_serialize_with_options(&roots, options).unwrap()
+}
+
+fn v2_doc() -> v2::Doc {
+    let text = v2::Para::new(v2::Kind::Prose("migrate_hello".to_string()), None);
+    let code = v2::Para::new(v2::Kind::Code("migrate_x = 1".to_string()), None);
+    v2::Doc::new("migrate_doc".to_string(), vec![text.clone(), code, text], 1)
+}
+
+fn v1_to_v2() -> HirpdagMigrations {
+    let mut migrations = HirpdagMigrations::new();
+    migrations.register(
+        v1::hirpdag_schema_fingerprint().hash,
+        v2::hirpdag_schema_fingerprint().hash,
+        |archive| {
+            archive.add_field("Doc", "revision", HirpdagDynValue::Unsigned(1));
+            archive.rename_variant("Kind", "Text", "Prose");
+            Ok(())
+        },
+    );
+    migrations
+}
+
+#[test]
+fn schema_description() {
+    let schema = v2::hirpdag_schema();
+    let names: Vec<&str> = schema.types.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["Doc", "Para", "Kind"]);
+    assert!(schema.types[0].root);
+    match &schema.get("Para").unwrap().kind {
+        HirpdagSchemaKind::Struct(fields) => {
+            assert_eq!(fields[0].ty, HirpdagSchemaTy::Enum("Kind".to_string()));
+            assert_eq!(
+                fields[1].ty,
+                HirpdagSchemaTy::Option(Box::new(HirpdagSchemaTy::U8))
+            );
+        }
+        other => panic!("expected a struct, got {:?}", other),
+    }
+    match &schema.get("Doc").unwrap().kind {
+        HirpdagSchemaKind::Struct(fields) => assert_eq!(
+            fields[1].ty,
+            HirpdagSchemaTy::Vec(Box::new(HirpdagSchemaTy::Ref("Para".to_string())))
+        ),
+        other => panic!("expected a struct, got {:?}", other),
+    }
+}
+
+#[test]
+fn embedded_schema_round_trip() {
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        ..Default::default()
+    };
+    let bytes = v1_archive(&options);
+    let (header, _) = hirpdag::base::hirpdag_parse_binary_header(&bytes).unwrap();
+    assert_eq!(header.fingerprint, v1::hirpdag_schema_fingerprint());
+    assert_eq!(header.schema, Some(v1::hirpdag_schema()));
+    // The same types read it as usual.
+    assert_eq!(v1::hirpdag_deserialize(&bytes).unwrap().doc.len(), 1);
+}
+
+#[test]
+fn migrate_old_archive() {
+    let bytes = v1_archive(&HirpdagSerializeOptions {
+        embed_schema: true,
+        ..Default::default()
+    });
+
+    // Without migrations, the archive is rejected.
+    assert!(matches!(
+        v2::hirpdag_deserialize(&bytes),
+        Err(HirpdagDeserializeError::SchemaMismatch { .. })
+    ));
+
+    let options = HirpdagDeserializeOptions {
+        migrations: v1_to_v2(),
+        ..Default::default()
+    };
+    let out = v2::hirpdag_deserialize_with_options(&bytes, &options).unwrap();
+    assert_eq!(out.doc, vec![v2_doc()]);
+    // Sharing survives the migration.
+    assert_eq!(out.doc[0].body[0], out.doc[0].body[2]);
+
+    let out = v2::hirpdag_deserialize_from_with_options(&bytes[..], &options).unwrap();
+    assert_eq!(out.doc, vec![v2_doc()]);
+}
+
+#[test]
+fn migrate_requires_embedded_schema() {
+    let bytes = v1_archive(&HirpdagSerializeOptions::default());
+    let options = HirpdagDeserializeOptions {
+        migrations: v1_to_v2(),
+        ..Default::default()
+    };
+    let err = v2::hirpdag_deserialize_with_options(&bytes, &options).unwrap_err();
+    assert!(
+        matches!(&err, HirpdagDeserializeError::Migration(msg) if msg.contains("embed")),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn migration_errors_are_reported() {
+    let bytes = v1_archive(&HirpdagSerializeOptions {
+        embed_schema: true,
+        ..Default::default()
+    });
+    let mut migrations = HirpdagMigrations::new();
+    migrations.register(
+        v1::hirpdag_schema_fingerprint().hash,
+        v2::hirpdag_schema_fingerprint().hash,
+        |_| Err("cannot migrate".to_string()),
+    );
+    let options = HirpdagDeserializeOptions {
+        migrations,
+        ..Default::default()
+    };
+    let err = v2::hirpdag_deserialize_with_options(&bytes, &options).unwrap_err();
+    assert!(err.to_string().contains("cannot migrate"), "{}", err);
+
+    // A migration which leaves the archive incompatible fails to load.
+    let mut migrations = HirpdagMigrations::new();
+    migrations.register(
+        v1::hirpdag_schema_fingerprint().hash,
+        v2::hirpdag_schema_fingerprint().hash,
+        |_| Ok(()),
+    );
+    let options = HirpdagDeserializeOptions {
+        migrations,
+        ..Default::default()
+    };
+    assert!(v2::hirpdag_deserialize_with_options(&bytes, &options).is_err());
+}
+
+#[test]
+fn version_1_header_accepted() {
+    // Version 1 headers hold the fingerprint right after the magic prefix.
+    let bytes = v1_archive(&HirpdagSerializeOptions::default());
+    let (_, payload) = hirpdag::base::hirpdag_parse_binary_header(&bytes).unwrap();
+    let mut old = hirpdag::base::HIRPDAG_MAGIC.to_vec();
+    old.extend(hirpdag::postcard::to_stdvec(&v1::hirpdag_schema_fingerprint()).unwrap());
+    old.extend_from_slice(payload);
+
+    let (header, _) = hirpdag::base::hirpdag_parse_binary_header(&old).unwrap();
+    assert_eq!(header.fingerprint, v1::hirpdag_schema_fingerprint());
+    assert_eq!(header.schema, None);
+    assert_eq!(
+        v1::hirpdag_deserialize(&old).unwrap(),
+        v1::hirpdag_deserialize(&bytes).unwrap()
+    );
+    assert_eq!(
+        v1::hirpdag_deserialize_from(&old[..]).unwrap(),
+        v1::hirpdag_deserialize(&bytes).unwrap()
+    );
+}
@@ -0,0 +1,198 @@
+// Tests for partial reads of selected archive roots.
+
+use hirpdag::base::{
+    hirpdag_inspect, hirpdag_parse_binary_header, hirpdag_unframe_indexed, HirpdagDeserializeError,
+    HirpdagDeserializeOptions, HirpdagSerializeOptions,
+};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod docs {
+    #[hirpdag(root)]
+    struct Doc {
+        pub title: String,
+        pub sections: Vec<Section>,
+    }
+
+    #[hirpdag(root)]
+    struct Section {
+        pub text: String,
+        pub cites: Vec<Section>,
+    }
+}
+
+use docs::*;
+
+fn section(text: &str, cites: Vec<Section>) -> Section {
+    Section::new(format!("partial_{}", text), cites)
+}
+
+/// Three documents; `b` and `c` share a section.
+fn roots() -> HirpdagArchiveRoots {
+    let shared = section("shared", vec![]);
+    let a = Doc::new(
+        "a".to_string(),
+        vec![section("a1", vec![]), section("a2", vec![])],
+    );
+    let b = Doc::new("b".to_string(), vec![section("b1", vec![shared.clone()])]);
+    let c = Doc::new("c".to_string(), vec![shared.clone()]);
+    HirpdagArchiveRoots {
+        doc: vec![a, b, c],
+        section: vec![shared],
+    }
+}
+
+fn indexed() -> HirpdagSerializeOptions {
+    HirpdagSerializeOptions {
+        index: true,
+        ..Default::default()
+    }
+}
+
+fn no_options() -> HirpdagDeserializeOptions {
+    HirpdagDeserializeOptions::default()
+}
+
+#[test]
+fn reads_selected_roots() {
+    let bytes = hirpdag_serialize_with_options(&roots(), &indexed()).unwrap();
+    let selection = HirpdagRootSelection {
+        doc: vec![2, 1],
+        ..Default::default()
+    };
+    let out = hirpdag_deserialize_partial(&bytes, &selection, &no_options()).unwrap();
+    assert_eq!(
+        out,
+        HirpdagArchiveRoots {
+            doc: vec![roots().doc[2].clone(), roots().doc[1].clone()],
+            section: vec![],
+        }
+    );
+    // Sharing is restored as by a full read.
+    assert_eq!(out.doc[0].sections[0], out.doc[1].sections[0].cites[0]);
+
+    let selection = HirpdagRootSelection {
+        doc: vec![0],
+        section: vec![0, 0],
+    };
+    let out = hirpdag_deserialize_partial(&bytes, &selection, &no_options()).unwrap();
+    assert_eq!(out.doc, vec![roots().doc[0].clone()]);
+    assert_eq!(out.section, vec![roots().section[0].clone(); 2]);
+
+    // The index leaves the archive readable as a whole.
+    assert_eq!(hirpdag_deserialize(&bytes).unwrap(), roots());
+    assert_eq!(hirpdag_deserialize_from(&bytes[..]).unwrap(), roots());
+    let mut streamed = Vec::new();
+    hirpdag_serialize_to_with_options(&roots(), &mut streamed, &indexed()).unwrap();
+    assert_eq!(streamed, bytes);
+}
+
+#[test]
+fn skips_unreachable_nodes() {
+    let bytes = hirpdag_serialize_with_options(&roots(), &indexed()).unwrap();
+    // Spoil the text of a section only document `a` reaches: reading all of
+    // the archive fails, reading document `c` never decodes it.
+    let mut spoiled = bytes.clone();
+    let at = spoiled
+        .windows(b"partial_a2".len())
+        .position(|window| window == b"partial_a2")
+        .unwrap();
+    spoiled[at] = 0xff;
+    assert!(matches!(
+        hirpdag_deserialize(&spoiled),
+        Err(HirpdagDeserializeError::Format(_))
+    ));
+    let selection = HirpdagRootSelection {
+        doc: vec![2],
+        ..Default::default()
+    };
+    let out = hirpdag_deserialize_partial(&spoiled, &selection, &no_options()).unwrap();
+    assert_eq!(out.doc, vec![roots().doc[2].clone()]);
+    assert!(hirpdag_deserialize_partial(
+        &spoiled,
+        &HirpdagRootSelection {
+            doc: vec![0],
+            ..Default::default()
+        },
+        &no_options()
+    )
+    .is_err());
+}
+
+#[test]
+fn reads_archives_without_index() {
+    let selection = HirpdagRootSelection {
+        doc: vec![1],
+        section: vec![0],
+    };
+    let expected = HirpdagArchiveRoots {
+        doc: vec![roots().doc[1].clone()],
+        section: roots().section,
+    };
+    let options = HirpdagSerializeOptions {
+        checksum: true,
+        ..Default::default()
+    };
+    for options in [HirpdagSerializeOptions::default(), options] {
+        let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+        assert_eq!(
+            hirpdag_deserialize_partial(&bytes, &selection, &no_options()).unwrap(),
+            expected
+        );
+        let options = HirpdagSerializeOptions {
+            index: true,
+            ..options
+        };
+        let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+        assert_eq!(
+            hirpdag_deserialize_partial(&bytes, &selection, &no_options()).unwrap(),
+            expected
+        );
+    }
+}
+
+#[test]
+fn missing_root_rejected() {
+    let selection = HirpdagRootSelection {
+        section: vec![1],
+        ..Default::default()
+    };
+    for options in [HirpdagSerializeOptions::default(), indexed()] {
+        let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+        let err = hirpdag_deserialize_partial(&bytes, &selection, &no_options()).unwrap_err();
+        assert_eq!(
+            err,
+            HirpdagDeserializeError::MissingRoot {
+                root: "section",
+                position: 1,
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "hirpdag: the archive has no section root at position 1"
+        );
+    }
+}
+
+#[test]
+fn indexed_archives_inspect() {
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        ..indexed()
+    };
+    let bytes = hirpdag_serialize_with_options(&roots(), &options).unwrap();
+    let inspection = hirpdag_inspect(&bytes).unwrap();
+    assert_eq!(inspection.archive.nodes.len(), 7);
+    assert!(inspection.archive.validate(&inspection.schema).is_empty());
+
+    // Past the index, the payload is the plain archive's.
+    let plain = hirpdag_serialize(&roots()).unwrap();
+    let bytes = hirpdag_serialize_with_options(&roots(), &indexed()).unwrap();
+    let (header, rest) = hirpdag_parse_binary_header(&bytes).unwrap();
+    let (index, payload) = hirpdag_unframe_indexed(&header, rest).unwrap();
+    assert_eq!(&payload[..], hirpdag_parse_binary_header(&plain).unwrap().1);
+    let index = index.unwrap();
+    assert_eq!(index.offsets.len(), 7);
+    assert_eq!(index.roots, vec![vec![2, 5, 6], vec![3]]);
+    assert_eq!(index.children[5], vec![4]);
+}
@@ -0,0 +1,150 @@
+// Tests for sharing-aware printing.
+
+use hirpdag::base::{hirpdag_pretty, HirpdagPrettyOptions};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod calc {
+    #[hirpdag]
+    enum Arg {
+        Num(i64),
+        Sub(Expr),
+    }
+
+    #[hirpdag(pretty)]
+    struct Expr {
+        pub op: String,
+        pub args: Vec<Arg>,
+    }
+
+    #[hirpdag]
+    struct Var {
+        pub name: String,
+        pub value: Option<Expr>,
+    }
+
+    // Prints `(op arg ..)`, with numbers bare.
+    impl Expr {
+        pub fn hirpdag_pretty(
+            &self,
+            p: &mut hirpdag::base::HirpdagPrinter<'_, HirpdagNodeRef>,
+        ) -> std::fmt::Result {
+            write!(p, "({}", self.op)?;
+            for arg in &self.args {
+                p.write_str(" ")?;
+                match arg {
+                    Arg::Num(n) => write!(p, "{}", n)?,
+                    Arg::Sub(e) => p.print(e)?,
+                }
+            }
+            p.write_str(")")
+        }
+    }
+}
+
+use calc::*;
+
+fn num(n: i64) -> Expr {
+    Expr::new("pretty_num".to_string(), vec![Arg::Num(n)])
+}
+
+fn add(a: &Expr, b: &Expr) -> Expr {
+    Expr::new(
+        "pretty_add".to_string(),
+        vec![Arg::Sub(a.clone()), Arg::Sub(b.clone())],
+    )
+}
+
+/// Fibonacci-style chain: each level adds the previous two, so the tree
+/// expansion doubles with each level while the DAG grows by one node.
+fn fib(levels: usize) -> Expr {
+    let (mut a, mut b) = (num(0), num(1));
+    for _ in 0..levels {
+        let c = add(&a, &b);
+        a = b;
+        b = c;
+    }
+    b
+}
+
+#[test]
+fn debug_labels_shared_nodes() {
+    let x = num(1);
+    let sum = add(&x, &x);
+    assert_eq!(
+        format!("{:?}", sum),
+        "Expr { op: \"pretty_add\", args: [Sub(#1 = Expr { op: \"pretty_num\", args: [Num(1)] }), Sub(#1)] }"
+    );
+    // Unshared nodes are not labelled.
+    assert_eq!(
+        format!("{:?}", num(2)),
+        "Expr { op: \"pretty_num\", args: [Num(2)] }"
+    );
+}
+
+#[test]
+fn display_uses_custom_form() {
+    let x = num(1);
+    let sum = add(&x, &x);
+    assert_eq!(sum.to_string(), "(pretty_add #1 = (pretty_num 1) #1)");
+    let var = Var::new("pretty_v".to_string(), Some(sum));
+    assert_eq!(
+        var.to_string(),
+        "Var { name: \"pretty_v\", value: Some((pretty_add #1 = (pretty_num 1) #1)) }"
+    );
+    assert_eq!(
+        HirpdagNodeRef::from(Var::new("pretty_w".to_string(), None)).to_string(),
+        "Var { name: \"pretty_w\", value: None }"
+    );
+}
+
+#[test]
+fn prints_diamond_chains_linearly() {
+    // 2^64 paths to the leaves; a tree expansion would never finish.
+    let text = format!("{:?}", fib(64));
+    assert!(text.len() < 20_000, "{} bytes", text.len());
+    // Every node but the root, its right child and the first leaf is shared.
+    assert!(text.contains("#63 = "));
+    assert!(!text.contains("#64"));
+}
+
+#[test]
+fn truncates_depth_and_width() {
+    let x = num(1);
+    let deep = add(&add(&x, &num(2)), &x);
+    let options = HirpdagPrettyOptions {
+        max_depth: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(hirpdag_pretty(&deep, &options), "(pretty_add ... ...)");
+    let options = HirpdagPrettyOptions {
+        max_depth: Some(2),
+        ..Default::default()
+    };
+    // `x` is reached once within the limit, so it is not labelled.
+    assert_eq!(
+        hirpdag_pretty(&deep, &options),
+        "(pretty_add (pretty_add ... ...) (pretty_num 1))"
+    );
+
+    let wide = Expr::new("pretty_wide".to_string(), (0..5).map(Arg::Num).collect());
+    let options = HirpdagPrettyOptions {
+        max_width: Some(2),
+        debug: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        hirpdag_pretty(&wide, &options),
+        "Expr { op: \"pretty_wide\", args: [Num(0), Num(1), ...] }"
+    );
+}
+
+#[test]
+fn shares_labels_across_values() {
+    let x = num(1);
+    let exprs = vec![add(&x, &num(2)), x];
+    assert_eq!(
+        hirpdag_pretty(&exprs, &Default::default()),
+        "[(pretty_add #1 = (pretty_num 1) (pretty_num 2)), #1]"
+    );
+}
@@ -0,0 +1,157 @@
+use hirpdag::*;
+
+#[hirpdag_module]
+mod datamodel {
+    #[hirpdag]
+    struct Expr {
+        // pub so rewriters defined outside the module can read this field.
+        pub x: ExprKind,
+    }
+
+    #[hirpdag]
+    enum ExprKind {
+        Num(u32),
+        Var(String),
+        Add(Vec<Expr>),
+    }
+}
+
+use datamodel::*;
+
+fn num(n: u32) -> Expr {
+    Expr::new(ExprKind::Num(n))
+}
+
+fn var(name: &str) -> Expr {
+    Expr::new(ExprKind::Var(name.to_string()))
+}
+
+fn add(terms: Vec<Expr>) -> Expr {
+    Expr::new(ExprKind::Add(terms))
+}
+
+// Replaces a variable with an expression.
+struct Substitute {
+    var: String,
+    s: Expr,
+}
+
+impl HirpdagRewriter for Substitute {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        if let ExprKind::Var(name) = &x.x {
+            if *name == self.var {
+                return self.s.clone();
+            }
+        }
+        x.default_rewrite(self)
+    }
+}
+
+// Folds additions of constants, children first.
+struct ConstantFold;
+
+impl HirpdagRewriter for ConstantFold {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        let x = x.default_rewrite(self);
+        if let ExprKind::Add(terms) = &x.x {
+            let mut sum = 0;
+            for t in terms {
+                match &t.x {
+                    ExprKind::Num(n) => sum += n,
+                    _ => return x.clone(),
+                }
+            }
+            return num(sum);
+        }
+        x
+    }
+}
+
+#[test]
+fn provenance_records_changed_nodes() {
+    let a_plus_1 = add(vec![var("a"), num(1)]);
+    let input = add(vec![a_plus_1.clone(), var("b")]);
+
+    let traced = HirpdagRewriteTraced::new(Substitute {
+        var: "a".to_string(),
+        s: num(2),
+    });
+    let output = traced.rewrite(&input);
+    assert_eq!(output, add(vec![add(vec![num(2), num(1)]), var("b")]));
+
+    let provenance = traced.provenance();
+    // a => 2, a + 1 => 2 + 1, (a + 1) + b => (2 + 1) + b. The unchanged
+    // nodes (1, b) are not recorded.
+    assert_eq!(provenance.len(), 3);
+    let steps = provenance.steps();
+    assert_eq!(steps[0].input, HirpdagNodeRef::from(var("a")));
+    assert_eq!(steps[0].output, HirpdagNodeRef::from(num(2)));
+    assert_eq!(steps[0].method, "rewrite_Expr");
+    assert!(steps[0].rewriter.ends_with("Substitute"));
+
+    let origin = provenance
+        .origin_of(&output.clone().into())
+        .expect("output was produced by a rewrite");
+    assert_eq!(origin.input, HirpdagNodeRef::from(input));
+    assert!(provenance.origin_of(&var("b").into()).is_none());
+}
+
+#[test]
+fn provenance_across_passes() {
+    let input = add(vec![var("a"), num(1)]);
+
+    let substitute = HirpdagRewriteTraced::new(Substitute {
+        var: "a".to_string(),
+        s: num(2),
+    });
+    let substituted = substitute.rewrite(&input);
+    let (_, mut provenance) = substitute.into_inner();
+
+    let fold = HirpdagRewriteTraced::new(ConstantFold);
+    let folded = fold.rewrite(&substituted);
+    assert_eq!(folded, num(3));
+    provenance.extend(fold.take_provenance());
+    assert!(fold.provenance().is_empty());
+
+    // 3 <= 2 + 1 (ConstantFold) <= a + 1 (Substitute)
+    let chain = provenance.trace_back(&folded.into());
+    assert_eq!(chain.len(), 2);
+    assert!(chain[0].rewriter.ends_with("ConstantFold"));
+    assert!(chain[1].rewriter.ends_with("Substitute"));
+    assert_eq!(chain[1].input, HirpdagNodeRef::from(input));
+
+    let json = provenance.to_json();
+    assert_eq!(json["steps"].as_array().unwrap().len(), provenance.len());
+    assert_eq!(json["steps"][0]["output"]["type"], "Expr");
+    let dot = provenance.to_dot();
+    assert!(dot.starts_with("digraph hirpdag_provenance {"));
+    assert_eq!(dot.matches("->").count(), provenance.len());
+}
+
+#[test]
+fn provenance_nested_memoized() {
+    let input = add(vec![var("a"), var("a")]);
+    let traced = HirpdagRewriteTraced::new(HirpdagRewriteMemoized::new(Substitute {
+        var: "a".to_string(),
+        s: num(2),
+    }));
+    let output = traced.rewrite(&input);
+    assert_eq!(output, add(vec![num(2), num(2)]));
+    // a => 2 and the root. Both children are the same interned node, and
+    // the root is reported by both the memoizing wrapper and the inner
+    // rewriter, but each step is recorded once.
+    assert_eq!(traced.provenance().len(), 2);
+}
+
+#[test]
+fn untraced_rewrites_record_nothing() {
+    let input = add(vec![var("a"), num(1)]);
+    let traced = HirpdagRewriteTraced::new(ConstantFold);
+    let plain = Substitute {
+        var: "a".to_string(),
+        s: num(2),
+    };
+    let substituted = plain.rewrite(&input);
+    assert_eq!(traced.rewrite(&substituted), num(3));
+    assert_eq!(traced.provenance().len(), 1);
+}
@@ -0,0 +1,150 @@
+use hirpdag::*;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[hirpdag_module]
+mod datamodel {
+    #[hirpdag]
+    struct Expr {
+        // pub so rewriters defined outside the module can read this field.
+        pub x: ExprKind,
+    }
+
+    #[hirpdag]
+    enum ExprKind {
+        Num(u32),
+        Var(String),
+        Add(Vec<Expr>),
+    }
+}
+
+use datamodel::*;
+
+fn num(n: u32) -> Expr {
+    Expr::new(ExprKind::Num(n))
+}
+
+fn var(name: &str) -> Expr {
+    Expr::new(ExprKind::Var(name.to_string()))
+}
+
+fn add(terms: Vec<Expr>) -> Expr {
+    Expr::new(ExprKind::Add(terms))
+}
+
+// Replaces every variable with 0, caching every node it visits in a shared
+// cache and counting the nodes it actually rewrites.
+struct ZeroVars {
+    cache: Arc<HirpdagRewriteCache>,
+    calls: Arc<AtomicUsize>,
+}
+
+impl HirpdagRewriter for ZeroVars {
+    fn rewrite_Expr(&self, x: &Expr) -> Expr {
+        self.cache.get_or_rewrite_Expr(x, |x| {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            match &x.x {
+                ExprKind::Var(_) => num(0),
+                _ => x.default_rewrite(self),
+            }
+        })
+    }
+}
+
+#[test]
+fn rewrite_cache_reused_across_calls() {
+    let cache = Arc::new(HirpdagRewriteCache::new());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let shared = add(vec![var("a"), num(1)]);
+
+    let first = ZeroVars {
+        cache: cache.clone(),
+        calls: calls.clone(),
+    };
+    let out = first.rewrite(&add(vec![shared.clone(), var("b")]));
+    assert_eq!(out, add(vec![add(vec![num(0), num(1)]), num(0)]));
+    // root, shared, a, 1, b
+    assert_eq!(calls.load(Ordering::Relaxed), 5);
+    drop(first);
+
+    // A later pass, with a new rewriter, reuses the cached subtree.
+    let second = ZeroVars {
+        cache: cache.clone(),
+        calls: calls.clone(),
+    };
+    let out = second.rewrite(&add(vec![shared.clone(), var("c")]));
+    assert_eq!(out, add(vec![add(vec![num(0), num(1)]), num(0)]));
+    // root, c
+    assert_eq!(calls.load(Ordering::Relaxed), 7);
+
+    // Invalidation forces a rewrite of that node only.
+    assert!(cache.invalidate_Expr(&shared));
+    second.rewrite(&shared);
+    assert_eq!(calls.load(Ordering::Relaxed), 8);
+    assert!(cache.stats().hits >= 3);
+}
+
+#[test]
+fn rewrite_cache_does_not_keep_inputs_alive() {
+    let cache = Arc::new(HirpdagRewriteCache::new());
+    let rewriter = ZeroVars {
+        cache: cache.clone(),
+        calls: Arc::new(AtomicUsize::new(0)),
+    };
+    let input = add(vec![var("rewrite_cache_dead"), num(7)]);
+    rewriter.rewrite(&input);
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.purge(), 0);
+
+    // The input and its Var child die; 7 is still referenced by the result.
+    drop(input);
+    assert_eq!(cache.purge(), 2);
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn rewrite_cache_lru_bound() {
+    let cache = Arc::new(HirpdagRewriteCache::with_capacity(Some(2)));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let rewriter = HirpdagRewriteCached::new(
+        ZeroVars {
+            cache: Arc::new(HirpdagRewriteCache::new()),
+            calls: calls.clone(),
+        },
+        cache.clone(),
+    );
+    let nodes: Vec<Expr> = (0..4).map(|n| add(vec![num(n), var("x")])).collect();
+    for n in &nodes {
+        rewriter.rewrite(n);
+    }
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.stats().evictions, 2);
+    // The most recent entries are still cached.
+    assert_eq!(
+        cache.table_Expr().get(&nodes[3]),
+        Some(add(vec![num(3), num(0)]))
+    );
+    assert_eq!(cache.table_Expr().get(&nodes[0]), None);
+}
+
+#[test]
+fn rewrite_cache_shared_between_threads() {
+    let cache = Arc::new(HirpdagRewriteCache::new());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let input = add(vec![var("a"), var("b"), num(2)]);
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let rewriter = ZeroVars {
+                cache: cache.clone(),
+                calls: calls.clone(),
+            };
+            let input = input.clone();
+            std::thread::spawn(move || rewriter.rewrite(&input))
+        })
+        .collect();
+    for h in handles {
+        assert_eq!(h.join().unwrap(), add(vec![num(0), num(0), num(2)]));
+    }
+    assert_eq!(cache.len(), 4);
+}
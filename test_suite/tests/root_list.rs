@@ -0,0 +1,161 @@
+// Tests for root list archives: ordered, optionally named roots of any type.
+
+use hirpdag::base::{
+    hirpdag_inspect, hirpdag_parse_binary_header, HirpdagArchiveProblem, HirpdagDeserializeError,
+    HirpdagRootLayout, HirpdagSerializeOptions,
+};
+use hirpdag::*;
+use std::convert::TryFrom;
+
+// No type is marked as a root.
+#[hirpdag_module]
+mod snapshot {
+    #[hirpdag]
+    struct Expr {
+        pub op: String,
+        pub args: Vec<Expr>,
+    }
+
+    #[hirpdag]
+    struct Vars {
+        pub names: Vec<String>,
+        pub values: Vec<Expr>,
+    }
+}
+
+#[hirpdag_module]
+mod typed {
+    #[hirpdag(root)]
+    struct Item {
+        pub name: String,
+    }
+}
+
+use snapshot::*;
+
+fn expr(op: &str, args: Vec<Expr>) -> Expr {
+    Expr::new(format!("root_list_{}", op), args)
+}
+
+/// `main` and `env` share the `x` expression.
+fn snapshot() -> HirpdagRootList {
+    let x = expr("x", vec![]);
+    let main = expr("add", vec![x.clone(), expr("one", vec![])]);
+    let env = Vars::new(vec!["x".to_string()], vec![x.clone()]);
+    let mut list = HirpdagRootList::new();
+    list.push_named("main", main).push_named("env", env).push(x);
+    list
+}
+
+#[test]
+fn round_trips_named_roots() {
+    let bytes = hirpdag_serialize_list(&snapshot()).unwrap();
+    let list = hirpdag_deserialize_list(&bytes).unwrap();
+    assert_eq!(list, snapshot());
+    assert_eq!(list.len(), 3);
+    assert_eq!(list.entries()[2].name, None);
+
+    let main = Expr::try_from(list.get("main").unwrap().clone()).unwrap();
+    let env = Vars::try_from(list.get("env").unwrap().clone()).unwrap();
+    assert_eq!(main.args[0], env.values[0]);
+    assert_eq!(main.op, "root_list_add");
+    // A node of another type is handed back.
+    let node = list.get("env").unwrap().clone();
+    assert_eq!(Expr::try_from(node.clone()), Err(node));
+
+    let text = hirpdag_serialize_list_json(&snapshot()).unwrap();
+    assert!(text.contains(r#""root_list":[{"name":"main","node":2}"#));
+    assert_eq!(hirpdag_deserialize_list_json(&text).unwrap(), snapshot());
+}
+
+#[test]
+fn round_trips_with_options() {
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        checksum: true,
+        compress: true,
+        index: true,
+        canonical: false,
+    };
+    let bytes = hirpdag_serialize_list_with_options(&snapshot(), &options).unwrap();
+    assert_eq!(hirpdag_deserialize_list(&bytes).unwrap(), snapshot());
+
+    let (header, _) = hirpdag_parse_binary_header(&bytes).unwrap();
+    assert_eq!(header.root_layout(), HirpdagRootLayout::List);
+    let inspection = hirpdag_inspect(&bytes).unwrap();
+    let archive = &inspection.archive;
+    assert_eq!(archive.nodes.len(), 4);
+    assert!(archive.roots.is_empty());
+    let root_list = archive.root_list.as_ref().unwrap();
+    assert_eq!(root_list.get("env"), Some(&3));
+    assert_eq!(archive.root_nodes().collect::<Vec<_>>(), vec![2, 3, 0]);
+    assert!(archive.validate(&inspection.schema).is_empty());
+}
+
+#[test]
+fn empty_list_round_trips() {
+    let bytes = hirpdag_serialize_list(&HirpdagRootList::new()).unwrap();
+    assert!(hirpdag_deserialize_list(&bytes).unwrap().is_empty());
+}
+
+#[test]
+fn layout_mismatch_rejected() {
+    let mut list = typed::HirpdagRootList::new();
+    list.push_named("first", typed::Item::new("root_list_item".to_string()));
+    let bytes = typed::hirpdag_serialize_list(&list).unwrap();
+    let err = typed::hirpdag_deserialize(&bytes).unwrap_err();
+    assert_eq!(
+        err,
+        HirpdagDeserializeError::RootLayoutMismatch {
+            expected: HirpdagRootLayout::Typed,
+            found: HirpdagRootLayout::List,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "hirpdag: root layout mismatch: the archive holds a root list \
+         but is being read as typed roots"
+    );
+    assert_eq!(
+        typed::hirpdag_deserialize_from(&bytes[..]).unwrap_err(),
+        err
+    );
+
+    let roots = typed::HirpdagArchiveRoots {
+        item: vec![typed::Item::new("root_list_item".to_string())],
+    };
+    let bytes = typed::hirpdag_serialize(&roots).unwrap();
+    assert_eq!(
+        typed::hirpdag_deserialize_list(&bytes).unwrap_err(),
+        HirpdagDeserializeError::RootLayoutMismatch {
+            expected: HirpdagRootLayout::List,
+            found: HirpdagRootLayout::Typed,
+        }
+    );
+    // Either way round, the data is intact.
+    assert_eq!(typed::hirpdag_deserialize(&bytes).unwrap(), roots);
+}
+
+#[test]
+fn out_of_range_entries_reported() {
+    let text = r#"{"version":1,"nodes":[{"Expr":{"op":"root_list_y","args":[]}}],
+        "root_list":[{"name":"main","node":1}]}"#;
+    assert_eq!(
+        hirpdag_deserialize_list_json(text).unwrap_err(),
+        HirpdagDeserializeError::InvalidNodeIndex {
+            index: 1,
+            at_node: None,
+        }
+    );
+    let archive = hirpdag::base::hirpdag_inspect_json(text, &hirpdag_schema()).unwrap();
+    assert_eq!(
+        archive.validate(&hirpdag_schema()),
+        vec![
+            HirpdagArchiveProblem::ListRootOutOfRange {
+                position: 0,
+                index: 1,
+            },
+            HirpdagArchiveProblem::Unreachable { node: 0 },
+        ]
+    );
+}
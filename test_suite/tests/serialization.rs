@@ -209,13 +209,65 @@ fn truncated_input_rejected() {
         ..Default::default()
     };
     let bytes = hirpdag_serialize(&roots).unwrap();
+    // The last byte belongs to the roots, after the node table.
     let err = hirpdag_deserialize(&bytes[..bytes.len() - 1]).unwrap_err();
-    assert!(matches!(
+    assert_eq!(
+        err,
+        hirpdag::base::HirpdagDeserializeError::Truncated { at_node: None }
+    );
+
+    // Cut inside the name of the second node.
+    let leaf = Item::new("trunc_leaf".to_string(), vec![]);
+    let roots = HirpdagArchiveRoots {
+        item: vec![Item::new("trunc_parent".to_string(), vec![leaf])],
+        ..Default::default()
+    };
+    let bytes = hirpdag_serialize(&roots).unwrap();
+    let at = bytes
+        .windows(b"trunc_parent".len())
+        .position(|window| window == b"trunc_parent")
+        .unwrap();
+    let err = hirpdag_deserialize(&bytes[..at + 5]).unwrap_err();
+    assert_eq!(
         err,
-        hirpdag::base::HirpdagDeserializeError::Format(_)
+        hirpdag::base::HirpdagDeserializeError::Truncated { at_node: Some(1) }
+    );
+    assert_eq!(err.to_string(), "hirpdag: the input ends inside node 1");
+    let text = hirpdag_serialize_json(&roots).unwrap();
+    assert!(matches!(
+        hirpdag_deserialize_json(&text[..text.len() / 2]),
+        Err(hirpdag::base::HirpdagDeserializeError::Truncated { .. })
     ));
 }
 
+#[test]
+fn trailing_bytes_rejected() {
+    let roots = HirpdagArchiveRoots {
+        item: vec![Item::new("trailing_item".to_string(), vec![])],
+        ..Default::default()
+    };
+    let mut bytes = hirpdag_serialize(&roots).unwrap();
+    bytes.push(0);
+    // After the node table, so at no node.
+    let err = hirpdag_deserialize(&bytes).unwrap_err();
+    assert_eq!(
+        err,
+        hirpdag::base::HirpdagDeserializeError::TrailingBytes { at_node: None }
+    );
+    assert_eq!(
+        err.to_string(),
+        "hirpdag: unexpected input after the archive"
+    );
+    let text = hirpdag_serialize_json(&roots).unwrap() + " {}";
+    assert_eq!(
+        hirpdag_deserialize_json(&text),
+        Err(hirpdag::base::HirpdagDeserializeError::TrailingBytes { at_node: None })
+    );
+    // Trailing whitespace is not an error.
+    let text = hirpdag_serialize_json(&roots).unwrap() + "\n";
+    assert_eq!(hirpdag_deserialize_json(&text).unwrap(), roots);
+}
+
 #[test]
 fn ref_serialize_outside_session_fails() {
     // Serializing a hirpdag ref without going through hirpdag_serialize
@@ -228,13 +280,37 @@ fn ref_serialize_outside_session_fails() {
 
 #[test]
 fn unsupported_version_rejected() {
+    use hirpdag::base::{HirpdagDeserializeError, HirpdagVersionKind};
+
     let err = hirpdag_deserialize_json(r#"{"version":99,"nodes":[],"roots":{}}"#).unwrap_err();
-    match err {
-        hirpdag::base::HirpdagDeserializeError::Format(msg) => {
-            assert!(msg.contains("version"), "unexpected message: {}", msg)
+    assert_eq!(
+        err,
+        HirpdagDeserializeError::UnsupportedVersion {
+            kind: HirpdagVersionKind::Format,
+            found: 99,
+            at_node: None,
         }
-        other => panic!("expected Format error, got {:?}", other),
-    }
+    );
+    assert_eq!(
+        err.to_string(),
+        "hirpdag: unsupported format version 99 (supported: 1)"
+    );
+
+    // A binary header version from the future.
+    let mut bytes = hirpdag::base::HIRPDAG_MAGIC.to_vec();
+    bytes.extend(hirpdag::postcard::to_stdvec(&50u32).unwrap());
+    assert_eq!(
+        hirpdag_deserialize(&bytes),
+        Err(HirpdagDeserializeError::UnsupportedVersion {
+            kind: HirpdagVersionKind::Header,
+            found: 50,
+            at_node: None,
+        })
+    );
+    assert_eq!(
+        hirpdag_deserialize(&bytes).unwrap_err().to_string(),
+        "hirpdag: unsupported header version 50 (supported: 1 to 2)"
+    );
 }
 
 #[test]
@@ -243,12 +319,37 @@ fn forward_or_out_of_range_index_rejected() {
     // forward references and out-of-range indices are both rejected.
     let text = r#"{"version":1,"nodes":[{"Item":{"name":"x","deps":[5]}}],"roots":{}}"#;
     let err = hirpdag_deserialize_json(text).unwrap_err();
-    match err {
-        hirpdag::base::HirpdagDeserializeError::Format(msg) => {
-            assert!(msg.contains("invalid"), "unexpected message: {}", msg)
+    assert_eq!(
+        err,
+        hirpdag::base::HirpdagDeserializeError::InvalidNodeIndex {
+            index: 5,
+            at_node: Some(0),
         }
-        other => panic!("expected Format error, got {:?}", other),
-    }
+    );
+    assert!(err.to_string().contains("in node 0"), "{}", err);
+
+    // A reference to the node itself is a forward reference.
+    let text = r#"{"version":1,"nodes":[
+        {"Item":{"name":"x","deps":[]}},
+        {"Item":{"name":"y","deps":[0,1]}}
+    ],"roots":{}}"#;
+    assert_eq!(
+        hirpdag_deserialize_json(text),
+        Err(hirpdag::base::HirpdagDeserializeError::InvalidNodeIndex {
+            index: 1,
+            at_node: Some(1),
+        })
+    );
+
+    // In the roots.
+    let text = r#"{"version":1,"nodes":[],"roots":{"item":[0]}}"#;
+    assert_eq!(
+        hirpdag_deserialize_json(text),
+        Err(hirpdag::base::HirpdagDeserializeError::InvalidNodeIndex {
+            index: 0,
+            at_node: None,
+        })
+    );
 }
 
 #[test]
@@ -256,12 +357,29 @@ fn node_type_mismatch_rejected() {
     // The roots claim node 0 is a Node, but node 0 is an Item.
     let text = r#"{"version":1,"nodes":[{"Item":{"name":"x","deps":[]}}],"roots":{"node":[0]}}"#;
     let err = hirpdag_deserialize_json(text).unwrap_err();
-    match err {
-        hirpdag::base::HirpdagDeserializeError::Format(msg) => {
-            assert!(msg.contains("type mismatch"), "unexpected message: {}", msg)
+    assert_eq!(
+        err,
+        hirpdag::base::HirpdagDeserializeError::NodeTypeMismatch {
+            expected: "Node",
+            found: "Item",
+            at_node: None,
         }
-        other => panic!("expected Format error, got {:?}", other),
-    }
+    );
+    assert!(err.to_string().contains("type mismatch"), "{}", err);
+
+    // A Sum node referencing an Item, in the node table.
+    let text = r#"{"version":1,"nodes":[
+        {"Item":{"name":"x","deps":[]}},
+        {"Node":{"kind":{"Sum":[0]}}}
+    ],"roots":{}}"#;
+    assert_eq!(
+        hirpdag_deserialize_json(text),
+        Err(hirpdag::base::HirpdagDeserializeError::NodeTypeMismatch {
+            expected: "Node",
+            found: "Item",
+            at_node: Some(1),
+        })
+    );
 }
 
 #[test]
@@ -356,3 +474,117 @@ fn schema_match_accepted_across_modules_with_same_shape() {
     let out = hirpdag_deserialize(&bytes).unwrap();
     assert_eq!(out, roots);
 }
+
+#[test]
+fn streaming_binary_round_trip() {
+    let leaf = Item::new("stream_leaf".to_string(), vec![]);
+    let root = Item::new("stream_root".to_string(), vec![leaf.clone(), leaf]);
+    let roots = HirpdagArchiveRoots {
+        item: vec![root.clone()],
+        ..Default::default()
+    };
+    let mut bytes = Vec::new();
+    hirpdag_serialize_to(&roots, &mut bytes).unwrap();
+    // Same bytes as the buffered entry point.
+    assert_eq!(bytes, hirpdag_serialize(&roots).unwrap());
+
+    // The reader is left just past each archive, so archives can be
+    // concatenated in one stream.
+    let other = HirpdagArchiveRoots {
+        node: vec![Node::new(Kind::Num(6001))],
+        ..Default::default()
+    };
+    hirpdag_serialize_to(&other, &mut bytes).unwrap();
+    let mut reader = std::io::BufReader::new(&bytes[..]);
+    assert_eq!(hirpdag_deserialize_from(&mut reader).unwrap(), roots);
+    assert_eq!(hirpdag_deserialize_from(&mut reader).unwrap(), other);
+}
+
+#[test]
+fn streaming_json_round_trip() {
+    let root = Item::new(
+        "stream_json_root".to_string(),
+        vec![Item::new("stream_json_leaf".to_string(), vec![])],
+    );
+    let roots = HirpdagArchiveRoots {
+        item: vec![root],
+        ..Default::default()
+    };
+    let mut text = Vec::new();
+    hirpdag_serialize_json_to(&roots, &mut text).unwrap();
+    assert_eq!(text, hirpdag_serialize_json(&roots).unwrap().into_bytes());
+    assert_eq!(hirpdag_deserialize_json_from(&text[..]).unwrap(), roots);
+}
+
+struct FailingIo;
+
+impl std::io::Write for FailingIo {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("disk full"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::io::Read for FailingIo {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("device gone"))
+    }
+}
+
+#[test]
+fn streaming_errors() {
+    use hirpdag::base::{HirpdagDeserializeError, HirpdagSerializeError};
+
+    let roots = HirpdagArchiveRoots {
+        item: vec![Item::new("stream_err_item".to_string(), vec![])],
+        ..Default::default()
+    };
+    assert!(matches!(
+        hirpdag_serialize_to(&roots, FailingIo),
+        Err(HirpdagSerializeError::Io(_))
+    ));
+    assert!(matches!(
+        hirpdag_serialize_json_to(&roots, FailingIo),
+        Err(HirpdagSerializeError::Io(_))
+    ));
+
+    // Reaching the end of the input early is reported like truncated bytes.
+    let bytes = hirpdag_serialize(&roots).unwrap();
+    assert_eq!(
+        hirpdag_deserialize_from(&bytes[..bytes.len() - 1]),
+        Err(HirpdagDeserializeError::Truncated { at_node: None })
+    );
+    let at = bytes
+        .windows(b"stream_err_item".len())
+        .position(|window| window == b"stream_err_item")
+        .unwrap();
+    assert_eq!(
+        hirpdag_deserialize_from(&bytes[..at + 3]),
+        Err(HirpdagDeserializeError::Truncated { at_node: Some(0) })
+    );
+    // A forged string length is not allocated before its bytes arrive.
+    let mut forged = bytes[..at - 1].to_vec();
+    forged.extend_from_slice(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x20]);
+    forged.extend_from_slice(&bytes[at..]);
+    assert_eq!(
+        hirpdag_deserialize_from(&forged[..]),
+        Err(HirpdagDeserializeError::Truncated { at_node: Some(0) })
+    );
+    assert_eq!(
+        hirpdag_deserialize_from(&b"XX"[..]),
+        Err(HirpdagDeserializeError::BadMagic)
+    );
+
+    let mut failing = std::io::Read::chain(&bytes[..20], FailingIo);
+    assert!(matches!(
+        hirpdag_deserialize_from(&mut failing),
+        Err(HirpdagDeserializeError::Io(_))
+    ));
+    assert!(matches!(
+        hirpdag_deserialize_json_from(FailingIo),
+        Err(HirpdagDeserializeError::Io(_))
+    ));
+}
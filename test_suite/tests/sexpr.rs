@@ -0,0 +1,149 @@
+// Tests for the S-expression text format.
+
+use hirpdag::*;
+
+#[hirpdag_module]
+mod lang {
+    #[hirpdag]
+    enum Op {
+        Num(i64),
+        Var(String),
+        Add(Vec<Expr>),
+    }
+
+    #[hirpdag(normalizer)]
+    struct Expr {
+        pub op: Op,
+    }
+
+    #[hirpdag]
+    struct Binding {
+        pub name: String,
+        pub value: Option<Expr>,
+        pub weight: u32,
+    }
+
+    // Sums of one term are the term.
+    impl Expr {
+        pub fn new(op: Op) -> Self {
+            match op {
+                Op::Add(mut terms) if terms.len() == 1 => terms.pop().unwrap(),
+                op => Self::spawn(op),
+            }
+        }
+    }
+}
+
+use lang::*;
+
+fn num(n: i64) -> Expr {
+    Expr::new(Op::Num(n))
+}
+
+fn var(name: &str) -> Expr {
+    Expr::new(Op::Var(name.to_string()))
+}
+
+fn add(terms: &[&Expr]) -> Expr {
+    Expr::new(Op::Add(terms.iter().map(|&t| t.clone()).collect()))
+}
+
+#[test]
+fn reads_hand_written_nodes() {
+    let expr: Expr = hirpdag_from_sexpr(
+        r#"
+        ; 1 + x
+        (Expr (Add [(Expr (Num 1)) (Expr (Var "sexpr_x"))]))
+        "#,
+    )
+    .unwrap();
+    assert_eq!(expr, add(&[&num(1), &var("sexpr_x")]));
+
+    let binding: Binding =
+        hirpdag_from_sexpr(r#"(Binding "sexpr_b \"q\"" (Some (Expr (Num -2))) 7)"#).unwrap();
+    assert_eq!(
+        binding,
+        Binding::new("sexpr_b \"q\"".to_string(), Some(num(-2)), 7)
+    );
+}
+
+#[test]
+fn reads_through_the_normalizer() {
+    let expr: Expr = hirpdag_from_sexpr("(Expr (Add [(Expr (Num 3))]))").unwrap();
+    assert_eq!(expr, num(3));
+}
+
+#[test]
+fn writes_unshared_nodes_inline() {
+    let expr = add(&[&num(1), &var("sexpr_y")]);
+    assert_eq!(
+        hirpdag_to_sexpr(&expr),
+        r#"(Expr (Add [(Expr (Num 1)) (Expr (Var "sexpr_y"))]))"#
+    );
+    let binding = Binding::new("sexpr_c".to_string(), None, 0);
+    assert_eq!(hirpdag_to_sexpr(&binding), r#"(Binding "sexpr_c" None 0)"#);
+}
+
+#[test]
+fn binds_shared_nodes() {
+    let x = var("sexpr_z");
+    let double = add(&[&x, &x]);
+    let exprs = vec![add(&[&double, &x]), double];
+    let text = hirpdag_to_sexpr(&exprs);
+    assert_eq!(
+        text,
+        r#"(let [
+  ($1 (Expr (Var "sexpr_z")))
+  ($2 (Expr (Add [$1 $1])))
+]
+  [(Expr (Add [$2 $1])) $2])"#
+    );
+    let read: Vec<Expr> = hirpdag_from_sexpr(&text).unwrap();
+    assert_eq!(read, exprs);
+}
+
+#[test]
+fn scopes_let_bindings() {
+    let expr: HirpdagNodeRef = hirpdag_from_sexpr(
+        r#"(Expr (Add [
+            (let [($a (Expr (Num 1))) ($b (Expr (Add [$a $a])))] $b)
+            (let [($a (Expr (Num 2)))] $a)
+        ]))"#,
+    )
+    .unwrap();
+    let one = num(1);
+    assert_eq!(
+        expr,
+        HirpdagNodeRef::from(add(&[&add(&[&one, &one]), &num(2)]))
+    );
+
+    let error =
+        hirpdag_from_sexpr::<Expr>("(Expr (Add [(let [($a (Expr (Num 1)))] $a) $a]))").unwrap_err();
+    assert_eq!(error.message, "`$a` is not bound");
+    assert_eq!((error.line, error.column), (1, 44));
+}
+
+#[test]
+fn reports_type_errors() {
+    let error = |text| hirpdag_from_sexpr::<Expr>(text).unwrap_err();
+
+    let e = error("(Expr\n  (Mul 1))");
+    assert_eq!(e.message, "expected a variant of Op: Num, Var, Add");
+    assert_eq!((e.line, e.column), (2, 3));
+
+    let e = error("(Expr (Num \"1\"))");
+    assert_eq!(e.message, "expected i64");
+    assert_eq!((e.line, e.column), (1, 12));
+
+    let e = error("(Expr (Num 1) (Num 2))");
+    assert_eq!(e.message, "`Expr` takes 1 values, found 2");
+
+    let e = error(r#"(let [($b (Binding "sexpr_d" None 1))] $b)"#);
+    assert_eq!(e.message, "expected Expr, found Binding");
+
+    let e = error("(Expr (Var sexpr_x))");
+    assert_eq!(e.message, "expected a string");
+
+    let e = hirpdag_from_sexpr::<HirpdagNodeRef>("[]").unwrap_err();
+    assert_eq!(e.message, "expected a node: Expr, Binding");
+}
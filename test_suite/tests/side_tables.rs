@@ -0,0 +1,153 @@
+// Tests for side-tables written alongside an archive.
+
+use hirpdag::base::{
+    hirpdag_inspect_json, hirpdag_inspect_with_schema, hirpdag_parse_binary_header,
+    HirpdagDeserializeError, HirpdagSerializeOptions, HirpdagSideTable, HIRPDAG_FLAG_SIDE_TABLES,
+};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod calc {
+    #[hirpdag(root)]
+    struct Term {
+        pub op: String,
+        pub args: Vec<Term>,
+    }
+}
+
+use calc::*;
+
+fn term(op: &str, args: Vec<Term>) -> Term {
+    Term::new(format!("side_tables_{}", op), args)
+}
+
+type Sizes = HirpdagSideTable<Term, u64>;
+type Notes = HirpdagSideTable<Term, (String, Option<Term>)>;
+
+/// The DAG of `(x + 1) * x`, and side-tables describing it: the size of each
+/// term, and notes, one keyed by a term not reachable from the roots.
+fn analysed() -> (HirpdagArchiveRoots, (Sizes, Notes)) {
+    let x = term("x", vec![]);
+    let one = term("one", vec![]);
+    let add = term("add", vec![x.clone(), one.clone()]);
+    let mul = term("mul", vec![add.clone(), x.clone()]);
+    let sizes: Sizes = vec![(x.clone(), 1), (one, 1), (add.clone(), 3), (mul.clone(), 5)]
+        .into_iter()
+        .collect();
+    let mut notes = Notes::new();
+    notes.insert(
+        add,
+        ("simplifies to".to_string(), Some(term("inc", vec![x]))),
+    );
+    notes.insert(term("unused", vec![]), ("dead".to_string(), None));
+    let roots = HirpdagArchiveRoots { term: vec![mul] };
+    (roots, (sizes, notes))
+}
+
+#[test]
+fn round_trips_side_tables() {
+    let (roots, tables) = analysed();
+    let bytes = hirpdag_serialize_tables(&roots, &tables).unwrap();
+    let (header, _) = hirpdag_parse_binary_header(&bytes).unwrap();
+    assert_ne!(header.flags & HIRPDAG_FLAG_SIDE_TABLES, 0);
+
+    let (read_roots, (sizes, notes)): (_, (Sizes, Notes)) =
+        hirpdag_deserialize_tables(&bytes).unwrap();
+    assert_eq!(read_roots, roots);
+    assert_eq!((&sizes, &notes), (&tables.0, &tables.1));
+    let mul = &read_roots.term[0];
+    assert_eq!(sizes.get(mul), Some(&5));
+    assert_eq!(sizes.get(&mul.args[0]), Some(&3));
+    let (note, target) = notes.get(&mul.args[0]).unwrap();
+    assert_eq!(note, "simplifies to");
+    assert_eq!(target.as_ref().unwrap().args[0], mul.args[1]);
+
+    // Equal tables encode to equal bytes.
+    let (roots, tables) = analysed();
+    assert_eq!(hirpdag_serialize_tables(&roots, &tables).unwrap(), bytes);
+}
+
+#[test]
+fn other_readers_skip_side_tables() {
+    let (roots, tables) = analysed();
+    let bytes = hirpdag_serialize_tables(&roots, &tables).unwrap();
+    assert_eq!(hirpdag_deserialize(&bytes).unwrap(), roots);
+    assert_eq!(hirpdag_deserialize_from(&bytes[..]).unwrap(), roots);
+    let inspection = hirpdag_inspect_with_schema(&bytes, &hirpdag_schema()).unwrap();
+    // The unreachable `unused` and `inc` terms are in the node table.
+    assert_eq!(inspection.archive.nodes.len(), 6);
+
+    let plain = hirpdag_serialize(&roots).unwrap();
+    assert_eq!(
+        hirpdag_deserialize_tables::<Sizes>(&plain).unwrap_err(),
+        HirpdagDeserializeError::MissingSideTables
+    );
+}
+
+#[test]
+fn round_trips_with_options() {
+    let (roots, tables) = analysed();
+    let options = HirpdagSerializeOptions {
+        embed_schema: true,
+        checksum: true,
+        compress: true,
+        index: true,
+        canonical: false,
+    };
+    let bytes = hirpdag_serialize_tables_with_options(&roots, &tables, &options).unwrap();
+    let (read_roots, read_tables): (_, (Sizes, Notes)) =
+        hirpdag_deserialize_tables(&bytes).unwrap();
+    assert_eq!((read_roots, read_tables), (roots.clone(), tables));
+
+    let selection = HirpdagRootSelection { term: vec![0] };
+    let partial = hirpdag_deserialize_partial(&bytes, &selection, &Default::default()).unwrap();
+    assert_eq!(partial, roots);
+}
+
+#[test]
+fn round_trips_json() {
+    let (roots, tables) = analysed();
+    let text = hirpdag_serialize_tables_json(&roots, &tables).unwrap();
+    assert!(text.starts_with(r#"{"version":1,"nodes":"#), "{}", text);
+    assert!(text.contains(r#""side_tables":[[[0,1],"#), "{}", text);
+    let (read_roots, read_tables): (_, (Sizes, Notes)) =
+        hirpdag_deserialize_tables_json(&text).unwrap();
+    assert_eq!((read_roots, read_tables), (roots.clone(), tables));
+
+    assert_eq!(hirpdag_deserialize_json(&text).unwrap(), roots);
+    let archive = hirpdag_inspect_json(&text, &hirpdag_schema()).unwrap();
+    assert_eq!(archive.nodes.len(), 6);
+
+    // A hand-edited file may put the side-tables first.
+    let moved = r#"{"side_tables":[[1,"x"]],"version":1,
+        "nodes":[{"Term":{"op":"side_tables_y","args":[]}},
+                 {"Term":{"op":"side_tables_z","args":[0]}}],
+        "roots":{"term":[1]}}"#;
+    let (read_roots, names): (_, HirpdagSideTable<Term, String>) =
+        hirpdag_deserialize_tables_json(moved).unwrap();
+    assert_eq!(names.get(&read_roots.term[0]).unwrap(), "x");
+
+    let dangling = r#"{"version":1,"nodes":[],"roots":{"term":[]},"side_tables":[[0,"x"]]}"#;
+    assert_eq!(
+        hirpdag_deserialize_tables_json::<HirpdagSideTable<Term, String>>(dangling).unwrap_err(),
+        HirpdagDeserializeError::InvalidNodeIndex {
+            index: 0,
+            at_node: None,
+        }
+    );
+}
+
+#[test]
+fn root_lists_carry_side_tables() {
+    let (roots, (sizes, _)) = analysed();
+    let mut list = HirpdagRootList::new();
+    list.push_named("main", roots.term[0].clone());
+    let bytes = hirpdag_serialize_list_tables(&list, &sizes).unwrap();
+    let (read_list, read_sizes): (_, Sizes) = hirpdag_deserialize_list_tables(&bytes).unwrap();
+    assert_eq!((read_list, read_sizes), (list.clone(), sizes.clone()));
+    assert_eq!(hirpdag_deserialize_list(&bytes).unwrap(), list);
+
+    let text = hirpdag_serialize_list_tables_json(&list, &sizes).unwrap();
+    let (read_list, read_sizes): (_, Sizes) = hirpdag_deserialize_list_tables_json(&text).unwrap();
+    assert_eq!((read_list, read_sizes), (list, sizes));
+}
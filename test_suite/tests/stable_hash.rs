@@ -0,0 +1,92 @@
+// Tests for stable (content) hashes of nodes.
+#![cfg(feature = "stable-hash")]
+
+use hirpdag::*;
+
+#[hirpdag_module]
+mod shapes {
+    #[hirpdag]
+    enum Colour {
+        Named(String),
+        Rgb(u32),
+    }
+
+    #[hirpdag]
+    struct Point {
+        pub x: i64,
+        pub y: i64,
+    }
+
+    #[hirpdag]
+    struct Size {
+        pub x: i64,
+        pub y: i64,
+    }
+
+    #[hirpdag]
+    struct Shape {
+        pub points: Vec<Point>,
+        pub colour: Option<Colour>,
+        pub parts: Vec<Shape>,
+    }
+}
+
+use shapes::*;
+
+fn square(side: i64, colour: Option<Colour>) -> Shape {
+    let points = vec![
+        Point::new(0, 0),
+        Point::new(side, 0),
+        Point::new(side, side),
+        Point::new(0, side),
+    ];
+    Shape::new(points, colour, vec![])
+}
+
+#[test]
+fn pins_the_algorithm() {
+    // Stored hashes may be persisted: these must not change.
+    assert_eq!(
+        Point::new(1, 2).hirpdag_stable_hash(),
+        0xedd0_86cb_4484_c295
+    );
+    assert_eq!(
+        square(2, Some(Colour::Rgb(0xff0000))).hirpdag_stable_hash(),
+        0xe2f5_5b74_955e_5d3d
+    );
+}
+
+#[test]
+fn hashes_content() {
+    let a = square(3, Some(Colour::Named("red".to_string())));
+    let b = square(3, Some(Colour::Named("red".to_string())));
+    assert_eq!(a.hirpdag_stable_hash(), b.hirpdag_stable_hash());
+
+    // Any change below a node changes its hash.
+    let hashes = [
+        a.hirpdag_stable_hash(),
+        square(4, Some(Colour::Named("red".to_string()))).hirpdag_stable_hash(),
+        square(3, Some(Colour::Named("blue".to_string()))).hirpdag_stable_hash(),
+        square(3, None).hirpdag_stable_hash(),
+        Shape::new(vec![], None, vec![a.clone()]).hirpdag_stable_hash(),
+        Shape::new(vec![], None, vec![a.clone(), a.clone()]).hirpdag_stable_hash(),
+    ];
+    for (i, x) in hashes.iter().enumerate() {
+        for y in &hashes[i + 1..] {
+            assert_ne!(x, y);
+        }
+    }
+
+    // Types with the same fields hash apart.
+    assert_ne!(
+        Point::new(1, 2).hirpdag_stable_hash(),
+        Size::new(1, 2).hirpdag_stable_hash()
+    );
+}
+
+#[test]
+fn node_refs_report_the_stable_hash() {
+    let shape = square(5, Some(Colour::Rgb(7)));
+    let node = HirpdagNodeRef::from(shape.clone());
+    assert_eq!(node.hirpdag_stable_hash(), shape.hirpdag_stable_hash());
+}
@@ -0,0 +1,160 @@
+// Tests for content-addressed node stores.
+#![cfg(feature = "stable-hash")]
+
+use hirpdag::base::HirpdagDeserializeError;
+use hirpdag::*;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+#[hirpdag_module]
+mod tree {
+    #[hirpdag]
+    enum Label {
+        Text(String),
+        Number(i64),
+    }
+
+    #[hirpdag(root)]
+    struct Tree {
+        pub label: Label,
+        pub children: Vec<Tree>,
+        pub link: Option<Tree>,
+    }
+}
+
+use tree::*;
+
+/// An empty scratch directory per test, under the target directory.
+fn scratch(test: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("store")
+        .join(test);
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn leaf(text: &str) -> Tree {
+    Tree::new(Label::Text(format!("store_{}", text)), vec![], None)
+}
+
+/// Two trees sharing the `shared` subtree.
+fn trees() -> (Tree, Tree) {
+    let shared = Tree::new(Label::Number(1), vec![leaf("a"), leaf("b")], None);
+    let first = Tree::new(Label::Number(2), vec![shared.clone(), leaf("c")], None);
+    let second = Tree::new(
+        Label::Text("second".to_string()),
+        vec![leaf("d")],
+        Some(shared),
+    );
+    (first, second)
+}
+
+fn packs(dir: &Path) -> usize {
+    std::fs::read_dir(dir.join("packs")).unwrap().count()
+}
+
+#[test]
+fn round_trips_nodes() {
+    let dir = scratch("round_trips_nodes");
+    let (first, second) = trees();
+    let mut store = HirpdagNodeStore::open(&dir).unwrap();
+    let hash = store.put(first.clone()).unwrap();
+    assert_eq!(hash, first.hirpdag_stable_hash());
+    // first, shared, a, b, c
+    assert_eq!(store.len(), 5);
+    store.put(second.clone()).unwrap();
+    // and second, d
+    assert_eq!(store.len(), 7);
+    drop(store);
+
+    let store = HirpdagNodeStore::open(&dir).unwrap();
+    assert_eq!(store.len(), 7);
+    let read = Tree::try_from(store.get(hash).unwrap()).unwrap();
+    assert_eq!(read, first);
+    let read = store.get(second.hirpdag_stable_hash()).unwrap();
+    assert_eq!(read, HirpdagNodeRef::from(second));
+}
+
+#[test]
+fn writes_each_node_once() {
+    let dir = scratch("writes_each_node_once");
+    let (first, second) = trees();
+    let mut store = HirpdagNodeStore::open(&dir).unwrap();
+    store.put(first.clone()).unwrap();
+    store.put(first.clone()).unwrap();
+    assert_eq!(packs(&dir), 1);
+
+    // Another run shares the nodes already stored.
+    let mut store = HirpdagNodeStore::open(&dir).unwrap();
+    store.put(first.children[0].clone()).unwrap();
+    assert_eq!(packs(&dir), 1);
+    store.put(second).unwrap();
+    assert_eq!(packs(&dir), 2);
+    assert_eq!(store.len(), 7);
+}
+
+#[test]
+fn stores_archived_roots() {
+    let dir = scratch("stores_archived_roots");
+    let (first, second) = trees();
+    let bytes = hirpdag_serialize(&HirpdagArchiveRoots {
+        tree: vec![first, second],
+    })
+    .unwrap();
+    let roots = hirpdag_deserialize(&bytes).unwrap();
+    let mut store = HirpdagNodeStore::open(&dir).unwrap();
+    let hashes: Vec<u64> = roots
+        .tree
+        .iter()
+        .map(|root| store.put(root.clone()).unwrap())
+        .collect();
+    for (root, hash) in roots.tree.iter().zip(hashes) {
+        assert_eq!(store.get(hash).unwrap(), HirpdagNodeRef::from(root.clone()));
+    }
+}
+
+#[test]
+fn reports_missing_nodes() {
+    let dir = scratch("reports_missing_nodes");
+    let (first, _) = trees();
+    let mut store = HirpdagNodeStore::open(&dir).unwrap();
+    assert_eq!(
+        store.get(first.hirpdag_stable_hash()),
+        Err(HirpdagDeserializeError::MissingNode {
+            hash: first.hirpdag_stable_hash(),
+        })
+    );
+    store.put(first.clone()).unwrap();
+    assert!(store.contains(first.children[1].hirpdag_stable_hash()));
+
+    #[hirpdag_module]
+    mod other {
+        #[hirpdag]
+        struct Note {
+            pub text: String,
+        }
+    }
+
+    assert!(matches!(
+        other::HirpdagNodeStore::open(&dir),
+        Err(HirpdagDeserializeError::SchemaMismatch { .. })
+    ));
+}
+
+#[test]
+fn detects_corrupt_records() {
+    let dir = scratch("detects_corrupt_records");
+    let (first, _) = trees();
+    let mut store = HirpdagNodeStore::open(&dir).unwrap();
+    let hash = store.put(first).unwrap();
+
+    let pack = dir.join("packs").join("0.pack");
+    let mut bytes = std::fs::read(&pack).unwrap();
+    let at = bytes.windows(7).position(|w| w == b"store_a").unwrap();
+    bytes[at + 6] = b'z';
+    std::fs::write(&pack, bytes).unwrap();
+    assert!(matches!(
+        store.get(hash),
+        Err(HirpdagDeserializeError::Format(msg)) if msg.contains("does not match")
+    ));
+}
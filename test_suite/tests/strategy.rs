@@ -0,0 +1,261 @@
+use hirpdag::*;
+
+#[hirpdag_module]
+mod datamodel {
+    #[hirpdag]
+    struct Expr {
+        // pub so rules defined outside the module can read this field.
+        pub x: ExprKind,
+    }
+
+    #[hirpdag]
+    enum ExprKind {
+        Num(u32),
+        Var(String),
+        Add(Vec<Expr>),
+        Mul(Vec<Expr>),
+    }
+}
+
+use datamodel::*;
+
+fn num(n: u32) -> Expr {
+    Expr::new(ExprKind::Num(n))
+}
+
+fn var(name: &str) -> Expr {
+    Expr::new(ExprKind::Var(name.to_string()))
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::new(ExprKind::Add(vec![a, b]))
+}
+
+fn mul(a: Expr, b: Expr) -> Expr {
+    Expr::new(ExprKind::Mul(vec![a, b]))
+}
+
+// Rules only rewrite the node they are given; strategies do the traversal.
+
+// n + m => (n+m), n * m => (n*m)
+struct Fold;
+
+impl HirpdagRewriter for Fold {
+    fn rule_Expr(&self, x: &Expr) -> Option<Expr> {
+        let (ExprKind::Add(v) | ExprKind::Mul(v)) = &x.x else {
+            return None;
+        };
+        match (&x.x, &v[0].x, &v[1].x) {
+            (ExprKind::Add(_), ExprKind::Num(a), ExprKind::Num(b)) => Some(num(a + b)),
+            (ExprKind::Mul(_), ExprKind::Num(a), ExprKind::Num(b)) => Some(num(a * b)),
+            _ => None,
+        }
+    }
+}
+
+// a + 0 => a, a * 1 => a
+struct Identity;
+
+impl HirpdagRewriter for Identity {
+    fn rule_Expr(&self, x: &Expr) -> Option<Expr> {
+        match &x.x {
+            ExprKind::Add(v) if v[1] == num(0) => Some(v[0].clone()),
+            ExprKind::Mul(v) if v[1] == num(1) => Some(v[0].clone()),
+            _ => None,
+        }
+    }
+}
+
+// a * (b + c) => a * b + a * c
+struct Distribute;
+
+impl HirpdagRewriter for Distribute {
+    fn rule_Expr(&self, x: &Expr) -> Option<Expr> {
+        if let ExprKind::Mul(v) = &x.x {
+            if let ExprKind::Add(w) = &v[1].x {
+                return Some(add(
+                    mul(v[0].clone(), w[0].clone()),
+                    mul(v[0].clone(), w[1].clone()),
+                ));
+            }
+        }
+        None
+    }
+}
+
+// Counts the nodes it is applied to, and folds like `Fold`.
+struct CountingFold<'a>(&'a std::cell::Cell<usize>);
+
+impl HirpdagRewriter for CountingFold<'_> {
+    fn rule_Expr(&self, x: &Expr) -> Option<Expr> {
+        self.0.set(self.0.get() + 1);
+        Fold.rule_Expr(x)
+    }
+}
+
+// A strategy implemented directly: succeeds on variables only.
+struct IsVar;
+
+impl HirpdagStrategy for IsVar {
+    fn apply_Expr(&self, x: &Expr) -> Option<Expr> {
+        match &x.x {
+            ExprKind::Var(_) => Some(x.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn strategy_basic_combinators() {
+    let two_plus_three = add(num(2), num(3));
+    assert_eq!(HirpdagRule(Fold).apply_Expr(&two_plus_three), Some(num(5)));
+    assert_eq!(HirpdagRule(Fold).apply_Expr(&num(5)), None);
+    assert_eq!(
+        HirpdagTry(HirpdagRule(Fold)).apply_Expr(&num(5)),
+        Some(num(5))
+    );
+    assert_eq!(HirpdagId.apply_Expr(&num(5)), Some(num(5)));
+    assert_eq!(HirpdagFail.apply_Expr(&num(5)), None);
+
+    let a_plus_0 = add(var("a"), num(0));
+    let fold_or_identity = HirpdagRule(Fold).choice(HirpdagRule(Identity));
+    assert_eq!(fold_or_identity.apply_Expr(&a_plus_0), Some(var("a")));
+    // a + 0 => a, then Fold fails on a.
+    let identity_then_fold = HirpdagRule(Identity).seq(HirpdagRule(Fold));
+    assert_eq!(identity_then_fold.apply_Expr(&a_plus_0), None);
+}
+
+#[test]
+fn strategy_all_one() {
+    let e = add(add(num(1), num(2)), var("a"));
+    // Fold fails on `a`.
+    assert_eq!(HirpdagAll(HirpdagRule(Fold)).apply_Expr(&e), None);
+    assert_eq!(
+        HirpdagAll(HirpdagTry(HirpdagRule(Fold))).apply_Expr(&e),
+        Some(add(num(3), var("a")))
+    );
+    assert_eq!(
+        HirpdagOne(HirpdagRule(Fold)).apply_Expr(&e),
+        Some(add(num(3), var("a")))
+    );
+    assert_eq!(HirpdagOne(HirpdagRule(Fold)).apply_Expr(&num(1)), None);
+    assert_eq!(HirpdagAll(HirpdagFail).apply_Expr(&num(1)), Some(num(1)));
+}
+
+#[test]
+fn strategy_traversals() {
+    // ((1 + 2) * (3 + 4)) + 0
+    let e = add(mul(add(num(1), num(2)), add(num(3), num(4))), num(0));
+    let fold = HirpdagTry(HirpdagRule(Fold));
+
+    // Bottom-up folds everything in one pass.
+    assert_eq!(HirpdagBottomUp(&fold).apply_Expr(&e), Some(num(21)));
+    // Top-down only folds nodes whose children were already numbers.
+    assert_eq!(
+        HirpdagTopDown(&fold).apply_Expr(&e),
+        Some(add(mul(num(3), num(7)), num(0)))
+    );
+    // Without Try, top-down fails as soon as the rule does.
+    assert_eq!(HirpdagTopDown(HirpdagRule(Fold)).apply_Expr(&e), None);
+
+    // Once rewrites the first redex in pre-order only.
+    assert_eq!(
+        HirpdagOnce(HirpdagRule(Fold)).apply_Expr(&e),
+        Some(add(mul(num(3), add(num(3), num(4))), num(0)))
+    );
+
+    // Check a custom strategy reaches all leaves.
+    let vars = add(var("a"), var("b"));
+    assert_eq!(HirpdagBottomUp(IsVar).apply_Expr(&vars), None);
+    assert_eq!(
+        HirpdagBottomUp(IsVar.choice(HirpdagId)).apply_Expr(&vars),
+        Some(vars.clone())
+    );
+}
+
+#[test]
+fn strategy_normal_forms() {
+    // a * ((1 + 1) + 0) => a * 2
+    let e = mul(var("a"), add(add(num(1), num(1)), num(0)));
+    let rules = HirpdagRule(Fold).choice(HirpdagRule(Identity));
+    let expected = mul(var("a"), num(2));
+    assert_eq!(
+        HirpdagInnermost(&rules).apply_Expr(&e),
+        Some(expected.clone())
+    );
+    assert_eq!(HirpdagOutermost(&rules).apply_Expr(&e), Some(expected));
+
+    // Outermost distributes before folding: a * (1 + 1) => a * 1 + a * 1 => a + a.
+    let e = mul(var("a"), add(num(1), num(1)));
+    let rules = HirpdagRule(Distribute).choice(HirpdagRule(Identity));
+    assert_eq!(
+        HirpdagOutermost(&rules).apply_Expr(&e),
+        Some(add(var("a"), var("a")))
+    );
+    let rules = HirpdagRule(Fold).choice(rules);
+    assert_eq!(
+        HirpdagInnermost(&rules).apply_Expr(&e),
+        Some(mul(var("a"), num(2)))
+    );
+
+    assert_eq!(
+        HirpdagRepeat(HirpdagRule(Identity)).apply_Expr(&add(add(var("a"), num(0)), num(0))),
+        Some(var("a"))
+    );
+}
+
+#[test]
+fn strategy_as_rewriter() {
+    let e = add(add(num(1), num(2)), var("a"));
+    let rewriter = HirpdagBottomUp(HirpdagTry(HirpdagRule(Fold))).into_rewriter();
+    assert_eq!(rewriter.rewrite(&e), add(num(3), var("a")));
+    // A failing strategy leaves the node unchanged.
+    assert_eq!(HirpdagFail.into_rewriter().rewrite(&e), e);
+}
+
+#[test]
+fn strategy_rules_rewrite_the_root_only() {
+    // A rewriter's `rewrite_Expr` traverses, so the rule does not use it.
+    let e = add(add(num(1), num(2)), var("a"));
+    assert_eq!(HirpdagRule(Fold).apply_Expr(&e), None);
+    assert_eq!(
+        HirpdagRule(HirpdagBottomUp(HirpdagTry(HirpdagRule(Fold))).into_rewriter()).apply_Expr(&e),
+        None
+    );
+}
+
+#[test]
+fn strategy_traversals_visit_shared_nodes_once() {
+    // x(n+1) = x(n) + x(n): 2^n paths, n+1 distinct nodes.
+    let mut e = var("a");
+    for _ in 0..40 {
+        e = add(e.clone(), e);
+    }
+    let count = |strategy: &dyn Fn(CountingFold) -> Option<Expr>| {
+        let count = std::cell::Cell::new(0);
+        assert_eq!(strategy(CountingFold(&count)), Some(e.clone()));
+        count.get()
+    };
+    assert_eq!(
+        count(&|r| HirpdagBottomUp(HirpdagTry(HirpdagRule(r))).apply_Expr(&e)),
+        41
+    );
+    assert_eq!(
+        count(&|r| HirpdagTopDown(HirpdagTry(HirpdagRule(r))).apply_Expr(&e)),
+        41
+    );
+    assert_eq!(
+        count(&|r| HirpdagInnermost(HirpdagRule(r)).apply_Expr(&e)),
+        41
+    );
+    assert_eq!(
+        count(&|r| HirpdagOutermost(HirpdagRule(r)).apply_Expr(&e)),
+        41
+    );
+    let count = std::cell::Cell::new(0);
+    assert_eq!(
+        HirpdagOnce(HirpdagRule(CountingFold(&count))).apply_Expr(&e),
+        None
+    );
+    assert_eq!(count.get(), 41);
+}
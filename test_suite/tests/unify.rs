@@ -0,0 +1,156 @@
+// Tests for matching and unification.
+
+use hirpdag::base::{HirpdagComputeMeta, HirpdagTerm, HIRPDAG_META_FLAG_METAVAR};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod types {
+    #[hirpdag]
+    enum Kind {
+        #[hirpdag(metavar)]
+        Meta(String),
+        Con(String),
+        Fun(Vec<Type>),
+    }
+
+    #[hirpdag]
+    struct Type {
+        pub kind: Kind,
+    }
+
+    #[hirpdag]
+    struct Scheme {
+        pub name: String,
+        pub ty: Type,
+    }
+}
+
+use types::*;
+
+fn meta(name: &str) -> Type {
+    Type::new(Kind::Meta(format!("unify_{}", name)))
+}
+
+fn con(name: &str) -> Type {
+    Type::new(Kind::Con(format!("unify_{}", name)))
+}
+
+fn fun(args: &[&Type]) -> Type {
+    Type::new(Kind::Fun(args.iter().map(|&t| t.clone()).collect()))
+}
+
+fn is_ground(ty: &Type) -> bool {
+    HirpdagNodeRef::from(ty.clone()).hirpdag_is_ground()
+}
+
+#[test]
+fn flags_metavariables() {
+    let (a, int) = (meta("a"), con("int"));
+    assert!(HirpdagNodeRef::from(a.clone()).hirpdag_is_metavar());
+    assert!(!HirpdagNodeRef::from(int.clone()).hirpdag_is_metavar());
+    assert!(!HirpdagNodeRef::from(fun(&[&a])).hirpdag_is_metavar());
+
+    assert!(!is_ground(&a));
+    assert!(!is_ground(&fun(&[&int, &fun(&[&a])])));
+    assert!(is_ground(&fun(&[&int, &int])));
+    let flags = fun(&[&int, &fun(&[&a])]).hirpdag_compute_meta().get_flags();
+    assert_eq!(flags, HIRPDAG_META_FLAG_METAVAR);
+}
+
+#[test]
+fn matches_patterns() {
+    let (a, int, bool_) = (meta("a"), con("int"), con("bool"));
+    let pattern = fun(&[&a, &a]);
+
+    let term = fun(&[&int, &int]);
+    let subst = hirpdag_match_term(&pattern, &term).unwrap();
+    assert_eq!(subst.len(), 1);
+    assert_eq!(
+        subst.get(&HirpdagNodeRef::from(a.clone())),
+        Some(&HirpdagNodeRef::from(int.clone()))
+    );
+    assert_eq!(hirpdag_substitute(&subst, &pattern), term);
+
+    assert!(hirpdag_match_term(&pattern, &fun(&[&int, &bool_])).is_none());
+    assert!(hirpdag_match_term(&pattern, &fun(&[&int])).is_none());
+
+    // Ground patterns match only themselves.
+    let ground = fun(&[&int]);
+    assert!(hirpdag_match_term(&ground, &fun(&[&int]))
+        .unwrap()
+        .is_empty());
+    assert!(hirpdag_match_term(&ground, &fun(&[&bool_])).is_none());
+
+    // Matching goes through struct fields other than nodes.
+    let scheme = Scheme::new("unify_id".to_string(), pattern);
+    let instance = Scheme::new("unify_id".to_string(), term.clone());
+    assert!(hirpdag_match_term(&scheme, &instance).is_some());
+    let other = Scheme::new("unify_other".to_string(), term);
+    assert!(hirpdag_match_term(&scheme, &other).is_none());
+}
+
+#[test]
+fn matching_binds_pattern_metavariables_only() {
+    let (a, b, int) = (meta("a"), meta("b"), con("int"));
+    let subst = hirpdag_match_term(&fun(&[&a, &int]), &fun(&[&b, &int])).unwrap();
+    assert_eq!(
+        subst.get(&HirpdagNodeRef::from(a)),
+        Some(&HirpdagNodeRef::from(b.clone()))
+    );
+    // A metavariable in the term is an ordinary node.
+    assert!(hirpdag_match_term(&int, &b).is_none());
+}
+
+#[test]
+fn unifies_terms() {
+    let (a, b, int, bool_) = (meta("a"), meta("b"), con("int"), con("bool"));
+    let left = fun(&[&a, &bool_]);
+    let right = fun(&[&int, &b]);
+    let subst = hirpdag_unify(&left, &right).unwrap();
+    assert_eq!(subst.len(), 2);
+    let unified = hirpdag_substitute(&subst, &left);
+    assert_eq!(unified, fun(&[&int, &bool_]));
+    assert_eq!(hirpdag_substitute(&subst, &right), unified);
+
+    assert!(hirpdag_unify(&fun(&[&a, &a]), &fun(&[&int, &bool_])).is_none());
+    assert!(hirpdag_unify(&int, &bool_).is_none());
+    assert!(hirpdag_unify(&left, &left).unwrap().is_empty());
+}
+
+#[test]
+fn follows_binding_chains() {
+    let (a, b, int) = (meta("a"), meta("b"), con("int"));
+    // `a` is bound to `b`, which is bound to `int` later.
+    let left = fun(&[&a, &b]);
+    let right = fun(&[&b, &int]);
+    let subst = hirpdag_unify(&left, &right).unwrap();
+    assert_eq!(
+        subst.get(&HirpdagNodeRef::from(a.clone())),
+        Some(&HirpdagNodeRef::from(b.clone()))
+    );
+    assert_eq!(
+        subst.resolve(&HirpdagNodeRef::from(a)),
+        HirpdagNodeRef::from(int.clone())
+    );
+    assert_eq!(hirpdag_substitute(&subst, &left), fun(&[&int, &int]));
+    assert_eq!(
+        hirpdag_substitute(&subst, &vec![left.clone(), right]),
+        vec![fun(&[&int, &int]); 2]
+    );
+}
+
+#[test]
+fn occurs_check_rejects_infinite_terms() {
+    let (a, b, int) = (meta("a"), meta("b"), con("int"));
+    assert!(hirpdag_unify(&a, &fun(&[&a, &int])).is_none());
+    // Also through a binding.
+    assert!(hirpdag_unify(&fun(&[&a, &b]), &fun(&[&fun(&[&b]), &a])).is_none());
+}
+
+#[test]
+fn unifies_only_nodes_of_one_type() {
+    let a = HirpdagNodeRef::from(meta("a"));
+    let scheme = HirpdagNodeRef::from(Scheme::new("unify_s".to_string(), con("int")));
+    assert!(hirpdag_unify(&a, &scheme).is_none());
+    assert!(hirpdag_unify(&a, &HirpdagNodeRef::from(con("int"))).is_some());
+}
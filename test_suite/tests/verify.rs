@@ -0,0 +1,98 @@
+use hirpdag::base::{
+    hirpdag_set_verify_mode, hirpdag_take_verify_violations, HirpdagDeserializeOptions,
+    HirpdagVerifyMode,
+};
+use hirpdag::*;
+
+#[hirpdag_module]
+mod datamodel {
+    #[hirpdag(normalizer)]
+    struct EvenNumber {
+        pub a: u32,
+    }
+
+    #[hirpdag(root)]
+    struct Holder {
+        pub x: EvenNumber,
+    }
+
+    impl EvenNumber {
+        pub fn new(a: u32) -> EvenNumber {
+            EvenNumber::spawn(a & !1)
+        }
+
+        // Builds a node the current normalizer would reject, as an older
+        // version of it might have.
+        pub fn unnormalized(a: u32) -> EvenNumber {
+            EvenNumber::spawn(a)
+        }
+    }
+}
+
+use datamodel::*;
+
+// The verify mode is process-global: tests which intern nodes a verifier
+// would reject hold this lock.
+static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[test]
+fn verify_mode() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    hirpdag_set_verify_mode(HirpdagVerifyMode::Record);
+
+    // Nodes built through new() are in normal form.
+    EvenNumber::new(1001);
+    Holder::new(EvenNumber::new(1003));
+    assert!(hirpdag_take_verify_violations().is_empty());
+
+    // spawn bypasses the normalizer.
+    let odd = EvenNumber::unnormalized(1005);
+    let violations = hirpdag_take_verify_violations();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].type_name, "EvenNumber");
+    assert!(violations[0].to_string().contains("1005"));
+    assert!(violations[0].normalized.contains("1004"));
+
+    // Only freshly interned nodes are verified.
+    EvenNumber::unnormalized(1005);
+    assert!(hirpdag_take_verify_violations().is_empty());
+    drop(odd);
+
+    hirpdag_set_verify_mode(HirpdagVerifyMode::Panic);
+    let panicked = std::panic::catch_unwind(|| EvenNumber::unnormalized(1007)).is_err();
+    hirpdag_set_verify_mode(HirpdagVerifyMode::Off);
+    assert!(panicked);
+
+    EvenNumber::unnormalized(1009);
+    assert!(hirpdag_take_verify_violations().is_empty());
+}
+
+#[test]
+fn deserialize_renormalize() {
+    let _serial = SERIAL
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    // An archive written before the normalizer existed.
+    let old = Holder::new(EvenNumber::unnormalized(2001));
+    let roots = HirpdagArchiveRoots {
+        holder: vec![old.clone()],
+    };
+    let bytes = hirpdag_serialize(&roots).unwrap();
+    let text = hirpdag_serialize_json(&roots).unwrap();
+
+    // By default, nodes are loaded as is.
+    let out = hirpdag_deserialize(&bytes).unwrap();
+    assert_eq!(out.holder, vec![old]);
+
+    let options = HirpdagDeserializeOptions {
+        renormalize: true,
+        ..Default::default()
+    };
+    let expected = Holder::new(EvenNumber::new(2000));
+    let out = hirpdag_deserialize_with_options(&bytes, &options).unwrap();
+    assert_eq!(out.holder, vec![expected.clone()]);
+    let out = hirpdag_deserialize_json_with_options(&text, &options).unwrap();
+    assert_eq!(out.holder, vec![expected]);
+}